@@ -12,7 +12,7 @@ use gtk::{gio, glib, prelude::*};
 use log::{debug, warn};
 
 use crate::{
-    audio::AudioPlayer,
+    audio::{AudioPlayer, PlayHistory, SavedPlaylist, TrackPosition},
     config::{APPLICATION_ID, VERSION},
     i18n::i18n,
     utils,
@@ -21,6 +21,14 @@ use crate::{
 
 pub enum ApplicationAction {
     Present,
+    ActivatePlaylist(SavedPlaylist),
+    // The URI of a song, and its rating
+    SetRating(String, u32),
+    // The URI of a song, where to add it to the queue, and whether to
+    // play it
+    AddTrack(String, TrackPosition, bool),
+    // The URI of a song to play
+    OpenTrack(String),
 }
 
 mod imp {
@@ -77,6 +85,7 @@ mod imp {
             obj.set_accels_for_action("win.next", &["<primary>n"]);
             obj.set_accels_for_action("win.play", &["<primary>p"]);
             obj.set_accels_for_action("win.copy", &["<primary>c"]);
+            obj.set_accels_for_action("win.preferences", &["<primary>comma"]);
         }
     }
 
@@ -93,6 +102,13 @@ mod imp {
             self.obj().present_main_window();
         }
 
+        fn shutdown(&self) {
+            // Write the plays that are still pending
+            PlayHistory::global().lock().unwrap().flush();
+
+            self.parent_shutdown();
+        }
+
         fn open(&self, files: &[gio::File], _hint: &str) {
             debug!("Application::open");
 
@@ -167,6 +183,12 @@ impl Application {
     fn process_action(&self, action: ApplicationAction) -> glib::ControlFlow {
         match action {
             ApplicationAction::Present => self.present_main_window(),
+            ApplicationAction::ActivatePlaylist(playlist) => self.activate_playlist(&playlist),
+            ApplicationAction::SetRating(uri, rating) => self.set_song_rating(&uri, rating),
+            ApplicationAction::AddTrack(uri, position, set_as_current) => {
+                self.add_track(&uri, position, set_as_current)
+            }
+            ApplicationAction::OpenTrack(uri) => self.open_track(&uri),
             // _ => debug!("Received action {:?}", action),
         }
 
@@ -187,6 +209,51 @@ impl Application {
         window.present();
     }
 
+    // The main window loads the songs of the playlist, but it does not
+    // need to be visible for that
+    fn activate_playlist(&self, playlist: &SavedPlaylist) {
+        let window = self
+            .windows()
+            .into_iter()
+            .find_map(|w| w.downcast::<Window>().ok())
+            .unwrap_or_else(|| Window::new(self));
+
+        window.activate_playlist(playlist);
+    }
+
+    // The songs being rated are in the queue, so there must be a window
+    fn set_song_rating(&self, uri: &str, rating: u32) {
+        let window = self
+            .windows()
+            .into_iter()
+            .find_map(|w| w.downcast::<Window>().ok());
+
+        if let Some(window) = window {
+            window.set_song_rating(uri, rating);
+        }
+    }
+
+    // Like playlists, tracks can be added without a visible window
+    fn add_track(&self, uri: &str, position: TrackPosition, set_as_current: bool) {
+        let window = self
+            .windows()
+            .into_iter()
+            .find_map(|w| w.downcast::<Window>().ok())
+            .unwrap_or_else(|| Window::new(self));
+
+        window.add_track(&gio::File::for_uri(uri), position, set_as_current);
+    }
+
+    fn open_track(&self, uri: &str) {
+        let window = self
+            .windows()
+            .into_iter()
+            .find_map(|w| w.downcast::<Window>().ok())
+            .unwrap_or_else(|| Window::new(self));
+
+        window.open_track(&gio::File::for_uri(uri));
+    }
+
     fn setup_gactions(&self) {
         self.add_action_entries([
             gio::ActionEntry::builder("quit")
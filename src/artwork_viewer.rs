@@ -0,0 +1,207 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::cell::RefCell;
+
+use adw::{prelude::*, subclass::prelude::*};
+use glib::clone;
+use gtk::{gdk, glib, CompositeTemplate};
+
+use crate::{audio::Artwork, i18n::i18n_f, zoomable_picture::ZoomablePicture};
+
+mod imp {
+    use super::*;
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/io/bassi/Amberol/artwork-viewer.ui")]
+    pub struct ArtworkViewer {
+        #[template_child]
+        pub window_title: TemplateChild<adw::WindowTitle>,
+        #[template_child]
+        pub carousel: TemplateChild<adw::Carousel>,
+        #[template_child]
+        pub previous_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub next_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub indicator: TemplateChild<adw::CarouselIndicatorDots>,
+
+        pub labels: RefCell<Vec<String>>,
+        pub pictures: RefCell<Vec<ZoomablePicture>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for ArtworkViewer {
+        const NAME: &'static str = "AmberolArtworkViewer";
+        type Type = super::ArtworkViewer;
+        type ParentType = adw::Dialog;
+
+        fn class_init(klass: &mut Self::Class) {
+            Self::bind_template(klass);
+
+            klass.install_action("viewer.zoom-in", None, move |viewer, _, _| {
+                if let Some(picture) = viewer.current_picture() {
+                    picture.zoom_in();
+                }
+            });
+            klass.install_action("viewer.zoom-out", None, move |viewer, _, _| {
+                if let Some(picture) = viewer.current_picture() {
+                    picture.zoom_out();
+                }
+            });
+            klass.install_action("viewer.zoom-fit", None, move |viewer, _, _| {
+                if let Some(picture) = viewer.current_picture() {
+                    picture.zoom_to_fit();
+                }
+            });
+            klass.install_action("viewer.previous", None, move |viewer, _, _| {
+                viewer.scroll_by(-1);
+            });
+            klass.install_action("viewer.next", None, move |viewer, _, _| {
+                viewer.scroll_by(1);
+            });
+
+            let ctrl = gdk::ModifierType::CONTROL_MASK;
+            klass.add_binding_action(gdk::Key::plus, ctrl, "viewer.zoom-in");
+            klass.add_binding_action(gdk::Key::equal, ctrl, "viewer.zoom-in");
+            klass.add_binding_action(gdk::Key::KP_Add, ctrl, "viewer.zoom-in");
+            klass.add_binding_action(gdk::Key::minus, ctrl, "viewer.zoom-out");
+            klass.add_binding_action(gdk::Key::KP_Subtract, ctrl, "viewer.zoom-out");
+            klass.add_binding_action(gdk::Key::_0, ctrl, "viewer.zoom-fit");
+            klass.add_binding_action(gdk::Key::KP_0, ctrl, "viewer.zoom-fit");
+            klass.add_binding_action(
+                gdk::Key::Page_Up,
+                gdk::ModifierType::empty(),
+                "viewer.previous",
+            );
+            klass.add_binding_action(
+                gdk::Key::Page_Down,
+                gdk::ModifierType::empty(),
+                "viewer.next",
+            );
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for ArtworkViewer {
+        fn constructed(&self) {
+            self.parent_constructed();
+
+            self.obj().setup_widgets();
+        }
+    }
+
+    impl WidgetImpl for ArtworkViewer {}
+    impl AdwDialogImpl for ArtworkViewer {}
+}
+
+glib::wrapper! {
+    pub struct ArtworkViewer(ObjectSubclass<imp::ArtworkViewer>)
+        @extends gtk::Widget, adw::Dialog;
+}
+
+impl Default for ArtworkViewer {
+    fn default() -> Self {
+        glib::Object::new()
+    }
+}
+
+impl ArtworkViewer {
+    pub fn new(artwork: &[Artwork]) -> Self {
+        let res = Self::default();
+        res.set_artwork(artwork);
+        res
+    }
+
+    fn setup_widgets(&self) {
+        self.imp().carousel.connect_page_changed(clone!(@weak self as this => move |_, _| {
+            this.update_state();
+        }));
+    }
+
+    fn set_artwork(&self, artwork: &[Artwork]) {
+        let imp = self.imp();
+
+        for a in artwork {
+            let picture = ZoomablePicture::new(&a.texture);
+            picture.update_property(&[gtk::accessible::Property::Label(&a.label)]);
+            picture.connect_notify_local(
+                Some("zoomed"),
+                clone!(@weak self as this => move |_, _| {
+                    this.update_state();
+                }),
+            );
+
+            imp.carousel.append(&picture);
+            imp.pictures.borrow_mut().push(picture);
+            imp.labels.borrow_mut().push(a.label.clone());
+        }
+
+        // There is nothing to navigate with a single picture
+        let has_pages = artwork.len() > 1;
+        imp.previous_button.set_visible(has_pages);
+        imp.next_button.set_visible(has_pages);
+        imp.indicator.set_visible(has_pages);
+
+        self.update_state();
+    }
+
+    fn current_index(&self) -> usize {
+        self.imp().carousel.position().round().max(0.0) as usize
+    }
+
+    fn current_picture(&self) -> Option<ZoomablePicture> {
+        self.imp()
+            .pictures
+            .borrow()
+            .get(self.current_index())
+            .cloned()
+    }
+
+    fn scroll_by(&self, delta: i32) {
+        let idx = self.current_index() as i32 + delta;
+        if idx < 0 {
+            return;
+        }
+
+        let picture = self.imp().pictures.borrow().get(idx as usize).cloned();
+        if let Some(picture) = picture {
+            self.imp().carousel.scroll_to(&picture, true);
+        }
+    }
+
+    fn update_state(&self) {
+        let imp = self.imp();
+        let idx = self.current_index();
+        let n_pictures = imp.pictures.borrow().len();
+
+        let label = imp.labels.borrow().get(idx).cloned().unwrap_or_default();
+        imp.window_title.set_title(&label);
+        if n_pictures > 1 {
+            imp.window_title.set_subtitle(&i18n_f(
+                // Translators: the first `{}` is replaced by the position
+                // of the picture, and the second `{}` by the number of
+                // pictures
+                "{} of {}",
+                &[&(idx + 1).to_string(), &n_pictures.to_string()],
+            ));
+        } else {
+            imp.window_title.set_subtitle("");
+        }
+
+        let has_picture = idx < n_pictures;
+        let zoomed = self.current_picture().is_some_and(|p| p.is_zoomed());
+
+        // Swiping would get in the way of panning the picture
+        imp.carousel.set_interactive(!zoomed);
+
+        self.action_set_enabled("viewer.zoom-in", has_picture);
+        self.action_set_enabled("viewer.zoom-out", zoomed);
+        self.action_set_enabled("viewer.zoom-fit", zoomed);
+        self.action_set_enabled("viewer.previous", idx > 0);
+        self.action_set_enabled("viewer.next", idx + 1 < n_pictures);
+    }
+}
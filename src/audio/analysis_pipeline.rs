@@ -0,0 +1,162 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The waveform and the spectrogram of a song are computed by decoding it as
+// fast as possible, and collecting the messages posted by an analysis
+// element, like "level" or "spectrum", at regular intervals.
+//
+// Songs analysed in the background, ahead of being played, would compete
+// with the playback and the UI for the CPU if decoded as fast as possible;
+// their pipeline is synchronised to the clock instead, and played at a
+// fixed rate.
+
+use std::cell::{Cell, RefCell};
+
+use glib::clone;
+use gst::prelude::*;
+use gtk::glib;
+use log::{debug, warn};
+
+// How many times faster than real time songs are analysed in the
+// background
+const BACKGROUND_RATE: f64 = 8.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AnalysisSpeed {
+    // As fast as possible, for the song being played
+    Full,
+    // Throttled, for the songs coming up next
+    Background,
+}
+
+// A pipeline analysing a song; dropping it stops the decoding
+#[derive(Debug)]
+pub struct AnalysisPipeline {
+    pipeline: gst::Element,
+    _bus_watch: gst::bus::BusWatchGuard,
+}
+
+impl AnalysisPipeline {
+    // Starts decoding the song at the given URI, converted to the given
+    // caps, and passes the messages posted by the analyser element to
+    // `on_message`; `on_done` is called at the end of the stream, or if
+    // decoding fails
+    pub fn new<M, D>(
+        uri: &str,
+        caps: &str,
+        analyser: &str,
+        speed: AnalysisSpeed,
+        mut on_message: M,
+        on_done: D,
+    ) -> Option<Self>
+    where
+        M: FnMut(&gst::StructureRef) + 'static,
+        D: FnOnce() + 'static,
+    {
+        let pipeline_str = format!(
+            "uridecodebin name=uridecodebin ! audioconvert ! {} ! {} ! fakesink name=faked",
+            caps, analyser
+        );
+        let pipeline = match gst::parse::launch(&pipeline_str) {
+            Ok(pipeline) => pipeline,
+            Err(err) => {
+                warn!("Unable to analyse the song: {}", err);
+                return None;
+            }
+        };
+
+        let uridecodebin = pipeline
+            .downcast_ref::<gst::Bin>()
+            .unwrap()
+            .by_name("uridecodebin")
+            .unwrap();
+        uridecodebin.set_property("uri", uri);
+
+        let fakesink = pipeline
+            .downcast_ref::<gst::Bin>()
+            .unwrap()
+            .by_name("faked")
+            .unwrap();
+        fakesink.set_property("qos", false);
+        fakesink.set_property("sync", speed == AnalysisSpeed::Background);
+
+        let bus = pipeline
+            .bus()
+            .expect("Pipeline without bus. Shouldn't happen!");
+
+        let on_done = RefCell::new(Some(on_done));
+        let rate_set = Cell::new(speed == AnalysisSpeed::Full);
+
+        debug!("Adding bus watch");
+        let bus_watch = bus
+            .add_watch_local(
+                clone!(@weak pipeline => @default-return glib::ControlFlow::Break, move |_, msg| {
+                    use gst::MessageView;
+
+                    match msg.view() {
+                        MessageView::Eos(..) => {
+                            debug!("End of analysis stream");
+                        }
+                        MessageView::Error(err) => {
+                            warn!("Pipeline error: {:?}", err);
+                        }
+                        MessageView::Element(element) => {
+                            if let Some(s) = element.structure() {
+                                on_message(s);
+                            }
+                            return glib::ControlFlow::Continue;
+                        }
+                        MessageView::AsyncDone(..) if !rate_set.replace(true) => {
+                            // The seek prerolls the pipeline again, which
+                            // posts another AsyncDone message
+                            if let Err(err) = pipeline.seek(
+                                BACKGROUND_RATE,
+                                gst::SeekFlags::FLUSH,
+                                gst::SeekType::Set,
+                                gst::ClockTime::ZERO,
+                                gst::SeekType::None,
+                                gst::ClockTime::NONE,
+                            ) {
+                                // Decoding in real time is still better
+                                // than decoding as fast as possible
+                                debug!("Unable to change the analysis rate: {}", err);
+                            }
+                            return glib::ControlFlow::Continue;
+                        }
+                        _ => return glib::ControlFlow::Continue,
+                    };
+
+                    // We're done
+                    pipeline.set_state(gst::State::Null).expect("Unable to set 'null' state");
+                    let on_done = on_done.borrow_mut().take();
+                    if let Some(on_done) = on_done {
+                        on_done();
+                    }
+
+                    glib::ControlFlow::Break
+                }),
+            )
+            .expect("failed to add bus watch");
+
+        if let Err(err) = pipeline.set_state(gst::State::Playing) {
+            warn!("Unable to analyse the song: {}", err);
+            pipeline
+                .set_state(gst::State::Null)
+                .expect("Pipeline reset failed");
+            return None;
+        }
+
+        Some(AnalysisPipeline {
+            pipeline,
+            _bus_watch: bus_watch,
+        })
+    }
+}
+
+impl Drop for AnalysisPipeline {
+    fn drop(&mut self) {
+        if let Err(err) = self.pipeline.set_state(gst::State::Null) {
+            warn!("Unable to set existing pipeline to Null state: {}", err);
+        }
+    }
+}
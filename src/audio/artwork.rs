@@ -0,0 +1,117 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The artwork of a song, at its native resolution, for the artwork viewer;
+// unlike the cover art, which is scaled down and cached, it is loaded on
+// demand and never kept around.
+
+use std::path::Path;
+
+use gtk::{gdk, glib};
+use lofty::TaggedFileExt;
+use log::{debug, warn};
+
+use crate::{audio::CoverFiles, i18n::i18n};
+
+#[derive(Clone, Debug)]
+pub struct Artwork {
+    pub label: String,
+    pub texture: gdk::Texture,
+}
+
+fn picture_label(pic_type: &lofty::PictureType) -> String {
+    match pic_type {
+        lofty::PictureType::CoverFront => i18n("Front Cover"),
+        lofty::PictureType::CoverBack => i18n("Back Cover"),
+        lofty::PictureType::Leaflet => i18n("Booklet"),
+        lofty::PictureType::Media => i18n("Media"),
+        lofty::PictureType::LeadArtist | lofty::PictureType::Artist => i18n("Artist"),
+        lofty::PictureType::Band => i18n("Band"),
+        lofty::PictureType::BandLogo | lofty::PictureType::PublisherLogo => i18n("Logo"),
+        lofty::PictureType::Illustration => i18n("Illustration"),
+        _ => i18n("Picture"),
+    }
+}
+
+// Pictures are shown in the order of a physical release; the ones without
+// an obvious place come last, in the order they are stored
+fn picture_order(pic_type: &lofty::PictureType) -> u32 {
+    match pic_type {
+        lofty::PictureType::CoverFront => 0,
+        lofty::PictureType::CoverBack => 1,
+        lofty::PictureType::Leaflet => 2,
+        lofty::PictureType::Media => 3,
+        _ => 4,
+    }
+}
+
+fn load_texture(data: &[u8]) -> Option<gdk::Texture> {
+    match gdk::Texture::from_bytes(&glib::Bytes::from(data)) {
+        Ok(texture) => Some(texture),
+        Err(e) => {
+            warn!("Unable to load artwork: {}", e);
+            None
+        }
+    }
+}
+
+// The pictures embedded in every tag of the file
+fn load_embedded(path: &Path) -> Vec<Artwork> {
+    let tagged_file = match lofty::read_from_path(path) {
+        Ok(f) => f,
+        Err(e) => {
+            warn!("Unable to open file {:?}: {}", path, e);
+            return vec![];
+        }
+    };
+
+    // The same picture is often stored in more than one tag
+    let mut pictures: Vec<&lofty::Picture> = vec![];
+    for tag in tagged_file.tags() {
+        for picture in tag.pictures() {
+            if !pictures.iter().any(|p| p.data() == picture.data()) {
+                pictures.push(picture);
+            }
+        }
+    }
+    pictures.sort_by_key(|p| picture_order(&p.pic_type()));
+
+    pictures
+        .into_iter()
+        .filter_map(|p| {
+            let texture = load_texture(p.data())?;
+            Some(Artwork {
+                label: picture_label(&p.pic_type()),
+                texture,
+            })
+        })
+        .collect()
+}
+
+// Decoding large pictures is slow, so this function should be called
+// from a separate thread
+pub fn load_artwork(path: &Path, album: Option<&str>) -> Vec<Artwork> {
+    let res = load_embedded(path);
+    if !res.is_empty() {
+        return res;
+    }
+
+    // Songs without embedded pictures can still have a cover file
+    let cover_file = path.parent().and_then(|dir| CoverFiles::find(dir, album));
+    if let Some(cover_file) = cover_file {
+        debug!("Loading artwork from cover file: {:?}", &cover_file);
+        match std::fs::read(&cover_file) {
+            Ok(data) => {
+                if let Some(texture) = load_texture(&data) {
+                    return vec![Artwork {
+                        label: i18n("Cover"),
+                        texture,
+                    }];
+                }
+            }
+            Err(e) => warn!("Unable to read cover file {:?}: {}", &cover_file, e),
+        }
+    }
+
+    vec![]
+}
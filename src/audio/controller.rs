@@ -7,6 +7,13 @@ pub trait Controller {
     fn set_playback_state(&self, state: &PlaybackState);
 
     fn set_song(&self, song: &Song);
+    // The metadata of the current song has changed
+    fn update_song(&self, song: &Song);
+    // Positions, in milliseconds
     fn set_position(&self, position: u64);
+    // The position jumped, after a seek
+    fn seeked(&self, position: u64);
     fn set_repeat_mode(&self, repeat: RepeatMode);
+    fn set_shuffled(&self, shuffled: bool);
+    fn set_volume(&self, volume: f64);
 }
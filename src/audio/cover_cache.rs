@@ -2,28 +2,38 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap, HashSet},
     path::{Path, PathBuf},
     sync::Mutex,
 };
 
 use gtk::{gdk, gio, glib, prelude::*};
-use log::debug;
+use log::{debug, warn};
 use once_cell::sync::OnceCell;
 use sha2::{Digest, Sha256};
 
-use crate::utils;
+use crate::{
+    audio::{cover_files::CoverFiles, metadata_index::MetadataIndex},
+    utils,
+};
+
+// The default memory budget for the cover textures, in bytes
+const DEFAULT_BUDGET: usize = 128 * 1024 * 1024;
 
+// The cover art of a song; the texture is not part of it, as it can be
+// evicted from memory, and needs to be retrieved using CoverCache::texture()
 #[derive(Clone, Debug)]
 pub struct CoverArt {
-    texture: gdk::Texture,
     palette: Vec<gdk::RGBA>,
     cache: Option<PathBuf>,
+    // Whether the cover comes from an image file next to the song, instead
+    // of the song metadata
+    from_cover_file: bool,
 }
 
 impl CoverArt {
-    pub fn texture(&self) -> &gdk::Texture {
-        self.texture.as_ref()
+    pub fn is_from_cover_file(&self) -> bool {
+        self.from_cover_file
     }
 
     pub fn palette(&self) -> &Vec<gdk::RGBA> {
@@ -35,9 +45,50 @@ impl CoverArt {
     }
 }
 
+fn cache_file(uuid: &str) -> PathBuf {
+    let mut cache_file = glib::user_cache_dir();
+    cache_file.push("amberol");
+    cache_file.push("covers");
+    cache_file.push(format!("{}.png", uuid));
+    cache_file
+}
+
+#[derive(Debug)]
+struct Entry {
+    cover: CoverArt,
+    texture: Option<gdk::Texture>,
+    // Evicted textures might still be in use, for instance by the rows
+    // of the playlist; we keep a weak reference to avoid loading them
+    // again while they are alive
+    evicted_texture: glib::WeakRef<gdk::Texture>,
+    last_used: u64,
+}
+
+// The result of looking up a texture in the cache
+enum CachedTexture {
+    InMemory(gdk::Texture),
+    OnDisk(PathBuf),
+}
+
+fn texture_size(texture: &gdk::Texture) -> usize {
+    texture.width() as usize * texture.height() as usize * 4
+}
+
+// The cover cache keeps the textures of the most recently used covers in
+// memory, within a memory budget; the least recently used textures are
+// evicted, and loaded again from the copy cached on disk when needed
 #[derive(Debug)]
 pub struct CoverCache {
-    entries: HashMap<String, CoverArt>,
+    entries: HashMap<String, Entry>,
+    // The covers that should not be evicted, like the one of the current
+    // song and of the songs around it
+    pinned: HashSet<String>,
+    // The UUIDs of the textures in memory that can be evicted, ordered
+    // by their last use
+    lru: BTreeMap<u64, String>,
+    budget: usize,
+    used: usize,
+    clock: u64,
 }
 
 impl CoverCache {
@@ -53,21 +104,172 @@ impl CoverCache {
     fn new() -> Self {
         CoverCache {
             entries: HashMap::new(),
+            pinned: HashSet::new(),
+            lru: BTreeMap::new(),
+            budget: DEFAULT_BUDGET,
+            used: 0,
+            clock: 0,
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    fn add_entry(
+        &mut self,
+        uuid: &str,
+        cover: CoverArt,
+        texture: Option<gdk::Texture>,
+    ) -> &CoverArt {
+        if !self.entries.contains_key(uuid) {
+            let last_used = self.tick();
+            if let Some(ref texture) = texture {
+                self.used += texture_size(texture);
+                if cover.cache.is_some() {
+                    self.lru.insert(last_used, uuid.to_string());
+                }
+            }
+            self.entries.insert(
+                uuid.to_string(),
+                Entry {
+                    cover,
+                    texture,
+                    evicted_texture: glib::WeakRef::new(),
+                    last_used,
+                },
+            );
+            self.evict();
+        }
+
+        &self.entries[uuid].cover
+    }
+
+    // The UUIDs of the covers in the cache
+    pub fn uuids(&self) -> HashSet<String> {
+        self.entries.keys().cloned().collect()
+    }
+
+    fn lookup(&self, uuid: &str) -> Option<&CoverArt> {
+        self.entries.get(uuid).map(|e| &e.cover)
+    }
+
+    // Drops the least recently used textures until we are within budget;
+    // only textures we can load again from disk are evicted
+    fn evict(&mut self) {
+        let mut used = self.used;
+        let mut evicted = Vec::new();
+        for (last_used, uuid) in self.lru.iter() {
+            if used <= self.budget {
+                break;
+            }
+
+            if !self.pinned.contains(uuid) {
+                used -= self.entries[uuid].texture.as_ref().map_or(0, texture_size);
+                evicted.push(*last_used);
+            }
+        }
+
+        for last_used in evicted {
+            let uuid = self.lru.remove(&last_used).unwrap();
+            let entry = self.entries.get_mut(&uuid).unwrap();
+            if let Some(texture) = entry.texture.take() {
+                debug!("Evicting cover texture: {:?}", entry.cover.cache);
+                self.used -= texture_size(&texture);
+                entry.evicted_texture.set(Some(&texture));
+            }
         }
     }
 
-    fn add_entry(&mut self, uuid: &str, cover: CoverArt) -> &CoverArt {
-        self.entries.entry(uuid.to_string()).or_insert(cover)
+    // Sets the memory budget for the cover textures, in bytes
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.evict();
     }
 
-    fn lookup(&self, uuid: &String) -> Option<&CoverArt> {
-        self.entries.get(uuid)
+    pub fn set_pinned(&mut self, uuids: HashSet<String>) {
+        self.pinned = uuids;
+        self.evict();
     }
 
-    fn load_cover_art(&self, tag: &lofty::Tag, path: Option<&Path>) -> Option<glib::Bytes> {
+    // Marks the entry as used, and returns its texture if it is in memory,
+    // or the copy cached on disk otherwise
+    fn lookup_texture(&mut self, uuid: &str) -> Option<CachedTexture> {
+        let last_used = self.tick();
+        let entry = self.entries.get_mut(uuid)?;
+        let previous_use = std::mem::replace(&mut entry.last_used, last_used);
+
+        if let Some(ref texture) = entry.texture {
+            if let Some(uuid) = self.lru.remove(&previous_use) {
+                self.lru.insert(last_used, uuid);
+            }
+            return Some(CachedTexture::InMemory(texture.clone()));
+        }
+
+        if let Some(texture) = entry.evicted_texture.upgrade() {
+            return Some(CachedTexture::InMemory(self.insert_texture(uuid, texture)));
+        }
+
+        entry.cover.cache.clone().map(CachedTexture::OnDisk)
+    }
+
+    // Keeps the texture of the entry in memory; if another thread loaded
+    // the same texture in the meantime, its copy is used instead
+    fn insert_texture(&mut self, uuid: &str, texture: gdk::Texture) -> gdk::Texture {
+        let entry = match self.entries.get_mut(uuid) {
+            Some(e) => e,
+            None => return texture,
+        };
+
+        if let Some(ref texture) = entry.texture {
+            return texture.clone();
+        }
+
+        entry.texture = Some(texture.clone());
+        entry.evicted_texture.set(None);
+        self.lru.insert(entry.last_used, uuid.to_string());
+        self.used += texture_size(&texture);
+        self.evict();
+
+        texture
+    }
+
+    // The texture of the cover with the given UUID; if the texture was
+    // evicted, it is loaded again from the copy cached on disk, without
+    // holding the lock on the cache
+    pub fn texture(uuid: &str) -> Option<gdk::Texture> {
+        let cache_file = match CoverCache::global().lock().unwrap().lookup_texture(uuid)? {
+            CachedTexture::InMemory(texture) => return Some(texture),
+            CachedTexture::OnDisk(cache_file) => cache_file,
+        };
+
+        debug!("Loading evicted cover texture: {:?}", cache_file);
+        let texture = match gdk::Texture::from_filename(&cache_file) {
+            Ok(t) => t,
+            Err(e) => {
+                warn!("Unable to load cached cover {:?}: {}", cache_file, e);
+                return None;
+            }
+        };
+
+        Some(
+            CoverCache::global()
+                .lock()
+                .unwrap()
+                .insert_texture(uuid, texture),
+        )
+    }
+
+    // Returns the cover art, and whether it comes from a cover file
+    fn load_cover_art(
+        tag: &lofty::Tag,
+        path: Option<&Path>,
+        album: Option<&str>,
+    ) -> Option<(glib::Bytes, bool)> {
         if let Some(picture) = tag.get_picture_type(lofty::PictureType::CoverFront) {
             debug!("Found CoverFront");
-            return Some(glib::Bytes::from(picture.data()));
+            return Some((glib::Bytes::from(picture.data()), false));
         } else {
             // If we don't have a CoverFront picture, we fall back to Other
             // and BandLogo types
@@ -78,9 +280,9 @@ impl CoverCache {
                     _ => None,
                 };
 
-                if cover_art.is_some() {
+                if let Some(cover_art) = cover_art {
                     debug!("Found fallback");
-                    return cover_art;
+                    return Some((cover_art, false));
                 }
             }
         }
@@ -89,19 +291,13 @@ impl CoverCache {
         // to be in a hot cache; looking for a separate file will blow a bunch of
         // caches out of the water, which will slow down loading the song into the
         // playlist model
-        if let Some(p) = path {
-            let ext_covers = vec!["Cover.jpg", "Cover.png", "cover.jpg", "cover.png"];
-
-            for name in ext_covers {
-                let mut cover_file = PathBuf::from(p);
-                cover_file.push(name);
-                debug!("Looking for external cover file: {:?}", &cover_file);
-
-                let f = gio::File::for_path(&cover_file);
-                if let Ok((res, _)) = f.load_bytes(None::<&gio::Cancellable>) {
-                    debug!("Loading cover from external cover file");
-                    return Some(res);
-                }
+        if let Some(cover_file) = path.and_then(|p| CoverFiles::find(p, album)) {
+            debug!("Loading cover from external cover file: {:?}", &cover_file);
+
+            let f = gio::File::for_path(&cover_file);
+            match f.load_bytes(None::<&gio::Cancellable>) {
+                Ok((res, _)) => return Some((res, true)),
+                Err(e) => warn!("Unable to load cover file {:?}: {}", &cover_file, e),
             }
         }
 
@@ -110,7 +306,10 @@ impl CoverCache {
         None
     }
 
-    pub fn cover_art(&mut self, path: &Path, tag: &lofty::Tag) -> Option<(CoverArt, String)> {
+    // Songs are loaded by multiple threads at the same time, so the cache
+    // is only locked while looking up and adding entries; decoding the
+    // cover art happens outside of the lock
+    pub fn cover_art(path: &Path, tag: &lofty::Tag) -> Option<(CoverArt, String)> {
         let mut album_artist = None;
         let mut track_artist = None;
         let mut album = None;
@@ -135,8 +334,8 @@ impl CoverCache {
         // same cover data for every track in the album; if we
         // don't have an album, we use the file name
         let mut hasher = Sha256::new();
-        if let Some(album) = album {
-            hasher.update(&album);
+        if let Some(ref album) = album {
+            hasher.update(album);
 
             if let Some(artist) = album_artist {
                 hasher.update(&artist);
@@ -153,18 +352,20 @@ impl CoverCache {
 
         let uuid = format!("{:x}", hasher.finalize());
 
-        match self.lookup(&uuid) {
+        let cached = CoverCache::global().lock().unwrap().lookup(&uuid).cloned();
+        match cached {
             Some(c) => {
                 debug!("Found cover for UUID '{}'", &uuid);
-                Some((c.clone(), uuid))
+                Some((c, uuid))
             }
             None => {
                 debug!("Loading cover art for UUID: {}", &uuid);
 
-                let cover_art = self.load_cover_art(tag, path.parent());
+                let cover_art = CoverCache::load_cover_art(tag, path.parent(), album.as_deref());
+                let from_cover_file = cover_art.as_ref().is_some_and(|(_, f)| *f);
 
                 // The pixel buffer for the cover art
-                let cover_pixbuf = if let Some(ref cover_art) = cover_art {
+                let cover_pixbuf = if let Some((ref cover_art, _)) = cover_art {
                     utils::load_cover_texture(cover_art)
                 } else {
                     None
@@ -189,14 +390,20 @@ impl CoverCache {
                 };
 
                 // We want both texture and palette
-                if texture.is_some() && palette.is_some() {
+                if let (Some(texture), Some(palette)) = (texture, palette) {
                     let res = CoverArt {
-                        texture: texture.unwrap(),
-                        palette: palette.unwrap(),
+                        palette,
                         cache: cache_path,
+                        from_cover_file,
                     };
 
-                    self.add_entry(&uuid, res.clone());
+                    // Another thread might have loaded the same cover in
+                    // the meantime, in which case we use its copy
+                    let res = CoverCache::global()
+                        .lock()
+                        .unwrap()
+                        .add_entry(&uuid, res, Some(texture))
+                        .clone();
 
                     Some((res, uuid))
                 } else {
@@ -206,7 +413,81 @@ impl CoverCache {
         }
     }
 
+    // The cover art cached on disk by a previous session, using the palette
+    // stored in the metadata index; the texture is loaded when needed
+    pub fn cached_cover_art(
+        uuid: &str,
+        palette: Vec<gdk::RGBA>,
+        from_cover_file: bool,
+    ) -> Option<CoverArt> {
+        let mut cover_cache = CoverCache::global().lock().unwrap();
+        if let Some(c) = cover_cache.lookup(uuid) {
+            return Some(c.clone());
+        }
+
+        let cache_file = cache_file(uuid);
+        if palette.is_empty() || !cache_file.exists() {
+            return None;
+        }
+
+        let res = CoverArt {
+            palette,
+            cache: Some(cache_file),
+            from_cover_file,
+        };
+
+        Some(cover_cache.add_entry(uuid, res, None).clone())
+    }
+
+    // Drops the cover art for the given UUID, including the cached copy
+    // on disk, so that it can be loaded again from the song
+    pub fn remove(&mut self, uuid: &str) {
+        if let Some(entry) = self.entries.remove(uuid) {
+            if let Some(texture) = entry.texture {
+                self.used -= texture_size(&texture);
+                self.lru.remove(&entry.last_used);
+            }
+        }
+
+        let cache_file = cache_file(uuid);
+        if cache_file.exists() {
+            debug!("Removing cached cover: {:?}", &cache_file);
+            if let Err(e) = std::fs::remove_file(&cache_file) {
+                warn!("Unable to remove cached cover {:?}: {}", &cache_file, e);
+            }
+        }
+    }
+
+    // Drops every cover that comes from a cover file, in memory, on disk,
+    // and in the metadata index, so that the cover files are looked up
+    // again; this is needed when the names of the cover files change.
+    // Songs without cover art are dropped from the index as well, as
+    // they might have a cover file now. Returns the UUIDs of the covers
+    pub fn forget_cover_files() -> HashSet<String> {
+        let mut uuids = MetadataIndex::global().lock().unwrap().forget_cover_files();
+
+        let mut cover_cache = CoverCache::global().lock().unwrap();
+        uuids.extend(
+            cover_cache
+                .entries
+                .iter()
+                .filter(|(_, e)| e.cover.from_cover_file)
+                .map(|(uuid, _)| uuid.clone()),
+        );
+        for uuid in &uuids {
+            cover_cache.remove(uuid);
+        }
+
+        CoverFiles::global().lock().unwrap().forget_directories();
+
+        uuids
+    }
+
     pub fn clear(&mut self) {
         self.entries.clear();
+        self.lru.clear();
+        self.used = 0;
+
+        CoverFiles::global().lock().unwrap().forget_directories();
     }
 }
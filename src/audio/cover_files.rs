@@ -0,0 +1,293 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Cover art is often stored in an image file next to the songs of an album,
+// instead of the song metadata; there is no standard name for these files,
+// but there are some common conventions, like "cover.jpg", "folder.jpg",
+// "front.png", or "AlbumArt_{GUID}_Large.jpg", sometimes inside an
+// "artwork" or "covers" subdirectory.
+//
+// The names we look for are patterns, matched case-insensitively against
+// the name of the file without its extension; a "*" matches any sequence
+// of characters, and "{album}" is replaced by the album title. The order
+// of the patterns is the order of priority, and the user can change it.
+//
+// Listing a directory for every song is expensive, so we keep the image
+// files found in each directory around; albums without cover files are
+// not scanned again for each one of their songs.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use log::debug;
+use once_cell::sync::OnceCell;
+
+pub const DEFAULT_COVER_FILE_NAMES: &[&str] = &["cover", "folder", "front", "albumart*", "{album}"];
+
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp"];
+
+const COVER_SUBDIRECTORIES: &[&str] = &["covers", "artwork"];
+
+const ALBUM_PLACEHOLDER: &str = "{album}";
+
+#[derive(Debug)]
+pub struct CoverFiles {
+    // The patterns, in order of priority
+    names: Vec<String>,
+    // The image files in each directory, including its cover subdirectories
+    directories: HashMap<PathBuf, Arc<Vec<PathBuf>>>,
+}
+
+// Matches the text against a pattern where "*" matches any sequence of
+// characters; both are expected to be lower case
+fn matches_wildcard(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+
+    // The first part is anchored at the start of the text
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match text.strip_prefix(first) {
+        Some(r) => r,
+        None => return false,
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let last = match parts.split_last() {
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(pos) => rest = &rest[pos + part.len()..],
+                    None => return false,
+                }
+            }
+            last
+        }
+        // No wildcards
+        None => return rest.is_empty(),
+    };
+
+    // The last part is anchored at the end of the text
+    rest.ends_with(last)
+}
+
+// Whether the file name matches the pattern
+fn matches_name(pattern: &str, file_name: &str, album: Option<&str>) -> bool {
+    let path = Path::new(file_name);
+    let is_image = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| IMAGE_EXTENSIONS.contains(&e.to_lowercase().as_str()));
+    if !is_image {
+        return false;
+    }
+
+    let stem = match path.file_stem().and_then(|s| s.to_str()) {
+        Some(s) => s.to_lowercase(),
+        None => return false,
+    };
+
+    let pattern = pattern.trim().to_lowercase();
+    if pattern.contains(ALBUM_PLACEHOLDER) {
+        match album {
+            // The album title is matched literally, even if it contains
+            // a wildcard
+            Some(album) if pattern == ALBUM_PLACEHOLDER => stem == album.to_lowercase(),
+            Some(album) => {
+                let pattern = pattern.replace(ALBUM_PLACEHOLDER, &album.to_lowercase());
+                matches_wildcard(&pattern, &stem)
+            }
+            None => false,
+        }
+    } else {
+        !pattern.is_empty() && matches_wildcard(&pattern, &stem)
+    }
+}
+
+// Picks the cover file with the highest priority; for the same pattern,
+// files in the directory of the song win over the ones in subdirectories
+fn pick_cover_file<'a>(
+    files: &'a [PathBuf],
+    names: &[String],
+    album: Option<&str>,
+) -> Option<&'a PathBuf> {
+    names.iter().find_map(|pattern| {
+        files.iter().find(|f| {
+            f.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| matches_name(pattern, n, album))
+        })
+    })
+}
+
+fn image_files(dir: &Path) -> Vec<PathBuf> {
+    let mut res: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| {
+                p.extension()
+                    .and_then(|e| e.to_str())
+                    .is_some_and(|e| IMAGE_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+            })
+            .filter(|p| p.is_file())
+            .collect(),
+        Err(_) => vec![],
+    };
+
+    // Directory listings are in no particular order
+    res.sort();
+    res
+}
+
+// Lists the image files in the directory, followed by the ones in the
+// cover subdirectories
+fn list_directory(dir: &Path) -> Vec<PathBuf> {
+    debug!("Looking for external cover files in: {:?}", dir);
+
+    let mut subdirs: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| {
+                p.file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| COVER_SUBDIRECTORIES.contains(&n.to_lowercase().as_str()))
+            })
+            .filter(|p| p.is_dir())
+            .collect(),
+        Err(_) => return vec![],
+    };
+    subdirs.sort();
+
+    let mut res = image_files(dir);
+    for subdir in subdirs {
+        res.extend(image_files(&subdir));
+    }
+
+    res
+}
+
+impl CoverFiles {
+    pub fn global() -> &'static Mutex<CoverFiles> {
+        static COVER_FILES: OnceCell<Mutex<CoverFiles>> = OnceCell::new();
+
+        COVER_FILES.get_or_init(|| Mutex::new(CoverFiles::new()))
+    }
+
+    fn new() -> Self {
+        CoverFiles {
+            names: DEFAULT_COVER_FILE_NAMES
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            directories: HashMap::new(),
+        }
+    }
+
+    // Sets the patterns for the cover file names, in order of priority
+    pub fn set_names(&mut self, names: Vec<String>) {
+        self.names = names.into_iter().filter(|n| !n.trim().is_empty()).collect();
+    }
+
+    // Forgets the contents of the directories, so that they are scanned
+    // again the next time we look for a cover file
+    pub fn forget_directories(&mut self) {
+        self.directories.clear();
+    }
+
+    // Finds the cover file for a song in the given directory; the lock is
+    // not held while listing the directory, as songs are loaded by
+    // multiple threads at the same time
+    pub fn find(dir: &Path, album: Option<&str>) -> Option<PathBuf> {
+        let (names, files) = {
+            let cover_files = CoverFiles::global().lock().unwrap();
+            let files = cover_files.directories.get(dir).cloned();
+            (cover_files.names.clone(), files)
+        };
+
+        let files = match files {
+            Some(f) => f,
+            None => {
+                let files = Arc::new(list_directory(dir));
+                CoverFiles::global()
+                    .lock()
+                    .unwrap()
+                    .directories
+                    .insert(dir.to_path_buf(), files.clone());
+                files
+            }
+        };
+
+        pick_cover_file(&files, &names, album).cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn default_names() -> Vec<String> {
+        DEFAULT_COVER_FILE_NAMES
+            .iter()
+            .map(|s| s.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_wildcard() {
+        assert!(matches_wildcard("cover", "cover"));
+        assert!(!matches_wildcard("cover", "covers"));
+        assert!(matches_wildcard("albumart*", "albumart_{guid}_large"));
+        assert!(matches_wildcard("*front", "cd front"));
+        assert!(matches_wildcard("a*b*c", "axxbyyc"));
+        assert!(!matches_wildcard("a*b*c", "axxcyyb"));
+        assert!(matches_wildcard("*", ""));
+    }
+
+    #[test]
+    fn test_matches_name() {
+        assert!(matches_name("cover", "Cover.JPG", None));
+        assert!(matches_name("folder", "folder.webp", None));
+        assert!(matches_name("front", "Front.jpeg", None));
+        assert!(!matches_name("cover", "cover.txt", None));
+        assert!(!matches_name("cover", "cover", None));
+        assert!(matches_name("AlbumArt*", "AlbumArtSmall.jpg", None));
+        assert!(matches_name(
+            "{album}",
+            "Abbey Road.jpg",
+            Some("abbey road")
+        ));
+        assert!(!matches_name("{album}", "Abbey Road.jpg", None));
+        assert!(!matches_name("{album}", "Abbey Road.jpg", Some("Help*")));
+        assert!(matches_name(
+            "{album} - front",
+            "Help! - Front.png",
+            Some("Help!")
+        ));
+        assert!(!matches_name("", "cover.png", None));
+    }
+
+    #[test]
+    fn test_pick_cover_file() {
+        let files = vec![
+            PathBuf::from("/music/album/AlbumArtSmall.jpg"),
+            PathBuf::from("/music/album/Folder.jpg"),
+            PathBuf::from("/music/album/artwork/cover.png"),
+        ];
+
+        // Priority follows the order of the names, not of the files
+        let names = default_names();
+        let res = pick_cover_file(&files, &names, None);
+        assert_eq!(res, Some(&files[2]));
+
+        let names = vec!["albumart*".to_string(), "folder".to_string()];
+        let res = pick_cover_file(&files, &names, None);
+        assert_eq!(res, Some(&files[0]));
+
+        let names = vec!["back".to_string()];
+        assert_eq!(pick_cover_file(&files, &names, None), None);
+    }
+}
@@ -0,0 +1,379 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The cover art and the waveform of each song are cached on disk, so that
+// we do not need to decode and analyse the songs every time we load them.
+//
+// Left alone, the caches would grow forever; they are pruned every time
+// the application starts, by removing the entries that have not been used
+// in a while, and then the least recently used entries until each cache
+// is within its size limit.
+
+use std::{
+    collections::HashSet,
+    fs,
+    path::PathBuf,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use gtk::{glib, prelude::*};
+use log::{debug, warn};
+
+use crate::{
+    audio::{metadata_index::MetadataIndex, CoverCache},
+    utils,
+};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DiskCache {
+    Covers,
+    Waveforms,
+}
+
+// When to remove the entries of a cache
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PrunePolicy {
+    // Entries that have not been used for longer are removed
+    pub max_age: Option<Duration>,
+    // The maximum size of the cache, in bytes
+    pub max_size: Option<u64>,
+}
+
+impl PrunePolicy {
+    // A value of zero disables the corresponding limit
+    pub fn new(max_age_days: u32, max_size_mb: u32) -> Self {
+        PrunePolicy {
+            max_age: (max_age_days > 0)
+                .then(|| Duration::from_secs(max_age_days as u64 * SECONDS_PER_DAY)),
+            max_size: (max_size_mb > 0).then(|| max_size_mb as u64 * 1024 * 1024),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct CacheEntry {
+    path: PathBuf,
+    // The UUID of the song, or of the cover
+    uuid: String,
+    size: u64,
+    last_used: SystemTime,
+}
+
+impl DiskCache {
+    fn dir(&self) -> PathBuf {
+        let mut dir = glib::user_cache_dir();
+        dir.push("amberol");
+        match self {
+            DiskCache::Covers => dir.push("covers"),
+            DiskCache::Waveforms => dir.push("waveforms"),
+        }
+        dir
+    }
+
+    // The extensions of the entries; the waveforms cached by older
+    // versions are stored as JSON
+    fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            DiskCache::Covers => &["png"],
+            DiskCache::Waveforms => &["peaks", "json"],
+        }
+    }
+
+    fn entries(&self) -> Vec<CacheEntry> {
+        let dir = self.dir();
+        let read_dir = match fs::read_dir(&dir) {
+            Ok(r) => r,
+            Err(e) => {
+                debug!("Unable to read cache directory {:?}: {}", &dir, e);
+                return vec![];
+            }
+        };
+
+        let mut res = vec![];
+        for dir_entry in read_dir.flatten() {
+            let path = dir_entry.path();
+            match path.extension().and_then(|e| e.to_str()) {
+                Some(ext) if self.extensions().contains(&ext) => {}
+                _ => continue,
+            }
+
+            let uuid = match path.file_stem().and_then(|s| s.to_str()) {
+                Some(s) => s.to_string(),
+                None => continue,
+            };
+
+            let metadata = match dir_entry.metadata() {
+                Ok(m) if m.is_file() => m,
+                _ => continue,
+            };
+
+            // The access time is not always updated, depending on how the
+            // file system is mounted, so we also look at the modification
+            // time, which is updated every time an entry is written
+            let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            let accessed = metadata.accessed().unwrap_or(SystemTime::UNIX_EPOCH);
+
+            res.push(CacheEntry {
+                path,
+                uuid,
+                size: metadata.len(),
+                last_used: modified.max(accessed),
+            });
+        }
+
+        res
+    }
+
+    // The size of the cache on disk, in bytes
+    pub fn size(&self) -> u64 {
+        self.entries().iter().map(|e| e.size).sum()
+    }
+
+    fn remove_entries<F>(&self, select: F) -> usize
+    where
+        F: FnOnce(&[CacheEntry]) -> Vec<usize>,
+    {
+        let entries = self.entries();
+        let mut n_removed = 0;
+        for idx in select(&entries) {
+            let entry = &entries[idx];
+            match fs::remove_file(&entry.path) {
+                Ok(_) => n_removed += 1,
+                Err(e) => warn!("Unable to remove cache entry {:?}: {}", &entry.path, e),
+            }
+        }
+
+        if n_removed > 0 {
+            debug!("Removed {} entries from {:?}", n_removed, self.dir());
+        }
+
+        n_removed
+    }
+
+    // Removes the entries according to the given policy, except for the
+    // ones in `keep`; returns the number of removed entries
+    pub fn prune(&self, policy: &PrunePolicy, keep: &HashSet<String>) -> usize {
+        let now = SystemTime::now();
+        self.remove_entries(|entries| select_evictions(entries, now, policy, keep))
+    }
+
+    // Removes every entry, except for the ones in `keep`
+    pub fn clear(&self, keep: &HashSet<String>) -> usize {
+        self.remove_entries(|entries| {
+            entries
+                .iter()
+                .enumerate()
+                .filter(|(_, e)| !keep.contains(&e.uuid))
+                .map(|(idx, _)| idx)
+                .collect()
+        })
+    }
+}
+
+// Selects the entries that have not been used within the maximum age, and
+// then the least recently used entries, until the remaining ones fit into
+// the maximum size
+fn select_evictions(
+    entries: &[CacheEntry],
+    now: SystemTime,
+    policy: &PrunePolicy,
+    keep: &HashSet<String>,
+) -> Vec<usize> {
+    let mut candidates: Vec<usize> = (0..entries.len())
+        .filter(|idx| !keep.contains(&entries[*idx].uuid))
+        .collect();
+    candidates.sort_by_key(|idx| entries[*idx].last_used);
+
+    let mut total: u64 = entries.iter().map(|e| e.size).sum();
+    let mut res = vec![];
+    for idx in candidates {
+        let entry = &entries[idx];
+
+        let is_stale = match (policy.max_age, now.duration_since(entry.last_used)) {
+            (Some(max_age), Ok(age)) => age > max_age,
+            _ => false,
+        };
+        let is_over_size = match policy.max_size {
+            Some(max_size) => total > max_size,
+            None => false,
+        };
+
+        // The candidates are sorted from the least recently used, so we
+        // can stop at the first entry we need to keep
+        if !is_stale && !is_over_size {
+            break;
+        }
+
+        total -= entry.size;
+        res.push(idx);
+    }
+
+    res
+}
+
+// The UUIDs of all the songs we know about
+fn known_song_uuids() -> HashSet<String> {
+    MetadataIndex::global().lock().unwrap().song_uuids()
+}
+
+// Removes the waveforms of songs that are neither in the metadata index
+// nor in the `songs` set
+fn remove_orphaned_waveforms(songs: &HashSet<String>) -> usize {
+    let mut known = known_song_uuids();
+    if known.is_empty() {
+        // An empty index means we have not loaded any song yet, or that
+        // the index was discarded; either way, we cannot tell which
+        // waveforms are orphaned
+        return 0;
+    }
+    known.extend(songs.iter().cloned());
+
+    DiskCache::Waveforms.remove_entries(|entries| {
+        entries
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| !known.contains(&e.uuid))
+            .map(|(idx, _)| idx)
+            .collect()
+    })
+}
+
+// The covers currently referenced by the in-memory cover cache, which
+// needs the copies on disk to load the textures it evicted
+fn covers_in_use() -> HashSet<String> {
+    CoverCache::global().lock().unwrap().uuids()
+}
+
+// The UUIDs of the songs in the playlist cached by the last session, and
+// of their covers; the playlist might not have been restored yet, and we
+// do not want to throw away its data
+fn cached_playlist_uuids() -> (HashSet<String>, HashSet<String>) {
+    let uris: Vec<String> = utils::load_cached_songs()
+        .unwrap_or_default()
+        .iter()
+        .map(|s| s.file.uri().to_string())
+        .collect();
+
+    MetadataIndex::global()
+        .lock()
+        .unwrap()
+        .song_and_cover_uuids(uris.iter().map(|s| s.as_str()))
+}
+
+// Prunes the caches on disk in a separate thread; `songs` contains the
+// UUIDs of the songs whose waveforms should be kept, in addition to the
+// ones of the cached playlist
+pub fn prune_disk_caches(policy: PrunePolicy, mut songs: HashSet<String>) {
+    let res = thread::Builder::new()
+        .name("disk-cache".to_string())
+        .spawn(move || {
+            let (playlist_songs, mut covers) = cached_playlist_uuids();
+            songs.extend(playlist_songs);
+            covers.extend(covers_in_use());
+
+            DiskCache::Covers.prune(&policy, &covers);
+            DiskCache::Waveforms.prune(&policy, &songs);
+            remove_orphaned_waveforms(&songs);
+        });
+
+    if let Err(e) = res {
+        warn!("Unable to spawn disk cache thread: {}", e);
+    }
+}
+
+// Removes every entry of the caches on disk that is not in use; this
+// function blocks, so it should be called from a separate thread
+pub fn clear_disk_caches(songs: &HashSet<String>) -> usize {
+    DiskCache::Covers.clear(&covers_in_use()) + DiskCache::Waveforms.clear(songs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(SECONDS_PER_DAY);
+
+    fn entry(uuid: &str, size: u64, days_ago: u32, now: SystemTime) -> CacheEntry {
+        CacheEntry {
+            path: PathBuf::from(format!("{}.json", uuid)),
+            uuid: uuid.to_string(),
+            size,
+            last_used: now - DAY * days_ago,
+        }
+    }
+
+    fn evicted(entries: &[CacheEntry], policy: &PrunePolicy, keep: &[&str]) -> Vec<String> {
+        let now = entries[0].last_used;
+        let keep = keep.iter().map(|s| s.to_string()).collect();
+        let mut res: Vec<String> = select_evictions(entries, now, policy, &keep)
+            .into_iter()
+            .map(|idx| entries[idx].uuid.clone())
+            .collect();
+        res.sort();
+        res
+    }
+
+    #[test]
+    fn test_policy_new() {
+        let policy = PrunePolicy::new(0, 0);
+        assert_eq!(policy, PrunePolicy::default());
+
+        let policy = PrunePolicy::new(2, 3);
+        assert_eq!(policy.max_age, Some(DAY * 2));
+        assert_eq!(policy.max_size, Some(3 * 1024 * 1024));
+    }
+
+    #[test]
+    fn test_evict_by_age() {
+        let now = SystemTime::now();
+        let entries = vec![
+            entry("a", 10, 0, now),
+            entry("b", 10, 10, now),
+            entry("c", 10, 40, now),
+            entry("d", 10, 50, now),
+        ];
+
+        let policy = PrunePolicy::new(30, 0);
+        assert_eq!(evicted(&entries, &policy, &[]), vec!["c", "d"]);
+        assert_eq!(evicted(&entries, &policy, &["d"]), vec!["c"]);
+        assert!(evicted(&entries, &PrunePolicy::default(), &[]).is_empty());
+    }
+
+    #[test]
+    fn test_evict_by_size() {
+        let now = SystemTime::now();
+        let mb = 1024 * 1024;
+        let entries = vec![
+            entry("a", mb, 0, now),
+            entry("b", mb, 1, now),
+            entry("c", mb, 2, now),
+            entry("d", mb, 3, now),
+        ];
+
+        let policy = PrunePolicy::new(0, 2);
+        assert_eq!(evicted(&entries, &policy, &[]), vec!["c", "d"]);
+
+        // Kept entries still count towards the size
+        assert_eq!(evicted(&entries, &policy, &["d"]), vec!["b", "c"]);
+
+        let policy = PrunePolicy::new(0, 4);
+        assert!(evicted(&entries, &policy, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_evict_by_age_and_size() {
+        let now = SystemTime::now();
+        let mb = 1024 * 1024;
+        let entries = vec![
+            entry("a", mb, 0, now),
+            entry("b", 2 * mb, 1, now),
+            entry("c", mb, 60, now),
+        ];
+
+        let policy = PrunePolicy::new(30, 2);
+        assert_eq!(evicted(&entries, &policy, &[]), vec!["b", "c"]);
+    }
+}
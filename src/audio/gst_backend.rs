@@ -101,7 +101,7 @@ impl GstBackend {
         self.gst_player.connect_position_updated(
             clone!(@strong self.sender as sender => move |_, clock| {
                 if let Some(clock) = clock {
-                    let pos = clock.seconds();
+                    let pos = clock.mseconds();
                     if let Err(e) = sender.send_blocking(PlaybackAction::UpdatePosition(pos)) {
                         error!("Failed to send UpdatePosition({pos}): {e}");
                     }
@@ -109,6 +109,15 @@ impl GstBackend {
             }),
         );
 
+        self.gst_player.connect_seek_done(
+            clone!(@strong self.sender as sender => move |_, clock| {
+                let pos = clock.mseconds();
+                if let Err(e) = sender.send_blocking(PlaybackAction::SeekDone(pos)) {
+                    error!("Failed to send SeekDone({pos}): {e}");
+                }
+            }),
+        );
+
         self.gst_player.connect_volume_changed(
             clone!(@strong self.sender as sender => move |player| {
                 let volume = gst_audio::StreamVolume::convert_volume(
@@ -152,8 +161,10 @@ impl GstBackend {
         }
     }
 
+    // Seeks to the given position, in milliseconds
     pub fn seek_position(&self, position: u64) {
-        self.gst_player.seek(gst::ClockTime::from_seconds(position));
+        self.gst_player
+            .seek(gst::ClockTime::from_mseconds(position));
     }
 
     pub fn seek_start(&self) {
@@ -0,0 +1,73 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+    time::Duration,
+};
+
+use gtk::glib;
+use log::debug;
+
+use crate::audio::{Controller, PlayHistory, PlaybackState, RepeatMode, Song};
+
+// Rewriting the whole history after each song is wasteful when skipping
+// through the queue, so we write the plays recorded in this interval at once
+const STORE_DELAY: Duration = Duration::from_secs(30);
+
+// Records a play in the PlayHistory the first time the current
+// song starts playing
+#[derive(Debug, Default)]
+pub struct HistoryController {
+    song: RefCell<Option<Song>>,
+    recorded: Cell<bool>,
+    store_pending: Rc<Cell<bool>>,
+}
+
+impl HistoryController {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn schedule_store(&self) {
+        if self.store_pending.replace(true) {
+            return;
+        }
+
+        let store_pending = self.store_pending.clone();
+        glib::timeout_add_local_once(STORE_DELAY, move || {
+            store_pending.set(false);
+            PlayHistory::global().lock().unwrap().flush();
+        });
+    }
+}
+
+impl Controller for HistoryController {
+    fn set_playback_state(&self, playback_state: &PlaybackState) {
+        if playback_state != &PlaybackState::Playing || self.recorded.get() {
+            return;
+        }
+
+        if let Some(ref song) = *self.song.borrow() {
+            debug!("Recording play for '{}'", song.uri());
+            let mut history = PlayHistory::global().lock().unwrap();
+            history.record(&song.uri());
+            self.recorded.set(true);
+            self.schedule_store();
+        }
+    }
+
+    fn set_song(&self, song: &Song) {
+        self.song.replace(Some(song.clone()));
+        self.recorded.set(false);
+    }
+
+    fn update_song(&self, _song: &Song) {}
+
+    fn set_position(&self, _position: u64) {}
+    fn seeked(&self, _position: u64) {}
+    fn set_repeat_mode(&self, _mode: RepeatMode) {}
+    fn set_shuffled(&self, _shuffled: bool) {}
+    fn set_volume(&self, _volume: f64) {}
+}
@@ -0,0 +1,571 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The identity of a song is derived from its audio stream, so that it
+// survives renaming the file and editing its tags, while two different
+// recordings with the same file name and tags remain distinct.
+//
+// Hashing the whole stream would make loading a large playlist too slow,
+// so we hash the tail end of the audio data, together with the stream
+// properties. Tags are mostly stored at the start of the file, but some
+// formats append them at the end, so we locate the end of the audio data
+// first. Ogg streams need more care: rewriting the comment header can
+// change the number of pages before the audio, which renumbers all the
+// following pages, so we only hash the payload of the pages.
+
+use std::{
+    convert::TryInto,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use log::debug;
+use sha2::{Digest, Sha256};
+
+use crate::audio::WaveformGenerator;
+
+// The amount of audio data hashed
+const SAMPLE_SIZE: u64 = 64 * 1024;
+
+const ID3V1_SIZE: u64 = 128;
+const OGG_PAGE_HEADER_SIZE: usize = 27;
+const APE_FOOTER_SIZE: u64 = 32;
+const APE_HAS_HEADER: u32 = 1 << 31;
+
+// The stream properties mixed into the identity
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct StreamInfo {
+    pub duration_ms: u128,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Container {
+    // Audio data in MP4 "mdat" atoms
+    Mp4,
+    // Audio data in a RIFF "data" chunk
+    Riff,
+    // Audio data in an AIFF "SSND" chunk
+    Aiff,
+    // Audio data in Ogg pages, after the header pages
+    Ogg,
+    // Audio data followed by optional ID3v1 and APE tags
+    Stream,
+}
+
+impl Container {
+    pub fn from_file_type(file_type: &lofty::FileType) -> Self {
+        match file_type {
+            lofty::FileType::Mp4 => Container::Mp4,
+            lofty::FileType::Wav => Container::Riff,
+            lofty::FileType::Aiff => Container::Aiff,
+            lofty::FileType::Opus | lofty::FileType::Vorbis | lofty::FileType::Speex => {
+                Container::Ogg
+            }
+            _ => Container::Stream,
+        }
+    }
+}
+
+fn read_exact_at<R: Read + Seek>(reader: &mut R, pos: u64, buf: &mut [u8]) -> io::Result<()> {
+    reader.seek(SeekFrom::Start(pos))?;
+    reader.read_exact(buf)
+}
+
+// Strips the ID3v1 and APEv2 tags from the end of a stream, and returns
+// the end offset of the audio data
+fn stream_audio_end<R: Read + Seek>(reader: &mut R, len: u64) -> io::Result<u64> {
+    let mut end = len;
+
+    loop {
+        if end >= ID3V1_SIZE {
+            let mut magic = [0u8; 3];
+            read_exact_at(reader, end - ID3V1_SIZE, &mut magic)?;
+            if &magic == b"TAG" {
+                end -= ID3V1_SIZE;
+                continue;
+            }
+        }
+
+        if end >= APE_FOOTER_SIZE {
+            let mut footer = [0u8; APE_FOOTER_SIZE as usize];
+            read_exact_at(reader, end - APE_FOOTER_SIZE, &mut footer)?;
+            if &footer[0..8] == b"APETAGEX" {
+                // The size includes the items and the footer, but not
+                // the optional header
+                let size = u32::from_le_bytes(footer[12..16].try_into().unwrap()) as u64;
+                let flags = u32::from_le_bytes(footer[20..24].try_into().unwrap());
+                let size = if flags & APE_HAS_HEADER != 0 {
+                    size + APE_FOOTER_SIZE
+                } else {
+                    size
+                };
+                if size <= end {
+                    end -= size;
+                    continue;
+                }
+            }
+        }
+
+        return Ok(end);
+    }
+}
+
+// Finds the largest "mdat" atom among the top level atoms
+fn mp4_audio_range<R: Read + Seek>(reader: &mut R, len: u64) -> io::Result<Option<(u64, u64)>> {
+    let mut pos = 0;
+    let mut res: Option<(u64, u64)> = None;
+
+    while pos + 8 <= len {
+        let mut header = [0u8; 8];
+        read_exact_at(reader, pos, &mut header)?;
+
+        let mut header_size = 8;
+        let size = match u32::from_be_bytes(header[0..4].try_into().unwrap()) as u64 {
+            0 => len - pos,
+            1 => {
+                let mut large_size = [0u8; 8];
+                read_exact_at(reader, pos + 8, &mut large_size)?;
+                header_size = 16;
+                u64::from_be_bytes(large_size)
+            }
+            size => size,
+        };
+        if size < header_size || pos + size > len {
+            break;
+        }
+
+        if &header[4..8] == b"mdat" {
+            let range = (pos + header_size, pos + size);
+            let is_larger = match res {
+                Some((start, end)) => range.1 - range.0 > end - start,
+                None => true,
+            };
+            if is_larger {
+                res = Some(range);
+            }
+        }
+
+        pos += size;
+    }
+
+    Ok(res)
+}
+
+// Finds the chunk with the given id in a RIFF or AIFF file; the sizes
+// are little endian in RIFF files and big endian in AIFF files
+fn chunk_range<R: Read + Seek>(
+    reader: &mut R,
+    len: u64,
+    id: &[u8; 4],
+    little_endian: bool,
+) -> io::Result<Option<(u64, u64)>> {
+    // Skip the "RIFF"/"FORM" header
+    let mut pos = 12;
+
+    while pos + 8 <= len {
+        let mut header = [0u8; 8];
+        read_exact_at(reader, pos, &mut header)?;
+
+        let size_bytes = header[4..8].try_into().unwrap();
+        let size = if little_endian {
+            u32::from_le_bytes(size_bytes)
+        } else {
+            u32::from_be_bytes(size_bytes)
+        } as u64;
+
+        let start = pos + 8;
+        if &header[0..4] == id {
+            return Ok(Some((start, (start + size).min(len))));
+        }
+
+        // Chunks are padded to an even size
+        pos = start + size + (size & 1);
+    }
+
+    Ok(None)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct OggPage {
+    granule_position: u64,
+    header_size: usize,
+    payload_size: usize,
+}
+
+// Parses the header of the Ogg page at the start of the given data
+fn ogg_page(data: &[u8]) -> Option<OggPage> {
+    if data.len() < OGG_PAGE_HEADER_SIZE || &data[0..4] != b"OggS" || data[4] != 0 {
+        return None;
+    }
+
+    let granule_position = u64::from_le_bytes(data[6..14].try_into().unwrap());
+    let n_segments = data[26] as usize;
+    let segments = data.get(OGG_PAGE_HEADER_SIZE..OGG_PAGE_HEADER_SIZE + n_segments)?;
+
+    Some(OggPage {
+        granule_position,
+        header_size: OGG_PAGE_HEADER_SIZE + n_segments,
+        payload_size: segments.iter().map(|s| *s as usize).sum(),
+    })
+}
+
+// Finds the first page with audio data; the header pages, including the
+// comment header, have a granule position of 0, or -1 if no packet ends
+// on the page
+fn ogg_audio_start<R: Read + Seek>(reader: &mut R, len: u64) -> io::Result<Option<u64>> {
+    let mut pos = 0;
+    let mut header = [0u8; OGG_PAGE_HEADER_SIZE + 255];
+
+    while pos + OGG_PAGE_HEADER_SIZE as u64 <= len {
+        let size = (len - pos).min(header.len() as u64) as usize;
+        read_exact_at(reader, pos, &mut header[..size])?;
+
+        let page = match ogg_page(&header[..size]) {
+            Some(page) => page,
+            None => return Ok(None),
+        };
+        if page.granule_position != 0 && page.granule_position != u64::MAX {
+            return Ok(Some(pos));
+        }
+
+        pos += (page.header_size + page.payload_size) as u64;
+    }
+
+    Ok(None)
+}
+
+// The payload of the Ogg pages at the tail end of the audio data, without
+// the page headers
+fn ogg_tail_payload<R: Read + Seek>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    let audio_start = match ogg_audio_start(reader, len)? {
+        Some(pos) => pos,
+        None => return Ok(None),
+    };
+
+    let start = audio_start.max(len.saturating_sub(SAMPLE_SIZE));
+    let mut data = vec![0u8; (len - start) as usize];
+    read_exact_at(reader, start, &mut data)?;
+
+    // The tail starts in the middle of a page, most likely
+    let mut pos = match (0..data.len()).find(|i| ogg_page(&data[*i..]).is_some()) {
+        Some(pos) => pos,
+        None => return Ok(None),
+    };
+
+    let mut payload = Vec::with_capacity(data.len());
+    while let Some(page) = ogg_page(&data[pos..]) {
+        let payload_start = pos + page.header_size;
+        let payload_end = payload_start + page.payload_size;
+        if payload_end > data.len() {
+            break;
+        }
+
+        payload.extend(&data[payload_start..payload_end]);
+        pos = payload_end;
+    }
+
+    Ok(Some(payload))
+}
+
+// The range of the file containing the audio data; for Ogg streams, this
+// includes the page headers
+pub fn audio_range<R: Read + Seek>(reader: &mut R, container: Container) -> io::Result<(u64, u64)> {
+    let len = reader.seek(SeekFrom::End(0))?;
+
+    let range = match container {
+        Container::Mp4 => mp4_audio_range(reader, len)?,
+        Container::Riff => chunk_range(reader, len, b"data", true)?,
+        Container::Aiff => chunk_range(reader, len, b"SSND", false)?,
+        Container::Ogg => ogg_audio_start(reader, len)?.map(|start| (start, len)),
+        Container::Stream => Some((0, stream_audio_end(reader, len)?)),
+    };
+
+    Ok(range.unwrap_or((0, len)))
+}
+
+// The tail end of the audio data
+fn audio_sample<R: Read + Seek>(reader: &mut R, container: Container) -> io::Result<Vec<u8>> {
+    if container == Container::Ogg {
+        if let Some(payload) = ogg_tail_payload(reader)? {
+            return Ok(payload);
+        }
+    }
+
+    let (start, end) = audio_range(reader, container)?;
+    let start = start.max(end.saturating_sub(SAMPLE_SIZE));
+
+    let mut sample = vec![0u8; (end - start) as usize];
+    read_exact_at(reader, start, &mut sample)?;
+
+    Ok(sample)
+}
+
+pub fn stream_id<R: Read + Seek>(
+    reader: &mut R,
+    container: Container,
+    info: &StreamInfo,
+) -> io::Result<String> {
+    let sample = audio_sample(reader, container)?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(info.duration_ms.to_le_bytes());
+    hasher.update(info.sample_rate.unwrap_or(0).to_le_bytes());
+    hasher.update(info.channels.unwrap_or(0).to_le_bytes());
+    hasher.update(&sample);
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// The identity of the song at the given path
+pub fn song_id(path: &Path, file_type: &lofty::FileType, info: &StreamInfo) -> Option<String> {
+    let container = Container::from_file_type(file_type);
+    match File::open(path).and_then(|mut f| stream_id(&mut f, container, info)) {
+        Ok(id) => Some(id),
+        Err(e) => {
+            debug!("Unable to hash audio stream of {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+// The identity used by older versions, derived from the file name and the
+// tags; we only compute it to migrate the cached data
+pub fn legacy_song_id(
+    display_name: &str,
+    artist: Option<&str>,
+    title: Option<&str>,
+    album: Option<&str>,
+) -> String {
+    let mut hasher = Sha256::new();
+
+    hasher.update(display_name);
+
+    if let Some(artist) = artist {
+        hasher.update(artist);
+    }
+    if let Some(title) = title {
+        hasher.update(title);
+    }
+    if let Some(album) = album {
+        hasher.update(album);
+    }
+
+    format!("{:x}", hasher.finalize())
+}
+
+// Moves the data cached under the legacy identity of a song
+pub fn migrate_cached_data(legacy_id: &str, id: &str) {
+    if legacy_id != id {
+        WaveformGenerator::move_cached_peaks(legacy_id, id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn audio(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    fn ape_tag(items_len: usize, with_header: bool) -> Vec<u8> {
+        let mut footer = b"APETAGEX".to_vec();
+        footer.extend(2000u32.to_le_bytes());
+        footer.extend(((items_len as u64 + APE_FOOTER_SIZE) as u32).to_le_bytes());
+        footer.extend(1u32.to_le_bytes());
+        let flags = if with_header { APE_HAS_HEADER } else { 0 };
+        footer.extend(flags.to_le_bytes());
+        footer.extend([0u8; 8]);
+
+        let mut res = Vec::new();
+        if with_header {
+            res.extend(&footer);
+        }
+        res.extend(vec![b'x'; items_len]);
+        res.extend(&footer);
+        res
+    }
+
+    fn atom(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut res = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        res.extend(kind);
+        res.extend(payload);
+        res
+    }
+
+    #[test]
+    fn test_stream_trailing_tags() {
+        let data = audio(1000);
+
+        let mut file = data.clone();
+        let mut id3v1 = b"TAG".to_vec();
+        id3v1.resize(ID3V1_SIZE as usize, b' ');
+        file.extend(ape_tag(40, true));
+        file.extend(&id3v1);
+
+        let range = audio_range(&mut Cursor::new(&file), Container::Stream).unwrap();
+        assert_eq!(range, (0, data.len() as u64));
+
+        let range = audio_range(&mut Cursor::new(&data), Container::Stream).unwrap();
+        assert_eq!(range, (0, data.len() as u64));
+    }
+
+    #[test]
+    fn test_mp4_mdat() {
+        let mut file = atom(b"ftyp", b"M4A mp42");
+        let start = file.len() as u64 + 8;
+        file.extend(atom(b"mdat", &audio(500)));
+        let end = file.len() as u64;
+        file.extend(atom(b"moov", &[0u8; 64]));
+
+        let range = audio_range(&mut Cursor::new(&file), Container::Mp4).unwrap();
+        assert_eq!(range, (start, end));
+    }
+
+    #[test]
+    fn test_riff_data() {
+        let mut file = b"RIFF\0\0\0\0WAVE".to_vec();
+        file.extend(b"fmt ");
+        file.extend(3u32.to_le_bytes());
+        file.extend([1, 2, 3, 0]);
+        let start = file.len() as u64 + 8;
+        file.extend(b"data");
+        file.extend(100u32.to_le_bytes());
+        file.extend(audio(100));
+        file.extend(b"id3 ");
+        file.extend(10u32.to_le_bytes());
+        file.extend([0u8; 10]);
+
+        let range = audio_range(&mut Cursor::new(&file), Container::Riff).unwrap();
+        assert_eq!(range, (start, start + 100));
+    }
+
+    fn ogg_test_page(header_type: u8, granule: u64, sequence: u32, packet: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS".to_vec();
+        page.extend([0, header_type]);
+        page.extend(granule.to_le_bytes());
+        page.extend(1u32.to_le_bytes());
+        page.extend(sequence.to_le_bytes());
+        // A fake checksum, which changes with the sequence number
+        page.extend(sequence.wrapping_mul(0x9e37_79b9).to_le_bytes());
+
+        let mut segments = vec![255u8; packet.len() / 255];
+        segments.push((packet.len() % 255) as u8);
+        page.push(segments.len() as u8);
+        page.extend(segments);
+        page.extend(packet);
+        page
+    }
+
+    // An Ogg stream with the given comment header, split into pages
+    fn ogg_stream(comment: &[u8], audio_pages: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = ogg_test_page(0x02, 0, 0, b"OpusHead");
+        let mut sequence = 1;
+        for (i, chunk) in comment.chunks(4000).enumerate() {
+            let granule = if (i + 1) * 4000 < comment.len() {
+                u64::MAX
+            } else {
+                0
+            };
+            file.extend(ogg_test_page(0, granule, sequence, chunk));
+            sequence += 1;
+        }
+        for (i, packet) in audio_pages.iter().enumerate() {
+            file.extend(ogg_test_page(0, 960 * (i as u64 + 1), sequence, packet));
+            sequence += 1;
+        }
+        file
+    }
+
+    #[test]
+    fn test_ogg_comment_header() {
+        let info = StreamInfo {
+            duration_ms: 1000,
+            sample_rate: Some(48000),
+            channels: Some(2),
+        };
+
+        let audio_pages: Vec<Vec<u8>> = (0..40).map(|i| audio(3000 + i)).collect();
+        let file = ogg_stream(b"OpusTags short", &audio_pages);
+
+        let start = audio_range(&mut Cursor::new(&file), Container::Ogg)
+            .unwrap()
+            .0;
+        assert_eq!(&file[start as usize..start as usize + 4], b"OggS");
+        assert_eq!(
+            ogg_page(&file[start as usize..]).unwrap().granule_position,
+            960
+        );
+
+        // A longer comment header adds pages, and renumbers the audio pages
+        let retagged = ogg_stream(&vec![b'x'; 10000], &audio_pages);
+        assert_ne!(file.len(), retagged.len());
+
+        let id = stream_id(&mut Cursor::new(&file), Container::Ogg, &info).unwrap();
+        let retagged_id = stream_id(&mut Cursor::new(&retagged), Container::Ogg, &info).unwrap();
+        assert_eq!(id, retagged_id);
+
+        // The same holds for files shorter than the hashed sample
+        let short = ogg_stream(b"OpusTags short", &audio_pages[..2]);
+        let short_retagged = ogg_stream(&vec![b'x'; 10000], &audio_pages[..2]);
+        let id = stream_id(&mut Cursor::new(&short), Container::Ogg, &info).unwrap();
+        let retagged_id =
+            stream_id(&mut Cursor::new(&short_retagged), Container::Ogg, &info).unwrap();
+        assert_eq!(id, retagged_id);
+
+        // Different audio data does not
+        let mut other_pages = audio_pages.clone();
+        other_pages.last_mut().unwrap()[0] ^= 0xff;
+        let other = ogg_stream(b"OpusTags short", &other_pages);
+        let other_id = stream_id(&mut Cursor::new(&other), Container::Ogg, &info).unwrap();
+        assert_ne!(id, other_id);
+    }
+
+    #[test]
+    fn test_stream_id() {
+        let info = StreamInfo {
+            duration_ms: 1000,
+            sample_rate: Some(44100),
+            channels: Some(2),
+        };
+
+        // Leading tags and trailing tags do not change the identity
+        let data = audio(100 * 1024);
+        let mut tagged = b"ID3 some leading tag".to_vec();
+        tagged.extend(&data);
+        tagged.extend(ape_tag(10, false));
+
+        let id = stream_id(&mut Cursor::new(&data), Container::Stream, &info).unwrap();
+        let tagged_id = stream_id(&mut Cursor::new(&tagged), Container::Stream, &info).unwrap();
+        assert_eq!(id, tagged_id);
+
+        // Different audio data, or stream properties, do
+        let mut other = data.clone();
+        *other.last_mut().unwrap() ^= 0xff;
+        let other_id = stream_id(&mut Cursor::new(&other), Container::Stream, &info).unwrap();
+        assert_ne!(id, other_id);
+
+        let other_info = StreamInfo {
+            duration_ms: 2000,
+            ..info
+        };
+        let other_id = stream_id(&mut Cursor::new(&data), Container::Stream, &other_info).unwrap();
+        assert_ne!(id, other_id);
+    }
+
+    #[test]
+    fn test_legacy_song_id() {
+        // The hash of the display name, the first artist, the title, and
+        // the album, as computed by older versions
+        assert_eq!(
+            legacy_song_id("01 Song.flac", Some("First Artist"), Some("Title"), None),
+            "524620bd744a96b6b31c34f7f18cebf6ed32300195967c47e52a845a9e8c6413"
+        );
+    }
+}
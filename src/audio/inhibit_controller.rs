@@ -54,6 +54,10 @@ impl Controller for InhibitController {
     }
 
     fn set_song(&self, _song: &Song) {}
+    fn update_song(&self, _song: &Song) {}
     fn set_position(&self, _position: u64) {}
+    fn seeked(&self, _position: u64) {}
     fn set_repeat_mode(&self, _mode: RepeatMode) {}
+    fn set_shuffled(&self, _shuffled: bool) {}
+    fn set_volume(&self, _volume: f64) {}
 }
@@ -0,0 +1,311 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::path::Path;
+
+use gtk::{gio, prelude::*};
+use lofty::{AudioFile, TaggedFileExt};
+use log::{debug, warn};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LyricsLine {
+    // The start of the line, in milliseconds; unsynced lyrics do not
+    // have timestamps
+    pub time: Option<u64>,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Lyrics {
+    lines: Vec<LyricsLine>,
+}
+
+impl Lyrics {
+    // Parses the given text as LRC, if it contains timestamps; otherwise,
+    // each line of text is treated as an unsynced line
+    pub fn parse(text: &str) -> Self {
+        let mut offset: i64 = 0;
+        let mut lines = Vec::new();
+        let mut has_timestamps = false;
+
+        for raw_line in text.lines() {
+            let mut rest = raw_line.trim();
+            let mut times = Vec::new();
+
+            while let Some(tag) = rest.strip_prefix('[') {
+                let end = match tag.find(']') {
+                    Some(e) => e,
+                    None => break,
+                };
+
+                let content = &tag[..end];
+                if let Some(t) = parse_timestamp(content) {
+                    times.push(t);
+                } else if let Some(value) = content.strip_prefix("offset:") {
+                    offset = value.trim().parse::<i64>().unwrap_or(0);
+                }
+
+                rest = &tag[end + 1..];
+            }
+
+            if times.is_empty() {
+                // ID tags, like [ar:Artist], are not lyrics; but other
+                // tags, like [Chorus], are part of the text
+                if rest.is_empty() && is_id_tag(raw_line) {
+                    continue;
+                }
+
+                lines.push(LyricsLine {
+                    time: None,
+                    text: raw_line.trim().to_string(),
+                });
+            } else {
+                has_timestamps = true;
+                for t in times {
+                    lines.push(LyricsLine {
+                        time: Some(t),
+                        text: rest.trim().to_string(),
+                    });
+                }
+            }
+        }
+
+        if has_timestamps {
+            // A positive offset means the lyrics come earlier
+            lines.retain(|l| l.time.is_some());
+            for line in lines.iter_mut() {
+                let t = line.time.unwrap() as i64 - offset;
+                line.time = Some(t.max(0) as u64);
+            }
+            lines.sort_by_key(|l| l.time);
+        } else {
+            // Drop leading and trailing empty lines
+            while lines.first().is_some_and(|l| l.text.is_empty()) {
+                lines.remove(0);
+            }
+            while lines.last().is_some_and(|l| l.text.is_empty()) {
+                lines.pop();
+            }
+        }
+
+        Lyrics { lines }
+    }
+
+    fn from_synced(content: &[(u32, String)]) -> Self {
+        let mut lines: Vec<LyricsLine> = content
+            .iter()
+            .map(|(t, text)| LyricsLine {
+                time: Some(*t as u64),
+                text: text.trim().to_string(),
+            })
+            .collect();
+        lines.sort_by_key(|l| l.time);
+
+        Lyrics { lines }
+    }
+
+    // Loads the lyrics for the given file, looking for a sidecar LRC file
+    // first, and then for the lyrics embedded in the tags. This reads the
+    // file, so it should be called from a worker thread
+    pub fn load(file: &gio::File) -> Option<Self> {
+        let path = file.path()?;
+
+        let res = load_sidecar(&path).or_else(|| load_embedded(&path));
+
+        match res {
+            Some(lyrics) if !lyrics.is_empty() => Some(lyrics),
+            _ => None,
+        }
+    }
+
+    pub fn lines(&self) -> &[LyricsLine] {
+        &self.lines
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    pub fn is_synced(&self) -> bool {
+        self.lines.first().is_some_and(|l| l.time.is_some())
+    }
+
+    // The index of the line being sung at the given position, in
+    // milliseconds
+    pub fn line_at(&self, position: u64) -> Option<usize> {
+        if !self.is_synced() {
+            return None;
+        }
+
+        let n_lines = self
+            .lines
+            .partition_point(|l| l.time.is_some_and(|t| t <= position));
+        n_lines.checked_sub(1)
+    }
+}
+
+// Whether the given line is an LRC ID tag, like "[ar:Artist]"
+fn is_id_tag(line: &str) -> bool {
+    const ID_TAGS: [&str; 11] = [
+        "ar", "al", "ti", "au", "by", "length", "offset", "re", "tool", "ve", "#",
+    ];
+
+    let content = line.trim().trim_start_matches('[').trim_end_matches(']');
+    match content.split_once(':') {
+        Some((key, _)) => ID_TAGS.contains(&key.trim().to_lowercase().as_str()),
+        None => content.starts_with('#'),
+    }
+}
+
+// Parses "mm:ss", "mm:ss.xx", and "mm:ss.xxx" into milliseconds
+fn parse_timestamp(s: &str) -> Option<u64> {
+    let (minutes, rest) = s.split_once(':')?;
+    let minutes = minutes.trim().parse::<u64>().ok()?;
+
+    let (seconds, fraction) = match rest.split_once(['.', ':']) {
+        Some((s, f)) => (s, Some(f)),
+        None => (rest, None),
+    };
+    let seconds = seconds.trim().parse::<u64>().ok()?;
+
+    let millis = match fraction {
+        Some(f) if !f.is_empty() && f.len() <= 3 && f.chars().all(|c| c.is_ascii_digit()) => {
+            let value = f.parse::<u64>().ok()?;
+            value * 10u64.pow(3 - f.len() as u32)
+        }
+        Some(_) => return None,
+        None => 0,
+    };
+
+    Some((minutes * 60 + seconds) * 1000 + millis)
+}
+
+fn load_sidecar(path: &Path) -> Option<Lyrics> {
+    for ext in ["lrc", "LRC"] {
+        let lrc = path.with_extension(ext);
+        if let Ok(text) = std::fs::read_to_string(&lrc) {
+            debug!("Found lyrics file: {:?}", &lrc);
+            return Some(Lyrics::parse(&text));
+        }
+    }
+
+    None
+}
+
+// Synchronized ID3v2 lyrics take precedence over the unsynchronized
+// ones; SYLT frames are not mapped to generic tag items, so we need to
+// look at the ID3v2 tag directly. USLT frames and the Vorbis LYRICS field
+// are both mapped to the Lyrics item; some taggers store LRC in them
+fn load_embedded(path: &Path) -> Option<Lyrics> {
+    let probe = lofty::Probe::open(path).ok()?.guess_file_type().ok()?;
+
+    if probe.file_type() == Some(lofty::FileType::Mpeg) {
+        let mut file = std::fs::File::open(path).ok()?;
+        let mpeg = lofty::mpeg::MpegFile::read_from(&mut file, lofty::ParseOptions::new()).ok()?;
+        let id3v2 = mpeg.id3v2()?;
+
+        return synced_id3v2_lyrics(path, id3v2).or_else(|| {
+            let tag = lofty::Tag::from(id3v2.clone());
+            tag.get_string(&lofty::ItemKey::Lyrics).map(Lyrics::parse)
+        });
+    }
+
+    let tagged_file = probe.read().ok()?;
+    let tag = tagged_file
+        .primary_tag()
+        .or_else(|| tagged_file.first_tag())?;
+    let text = tag.get_string(&lofty::ItemKey::Lyrics)?;
+
+    Some(Lyrics::parse(text))
+}
+
+fn synced_id3v2_lyrics(path: &Path, id3v2: &lofty::id3::v2::Id3v2Tag) -> Option<Lyrics> {
+    use lofty::id3::v2::{Frame, SynchronizedTextFrame, TimestampFormat};
+
+    for frame in id3v2 {
+        if frame.id_str() != "SYLT" {
+            continue;
+        }
+
+        if let Frame::Binary(binary) = frame {
+            match SynchronizedTextFrame::parse(&binary.data, frame.flags()) {
+                Ok(sylt) if sylt.timestamp_format == TimestampFormat::MS => {
+                    debug!("Found synchronized lyrics in {:?}", path);
+                    return Some(Lyrics::from_synced(&sylt.content));
+                }
+                Ok(_) => debug!("Unsupported SYLT timestamp format in {:?}", path),
+                Err(e) => warn!("Unable to parse SYLT frame in {:?}: {}", path, e),
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("00:00"), Some(0));
+        assert_eq!(parse_timestamp("01:02"), Some(62_000));
+        assert_eq!(parse_timestamp("01:02.5"), Some(62_500));
+        assert_eq!(parse_timestamp("01:02.50"), Some(62_500));
+        assert_eq!(parse_timestamp("01:02.503"), Some(62_503));
+        assert_eq!(parse_timestamp("ar:Someone"), None);
+        assert_eq!(parse_timestamp("01:02.abc"), None);
+    }
+
+    #[test]
+    fn test_parse_lrc() {
+        let lyrics = Lyrics::parse(
+            "[ar:Someone]\n\
+             [ti:Something]\n\
+             [00:12.00]First line\n\
+             [00:05.50]Intro\n\
+             [00:20.00][01:00.00]Chorus\n",
+        );
+
+        assert!(lyrics.is_synced());
+        let texts: Vec<&str> = lyrics.lines().iter().map(|l| l.text.as_str()).collect();
+        assert_eq!(texts, vec!["Intro", "First line", "Chorus", "Chorus"]);
+        assert_eq!(lyrics.lines()[0].time, Some(5_500));
+        assert_eq!(lyrics.lines()[3].time, Some(60_000));
+    }
+
+    #[test]
+    fn test_parse_lrc_offset() {
+        let lyrics = Lyrics::parse("[offset:500]\n[00:01.00]One\n[00:00.20]Zero\n");
+        assert_eq!(lyrics.lines()[0].time, Some(0));
+        assert_eq!(lyrics.lines()[1].time, Some(500));
+    }
+
+    #[test]
+    fn test_parse_unsynced() {
+        let lyrics = Lyrics::parse("\nFirst line\n\nSecond line\n\n");
+        assert!(!lyrics.is_synced());
+        assert_eq!(lyrics.lines().len(), 3);
+        assert_eq!(lyrics.line_at(1_000), None);
+    }
+
+    #[test]
+    fn test_parse_unsynced_sections() {
+        let lyrics =
+            Lyrics::parse("[ar:Someone]\n[Verse 1]\nFirst line\n\n[Chorus]\nSecond line\n");
+        let texts: Vec<&str> = lyrics.lines().iter().map(|l| l.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            vec!["[Verse 1]", "First line", "", "[Chorus]", "Second line"]
+        );
+    }
+
+    #[test]
+    fn test_line_at() {
+        let lyrics = Lyrics::parse("[00:01.00]One\n[00:02.00]Two\n[00:03.00]Three\n");
+        assert_eq!(lyrics.line_at(0), None);
+        assert_eq!(lyrics.line_at(1_000), Some(0));
+        assert_eq!(lyrics.line_at(2_500), Some(1));
+        assert_eq!(lyrics.line_at(10_000), Some(2));
+    }
+}
@@ -0,0 +1,502 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The metadata index stores the metadata parsed from each song, so that
+// we do not need to parse the tags and decode the cover art of every
+// song in the playlist each time the application starts.
+//
+// Entries are keyed by the URI of the song, and stamped with the
+// modification time and size of the file; if either changes, the entry
+// is ignored, and replaced once the file has been parsed again.
+//
+// Each entry also records the last time it was used, so that the index
+// does not grow without bounds: once it holds more than `MAX_ENTRIES`
+// songs, the ones that were not used for the longest time are dropped.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use gtk::{gio, glib, prelude::*};
+use log::{debug, warn};
+use once_cell::sync::OnceCell;
+use serde_json::Value;
+
+// Bump this whenever the data stored for each song changes
+const INDEX_VERSION: u64 = 1;
+
+// The maximum number of songs in the index
+const MAX_ENTRIES: usize = 20_000;
+
+// How often the last use of an entry is updated, in seconds
+const LAST_USED_PRECISION: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FileStamp {
+    mtime: u64,
+    size: u64,
+}
+
+impl FileStamp {
+    pub fn for_path(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+        Some(FileStamp {
+            mtime: mtime.as_nanos() as u64,
+            size: metadata.len(),
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    stamp: FileStamp,
+    data: Value,
+    // Seconds since the epoch
+    last_used: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Default)]
+pub struct MetadataIndex {
+    entries: HashMap<String, Entry>,
+    dirty: bool,
+}
+
+fn index_file() -> PathBuf {
+    let mut index_file = glib::user_cache_dir();
+    index_file.push("amberol");
+    index_file.push("metadata.json");
+
+    index_file
+}
+
+impl MetadataIndex {
+    pub fn global() -> &'static Mutex<MetadataIndex> {
+        static INDEX: OnceCell<Mutex<MetadataIndex>> = OnceCell::new();
+
+        INDEX.get_or_init(|| Mutex::new(MetadataIndex::load()))
+    }
+
+    fn load() -> Self {
+        let index_file = index_file();
+        let contents = match fs::read(&index_file) {
+            Ok(c) => c,
+            Err(e) => {
+                debug!("Unable to read metadata index {:?}: {}", &index_file, e);
+                return MetadataIndex::default();
+            }
+        };
+
+        let json: Value = match serde_json::from_slice(&contents) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Discarding invalid metadata index: {}", e);
+                return MetadataIndex::default();
+            }
+        };
+
+        if json.get("version").and_then(Value::as_u64) != Some(INDEX_VERSION) {
+            debug!("Discarding outdated metadata index");
+            return MetadataIndex::default();
+        }
+
+        let now = now();
+        let mut entries = HashMap::new();
+        if let Some(songs) = json.get("songs").and_then(Value::as_object) {
+            for (uri, entry) in songs {
+                let mtime = entry.get("mtime").and_then(Value::as_u64);
+                let size = entry.get("size").and_then(Value::as_u64);
+                let data = entry.get("data");
+                let last_used = entry.get("last-used").and_then(Value::as_u64);
+                if let (Some(mtime), Some(size), Some(data)) = (mtime, size, data) {
+                    entries.insert(
+                        uri.to_string(),
+                        Entry {
+                            stamp: FileStamp { mtime, size },
+                            data: data.clone(),
+                            last_used: last_used.unwrap_or(now),
+                        },
+                    );
+                }
+            }
+        }
+
+        debug!("Loaded metadata index with {} songs", entries.len());
+
+        MetadataIndex {
+            entries,
+            dirty: false,
+        }
+    }
+
+    // The data stored for the given URI, if the file did not change
+    pub fn lookup(&mut self, uri: &str, stamp: &FileStamp) -> Option<&Value> {
+        let entry = self.entries.get_mut(uri).filter(|e| e.stamp == *stamp)?;
+
+        // Avoid writing the whole index every time we load a song
+        let now = now();
+        if now.saturating_sub(entry.last_used) >= LAST_USED_PRECISION {
+            entry.last_used = now;
+            self.dirty = true;
+        }
+
+        Some(&entry.data)
+    }
+
+    // Whether the song was indexed, even if it changed since then
+    pub fn contains(&self, uri: &str) -> bool {
+        self.entries.contains_key(uri)
+    }
+
+    pub fn insert(&mut self, uri: &str, stamp: FileStamp, data: Value) {
+        let entry = Entry {
+            stamp,
+            data,
+            last_used: now(),
+        };
+        self.entries.insert(uri.to_string(), entry);
+        self.dirty = true;
+    }
+
+    // Drops the entries of the songs whose cover comes from a cover file,
+    // or that have no cover at all; returns the UUIDs of their covers
+    pub fn forget_cover_files(&mut self) -> HashSet<String> {
+        let mut covers = HashSet::new();
+        self.entries.retain(|_, e| {
+            let cover_uuid = e.data.get("cover-uuid").and_then(Value::as_str);
+            // Older entries do not record where the cover comes from
+            let from_cover_file = e.data.get("cover-file").and_then(Value::as_bool);
+
+            match (cover_uuid, from_cover_file) {
+                (Some(_), Some(false)) => true,
+                (Some(uuid), _) => {
+                    covers.insert(uuid.to_string());
+                    false
+                }
+                (None, _) => false,
+            }
+        });
+        self.dirty = true;
+
+        covers
+    }
+
+    // The UUIDs of the given songs, and of their covers
+    pub fn song_and_cover_uuids<'a>(
+        &self,
+        uris: impl Iterator<Item = &'a str>,
+    ) -> (HashSet<String>, HashSet<String>) {
+        let mut songs = HashSet::new();
+        let mut covers = HashSet::new();
+        for data in uris
+            .filter_map(|uri| self.entries.get(uri))
+            .map(|e| &e.data)
+        {
+            if let Some(uuid) = data.get("uuid").and_then(Value::as_str) {
+                songs.insert(uuid.to_string());
+            }
+            if let Some(uuid) = data.get("cover-uuid").and_then(Value::as_str) {
+                covers.insert(uuid.to_string());
+            }
+        }
+
+        (songs, covers)
+    }
+
+    // Drops the least recently used entries, if there are more than
+    // `max_entries`
+    fn prune(&mut self, max_entries: usize) {
+        if self.entries.len() <= max_entries {
+            return;
+        }
+
+        let mut by_last_use: Vec<(u64, String)> = self
+            .entries
+            .iter()
+            .map(|(uri, e)| (e.last_used, uri.to_string()))
+            .collect();
+        by_last_use.sort_unstable();
+
+        let n_pruned = self.entries.len() - max_entries;
+        for (_, uri) in by_last_use.iter().take(n_pruned) {
+            self.entries.remove(uri);
+        }
+
+        debug!("Pruned {} songs from the metadata index", n_pruned);
+    }
+
+    // The UUIDs of the songs in the index
+    pub fn song_uuids(&self) -> HashSet<String> {
+        self.entries
+            .values()
+            .filter_map(|e| e.data.get("uuid").and_then(Value::as_str))
+            .map(|s| s.to_string())
+            .collect()
+    }
+
+    // The serialized index, if it changed since the last time
+    fn serialize_if_dirty(&mut self) -> Option<String> {
+        if !self.dirty {
+            return None;
+        }
+
+        self.prune(MAX_ENTRIES);
+        self.dirty = false;
+
+        Some(self.serialize())
+    }
+
+    // The index can hold many songs, so the data of each entry is written
+    // in place instead of being copied into a new JSON object first
+    fn serialize(&self) -> String {
+        let mut contents = format!("{{\"version\":{},\"songs\":{{", INDEX_VERSION);
+        for (i, (uri, e)) in self.entries.iter().enumerate() {
+            if i > 0 {
+                contents.push(',');
+            }
+            let _ = write!(
+                contents,
+                "{}:{{\"mtime\":{},\"size\":{},\"last-used\":{},\"data\":{}}}",
+                Value::from(uri.as_str()),
+                e.stamp.mtime,
+                e.stamp.size,
+                e.last_used,
+                e.data
+            );
+        }
+        contents.push_str("}}");
+
+        contents
+    }
+
+    // The URIs of the songs in the index, and the stamps of their files
+    fn stamps(&self) -> Vec<(String, FileStamp)> {
+        self.entries
+            .iter()
+            .map(|(uri, e)| (uri.to_string(), e.stamp))
+            .collect()
+    }
+
+    // Drops the entries for files that do not exist any more, unless they
+    // were replaced in the meantime
+    fn remove_missing(&mut self, missing: &[(String, FileStamp)]) {
+        for (uri, stamp) in missing {
+            if self.entries.get(uri).map(|e| e.stamp) == Some(*stamp) {
+                self.entries.remove(uri);
+                self.dirty = true;
+            }
+        }
+    }
+}
+
+// Writes the metadata index to disk in a separate thread
+pub fn store_metadata_index() {
+    static STORE_LOCK: Mutex<()> = Mutex::new(());
+    static MISSING_CHECKED: AtomicBool = AtomicBool::new(false);
+
+    let res = thread::Builder::new()
+        .name("metadata-index".to_string())
+        .spawn(|| {
+            let _guard = STORE_LOCK.lock().unwrap();
+
+            // Checking every file can take a while, so we only do it once
+            // per session, and we do not want to block the threads loading
+            // songs in the meantime
+            if !MISSING_CHECKED.swap(true, Ordering::SeqCst) {
+                let stamps = MetadataIndex::global().lock().unwrap().stamps();
+                let missing: Vec<_> = stamps
+                    .into_iter()
+                    .filter(|(uri, _)| {
+                        !gio::File::for_uri(uri).query_exists(gio::Cancellable::NONE)
+                    })
+                    .collect();
+                if !missing.is_empty() {
+                    debug!(
+                        "Dropping {} missing songs from the metadata index",
+                        missing.len()
+                    );
+                    MetadataIndex::global()
+                        .lock()
+                        .unwrap()
+                        .remove_missing(&missing);
+                }
+            }
+
+            let contents = match MetadataIndex::global().lock().unwrap().serialize_if_dirty() {
+                Some(contents) => contents,
+                None => return,
+            };
+
+            let index_file = index_file();
+            if let Some(parent) = index_file.parent() {
+                glib::mkdir_with_parents(parent, 0o755);
+            }
+
+            let file = gio::File::for_path(&index_file);
+            match file.replace_contents(
+                contents.as_bytes(),
+                None,
+                false,
+                gio::FileCreateFlags::NONE,
+                gio::Cancellable::NONE,
+            ) {
+                Ok(_) => debug!("Metadata index saved to: {:?}", &index_file),
+                Err(e) => warn!("Unable to save metadata index: {}", e),
+            }
+        });
+
+    if let Err(e) = res {
+        warn!("Unable to spawn metadata index thread: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(last_used: u64) -> Entry {
+        Entry {
+            stamp: FileStamp { mtime: 0, size: 0 },
+            data: Value::Null,
+            last_used,
+        }
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut index = MetadataIndex::default();
+        for (i, last_used) in [5, 1, 4, 2, 3, 2].iter().enumerate() {
+            index
+                .entries
+                .insert(format!("file:///{}", i), entry(*last_used));
+        }
+
+        index.prune(6);
+        assert_eq!(index.entries.len(), 6);
+
+        index.prune(3);
+        let mut uris: Vec<&str> = index.entries.keys().map(|s| s.as_str()).collect();
+        uris.sort_unstable();
+        assert_eq!(uris, vec!["file:///0", "file:///2", "file:///4"]);
+    }
+
+    #[test]
+    fn test_serialize() {
+        let mut index = MetadataIndex::default();
+        index.entries.insert(
+            "file:///a \"b\"".to_string(),
+            Entry {
+                stamp: FileStamp { mtime: 1, size: 2 },
+                data: serde_json::json!({ "uuid": "song" }),
+                last_used: 3,
+            },
+        );
+        index.entries.insert("file:///c".to_string(), entry(4));
+
+        let json: Value = serde_json::from_str(&index.serialize()).unwrap();
+        assert_eq!(json["version"], INDEX_VERSION);
+        assert_eq!(json["songs"].as_object().unwrap().len(), 2);
+
+        let song = &json["songs"]["file:///a \"b\""];
+        assert_eq!(song["mtime"], 1);
+        assert_eq!(song["size"], 2);
+        assert_eq!(song["last-used"], 3);
+        assert_eq!(song["data"]["uuid"], "song");
+    }
+
+    #[test]
+    fn test_song_and_cover_uuids() {
+        let mut index = MetadataIndex::default();
+        index.entries.insert(
+            "file:///a".to_string(),
+            Entry {
+                data: serde_json::json!({ "uuid": "song-a", "cover-uuid": "cover" }),
+                ..entry(1)
+            },
+        );
+        index.entries.insert(
+            "file:///b".to_string(),
+            Entry {
+                data: serde_json::json!({ "uuid": "song-b", "cover-uuid": null }),
+                ..entry(1)
+            },
+        );
+
+        let (songs, covers) =
+            index.song_and_cover_uuids(["file:///b", "file:///c"].iter().copied());
+        assert_eq!(songs, ["song-b".to_string()].iter().cloned().collect());
+        assert!(covers.is_empty());
+
+        let (songs, covers) = index.song_and_cover_uuids(["file:///a"].iter().copied());
+        assert_eq!(songs.len(), 1);
+        assert!(covers.contains("cover"));
+    }
+
+    #[test]
+    fn test_forget_cover_files() {
+        let mut index = MetadataIndex::default();
+        let songs = [
+            (
+                "embedded",
+                serde_json::json!({ "cover-uuid": "a", "cover-file": false }),
+            ),
+            (
+                "cover-file",
+                serde_json::json!({ "cover-uuid": "b", "cover-file": true }),
+            ),
+            ("older", serde_json::json!({ "cover-uuid": "c" })),
+            (
+                "no-cover",
+                serde_json::json!({ "cover-uuid": null, "cover-file": null }),
+            ),
+        ];
+        for (name, data) in songs.iter() {
+            index.entries.insert(
+                format!("file:///{}", name),
+                Entry {
+                    data: data.clone(),
+                    ..entry(1)
+                },
+            );
+        }
+
+        let covers = index.forget_cover_files();
+        assert_eq!(covers, ["b", "c"].iter().map(|s| s.to_string()).collect());
+        assert_eq!(index.entries.len(), 1);
+        assert!(index.entries.contains_key("file:///embedded"));
+    }
+
+    #[test]
+    fn test_remove_missing() {
+        let mut index = MetadataIndex::default();
+        index.entries.insert("file:///a".to_string(), entry(1));
+        index.entries.insert("file:///b".to_string(), entry(1));
+
+        let missing = index.stamps();
+
+        // The second file was parsed again after checking the files
+        index.entries.get_mut("file:///b").unwrap().stamp.size = 1;
+
+        index.remove_missing(&missing);
+        assert!(!index.entries.contains_key("file:///a"));
+        assert!(index.entries.contains_key("file:///b"));
+        assert!(index.dirty);
+    }
+}
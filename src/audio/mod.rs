@@ -22,7 +22,11 @@
 // ├── Queue: the playlist tracker GListModel
 // ├── GstBackend: a GstPlayer wrapper
 // ╰── controllers: external bits of code that interact with the state
-//     ╰── MprisController: an MPRIS wrapper
+//     ├── MprisController: the MPRIS player, track list, and playlists
+//     ├── HistoryController: records plays into the PlayHistory
+//     ├── WaveformGenerator: the peaks of the current song
+//     ├── SpectrogramGenerator: the spectrogram of the current song
+//     ╰── WaveformPrecomputer: caches the peaks of the upcoming songs
 //
 // The AudioPlayer object creates a glib::Sender/Receiver channel pair, and
 // passes the sender to the controllers; whenever the controllers update their
@@ -37,32 +41,60 @@
 //
 // Playback actions are proxied to the AudioPlayer object from the controllers.
 
+mod analysis_pipeline;
+mod artwork;
+pub use artwork::{load_artwork, Artwork};
+
 mod controller;
 pub use controller::Controller;
 
 mod cover_cache;
+mod cover_files;
 pub use cover_cache::CoverCache;
+pub use cover_files::CoverFiles;
 
+mod history_controller;
 mod inhibit_controller;
 mod mpris_controller;
+pub use history_controller::HistoryController;
 pub use inhibit_controller::InhibitController;
 pub use mpris_controller::MprisController;
 
 mod gst_backend;
 pub use gst_backend::GstBackend;
 
+mod lyrics;
+pub use lyrics::Lyrics;
+
+mod disk_cache;
+mod identity;
+mod metadata_index;
+mod play_history;
 mod player;
 mod queue;
 mod shuffle;
 mod song;
+mod song_loader;
+mod spectrogram_generator;
 mod state;
+mod tags;
+mod waveform_cache;
 mod waveform_generator;
+mod waveform_precomputer;
 
+pub use disk_cache::{clear_disk_caches, prune_disk_caches, DiskCache, PrunePolicy};
+pub use metadata_index::store_metadata_index;
+pub use play_history::PlayHistory;
 pub use player::{
-    AudioPlayer, PlaybackAction, PlaybackState, RepeatMode, ReplayGainMode, SeekDirection,
+    AudioPlayer, PlaybackAction, PlaybackState, RepeatMode, ReplayGainMode, SavedPlaylist,
+    SeekDirection, TrackPosition,
 };
 pub use queue::Queue;
 pub use shuffle::ShuffleListModel;
-pub use song::Song;
+pub use song::{Song, SongData};
+pub use song_loader::{load_matching_song_data, load_song_data};
+pub use spectrogram_generator::SpectrogramGenerator;
 pub use state::PlayerState;
-pub use waveform_generator::WaveformGenerator;
+pub use tags::{write_tags, TagChanges, MAX_RATING};
+pub use waveform_generator::{WaveformGenerator, WaveformLevels};
+pub use waveform_precomputer::WaveformPrecomputer;
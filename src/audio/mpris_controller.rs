@@ -1,109 +1,946 @@
 // SPDX-FileCopyrightText: 2022  Emmanuele Bassi
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+// The MPRIS server exposes the Player interface, the TrackList interface
+// on top of the queue, and the Playlists interface on top of the saved
+// playlists. The interfaces are implemented by MprisPlayer, which lives in
+// the main context like the rest of the player, and shares its state with
+// the MprisController.
+//
+// Every entry of the queue has its own track id, derived from the serial
+// number of the song object, so remote controls can refer to the songs in
+// the queue while it changes, even if a song is queued more than once.
+// Adding tracks loads the songs asynchronously, through the window, so
+// they end up in the saved playlist like any other song.
+//
+// MPRIS has no way to change the metadata of a track, so the rating of
+// the songs can be set through an additional interface on the same
+// object, using the same range as xesam:userRating.
+
 use std::{
-    cell::{OnceCell, RefCell},
-    rc::Rc,
+    cell::{Cell, OnceCell, RefCell},
+    convert::TryFrom,
+    fmt,
+    ops::Range,
+    path::PathBuf,
+    rc::{Rc, Weak},
 };
 
-use async_channel::Sender;
+use async_channel::{Receiver, Sender};
 use glib::clone;
 use gtk::{gio, glib, prelude::*};
-use log::error;
-use mpris_server::{LoopStatus, Metadata, PlaybackStatus, Player, Time};
+use log::{debug, error};
+use mpris_server::{
+    LocalPlayerInterface, LocalPlaylistsInterface, LocalRootInterface, LocalServer,
+    LocalTrackListInterface, LoopStatus, Metadata, PlaybackRate, PlaybackStatus, Playlist,
+    PlaylistId, PlaylistOrdering, PlaylistsProperty, Property, Signal, Time, TrackId,
+    TrackListSignal, Volume,
+};
+use zbus::fdo;
 
 use crate::{
-    audio::{Controller, PlaybackAction, PlaybackState, RepeatMode, Song},
+    audio::{
+        Controller, PlaybackAction, PlaybackState, Queue, RepeatMode, SavedPlaylist, Song,
+        TrackPosition, MAX_RATING,
+    },
     config::APPLICATION_ID,
+    i18n::i18n,
+    smart_playlist::load_smart_playlists_in,
+    utils,
 };
 
+const MPRIS_OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const TRACK_PATH_PREFIX: &str = "/io/bassi/Amberol/Track/";
+const PLAYLIST_PATH_PREFIX: &str = "/io/bassi/Amberol/Playlist/";
+
+// Announcing each track when adding or removing many songs at once, like
+// a whole folder, would flood the bus; we replace the whole list instead
+const MAX_TRACK_SIGNALS: u32 = 32;
+
+// Track and playlist ids are D-Bus object paths, whose elements can only
+// contain ASCII letters, digits, and underscores
+fn object_path(prefix: &str, id: &str) -> String {
+    let element: String = id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+
+    format!("{}{}", prefix, element)
+}
+
+fn track_id(song: &Song) -> TrackId {
+    let path = object_path(TRACK_PATH_PREFIX, &song.serial().to_string());
+    TrackId::try_from(path.as_str()).unwrap_or(TrackId::NO_TRACK)
+}
+
+// The serial number of the song with the given track id
+fn track_serial(id: &TrackId) -> Option<u64> {
+    id.as_str().strip_prefix(TRACK_PATH_PREFIX)?.parse().ok()
+}
+
+// xesam:userRating is a fraction between 0 and 1
+fn user_rating(stars: u32) -> f64 {
+    stars.min(MAX_RATING) as f64 / MAX_RATING as f64
+}
+
+fn stars_from_user_rating(rating: f64) -> u32 {
+    (rating.clamp(0.0, 1.0) * MAX_RATING as f64).round() as u32
+}
+
+fn song_metadata(song: &Song) -> Metadata {
+    let mut metadata = Metadata::new();
+
+    metadata.set_trackid(Some(track_id(song)));
+    metadata.set_artist(Some(song.artists()));
+    metadata.set_title(Some(song.title()));
+    metadata.set_album(Some(song.album()));
+
+    if song.rating() > 0 {
+        metadata.set_user_rating(Some(user_rating(song.rating())));
+    }
+
+    let length = Time::from_secs(song.duration() as i64);
+    metadata.set_length(Some(length));
+
+    // MPRIS should really support passing a bytes buffer for
+    // the cover art, instead of requiring this ridiculous
+    // charade
+    if let Some(cache) = song.cover_cache() {
+        let file = gio::File::for_path(cache);
+        match file.query_info(
+            "standard::type",
+            gio::FileQueryInfoFlags::NONE,
+            gio::Cancellable::NONE,
+        ) {
+            Ok(info) if info.file_type() == gio::FileType::Regular => {
+                metadata.set_art_url(Some(file.uri()));
+            }
+            _ => metadata.set_art_url(None::<String>),
+        }
+    }
+
+    metadata
+}
+
+fn queue_song(queue: &Queue, id: &TrackId) -> Option<Song> {
+    track_serial(id)
+        .and_then(|serial| queue.song_position(serial))
+        .and_then(|pos| queue.song_at(pos))
+}
+
+fn queue_tracks(queue: &Queue) -> Vec<TrackId> {
+    (0..queue.n_songs())
+        .filter_map(|pos| queue.song_at(pos))
+        .map(|song| track_id(&song))
+        .collect()
+}
+
+fn playlist_id(playlist: &SavedPlaylist) -> PlaylistId {
+    let path = match playlist {
+        SavedPlaylist::Current => object_path(PLAYLIST_PATH_PREFIX, "Current"),
+        SavedPlaylist::Smart(id) => object_path(PLAYLIST_PATH_PREFIX, &format!("Smart_{}", id)),
+    };
+
+    // The elements of the path are always valid
+    PlaylistId::try_from(path).expect("Invalid playlist path")
+}
+
+fn mpris_playlist(playlist: &SavedPlaylist, name: &str) -> Playlist {
+    Playlist {
+        id: playlist_id(playlist),
+        name: name.to_string(),
+        icon: String::new(),
+    }
+}
+
+// Where the saved playlists are stored
+#[derive(Debug)]
+struct PlaylistDirs {
+    cache: PathBuf,
+    data: PathBuf,
+}
+
+impl Default for PlaylistDirs {
+    fn default() -> Self {
+        Self {
+            cache: glib::user_cache_dir(),
+            data: glib::user_data_dir(),
+        }
+    }
+}
+
+// The playlists that can be activated, with their name: the queue of the
+// last session, if any, followed by the smart playlists
+fn saved_playlists(dirs: &PlaylistDirs) -> Vec<(SavedPlaylist, String)> {
+    let mut res = Vec::new();
+    if utils::has_cached_playlist_in(&dirs.cache) {
+        res.push((SavedPlaylist::Current, i18n("Current Playlist")));
+    }
+
+    res.extend(
+        load_smart_playlists_in(&dirs.data)
+            .into_iter()
+            .map(|p| (SavedPlaylist::Smart(p.id), p.name)),
+    );
+
+    res
+}
+
+// Returns the requested page of playlists, in the given order
+fn playlists_page(
+    mut playlists: Vec<(SavedPlaylist, String)>,
+    index: u32,
+    max_count: u32,
+    alphabetical: bool,
+    reverse: bool,
+) -> Vec<(SavedPlaylist, String)> {
+    if alphabetical {
+        playlists.sort_by_key(|(_, name)| name.to_lowercase());
+    }
+    if reverse {
+        playlists.reverse();
+    }
+
+    playlists
+        .into_iter()
+        .skip(index as usize)
+        .take(max_count as usize)
+        .collect()
+}
+
+// The destination of a relative seek, in microseconds; seeking past the
+// end of the song skips to the next one, and we return None
+fn seek_destination(position: i64, offset: i64, length: i64) -> Option<i64> {
+    let destination = position.saturating_add(offset).max(0);
+    if destination > length {
+        None
+    } else {
+        Some(destination)
+    }
+}
+
+// Whether skipping forward and backwards would change the current song,
+// mirroring AudioPlayer::skip_next() and AudioPlayer::skip_previous()
+fn queue_navigation(n_songs: u32, current: Option<u32>, repeat_mode: RepeatMode) -> (bool, bool) {
+    if n_songs == 0 {
+        return (false, false);
+    }
+
+    match current {
+        Some(current) => {
+            let is_last = current >= n_songs - 1;
+            let can_go_next = !is_last || repeat_mode != RepeatMode::Consecutive;
+            (can_go_next, current > 0)
+        }
+        None => (true, false),
+    }
+}
+
+// How a change in the queue is announced on the bus
+#[derive(Debug, PartialEq)]
+enum TrackListChange {
+    Added(Range<usize>),
+    Removed(Range<usize>),
+    Replaced,
+}
+
+fn track_list_change(
+    n_tracks: usize,
+    position: u32,
+    removed: u32,
+    added: u32,
+) -> Option<TrackListChange> {
+    let start = position as usize;
+    if removed == 0 && added == 0 {
+        return None;
+    }
+
+    // Songs moving around, like when shuffling the queue, or changes we
+    // cannot describe with a few signals
+    if (removed > 0 && added > 0)
+        || removed > MAX_TRACK_SIGNALS
+        || added > MAX_TRACK_SIGNALS
+        || start + removed as usize > n_tracks
+        || (removed > 0 && removed as usize == n_tracks)
+    {
+        return Some(TrackListChange::Replaced);
+    }
+
+    if removed > 0 {
+        Some(TrackListChange::Removed(start..start + removed as usize))
+    } else {
+        Some(TrackListChange::Added(start..start + added as usize))
+    }
+}
+
+// The state of the player, as seen on the bus
+#[derive(Debug)]
+struct MprisState {
+    playback_status: Cell<PlaybackStatus>,
+    loop_status: Cell<LoopStatus>,
+    can_play: Cell<bool>,
+    can_go_next: Cell<bool>,
+    can_go_previous: Cell<bool>,
+    shuffle: Cell<bool>,
+    volume: Cell<f64>,
+    // In milliseconds
+    position: Cell<u64>,
+    metadata: RefCell<Metadata>,
+    // The tracks of the queue, as last announced on the bus
+    tracks: RefCell<Vec<TrackId>>,
+    // The playlist activated last, until the queue is cleared
+    active_playlist: RefCell<Option<SavedPlaylist>>,
+}
+
+impl Default for MprisState {
+    fn default() -> Self {
+        Self {
+            playback_status: Cell::new(PlaybackStatus::Stopped),
+            loop_status: Cell::new(LoopStatus::None),
+            can_play: Cell::new(false),
+            can_go_next: Cell::new(false),
+            can_go_previous: Cell::new(false),
+            shuffle: Cell::new(false),
+            volume: Cell::new(1.0),
+            position: Cell::new(0),
+            metadata: RefCell::new(Metadata::new()),
+            tracks: RefCell::new(Vec::new()),
+            active_playlist: RefCell::new(None),
+        }
+    }
+}
+
 #[derive(Debug)]
+struct MprisPlayer {
+    sender: Sender<PlaybackAction>,
+    queue: Queue,
+    state: Rc<MprisState>,
+    playlist_dirs: PlaylistDirs,
+    // The server owns the player, so we only keep a weak reference
+    server: Weak<OnceCell<LocalServer<MprisPlayer>>>,
+}
+
+impl MprisPlayer {
+    fn send(&self, action: PlaybackAction) {
+        if let Err(e) = self.sender.send_blocking(action.clone()) {
+            error!("Unable to send {action:?}: {e}");
+        }
+    }
+
+    // The position of the track in the queue
+    fn track_position(&self, id: &TrackId) -> Option<u32> {
+        track_serial(id).and_then(|serial| self.queue.song_position(serial))
+    }
+
+    // We can only play local files
+    fn check_uri(&self, uri: &str) -> fdo::Result<()> {
+        if gio::File::for_uri(uri).path().is_none() {
+            return Err(fdo::Error::NotSupported(format!(
+                "Unsupported URI “{}”",
+                uri
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl LocalRootInterface for MprisPlayer {
+    async fn raise(&self) -> fdo::Result<()> {
+        self.send(PlaybackAction::Raise);
+        Ok(())
+    }
+
+    async fn quit(&self) -> fdo::Result<()> {
+        Err(fdo::Error::NotSupported("Quitting is not supported".into()))
+    }
+
+    async fn can_quit(&self) -> fdo::Result<bool> {
+        Ok(false)
+    }
+
+    async fn fullscreen(&self) -> fdo::Result<bool> {
+        Ok(false)
+    }
+
+    async fn set_fullscreen(&self, _fullscreen: bool) -> zbus::Result<()> {
+        Ok(())
+    }
+
+    async fn can_set_fullscreen(&self) -> fdo::Result<bool> {
+        Ok(false)
+    }
+
+    async fn can_raise(&self) -> fdo::Result<bool> {
+        Ok(true)
+    }
+
+    async fn has_track_list(&self) -> fdo::Result<bool> {
+        Ok(true)
+    }
+
+    async fn identity(&self) -> fdo::Result<String> {
+        Ok("Amberol".to_string())
+    }
+
+    async fn desktop_entry(&self) -> fdo::Result<String> {
+        Ok(APPLICATION_ID.to_string())
+    }
+
+    async fn supported_uri_schemes(&self) -> fdo::Result<Vec<String>> {
+        Ok(vec!["file".to_string()])
+    }
+
+    async fn supported_mime_types(&self) -> fdo::Result<Vec<String>> {
+        Ok(vec![])
+    }
+}
+
+impl LocalPlayerInterface for MprisPlayer {
+    async fn next(&self) -> fdo::Result<()> {
+        self.send(PlaybackAction::SkipNext);
+        Ok(())
+    }
+
+    async fn previous(&self) -> fdo::Result<()> {
+        self.send(PlaybackAction::SkipPrevious);
+        Ok(())
+    }
+
+    async fn pause(&self) -> fdo::Result<()> {
+        self.send(PlaybackAction::Pause);
+        Ok(())
+    }
+
+    async fn play_pause(&self) -> fdo::Result<()> {
+        match self.state.playback_status.get() {
+            PlaybackStatus::Paused => self.send(PlaybackAction::Play),
+            PlaybackStatus::Stopped => self.send(PlaybackAction::Stop),
+            _ => self.send(PlaybackAction::Pause),
+        };
+        Ok(())
+    }
+
+    async fn stop(&self) -> fdo::Result<()> {
+        self.send(PlaybackAction::Stop);
+        Ok(())
+    }
+
+    async fn play(&self) -> fdo::Result<()> {
+        self.send(PlaybackAction::Play);
+        Ok(())
+    }
+
+    async fn seek(&self, offset: Time) -> fdo::Result<()> {
+        // Nothing to seek without a song
+        let length = match self.state.metadata.borrow().length() {
+            Some(length) => length,
+            None => return Ok(()),
+        };
+
+        let position = Time::from_millis(self.state.position.get() as i64);
+        match seek_destination(position.as_micros(), offset.as_micros(), length.as_micros()) {
+            Some(destination) => {
+                let destination = Time::from_micros(destination);
+                self.send(PlaybackAction::Seek(destination.as_millis() as u64));
+            }
+            None => self.send(PlaybackAction::SkipNext),
+        }
+
+        Ok(())
+    }
+
+    async fn set_position(&self, track_id: TrackId, position: Time) -> fdo::Result<()> {
+        let (current_track, length) = {
+            let metadata = self.state.metadata.borrow();
+            (metadata.trackid(), metadata.length())
+        };
+
+        // Requests for a song that is not playing any more are stale,
+        // and must be ignored, like positions outside of the song
+        if current_track != Some(track_id) {
+            debug!("Ignoring stale position request");
+            return Ok(());
+        }
+        let length = length.map_or(0, |l| l.as_micros());
+        if position.as_micros() < 0 || position.as_micros() > length {
+            return Ok(());
+        }
+
+        self.send(PlaybackAction::Seek(position.as_millis() as u64));
+        Ok(())
+    }
+
+    async fn open_uri(&self, uri: String) -> fdo::Result<()> {
+        self.check_uri(&uri)?;
+
+        // Songs already in the queue are played from there
+        debug!("Opening {}", uri);
+        self.send(PlaybackAction::OpenTrack(uri));
+
+        Ok(())
+    }
+
+    async fn playback_status(&self) -> fdo::Result<PlaybackStatus> {
+        Ok(self.state.playback_status.get())
+    }
+
+    async fn loop_status(&self) -> fdo::Result<LoopStatus> {
+        Ok(self.state.loop_status.get())
+    }
+
+    async fn set_loop_status(&self, loop_status: LoopStatus) -> zbus::Result<()> {
+        let mode = match loop_status {
+            LoopStatus::None => RepeatMode::Consecutive,
+            LoopStatus::Track => RepeatMode::RepeatOne,
+            LoopStatus::Playlist => RepeatMode::RepeatAll,
+        };
+
+        self.send(PlaybackAction::Repeat(mode));
+        Ok(())
+    }
+
+    async fn rate(&self) -> fdo::Result<PlaybackRate> {
+        Ok(1.0)
+    }
+
+    async fn set_rate(&self, _rate: PlaybackRate) -> zbus::Result<()> {
+        Ok(())
+    }
+
+    async fn shuffle(&self) -> fdo::Result<bool> {
+        Ok(self.state.shuffle.get())
+    }
+
+    async fn set_shuffle(&self, shuffle: bool) -> zbus::Result<()> {
+        self.send(PlaybackAction::Shuffle(shuffle));
+        Ok(())
+    }
+
+    async fn metadata(&self) -> fdo::Result<Metadata> {
+        Ok(self.state.metadata.borrow().clone())
+    }
+
+    async fn volume(&self) -> fdo::Result<Volume> {
+        Ok(self.state.volume.get())
+    }
+
+    // The volume control does not go past 1.0
+    async fn set_volume(&self, volume: Volume) -> zbus::Result<()> {
+        self.send(PlaybackAction::SetVolume(volume.clamp(0.0, 1.0)));
+        Ok(())
+    }
+
+    async fn position(&self) -> fdo::Result<Time> {
+        Ok(Time::from_millis(self.state.position.get() as i64))
+    }
+
+    async fn minimum_rate(&self) -> fdo::Result<PlaybackRate> {
+        Ok(1.0)
+    }
+
+    async fn maximum_rate(&self) -> fdo::Result<PlaybackRate> {
+        Ok(1.0)
+    }
+
+    async fn can_go_next(&self) -> fdo::Result<bool> {
+        Ok(self.state.can_go_next.get())
+    }
+
+    async fn can_go_previous(&self) -> fdo::Result<bool> {
+        Ok(self.state.can_go_previous.get())
+    }
+
+    async fn can_play(&self) -> fdo::Result<bool> {
+        Ok(self.state.can_play.get())
+    }
+
+    async fn can_pause(&self) -> fdo::Result<bool> {
+        Ok(true)
+    }
+
+    async fn can_seek(&self) -> fdo::Result<bool> {
+        Ok(true)
+    }
+
+    async fn can_control(&self) -> fdo::Result<bool> {
+        Ok(true)
+    }
+}
+
+impl LocalTrackListInterface for MprisPlayer {
+    async fn get_tracks_metadata(&self, track_ids: Vec<TrackId>) -> fdo::Result<Vec<Metadata>> {
+        // Unknown tracks are skipped
+        let res = track_ids
+            .iter()
+            .filter_map(|id| self.track_position(id))
+            .filter_map(|pos| self.queue.song_at(pos))
+            .map(|song| song_metadata(&song))
+            .collect();
+
+        Ok(res)
+    }
+
+    async fn add_track(
+        &self,
+        uri: String,
+        after_track: TrackId,
+        set_as_current: bool,
+    ) -> fdo::Result<()> {
+        self.check_uri(&uri)?;
+
+        // Tracks added after NoTrack go at the beginning of the list, and
+        // tracks added after an unknown track at the end
+        let position = if after_track == TrackId::NO_TRACK {
+            TrackPosition::Start
+        } else {
+            match track_serial(&after_track) {
+                Some(serial) => TrackPosition::After(serial),
+                None => TrackPosition::End,
+            }
+        };
+
+        debug!("Adding track {} at {:?}", uri, position);
+        self.send(PlaybackAction::AddTrack(uri, position, set_as_current));
+
+        Ok(())
+    }
+
+    async fn remove_track(&self, track_id: TrackId) -> fdo::Result<()> {
+        if let Some(serial) = track_serial(&track_id) {
+            self.send(PlaybackAction::Remove(serial));
+        }
+
+        Ok(())
+    }
+
+    async fn go_to(&self, track_id: TrackId) -> fdo::Result<()> {
+        if let Some(serial) = track_serial(&track_id) {
+            self.send(PlaybackAction::SkipTo(serial));
+        }
+
+        Ok(())
+    }
+
+    async fn tracks(&self) -> fdo::Result<Vec<TrackId>> {
+        Ok(self.state.tracks.borrow().clone())
+    }
+
+    async fn can_edit_tracks(&self) -> fdo::Result<bool> {
+        Ok(true)
+    }
+}
+
+impl LocalPlaylistsInterface for MprisPlayer {
+    async fn activate_playlist(&self, playlist_id: PlaylistId) -> fdo::Result<()> {
+        let (playlist, name) = saved_playlists(&self.playlist_dirs)
+            .into_iter()
+            .find(|(p, _)| self::playlist_id(p) == playlist_id)
+            .ok_or_else(|| fdo::Error::InvalidArgs("Unknown playlist".into()))?;
+
+        debug!("Activating playlist {}", name);
+        self.state.active_playlist.replace(Some(playlist.clone()));
+        self.send(PlaybackAction::ActivatePlaylist(playlist.clone()));
+
+        if let Some(server) = self.server.upgrade() {
+            let active = mpris_playlist(&playlist, &name);
+            emit_playlists_properties(
+                &server,
+                vec![PlaylistsProperty::ActivePlaylist(Some(active))],
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn get_playlists(
+        &self,
+        index: u32,
+        max_count: u32,
+        order: PlaylistOrdering,
+        reverse_order: bool,
+    ) -> fdo::Result<Vec<Playlist>> {
+        let alphabetical = matches!(order, PlaylistOrdering::Alphabetical);
+        let res = playlists_page(
+            saved_playlists(&self.playlist_dirs),
+            index,
+            max_count,
+            alphabetical,
+            reverse_order,
+        )
+        .iter()
+        .map(|(p, name)| mpris_playlist(p, name))
+        .collect();
+
+        Ok(res)
+    }
+
+    async fn playlist_count(&self) -> fdo::Result<u32> {
+        Ok(saved_playlists(&self.playlist_dirs).len() as u32)
+    }
+
+    async fn orderings(&self) -> fdo::Result<Vec<PlaylistOrdering>> {
+        Ok(vec![
+            PlaylistOrdering::UserDefined,
+            PlaylistOrdering::Alphabetical,
+        ])
+    }
+
+    async fn active_playlist(&self) -> fdo::Result<Option<Playlist>> {
+        let active = match *self.state.active_playlist.borrow() {
+            Some(ref p) => p.clone(),
+            None => return Ok(None),
+        };
+
+        // The playlist might have been removed in the meantime
+        let res = saved_playlists(&self.playlist_dirs)
+            .iter()
+            .find(|(p, _)| *p == active)
+            .map(|(p, name)| mpris_playlist(p, name));
+
+        Ok(res)
+    }
+}
+
+type Server = Rc<OnceCell<LocalServer<MprisPlayer>>>;
+
+// The interface must be thread safe, so it passes the ratings to the main
+// context, where we can look up the songs
+struct RatingInterface {
+    sender: Sender<(TrackId, f64)>,
+}
+
+#[zbus::interface(name = "io.bassi.Amberol.Rating")]
+impl RatingInterface {
+    async fn set_user_rating(&self, track_id: TrackId, rating: f64) -> fdo::Result<()> {
+        if !(0.0..=1.0).contains(&rating) {
+            return Err(fdo::Error::InvalidArgs(format!(
+                "Invalid rating {}",
+                rating
+            )));
+        }
+
+        self.sender
+            .send((track_id, rating))
+            .await
+            .map_err(|e| fdo::Error::Failed(e.to_string()))
+    }
+}
+
+async fn rate_tracks(
+    receiver: Receiver<(TrackId, f64)>,
+    sender: Sender<PlaybackAction>,
+    queue: glib::WeakRef<Queue>,
+) {
+    while let Ok((id, rating)) = receiver.recv().await {
+        let song = match queue.upgrade() {
+            Some(queue) => queue_song(&queue, &id),
+            None => break,
+        };
+
+        match song {
+            Some(song) => {
+                let action = PlaybackAction::SetRating(song.uri(), stars_from_user_rating(rating));
+                if let Err(e) = sender.send(action).await {
+                    error!("Unable to send SetRating: {e}");
+                }
+            }
+            None => debug!("Unknown track {} to rate", id),
+        }
+    }
+}
+
+fn emit_properties(server: &Server, properties: Vec<Property>) {
+    glib::spawn_future_local(clone!(@weak server => async move {
+        if let Some(server) = server.get() {
+            if let Err(err) = server.properties_changed(properties).await {
+                error!("Unable to notify MPRIS properties: {err:?}");
+            }
+        }
+    }));
+}
+
+fn emit_playlists_properties(server: &Server, properties: Vec<PlaylistsProperty>) {
+    glib::spawn_future_local(clone!(@weak server => async move {
+        if let Some(server) = server.get() {
+            if let Err(err) = server.playlists_properties_changed(properties).await {
+                error!("Unable to notify MPRIS playlists properties: {err:?}");
+            }
+        }
+    }));
+}
+
+// The signals are emitted in order, from a single task
+fn emit_track_list_signals(server: &Server, signals: Vec<TrackListSignal>) {
+    glib::spawn_future_local(clone!(@weak server => async move {
+        if let Some(server) = server.get() {
+            for signal in signals {
+                if let Err(err) = server.track_list_emit(signal).await {
+                    error!("Unable to emit MPRIS track list signal: {err:?}");
+                }
+            }
+        }
+    }));
+}
+
+// Announces whether we can skip forward and backwards from the current
+// song, when that changes
+fn update_navigation(server: &Server, state: &MprisState, queue: &Queue) {
+    let (can_go_next, can_go_previous) = queue_navigation(
+        queue.n_songs(),
+        queue.current_song_index(),
+        queue.repeat_mode(),
+    );
+
+    let mut properties = Vec::new();
+    if state.can_go_next.replace(can_go_next) != can_go_next {
+        properties.push(Property::CanGoNext(can_go_next));
+    }
+    if state.can_go_previous.replace(can_go_previous) != can_go_previous {
+        properties.push(Property::CanGoPrevious(can_go_previous));
+    }
+
+    if !properties.is_empty() {
+        emit_properties(server, properties);
+    }
+}
+
+// Announces the changes in the queue, driven by the items-changed signal
+// of its model
+fn update_tracks(
+    server: &Server,
+    state: &MprisState,
+    queue: &Queue,
+    position: u32,
+    removed: u32,
+    added: u32,
+) {
+    let tracks = queue_tracks(queue);
+    let old_tracks = state.tracks.replace(tracks.clone());
+
+    // Clearing the queue deactivates the playlist
+    if tracks.is_empty() && state.active_playlist.take().is_some() {
+        emit_playlists_properties(server, vec![PlaylistsProperty::ActivePlaylist(None)]);
+    }
+
+    let signals = match track_list_change(old_tracks.len(), position, removed, added) {
+        None => return,
+        Some(TrackListChange::Replaced) => vec![TrackListSignal::TrackListReplaced {
+            tracks,
+            current_track: queue
+                .current_song()
+                .map_or(TrackId::NO_TRACK, |s| track_id(&s)),
+        }],
+        Some(TrackListChange::Removed(range)) => old_tracks[range]
+            .iter()
+            .map(|id| TrackListSignal::TrackRemoved {
+                track_id: id.clone(),
+            })
+            .collect(),
+        Some(TrackListChange::Added(range)) => range
+            .filter_map(|pos| {
+                let song = queue.song_at(pos as u32)?;
+                let after_track = match pos.checked_sub(1) {
+                    Some(prev) => tracks.get(prev)?.clone(),
+                    None => TrackId::NO_TRACK,
+                };
+                Some(TrackListSignal::TrackAdded {
+                    metadata: song_metadata(&song),
+                    after_track,
+                })
+            })
+            .collect(),
+    };
+
+    emit_track_list_signals(server, signals);
+}
+
 pub struct MprisController {
-    mpris: Rc<OnceCell<Player>>,
+    server: Server,
+    state: Rc<MprisState>,
+    queue: Queue,
     song: RefCell<Option<Song>>,
 }
 
+impl fmt::Debug for MprisController {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MprisController").finish()
+    }
+}
+
 impl MprisController {
-    pub fn new(sender: Sender<PlaybackAction>) -> Self {
-        let builder = Player::builder(APPLICATION_ID)
-            .identity("Amberol")
-            .desktop_entry(APPLICATION_ID)
-            .can_raise(true)
-            .can_play(false)
-            .can_pause(true)
-            .can_seek(true)
-            .can_go_next(true)
-            .can_go_previous(true)
-            .can_set_fullscreen(false);
-
-        let mpris = Rc::new(OnceCell::new());
-
-        glib::spawn_future_local(clone!(
-            #[weak]
-            mpris,
-            #[strong]
+    pub fn new(sender: Sender<PlaybackAction>, queue: &Queue) -> Self {
+        let server: Server = Rc::new(OnceCell::new());
+        let state = Rc::new(MprisState::default());
+        state.tracks.replace(queue_tracks(queue));
+        state.shuffle.set(queue.is_shuffled());
+
+        let (rating_sender, rating_receiver) = async_channel::unbounded();
+        glib::spawn_future_local(rate_tracks(
+            rating_receiver,
+            sender.clone(),
+            queue.downgrade(),
+        ));
+
+        let player = MprisPlayer {
             sender,
-            async move {
-                match builder.build().await {
-                    Err(err) => error!("Failed to create MPRIS server: {:?}", err),
-                    Ok(player) => {
-                        setup_signals(sender, &player);
-                        let mpris_task = player.run();
-                        let _ = mpris.set(player);
-                        mpris_task.await;
+            queue: queue.clone(),
+            state: state.clone(),
+            playlist_dirs: PlaylistDirs::default(),
+            server: Rc::downgrade(&server),
+        };
+
+        glib::spawn_future_local(clone!(@weak server => async move {
+            match LocalServer::new_with_all(APPLICATION_ID, player).await {
+                Err(err) => error!("Failed to create MPRIS server: {:?}", err),
+                Ok(s) => {
+                    let rating = RatingInterface {
+                        sender: rating_sender,
+                    };
+                    if let Err(err) = s
+                        .connection()
+                        .object_server()
+                        .at(MPRIS_OBJECT_PATH, rating)
+                        .await
+                    {
+                        error!("Failed to export the rating interface: {:?}", err);
                     }
+
+                    let mpris_task = s.run();
+                    let _ = server.set(s);
+                    mpris_task.await;
                 }
             }
-        ));
+        }));
+
+        queue.model().connect_items_changed(clone!(@weak server, @weak state, @weak queue => move |_, position, removed, added| {
+            update_tracks(&server, &state, &queue, position, removed, added);
+            update_navigation(&server, &state, &queue);
+        }));
+
+        queue.connect_notify_local(
+            Some("current"),
+            clone!(@weak server, @weak state => move |queue, _| {
+                update_navigation(&server, &state, queue);
+            }),
+        );
 
         Self {
-            mpris,
+            server,
+            state,
+            queue: queue.clone(),
             song: RefCell::new(None),
         }
     }
 
     fn update_metadata(&self) {
-        let mut metadata = Metadata::new();
-
-        if let Some(song) = self.song.take() {
-            metadata.set_artist(Some(vec![song.artist()]));
-            metadata.set_title(Some(song.title()));
-            metadata.set_album(Some(song.album()));
-
-            let length = Time::from_secs(song.duration() as i64);
-            metadata.set_length(Some(length));
-
-            // MPRIS should really support passing a bytes buffer for
-            // the cover art, instead of requiring this ridiculous
-            // charade
-            if let Some(cache) = song.cover_cache() {
-                let file = gio::File::for_path(cache);
-                match file.query_info(
-                    "standard::type",
-                    gio::FileQueryInfoFlags::NONE,
-                    gio::Cancellable::NONE,
-                ) {
-                    Ok(info) if info.file_type() == gio::FileType::Regular => {
-                        metadata.set_art_url(Some(file.uri()));
-                    }
-                    _ => metadata.set_art_url(None::<String>),
-                }
-            }
-
-            self.song.replace(Some(song));
-        }
+        let metadata = match *self.song.borrow() {
+            Some(ref song) => song_metadata(song),
+            None => Metadata::new(),
+        };
 
-        glib::spawn_future_local(clone!(
-            #[weak(rename_to = mpris)]
-            self.mpris,
-            async move {
-                if let Some(mpris) = mpris.get() {
-                    if let Err(err) = mpris.set_metadata(metadata).await {
-                        error!("Unable to set MPRIS metadata: {err:?}");
-                    }
-                }
-            }
-        ));
+        self.state.metadata.replace(metadata.clone());
+        emit_properties(&self.server, vec![Property::Metadata(metadata)]);
     }
 }
 
@@ -115,20 +952,12 @@ impl Controller for MprisController {
             _ => PlaybackStatus::Stopped,
         };
 
-        glib::spawn_future_local(clone!(
-            #[weak(rename_to = mpris)]
-            self.mpris,
-            async move {
-                if let Some(mpris) = mpris.get() {
-                    if let Err(err) = mpris.set_can_play(true).await {
-                        error!("Unable to set MPRIS play capability: {err:?}");
-                    }
-                    if let Err(err) = mpris.set_playback_status(status).await {
-                        error!("Unable to set MPRIS playback status: {err:?}");
-                    }
-                }
-            }
-        ));
+        self.state.can_play.set(true);
+        self.state.playback_status.set(status);
+        emit_properties(
+            &self.server,
+            vec![Property::CanPlay(true), Property::PlaybackStatus(status)],
+        );
     }
 
     fn set_song(&self, song: &Song) {
@@ -136,24 +965,32 @@ impl Controller for MprisController {
         self.update_metadata();
     }
 
-    fn set_position(&self, position: u64, notify: bool) {
-        let pos = Time::from_secs(position as i64);
-        if let Some(mpris) = self.mpris.get() {
-            mpris.set_position(pos);
-        }
-        if notify {
-            glib::spawn_future_local(clone!(
-                #[weak(rename_to = mpris)]
-                self.mpris,
-                async move {
-                    if let Some(mpris) = mpris.get() {
-                        if let Err(err) = mpris.seeked(pos).await {
-                            error!("Unable to emit MPRIS Seeked: {err:?}");
-                        }
-                    }
+    fn update_song(&self, song: &Song) {
+        self.update_metadata();
+
+        let signal = TrackListSignal::TrackMetadataChanged {
+            track_id: track_id(song),
+            metadata: song_metadata(song),
+        };
+        emit_track_list_signals(&self.server, vec![signal]);
+    }
+
+    // The position advancing with playback is not announced
+    fn set_position(&self, position: u64) {
+        self.state.position.set(position);
+    }
+
+    fn seeked(&self, position: u64) {
+        self.state.position.set(position);
+
+        let pos = Time::from_millis(position as i64);
+        glib::spawn_future_local(clone!(@weak self.server as server => async move {
+            if let Some(server) = server.get() {
+                if let Err(err) = server.emit(Signal::Seeked { position: pos }).await {
+                    error!("Unable to emit MPRIS Seeked: {err:?}");
                 }
-            ));
-        }
+            }
+        }));
     }
 
     fn set_repeat_mode(&self, repeat: RepeatMode) {
@@ -163,129 +1000,373 @@ impl Controller for MprisController {
             RepeatMode::RepeatAll => LoopStatus::Playlist,
         };
 
-        glib::spawn_future_local(clone!(
-            #[weak(rename_to = mpris)]
-            self.mpris,
-            async move {
-                if let Some(mpris) = mpris.get() {
-                    if let Err(err) = mpris.set_loop_status(status).await {
-                        error!("Unable to set MPRIS loop status: {err:?}");
-                    }
-                }
-            }
-        ));
+        self.state.loop_status.set(status);
+        emit_properties(&self.server, vec![Property::LoopStatus(status)]);
+
+        // Repeating the queue lets us skip past the last song
+        update_navigation(&self.server, &self.state, &self.queue);
     }
-}
 
-fn setup_signals(sender: Sender<PlaybackAction>, mpris: &Player) {
-    mpris.connect_play_pause(clone!(
-        #[strong]
-        sender,
-        move |player| {
-            match player.playback_status() {
-                PlaybackStatus::Paused => {
-                    if let Err(e) = sender.send_blocking(PlaybackAction::Play) {
-                        error!("Unable to send Play: {e}");
-                    }
-                }
-                PlaybackStatus::Stopped => {
-                    if let Err(e) = sender.send_blocking(PlaybackAction::Stop) {
-                        error!("Unable to send Stop: {e}");
-                    }
-                }
-                _ => {
-                    if let Err(e) = sender.send_blocking(PlaybackAction::Pause) {
-                        error!("Unable to send Pause: {e}");
-                    }
-                }
-            };
-        }
-    ));
-
-    mpris.connect_play(clone!(
-        #[strong]
-        sender,
-        move |_| {
-            if let Err(e) = sender.send_blocking(PlaybackAction::Play) {
-                error!("Unable to send Play: {e}");
-            }
-        }
-    ));
-
-    mpris.connect_stop(clone!(
-        #[strong]
-        sender,
-        move |_| {
-            if let Err(e) = sender.send_blocking(PlaybackAction::Stop) {
-                error!("Unable to send Stop: {e}");
-            }
-        }
-    ));
-
-    mpris.connect_pause(clone!(
-        #[strong]
-        sender,
-        move |_| {
-            if let Err(e) = sender.send_blocking(PlaybackAction::Pause) {
-                error!("Unable to send Pause: {e}");
-            }
-        }
-    ));
-
-    mpris.connect_previous(clone!(
-        #[strong]
-        sender,
-        move |_| {
-            if let Err(e) = sender.send_blocking(PlaybackAction::SkipPrevious) {
-                error!("Unable to send SkipPrevious: {e}");
-            }
-        }
-    ));
-
-    mpris.connect_next(clone!(
-        #[strong]
-        sender,
-        move |_| {
-            if let Err(e) = sender.send_blocking(PlaybackAction::SkipNext) {
-                error!("Unable to send SkipNext: {e}");
-            }
-        }
-    ));
-
-    mpris.connect_raise(clone!(
-        #[strong]
-        sender,
-        move |_| {
-            if let Err(e) = sender.send_blocking(PlaybackAction::Raise) {
-                error!("Unable to send Raise: {e}");
-            }
-        }
-    ));
-
-    mpris.connect_set_loop_status(clone!(
-        #[strong]
-        sender,
-        move |_, status| {
-            let mode = match status {
-                LoopStatus::None => RepeatMode::Consecutive,
-                LoopStatus::Track => RepeatMode::RepeatOne,
-                LoopStatus::Playlist => RepeatMode::RepeatAll,
-            };
-
-            if let Err(e) = sender.send_blocking(PlaybackAction::Repeat(mode)) {
-                error!("Unable to send Repeat({mode}): {e}");
-            }
+    fn set_shuffled(&self, shuffled: bool) {
+        self.state.shuffle.set(shuffled);
+        emit_properties(&self.server, vec![Property::Shuffle(shuffled)]);
+    }
+
+    fn set_volume(&self, volume: f64) {
+        if self.state.volume.replace(volume) != volume {
+            emit_properties(&self.server, vec![Property::Volume(volume)]);
         }
-    ));
-
-    mpris.connect_seek(clone!(
-        #[strong]
-        sender,
-        move |_, offset| {
-            let offset = offset.as_secs();
-            if let Err(e) = sender.send_blocking(PlaybackAction::Seek(offset)) {
-                error!("Unable to send Seek({offset}): {e}");
-            }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object_path() {
+        assert_eq!(
+            object_path(TRACK_PATH_PREFIX, "0b5a2f4c-7d1e-4c55-9a0e-5f3c1d2e4b6a"),
+            "/io/bassi/Amberol/Track/0b5a2f4c_7d1e_4c55_9a0e_5f3c1d2e4b6a"
+        );
+        assert_eq!(
+            object_path(TRACK_PATH_PREFIX, "abc_DEF_123"),
+            "/io/bassi/Amberol/Track/abc_DEF_123"
+        );
+        assert_eq!(
+            object_path(PLAYLIST_PATH_PREFIX, "a/b.c é"),
+            "/io/bassi/Amberol/Playlist/a_b_c__"
+        );
+    }
+
+    #[test]
+    fn test_track_serial() {
+        let song = Song::default();
+        let copy = Song::default();
+        assert_ne!(track_id(&song), track_id(&copy));
+        assert_eq!(track_serial(&track_id(&song)), Some(song.serial()));
+        assert_eq!(track_serial(&TrackId::NO_TRACK), None);
+
+        let id = TrackId::try_from("/io/bassi/Amberol/Track/abc_DEF_123").unwrap();
+        assert_eq!(track_serial(&id), None);
+    }
+
+    #[test]
+    fn test_playlists_page() {
+        let playlists = vec![
+            (SavedPlaylist::Current, "Current Playlist".to_string()),
+            (SavedPlaylist::Smart("a".to_string()), "rock".to_string()),
+            (SavedPlaylist::Smart("b".to_string()), "Jazz".to_string()),
+        ];
+        let names = |page: Vec<(SavedPlaylist, String)>| -> Vec<String> {
+            page.into_iter().map(|(_, name)| name).collect()
+        };
+
+        assert_eq!(
+            names(playlists_page(playlists.clone(), 0, 10, false, false)),
+            vec!["Current Playlist", "rock", "Jazz"]
+        );
+        assert_eq!(
+            names(playlists_page(playlists.clone(), 0, 10, true, false)),
+            vec!["Current Playlist", "Jazz", "rock"]
+        );
+        assert_eq!(
+            names(playlists_page(playlists.clone(), 0, 10, true, true)),
+            vec!["rock", "Jazz", "Current Playlist"]
+        );
+        assert_eq!(
+            names(playlists_page(playlists.clone(), 1, 1, false, false)),
+            vec!["rock"]
+        );
+        assert!(playlists_page(playlists, 5, 10, false, false).is_empty());
+    }
+
+    #[test]
+    fn test_track_list_change() {
+        assert_eq!(track_list_change(5, 0, 0, 0), None);
+
+        assert_eq!(
+            track_list_change(5, 5, 0, 2),
+            Some(TrackListChange::Added(5..7))
+        );
+        assert_eq!(
+            track_list_change(0, 0, 0, 3),
+            Some(TrackListChange::Added(0..3))
+        );
+        assert_eq!(
+            track_list_change(5, 1, 2, 0),
+            Some(TrackListChange::Removed(1..3))
+        );
+
+        // Shuffling, clearing, and large changes replace the whole list
+        assert_eq!(
+            track_list_change(5, 0, 5, 5),
+            Some(TrackListChange::Replaced)
+        );
+        assert_eq!(
+            track_list_change(5, 0, 5, 0),
+            Some(TrackListChange::Replaced)
+        );
+        assert_eq!(
+            track_list_change(5, 5, 0, 100),
+            Some(TrackListChange::Replaced)
+        );
+        // The model is out of sync with what we announced
+        assert_eq!(
+            track_list_change(2, 1, 3, 0),
+            Some(TrackListChange::Replaced)
+        );
+    }
+
+    #[test]
+    fn test_seek_destination() {
+        assert_eq!(
+            seek_destination(10_000_000, 5_000_000, 60_000_000),
+            Some(15_000_000)
+        );
+        assert_eq!(
+            seek_destination(10_000_000, -5_000_000, 60_000_000),
+            Some(5_000_000)
+        );
+        assert_eq!(
+            seek_destination(10_000_000, -20_000_000, 60_000_000),
+            Some(0)
+        );
+        assert_eq!(
+            seek_destination(10_000_000, 50_000_000, 60_000_000),
+            Some(60_000_000)
+        );
+        assert_eq!(seek_destination(10_000_000, 60_000_000, 60_000_000), None);
+        assert_eq!(seek_destination(10_000_000, i64::MAX, 60_000_000), None);
+    }
+
+    #[test]
+    fn test_user_rating() {
+        for stars in 0..=MAX_RATING {
+            assert_eq!(stars_from_user_rating(user_rating(stars)), stars);
         }
-    ));
+
+        assert_eq!(user_rating(MAX_RATING + 1), 1.0);
+        assert_eq!(stars_from_user_rating(0.5), 3);
+        assert_eq!(stars_from_user_rating(0.45), 2);
+        assert_eq!(stars_from_user_rating(2.0), MAX_RATING);
+    }
+
+    #[test]
+    fn test_queue_navigation() {
+        assert_eq!(
+            queue_navigation(0, None, RepeatMode::RepeatAll),
+            (false, false)
+        );
+        assert_eq!(
+            queue_navigation(3, None, RepeatMode::Consecutive),
+            (true, false)
+        );
+        assert_eq!(
+            queue_navigation(3, Some(0), RepeatMode::Consecutive),
+            (true, false)
+        );
+        assert_eq!(
+            queue_navigation(3, Some(1), RepeatMode::Consecutive),
+            (true, true)
+        );
+        assert_eq!(
+            queue_navigation(3, Some(2), RepeatMode::Consecutive),
+            (false, true)
+        );
+        assert_eq!(
+            queue_navigation(3, Some(2), RepeatMode::RepeatAll),
+            (true, true)
+        );
+        assert_eq!(
+            queue_navigation(3, Some(2), RepeatMode::RepeatOne),
+            (true, true)
+        );
+        assert_eq!(
+            queue_navigation(1, Some(0), RepeatMode::Consecutive),
+            (false, false)
+        );
+    }
+
+    // The D-Bus tests talk to the interfaces over a session bus, so they
+    // are ignored by default; run them with
+    // `dbus-run-session -- cargo test -- --ignored` to get a private one
+    fn test_player(
+        sender: Sender<PlaybackAction>,
+        playlist_dirs: PlaylistDirs,
+    ) -> (Server, Rc<MprisState>, MprisPlayer) {
+        let server: Server = Rc::new(OnceCell::new());
+        let state = Rc::new(MprisState::default());
+        let player = MprisPlayer {
+            sender,
+            queue: Queue::default(),
+            state: state.clone(),
+            playlist_dirs,
+            server: Rc::downgrade(&server),
+        };
+
+        (server, state, player)
+    }
+
+    // Every test owns a different name, as they run in parallel
+    async fn serve(
+        server: &Server,
+        player: MprisPlayer,
+        name: &str,
+        interface: &'static str,
+    ) -> zbus::Proxy<'static> {
+        let bus_name = format!("{}.Test{}{}", APPLICATION_ID, name, std::process::id());
+        let s = LocalServer::new_with_all(&bus_name, player).await.unwrap();
+        glib::spawn_future_local(s.run());
+        let _ = server.set(s);
+
+        let conn = zbus::Connection::session().await.unwrap();
+        zbus::Proxy::new(
+            &conn,
+            format!("org.mpris.MediaPlayer2.{}", bus_name),
+            "/org/mpris/MediaPlayer2",
+            interface,
+        )
+        .await
+        .unwrap()
+    }
+
+    #[test]
+    #[ignore = "needs a session bus"]
+    fn test_player_dbus() {
+        let (sender, receiver) = async_channel::unbounded();
+        glib::MainContext::new().block_on(async {
+            let (server, state, player) = test_player(sender, PlaylistDirs::default());
+
+            let track = TrackId::try_from("/io/bassi/Amberol/Track/current").unwrap();
+            let mut metadata = Metadata::new();
+            metadata.set_trackid(Some(track.clone()));
+            metadata.set_length(Some(Time::from_secs(120)));
+            state.metadata.replace(metadata);
+            state.position.set(30_000);
+            state.volume.set(0.5);
+
+            let proxy = serve(&server, player, "Player", "org.mpris.MediaPlayer2.Player").await;
+
+            let volume: f64 = proxy.get_property("Volume").await.unwrap();
+            assert_eq!(volume, 0.5);
+            let shuffle: bool = proxy.get_property("Shuffle").await.unwrap();
+            assert!(!shuffle);
+            let can_go_next: bool = proxy.get_property("CanGoNext").await.unwrap();
+            assert!(!can_go_next);
+
+            proxy.set_property("Shuffle", true).await.unwrap();
+            assert!(matches!(
+                receiver.try_recv(),
+                Ok(PlaybackAction::Shuffle(true))
+            ));
+
+            proxy.set_property("Volume", 1.5).await.unwrap();
+            assert!(matches!(
+                receiver.try_recv(),
+                Ok(PlaybackAction::SetVolume(v)) if v == 1.0
+            ));
+
+            // Relative seeks stop at the start of the song, and skip to
+            // the next one past its end
+            let () = proxy.call("Seek", &(-40_000_000i64,)).await.unwrap();
+            assert!(matches!(receiver.try_recv(), Ok(PlaybackAction::Seek(0))));
+            let () = proxy.call("Seek", &(15_000_000i64,)).await.unwrap();
+            assert!(matches!(
+                receiver.try_recv(),
+                Ok(PlaybackAction::Seek(45_000))
+            ));
+            let () = proxy.call("Seek", &(250_000i64,)).await.unwrap();
+            assert!(matches!(
+                receiver.try_recv(),
+                Ok(PlaybackAction::Seek(30_250))
+            ));
+            let () = proxy.call("Seek", &(100_000_000i64,)).await.unwrap();
+            assert!(matches!(receiver.try_recv(), Ok(PlaybackAction::SkipNext)));
+
+            // Stale tracks and positions past the end are ignored
+            let stale = TrackId::try_from("/io/bassi/Amberol/Track/stale").unwrap();
+            let () = proxy
+                .call("SetPosition", &(stale, 10_000_000i64))
+                .await
+                .unwrap();
+            let () = proxy
+                .call("SetPosition", &(track.clone(), 200_000_000i64))
+                .await
+                .unwrap();
+            assert!(receiver.try_recv().is_err());
+            let () = proxy
+                .call("SetPosition", &(track, 60_500_000i64))
+                .await
+                .unwrap();
+            assert!(matches!(
+                receiver.try_recv(),
+                Ok(PlaybackAction::Seek(60_500))
+            ));
+
+            let res: zbus::Result<()> = proxy
+                .call("OpenUri", &("https://example.com/song.mp3",))
+                .await;
+            assert!(res.is_err());
+            assert!(receiver.try_recv().is_err());
+        });
+    }
+
+    #[test]
+    #[ignore = "needs a session bus"]
+    fn test_playlists_dbus() {
+        // Point the cache and data directories at a known state, with the
+        // queue of the last session and no smart playlists
+        let root = std::env::temp_dir().join(format!("amberol-mpris-{}", std::process::id()));
+        let pls_dir = root.join("cache").join("amberol").join("playlists");
+        std::fs::create_dir_all(&pls_dir).unwrap();
+        std::fs::write(
+            pls_dir.join("current.pls"),
+            "[playlist]\nNumberOfEntries=0\n",
+        )
+        .unwrap();
+        let dirs = PlaylistDirs {
+            cache: root.join("cache"),
+            data: root.join("data"),
+        };
+
+        let (sender, receiver) = async_channel::unbounded();
+        glib::MainContext::new().block_on(async {
+            let (server, _, player) = test_player(sender, dirs);
+            let playlists = serve(
+                &server,
+                player,
+                "Playlists",
+                "org.mpris.MediaPlayer2.Playlists",
+            )
+            .await;
+
+            let count: u32 = playlists.get_property("PlaylistCount").await.unwrap();
+            assert_eq!(count, 1);
+
+            let res: Vec<(zbus::zvariant::OwnedObjectPath, String, String)> = playlists
+                .call("GetPlaylists", &(0u32, 10u32, "Alphabetical", false))
+                .await
+                .unwrap();
+            assert_eq!(res.len(), 1);
+            assert_eq!(res[0].0.as_str(), "/io/bassi/Amberol/Playlist/Current");
+
+            let current = res[0].0.clone();
+            let () = playlists
+                .call("ActivatePlaylist", &(current,))
+                .await
+                .unwrap();
+            assert!(matches!(
+                receiver.try_recv(),
+                Ok(PlaybackAction::ActivatePlaylist(SavedPlaylist::Current))
+            ));
+
+            let unknown =
+                zbus::zvariant::ObjectPath::try_from("/io/bassi/Amberol/Playlist/Nope").unwrap();
+            let res: zbus::Result<()> = playlists.call("ActivatePlaylist", &(unknown,)).await;
+            assert!(res.is_err());
+        });
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
 }
@@ -0,0 +1,126 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{collections::HashMap, path::PathBuf, sync::Mutex};
+
+use gtk::glib;
+use log::debug;
+use once_cell::sync::OnceCell;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PlayRecord {
+    play_count: u32,
+    last_played: Option<i64>,
+}
+
+impl PlayRecord {
+    pub fn play_count(&self) -> u32 {
+        self.play_count
+    }
+
+    // The UNIX timestamp of the last time the song started playing
+    pub fn last_played(&self) -> Option<i64> {
+        self.last_played
+    }
+}
+
+// PlayHistory keeps track of how many times a song was played, and
+// when; the history is keyed by the song URI, and it is stored in a
+// key file inside the user data directory. Plays are only recorded in
+// memory; the owner is responsible for calling flush() to write them
+#[derive(Debug)]
+pub struct PlayHistory {
+    entries: HashMap<String, PlayRecord>,
+    dirty: bool,
+}
+
+impl PlayHistory {
+    pub fn global() -> &'static Mutex<PlayHistory> {
+        static HISTORY: OnceCell<Mutex<PlayHistory>> = OnceCell::new();
+
+        HISTORY.get_or_init(|| {
+            let h = PlayHistory::load();
+            Mutex::new(h)
+        })
+    }
+
+    fn history_file() -> PathBuf {
+        let mut history_file = glib::user_data_dir();
+        history_file.push("amberol");
+        history_file.push("history.ini");
+
+        history_file
+    }
+
+    fn load() -> Self {
+        let mut entries = HashMap::new();
+
+        let keyfile = glib::KeyFile::new();
+        if let Err(e) = keyfile.load_from_file(Self::history_file(), glib::KeyFileFlags::NONE) {
+            debug!("Unable to load play history: {e}");
+            return PlayHistory {
+                entries,
+                dirty: false,
+            };
+        }
+
+        for group in keyfile.groups() {
+            let play_count = keyfile.uint64(&group, "PlayCount").unwrap_or(0) as u32;
+            let last_played = keyfile.int64(&group, "LastPlayed").ok();
+            entries.insert(
+                group.to_string(),
+                PlayRecord {
+                    play_count,
+                    last_played,
+                },
+            );
+        }
+
+        PlayHistory {
+            entries,
+            dirty: false,
+        }
+    }
+
+    fn store(&self) {
+        let keyfile = glib::KeyFile::new();
+        for (uri, record) in &self.entries {
+            keyfile.set_uint64(uri, "PlayCount", record.play_count as u64);
+            if let Some(last_played) = record.last_played {
+                keyfile.set_int64(uri, "LastPlayed", last_played);
+            }
+        }
+
+        let history_file = Self::history_file();
+        if let Some(parent) = history_file.parent() {
+            glib::mkdir_with_parents(parent, 0o755);
+        }
+
+        match keyfile.save_to_file(&history_file) {
+            Ok(_) => debug!("Play history updated to: {:?}", &history_file),
+            Err(e) => debug!("Unable to save play history: {e}"),
+        }
+    }
+
+    pub fn record(&mut self, uri: &str) {
+        let now = glib::DateTime::now_utc().map(|d| d.to_unix()).ok();
+
+        let record = self.entries.entry(uri.to_string()).or_default();
+        record.play_count += 1;
+        record.last_played = now;
+
+        self.dirty = true;
+    }
+
+    // Writes the history to disk, if it changed since the last time
+    pub fn flush(&mut self) {
+        if self.dirty {
+            self.store();
+            self.dirty = false;
+        }
+    }
+
+    pub fn lookup(&self, uri: &str) -> PlayRecord {
+        self.entries.get(uri).copied().unwrap_or_default()
+    }
+}
@@ -15,8 +15,8 @@ use log::{debug, error};
 use crate::{
     application::ApplicationAction,
     audio::{
-        Controller, CoverCache, GstBackend, InhibitController, MprisController, PlayerState, Queue,
-        Song, WaveformGenerator,
+        Controller, CoverCache, GstBackend, HistoryController, InhibitController, MprisController,
+        PlayerState, Queue, Song, SpectrogramGenerator, WaveformGenerator, WaveformPrecomputer,
     },
 };
 
@@ -28,15 +28,50 @@ pub enum PlaybackAction {
     SkipPrevious,
     SkipNext,
 
+    // Positions, in milliseconds
     UpdatePosition(u64),
     VolumeChanged(f64),
+    SetVolume(f64),
     Repeat(RepeatMode),
+    Shuffle(bool),
+    // Absolute position, in milliseconds
     Seek(u64),
+    // In milliseconds
+    SeekDone(u64),
     PlayNext,
+    // Songs in the queue, by serial number
+    SkipTo(u64),
+    Remove(u64),
+    // The URI of a song, where to add it, and whether to play it
+    AddTrack(String, TrackPosition, bool),
+    // The URI of a song to play, from the queue if it is already there
+    OpenTrack(String),
+    ActivatePlaylist(SavedPlaylist),
+    // The URI of a song, and its rating
+    SetRating(String, u32),
 
     Raise,
 }
 
+// Where to add a song to the queue
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrackPosition {
+    Start,
+    // After the song with the given serial number, or at the end if
+    // the song is not in the queue any more
+    After(u64),
+    End,
+}
+
+// The playlists stored on disk, which can be played again
+#[derive(Clone, Debug, PartialEq)]
+pub enum SavedPlaylist {
+    // The queue of the last session
+    Current,
+    // A smart playlist, by id
+    Smart(String),
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Default)]
 pub enum PlaybackState {
     #[default]
@@ -64,7 +99,7 @@ impl Display for RepeatMode {
     }
 }
 
-#[derive(Clone, Copy, Debug, glib::Enum, PartialEq)]
+#[derive(Clone, Copy, Debug, Default, glib::Enum, PartialEq)]
 #[enum_type(name = "AmberolReplayGainMode")]
 pub enum ReplayGainMode {
     #[enum_value(name = "album")]
@@ -72,15 +107,10 @@ pub enum ReplayGainMode {
     #[enum_value(name = "track")]
     Track,
     #[enum_value(name = "off")]
+    #[default]
     Off,
 }
 
-impl Default for ReplayGainMode {
-    fn default() -> Self {
-        Self::Off
-    }
-}
-
 impl From<i32> for ReplayGainMode {
     fn from(value: i32) -> Self {
         match value {
@@ -116,6 +146,7 @@ pub struct AudioPlayer {
     queue: Queue,
     state: PlayerState,
     waveform_generator: WaveformGenerator,
+    spectrogram_generator: SpectrogramGenerator,
 }
 
 impl fmt::Debug for AudioPlayer {
@@ -131,18 +162,28 @@ impl AudioPlayer {
 
         let mut controllers: Vec<Box<dyn Controller>> = Vec::new();
 
-        let mpris_controller = MprisController::new(sender.clone());
+        let queue = Queue::default();
+
+        let mpris_controller = MprisController::new(sender.clone(), &queue);
         controllers.push(Box::new(mpris_controller));
 
         let inhibit_controller = InhibitController::new();
         controllers.push(Box::new(inhibit_controller));
 
+        let history_controller = HistoryController::new();
+        controllers.push(Box::new(history_controller));
+
         let waveform_generator = WaveformGenerator::new();
         controllers.push(Box::new(waveform_generator.clone()));
 
+        let spectrogram_generator = SpectrogramGenerator::new();
+        controllers.push(Box::new(spectrogram_generator.clone()));
+
+        let waveform_precomputer = WaveformPrecomputer::new(&queue);
+        controllers.push(Box::new(waveform_precomputer));
+
         let backend = GstBackend::new(sender);
 
-        let queue = Queue::default();
         let state = PlayerState::default();
 
         let res = Rc::new(Self {
@@ -153,6 +194,7 @@ impl AudioPlayer {
             queue,
             state,
             waveform_generator,
+            spectrogram_generator,
         });
 
         res.clone().setup_channel();
@@ -182,10 +224,33 @@ impl AudioPlayer {
             PlaybackAction::SkipNext => self.skip_next(),
             PlaybackAction::UpdatePosition(pos) => self.update_position(pos),
             PlaybackAction::VolumeChanged(vol) => self.update_volume(vol),
+            PlaybackAction::SetVolume(vol) => self.set_volume(vol),
             PlaybackAction::PlayNext => self.play_next(),
             PlaybackAction::Raise => self.present(),
             PlaybackAction::Repeat(mode) => self.update_repeat_mode(mode),
-            PlaybackAction::Seek(pos) => self.seek_position_abs(pos),
+            PlaybackAction::Shuffle(shuffled) => self.set_shuffled(shuffled),
+            PlaybackAction::Seek(pos) => self.seek_position_ms(pos),
+            PlaybackAction::SeekDone(pos) => self.seek_done(pos),
+            PlaybackAction::SkipTo(serial) => {
+                if let Some(pos) = self.queue.song_position(serial) {
+                    self.skip_to(pos);
+                }
+            }
+            PlaybackAction::Remove(serial) => {
+                if let Some(song) = self
+                    .queue
+                    .song_position(serial)
+                    .and_then(|pos| self.queue.song_at(pos))
+                {
+                    self.remove_song(&song);
+                }
+            }
+            PlaybackAction::AddTrack(uri, position, set_as_current) => {
+                self.add_track(uri, position, set_as_current)
+            }
+            PlaybackAction::OpenTrack(uri) => self.open_track(uri),
+            PlaybackAction::ActivatePlaylist(playlist) => self.activate_playlist(playlist),
+            PlaybackAction::SetRating(uri, rating) => self.set_rating(uri, rating),
             // _ => debug!("Received action {:?}", action),
         }
 
@@ -430,13 +495,13 @@ impl AudioPlayer {
     }
 
     pub fn seek_position_rel(&self, position: f64) {
-        let duration = self.state.duration() as f64;
+        let duration = self.state.duration() as f64 * 1000.0;
         let pos = (duration * position).clamp(0.0, duration);
         self.backend.seek_position(pos as u64);
     }
 
-    pub fn seek_position_abs(&self, position: u64) {
-        let pos = u64::max(position, self.state.duration());
+    pub fn seek_position_ms(&self, position: u64) {
+        let pos = u64::min(position, self.state.duration() * 1000);
         self.backend.seek_position(pos);
     }
 
@@ -452,21 +517,50 @@ impl AudioPlayer {
         &self.waveform_generator
     }
 
+    pub fn spectrogram_generator(&self) -> &SpectrogramGenerator {
+        &self.spectrogram_generator
+    }
+
     pub fn set_current_song(&self, song: Option<Song>) {
         self.state.set_current_song(song);
     }
 
+    // Propagates the changes in the metadata of the given song, if it is
+    // the one currently playing
+    pub fn update_song(&self, song: &Song) {
+        let is_current = self.state.current_song().is_some_and(|s| s == *song);
+        if is_current {
+            self.state.update_current_song();
+
+            for c in &self.controllers {
+                c.update_song(song);
+            }
+        }
+    }
+
     fn update_position(&self, position: u64) {
-        self.state.set_position(position);
+        self.state.set_position_ms(position);
 
         for c in &self.controllers {
             c.set_position(position);
         }
     }
 
+    fn seek_done(&self, position: u64) {
+        self.update_position(position);
+
+        for c in &self.controllers {
+            c.seeked(position);
+        }
+    }
+
     fn update_volume(&self, volume: f64) {
         debug!("Updating volume to: {}", &volume);
         self.state.set_volume(volume);
+
+        for c in &self.controllers {
+            c.set_volume(volume);
+        }
     }
 
     pub fn set_volume(&self, volume: f64) {
@@ -487,6 +581,24 @@ impl AudioPlayer {
         }
     }
 
+    pub fn set_shuffled(&self, shuffled: bool) {
+        if shuffled == self.queue.is_shuffled() {
+            return;
+        }
+
+        let reset_song = self.queue.is_first_song() && !self.state.playing();
+
+        self.queue.set_shuffled(shuffled);
+
+        if reset_song {
+            self.skip_to(0);
+        }
+
+        for c in &self.controllers {
+            c.set_shuffled(shuffled);
+        }
+    }
+
     fn update_repeat_mode(&self, repeat: RepeatMode) {
         if repeat != self.queue.repeat_mode() {
             self.queue.set_repeat_mode(repeat);
@@ -503,6 +615,46 @@ impl AudioPlayer {
         }
     }
 
+    // Loading the songs of a playlist is up to the UI
+    fn activate_playlist(&self, playlist: SavedPlaylist) {
+        if let Err(e) = self
+            .app_sender
+            .send_blocking(ApplicationAction::ActivatePlaylist(playlist))
+        {
+            error!("Unable to send ActivatePlaylist: {e}");
+        }
+    }
+
+    // Writing the tags is up to the UI as well
+    fn set_rating(&self, uri: String, rating: u32) {
+        if let Err(e) = self
+            .app_sender
+            .send_blocking(ApplicationAction::SetRating(uri, rating))
+        {
+            error!("Unable to send SetRating: {e}");
+        }
+    }
+
+    // Loading the song, and storing the queue, is up to the UI too
+    fn add_track(&self, uri: String, position: TrackPosition, set_as_current: bool) {
+        if let Err(e) = self.app_sender.send_blocking(ApplicationAction::AddTrack(
+            uri,
+            position,
+            set_as_current,
+        )) {
+            error!("Unable to send AddTrack: {e}");
+        }
+    }
+
+    fn open_track(&self, uri: String) {
+        if let Err(e) = self
+            .app_sender
+            .send_blocking(ApplicationAction::OpenTrack(uri))
+        {
+            error!("Unable to send OpenTrack: {e}");
+        }
+    }
+
     pub fn clear_queue(&self) {
         self.stop();
         self.state.set_current_song(None);
@@ -8,7 +8,7 @@ use gtk::{gio, glib, prelude::*, subclass::prelude::*};
 use crate::audio::{RepeatMode, ShuffleListModel, Song};
 
 mod imp {
-    use glib::{ParamSpec, ParamSpecEnum, ParamSpecObject, ParamSpecUInt, Value};
+    use glib::{ParamSpec, ParamSpecBoolean, ParamSpecEnum, ParamSpecObject, ParamSpecUInt, Value};
     use once_cell::sync::Lazy;
 
     use super::*;
@@ -52,6 +52,7 @@ mod imp {
                         .read_only()
                         .build(),
                     ParamSpecUInt::builder("n-songs").read_only().build(),
+                    ParamSpecBoolean::builder("shuffled").read_only().build(),
                 ]
             });
 
@@ -63,6 +64,7 @@ mod imp {
                 "current" => self.obj().current_song().to_value(),
                 "repeat-mode" => self.repeat_mode.get().to_value(),
                 "n-songs" => self.store.n_items().to_value(),
+                "shuffled" => self.shuffled.get().to_value(),
                 _ => unimplemented!(),
             }
         }
@@ -100,6 +102,11 @@ impl Queue {
         None
     }
 
+    // The position of the song with the given serial number
+    pub fn song_position(&self, serial: u64) -> Option<u32> {
+        (0..self.n_songs()).find(|pos| self.song_at(*pos).map(|s| s.serial()) == Some(serial))
+    }
+
     pub fn current_song(&self) -> Option<Song> {
         if let Some(pos) = self.imp().current_pos.get() {
             return self.song_at(pos);
@@ -146,21 +153,42 @@ impl Queue {
         self.notify("n-songs");
     }
 
+    // Inserts the song at the given position, and returns the position it
+    // ended up at; while the queue is shuffled, songs are always appended
+    pub fn insert_song(&self, position: u32, song: &Song) -> u32 {
+        if self.imp().model.shuffled() {
+            self.imp().store.append(song);
+            self.notify("n-songs");
+            return self.n_songs() - 1;
+        }
+
+        let position = position.min(self.n_songs());
+        // Keep pointing at the same song
+        if let Some(current_pos) = self.imp().current_pos.get() {
+            if position <= current_pos {
+                self.imp().current_pos.replace(Some(current_pos + 1));
+            }
+        }
+
+        self.imp().store.insert(position, song);
+        self.notify("n-songs");
+        position
+    }
+
     pub fn remove_song(&self, song: &Song) {
         let was_shuffled = self.imp().model.shuffled();
         let n_songs = self.n_songs();
-        for pos in 0..n_songs {
-            let s = self
-                .imp()
-                .store
-                .item(pos)
-                .unwrap()
-                .downcast::<Song>()
-                .unwrap();
-            if s.equals(song) {
-                self.imp().store.remove(pos);
-                break;
-            }
+        let songs: Vec<Song> = (0..n_songs)
+            .filter_map(|pos| self.imp().store.item(pos).and_downcast::<Song>())
+            .collect();
+        // The same song can be queued more than once, so we prefer the
+        // entry itself to a copy of it
+        let position = songs
+            .iter()
+            .position(|s| s == song)
+            .or_else(|| songs.iter().position(|s| s.equals(song)));
+        if let Some(pos) = position {
+            self.imp().store.remove(pos as u32);
         }
 
         if n_songs != self.n_songs() {
@@ -282,6 +310,8 @@ impl Queue {
                 self.imp().model.unshuffle();
                 self.set_current_song(current_song);
             }
+
+            self.notify("shuffled");
         }
     }
 
@@ -5,36 +5,60 @@ use std::{
     cell::{Cell, RefCell},
     fmt::{self, Display, Formatter},
     path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
     time::Instant,
 };
 
-use glib::{ParamSpec, ParamSpecBoolean, ParamSpecObject, ParamSpecString, ParamSpecUInt, Value};
+use glib::{
+    ParamSpec, ParamSpecBoolean, ParamSpecBoxed, ParamSpecObject, ParamSpecString, ParamSpecUInt,
+    Value,
+};
 use gtk::{gdk, gio, glib, prelude::*, subclass::prelude::*};
 use lofty::{Accessor, TaggedFileExt};
 use log::{debug, warn};
 use once_cell::sync::Lazy;
-use sha2::{Digest, Sha256};
 
 use crate::{
-    audio::cover_cache::{CoverArt, CoverCache},
+    audio::{
+        cover_cache::{CoverArt, CoverCache},
+        identity::{self, StreamInfo},
+        metadata_index::{FileStamp, MetadataIndex},
+        tags::{self, tag_rating, MAX_RATING},
+    },
     i18n::i18n,
 };
 
+// The serial number of the next song object
+static NEXT_SERIAL: AtomicU64 = AtomicU64::new(1);
+
 #[derive(Debug, Clone)]
 pub struct SongData {
-    artist: Option<String>,
+    artists: Vec<String>,
     title: Option<String>,
     album: Option<String>,
+    album_artist: Option<String>,
+    track_number: Option<u32>,
+    disc_number: Option<u32>,
+    genre: Option<String>,
+    year: Option<u32>,
+    composer: Option<String>,
+    comment: Option<String>,
+    rating: Option<u32>,
     cover_art: Option<CoverArt>,
     cover_uuid: Option<String>,
     uuid: Option<String>,
     duration: u64,
+    codec: Option<String>,
+    bitrate: Option<u32>,
+    sample_rate: Option<u32>,
+    bit_depth: Option<u32>,
+    channels: Option<u32>,
     file: gio::File,
 }
 
 impl SongData {
-    pub fn artist(&self) -> Option<&str> {
-        self.artist.as_deref()
+    pub fn artists(&self) -> &[String] {
+        &self.artists
     }
 
     pub fn title(&self) -> Option<&str> {
@@ -45,6 +69,38 @@ impl SongData {
         self.album.as_deref()
     }
 
+    pub fn album_artist(&self) -> Option<&str> {
+        self.album_artist.as_deref()
+    }
+
+    pub fn track_number(&self) -> Option<u32> {
+        self.track_number
+    }
+
+    pub fn disc_number(&self) -> Option<u32> {
+        self.disc_number
+    }
+
+    pub fn genre(&self) -> Option<&str> {
+        self.genre.as_deref()
+    }
+
+    pub fn year(&self) -> Option<u32> {
+        self.year
+    }
+
+    pub fn composer(&self) -> Option<&str> {
+        self.composer.as_deref()
+    }
+
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
+    pub fn rating(&self) -> Option<u32> {
+        self.rating
+    }
+
     pub fn uuid(&self) -> Option<&str> {
         self.uuid.as_deref()
     }
@@ -57,12 +113,31 @@ impl SongData {
         self.duration
     }
 
-    pub fn cover_texture(&self) -> Option<&gdk::Texture> {
-        if let Some(cover) = &self.cover_art {
-            return Some(cover.texture());
-        }
+    pub fn codec(&self) -> Option<&str> {
+        self.codec.as_deref()
+    }
 
-        None
+    pub fn bitrate(&self) -> Option<u32> {
+        self.bitrate
+    }
+
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.sample_rate
+    }
+
+    pub fn bit_depth(&self) -> Option<u32> {
+        self.bit_depth
+    }
+
+    pub fn channels(&self) -> Option<u32> {
+        self.channels
+    }
+
+    // The texture is owned by the cover cache, which might need to load
+    // it again if it was evicted
+    pub fn cover_texture(&self) -> Option<gdk::Texture> {
+        let uuid = self.cover_uuid.as_deref()?;
+        CoverCache::texture(uuid)
     }
 
     pub fn cover_palette(&self) -> Option<&Vec<gdk::RGBA>> {
@@ -87,27 +162,63 @@ impl SongData {
         let file = gio::File::for_uri(uri);
         let path = file.path().expect("Unable to find file");
 
-        let tagged_file = match lofty::read_from_path(&path) {
-            Ok(f) => f,
+        // Skip parsing the file if it did not change since the last time
+        let stamp = FileStamp::for_path(&path);
+        let mut indexed = false;
+        if let Some(ref stamp) = stamp {
+            let cached = {
+                let mut index = MetadataIndex::global().lock().unwrap();
+                indexed = index.contains(uri);
+                index.lookup(uri, stamp).cloned()
+            };
+            if let Some(data) = cached.and_then(|v| SongData::from_json(&file, &v)) {
+                debug!("Using indexed metadata for {}", uri);
+                return data;
+            }
+        }
+
+        let (tagged_file, id3v2_rating) = match tags::read_tagged_file(&path) {
+            Ok(res) => res,
             Err(e) => {
                 warn!("Unable to open file {:?}: {}", path, e);
                 return SongData::default();
             }
         };
 
-        let mut cover_cache = CoverCache::global().lock().unwrap();
-
-        let mut artist = None;
+        let mut artists = Vec::new();
+        // Older versions only used the first artist
+        let mut legacy_artist = None;
         let mut title = None;
         let mut album = None;
+        let mut album_artist = None;
+        let mut track_number = None;
+        let mut disc_number = None;
+        let mut genre = None;
+        let mut year = None;
+        let mut composer = None;
+        let mut comment = None;
+        let mut rating = None;
         let mut cover_art = None;
         let mut cover_uuid = None;
         if let Some(tag) = tagged_file.primary_tag() {
             debug!("Found primary tag");
-            artist = tag.artist().map(|s| s.to_string());
+            artists = tag_artists(tag);
+            legacy_artist = tag.artist().map(|s| s.to_string());
             title = tag.title().map(|s| s.to_string());
             album = tag.album().map(|s| s.to_string());
-            if let Some(res) = cover_cache.cover_art(&path, tag) {
+            album_artist = tag
+                .get_string(&lofty::ItemKey::AlbumArtist)
+                .map(|s| s.to_string());
+            track_number = tag.track();
+            disc_number = tag.disk();
+            genre = tag.genre().map(|s| s.to_string());
+            year = tag.year();
+            composer = tag
+                .get_string(&lofty::ItemKey::Composer)
+                .map(|s| s.to_string());
+            comment = tag.comment().map(|s| s.to_string());
+            rating = id3v2_rating.or_else(|| tag_rating(tag));
+            if let Some(res) = CoverCache::cover_art(&path, tag) {
                 cover_art = Some(res.0);
                 cover_uuid = Some(res.1);
             }
@@ -115,47 +226,85 @@ impl SongData {
             warn!("Unable to load primary tag for: {}", uri);
             for tag in tagged_file.tags() {
                 debug!("Found tag: {:?}", tag.tag_type());
-                artist = tag.artist().map(|s| s.to_string());
+                artists = tag_artists(tag);
+                legacy_artist = tag.artist().map(|s| s.to_string());
                 title = tag.title().map(|s| s.to_string());
                 album = tag.album().map(|s| s.to_string());
-                if let Some(res) = cover_cache.cover_art(&path, tag) {
+                album_artist = tag
+                    .get_string(&lofty::ItemKey::AlbumArtist)
+                    .map(|s| s.to_string());
+                track_number = tag.track();
+                disc_number = tag.disk();
+                genre = tag.genre().map(|s| s.to_string());
+                year = tag.year();
+                composer = tag
+                    .get_string(&lofty::ItemKey::Composer)
+                    .map(|s| s.to_string());
+                comment = tag.comment().map(|s| s.to_string());
+                rating = id3v2_rating.or_else(|| tag_rating(tag));
+                if let Some(res) = CoverCache::cover_art(&path, tag) {
                     cover_art = Some(res.0);
                     cover_uuid = Some(res.1);
                 }
 
-                if artist.is_some() && title.is_some() {
+                if !artists.is_empty() && title.is_some() {
                     break;
                 }
             }
         };
 
-        let uuid = match file.query_info(
-            "standard::display-name",
-            gio::FileQueryInfoFlags::NONE,
-            gio::Cancellable::NONE,
-        ) {
-            Ok(info) => {
-                let mut hasher = Sha256::new();
-
-                hasher.update(info.display_name().as_str());
-
-                if let Some(ref artist) = artist {
-                    hasher.update(artist);
-                }
-                if let Some(ref title) = title {
-                    hasher.update(title);
-                }
-                if let Some(ref album) = album {
-                    hasher.update(album);
-                }
-
-                Some(format!("{:x}", hasher.finalize()))
-            }
-            _ => None,
-        };
-
         let properties = lofty::AudioFile::properties(&tagged_file);
         let duration = properties.duration().as_secs();
+        let bitrate = properties
+            .audio_bitrate()
+            .or_else(|| properties.overall_bitrate());
+        let sample_rate = properties.sample_rate();
+        let bit_depth = properties.bit_depth().map(u32::from);
+        let channels = properties.channels().map(u32::from);
+
+        let stream_info = StreamInfo {
+            duration_ms: properties.duration().as_millis(),
+            sample_rate,
+            channels,
+        };
+        let uuid = identity::song_id(&path, &tagged_file.file_type(), &stream_info);
+
+        // Older versions identified songs using their file name and tags;
+        // move the data cached using the old identity. Songs that were
+        // already indexed have been migrated the first time around
+        if let (Some(uuid), false) = (&uuid, indexed) {
+            if let Ok(info) = file.query_info(
+                "standard::display-name",
+                gio::FileQueryInfoFlags::NONE,
+                gio::Cancellable::NONE,
+            ) {
+                let legacy_uuid = identity::legacy_song_id(
+                    info.display_name().as_str(),
+                    legacy_artist.as_deref(),
+                    title.as_deref(),
+                    album.as_deref(),
+                );
+                identity::migrate_cached_data(&legacy_uuid, uuid);
+            }
+        }
+
+        let codec = match tagged_file.file_type() {
+            lofty::FileType::Aac => Some("AAC"),
+            lofty::FileType::Aiff => Some("AIFF"),
+            lofty::FileType::Ape => Some("Monkey's Audio"),
+            lofty::FileType::Flac => Some("FLAC"),
+            lofty::FileType::Mpeg => Some("MP3"),
+            lofty::FileType::Mp4 => Some("MPEG-4"),
+            lofty::FileType::Mpc => Some("Musepack"),
+            lofty::FileType::Opus => Some("Opus"),
+            lofty::FileType::Vorbis => Some("Ogg Vorbis"),
+            lofty::FileType::Speex => Some("Speex"),
+            lofty::FileType::Wav => Some("WAV"),
+            lofty::FileType::WavPack => Some("WavPack"),
+            lofty::FileType::Custom(name) => Some(name),
+            _ => None,
+        }
+        .map(|s| s.to_string());
 
         debug!(
             "Song {:?} ('{:?}') loading time: {} ms",
@@ -164,15 +313,150 @@ impl SongData {
             now.elapsed().as_millis()
         );
 
-        SongData {
-            artist,
+        let data = SongData {
+            artists,
             title,
             album,
+            album_artist,
+            track_number,
+            disc_number,
+            genre,
+            year,
+            composer,
+            comment,
+            rating,
             cover_art,
             cover_uuid,
             uuid,
             duration,
+            codec,
+            bitrate,
+            sample_rate,
+            bit_depth,
+            channels,
             file,
+        };
+
+        if let Some(stamp) = stamp {
+            MetadataIndex::global()
+                .lock()
+                .unwrap()
+                .insert(uri, stamp, data.to_json());
+        }
+
+        data
+    }
+
+    // The fields stored in the metadata index; the cover art is stored
+    // as the UUID of the cover cached on disk, and its palette
+    fn to_json(&self) -> serde_json::Value {
+        let palette = self.cover_art.as_ref().map(|c| {
+            c.palette()
+                .iter()
+                .map(|rgba| rgba.to_str().to_string())
+                .collect::<Vec<String>>()
+        });
+
+        serde_json::json!({
+            "artists": self.artists,
+            "title": self.title,
+            "album": self.album,
+            "album-artist": self.album_artist,
+            "track-number": self.track_number,
+            "disc-number": self.disc_number,
+            "genre": self.genre,
+            "year": self.year,
+            "composer": self.composer,
+            "comment": self.comment,
+            "rating": self.rating,
+            "cover-uuid": self.cover_uuid,
+            "cover-file": self.cover_art.as_ref().map(|c| c.is_from_cover_file()),
+            "palette": palette,
+            "uuid": self.uuid,
+            "duration": self.duration,
+            "codec": self.codec,
+            "bitrate": self.bitrate,
+            "sample-rate": self.sample_rate,
+            "bit-depth": self.bit_depth,
+            "channels": self.channels,
+        })
+    }
+
+    // Returns None if the cover art cached on disk is gone, in which case
+    // the file needs to be parsed again
+    fn from_json(file: &gio::File, value: &serde_json::Value) -> Option<SongData> {
+        let text = |key: &str| value.get(key).and_then(|v| v.as_str()).map(String::from);
+        let number = |key: &str| value.get(key).and_then(|v| v.as_u64()).map(|n| n as u32);
+
+        let artists = value
+            .get("artists")?
+            .as_array()?
+            .iter()
+            .filter_map(|v| v.as_str().map(String::from))
+            .collect();
+
+        let cover_uuid = text("cover-uuid");
+        let cover_art = match cover_uuid {
+            Some(ref uuid) => {
+                let palette = value
+                    .get("palette")
+                    .and_then(|v| v.as_array())
+                    .map(|colors| {
+                        colors
+                            .iter()
+                            .filter_map(|c| c.as_str())
+                            .filter_map(|c| gdk::RGBA::parse(c).ok())
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                let from_cover_file = value
+                    .get("cover-file")
+                    .and_then(|v| v.as_bool())
+                    .unwrap_or(true);
+                Some(CoverCache::cached_cover_art(
+                    uuid,
+                    palette,
+                    from_cover_file,
+                )?)
+            }
+            None => None,
+        };
+
+        Some(SongData {
+            artists,
+            title: text("title"),
+            album: text("album"),
+            album_artist: text("album-artist"),
+            track_number: number("track-number"),
+            disc_number: number("disc-number"),
+            genre: text("genre"),
+            year: number("year"),
+            composer: text("composer"),
+            comment: text("comment"),
+            rating: number("rating"),
+            cover_art,
+            cover_uuid,
+            uuid: text("uuid"),
+            duration: value.get("duration")?.as_u64()?,
+            codec: text("codec"),
+            bitrate: number("bitrate"),
+            sample_rate: number("sample-rate"),
+            bit_depth: number("bit-depth"),
+            channels: number("channels"),
+            file: file.clone(),
+        })
+    }
+
+    // Loads the metadata of the file at the given URI; this can be
+    // called from any thread
+    pub fn load(uri: &str) -> Option<SongData> {
+        let data = SongData::from_uri(uri);
+
+        // SongData::from_uri() returns the default data on failure
+        if data.file().equal(&gio::File::for_uri(uri)) {
+            Some(data)
+        } else {
+            None
         }
     }
 
@@ -185,16 +469,40 @@ impl SongData {
     }
 }
 
+// Multi-valued artist fields are either stored as separate items, like
+// in Vorbis comments, or as a single null-separated value, like in ID3v2.4
+fn tag_artists(tag: &lofty::Tag) -> Vec<String> {
+    tag.get_strings(&lofty::ItemKey::TrackArtist)
+        .flat_map(|s| s.split('\0'))
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect()
+}
+
 impl Default for SongData {
     fn default() -> Self {
         SongData {
-            artist: Some("Invalid Artist".to_string()),
+            artists: vec!["Invalid Artist".to_string()],
             title: Some("Invalid Title".to_string()),
             album: Some("Invalid Album".to_string()),
+            album_artist: None,
+            track_number: None,
+            disc_number: None,
+            genre: None,
+            year: None,
+            composer: None,
+            comment: None,
+            rating: None,
             cover_art: None,
             cover_uuid: None,
             uuid: None,
             duration: 0,
+            codec: None,
+            bitrate: None,
+            sample_rate: None,
+            bit_depth: None,
+            channels: None,
             file: gio::File::for_path("/does-not-exist"),
         }
     }
@@ -208,6 +516,7 @@ mod imp {
         pub data: RefCell<SongData>,
         pub playing: Cell<bool>,
         pub selected: Cell<bool>,
+        pub serial: Cell<u64>,
     }
 
     #[glib::object_subclass]
@@ -217,14 +526,38 @@ mod imp {
     }
 
     impl ObjectImpl for Song {
+        fn constructed(&self) {
+            self.parent_constructed();
+            self.serial.set(NEXT_SERIAL.fetch_add(1, Ordering::Relaxed));
+        }
+
         fn properties() -> &'static [ParamSpec] {
             static PROPERTIES: Lazy<Vec<ParamSpec>> = Lazy::new(|| {
                 vec![
                     ParamSpecString::builder("uri").construct_only().build(),
                     ParamSpecString::builder("artist").read_only().build(),
+                    ParamSpecBoxed::builder::<Vec<String>>("artists")
+                        .read_only()
+                        .build(),
                     ParamSpecString::builder("title").read_only().build(),
                     ParamSpecString::builder("album").read_only().build(),
+                    ParamSpecString::builder("album-artist").read_only().build(),
+                    ParamSpecUInt::builder("track-number").read_only().build(),
+                    ParamSpecUInt::builder("disc-number").read_only().build(),
+                    ParamSpecUInt::builder("year").read_only().build(),
+                    ParamSpecString::builder("genre").read_only().build(),
+                    ParamSpecString::builder("composer").read_only().build(),
+                    ParamSpecString::builder("comment").read_only().build(),
+                    ParamSpecUInt::builder("rating")
+                        .maximum(MAX_RATING)
+                        .read_only()
+                        .build(),
                     ParamSpecUInt::builder("duration").read_only().build(),
+                    ParamSpecString::builder("codec").read_only().build(),
+                    ParamSpecUInt::builder("bitrate").read_only().build(),
+                    ParamSpecUInt::builder("sample-rate").read_only().build(),
+                    ParamSpecUInt::builder("bit-depth").read_only().build(),
+                    ParamSpecUInt::builder("channels").read_only().build(),
                     ParamSpecObject::builder::<gdk::Texture>("cover")
                         .read_only()
                         .build(),
@@ -238,14 +571,9 @@ mod imp {
         fn set_property(&self, _id: usize, value: &Value, pspec: &ParamSpec) {
             match pspec.name() {
                 "uri" => {
-                    let obj = self.obj();
                     if let Ok(p) = value.get::<&str>() {
                         self.data.replace(SongData::from_uri(p));
-                        obj.notify("artist");
-                        obj.notify("title");
-                        obj.notify("album");
-                        obj.notify("duration");
-                        obj.notify("cover");
+                        self.obj().notify_metadata();
                     }
                 }
                 "playing" => {
@@ -264,9 +592,23 @@ mod imp {
             let obj = self.obj();
             match pspec.name() {
                 "artist" => obj.artist().to_value(),
+                "artists" => obj.artists().to_value(),
                 "title" => obj.title().to_value(),
                 "album" => obj.album().to_value(),
+                "album-artist" => obj.album_artist().to_value(),
+                "track-number" => obj.track_number().unwrap_or(0).to_value(),
+                "disc-number" => obj.disc_number().unwrap_or(0).to_value(),
+                "year" => obj.year().unwrap_or(0).to_value(),
+                "genre" => obj.genre().to_value(),
+                "composer" => obj.composer().to_value(),
+                "comment" => obj.comment().to_value(),
+                "rating" => obj.rating().to_value(),
                 "duration" => obj.duration().to_value(),
+                "codec" => obj.codec().to_value(),
+                "bitrate" => obj.bitrate().unwrap_or(0).to_value(),
+                "sample-rate" => obj.sample_rate().unwrap_or(0).to_value(),
+                "bit-depth" => obj.bit_depth().unwrap_or(0).to_value(),
+                "channels" => obj.channels().unwrap_or(0).to_value(),
                 "uri" => obj.uri().to_value(),
                 "cover" => obj.cover_texture().to_value(),
                 "playing" => self.playing.get().to_value(),
@@ -282,23 +624,23 @@ glib::wrapper! {
 }
 
 impl Song {
-    pub fn new(uri: &str) -> Self {
-        glib::Object::builder::<Self>().property("uri", uri).build()
-    }
-
-    pub fn from_uri(uri: &str) -> Result<Song, &'static str> {
-        let res = Song::new(uri);
-        if res.equals(&Song::default()) {
-            Err("Invalid song")
-        } else {
-            Ok(res)
-        }
+    // Creates a song from metadata loaded with SongData::load()
+    pub fn from_data(data: SongData) -> Self {
+        let res = Song::empty();
+        res.imp().data.replace(data);
+        res
     }
 
     pub fn empty() -> Self {
         glib::Object::new()
     }
 
+    // Identifies this song object for the lifetime of the process; unlike
+    // the UUID, it is different for every copy of the same song
+    pub fn serial(&self) -> u64 {
+        self.imp().serial.get()
+    }
+
     pub fn equals(&self, other: &Self) -> bool {
         if self.uuid().is_some() && other.uuid().is_some() {
             self.uuid() == other.uuid()
@@ -311,13 +653,78 @@ impl Song {
         self.imp().data.borrow().uri()
     }
 
+    // Update the location of the song after its file has been renamed
+    // or moved; the metadata is left untouched
+    pub fn set_file(&self, file: &gio::File) {
+        self.imp().data.borrow_mut().file = file.clone();
+        self.notify("uri");
+    }
+
+    // Reloads the metadata from the file, for instance after its tags
+    // have been changed; the playing and selected states are preserved
+    pub fn reload(&self) -> bool {
+        let file = self.file();
+        let data = SongData::from_uri(&file.uri());
+
+        // SongData::from_uri() returns the default data on failure
+        if !data.file().equal(&file) {
+            warn!("Unable to reload song: {}", file.uri());
+            return false;
+        }
+
+        self.set_data(data);
+
+        true
+    }
+
+    // Replaces the metadata, for instance with the one loaded again in a
+    // separate thread
+    // A copy of the metadata, which can be sent to other threads
+    pub fn data(&self) -> SongData {
+        self.imp().data.borrow().clone()
+    }
+
+    pub fn set_data(&self, data: SongData) {
+        self.imp().data.replace(data);
+        self.notify_metadata();
+    }
+
+    fn notify_metadata(&self) {
+        self.notify("artist");
+        self.notify("artists");
+        self.notify("title");
+        self.notify("album");
+        self.notify("album-artist");
+        self.notify("track-number");
+        self.notify("disc-number");
+        self.notify("year");
+        self.notify("genre");
+        self.notify("composer");
+        self.notify("comment");
+        self.notify("rating");
+        self.notify("duration");
+        self.notify("codec");
+        self.notify("bitrate");
+        self.notify("sample-rate");
+        self.notify("bit-depth");
+        self.notify("channels");
+        self.notify("cover");
+    }
+
+    // All the credited artists, joined for display
     pub fn artist(&self) -> String {
-        match self.imp().data.borrow().artist() {
-            Some(artist) => artist.to_string(),
-            None => i18n("Unknown artist"),
+        let data = self.imp().data.borrow();
+        if data.artists().is_empty() {
+            i18n("Unknown artist")
+        } else {
+            data.artists().join(", ")
         }
     }
 
+    pub fn artists(&self) -> Vec<String> {
+        self.imp().data.borrow().artists().to_vec()
+    }
+
     pub fn title(&self) -> String {
         match self.imp().data.borrow().title() {
             Some(title) => title.to_string(),
@@ -332,8 +739,55 @@ impl Song {
         }
     }
 
+    // The title and album as stored in the tags, without the fallbacks
+    // used for display
+    pub fn tagged_title(&self) -> Option<String> {
+        self.imp().data.borrow().title().map(|s| s.to_string())
+    }
+
+    pub fn tagged_album(&self) -> Option<String> {
+        self.imp().data.borrow().album().map(|s| s.to_string())
+    }
+
+    pub fn album_artist(&self) -> Option<String> {
+        self.imp()
+            .data
+            .borrow()
+            .album_artist()
+            .map(|s| s.to_string())
+    }
+
+    pub fn track_number(&self) -> Option<u32> {
+        self.imp().data.borrow().track_number()
+    }
+
+    pub fn disc_number(&self) -> Option<u32> {
+        self.imp().data.borrow().disc_number()
+    }
+
+    pub fn genre(&self) -> Option<String> {
+        self.imp().data.borrow().genre().map(|s| s.to_string())
+    }
+
+    pub fn year(&self) -> Option<u32> {
+        self.imp().data.borrow().year()
+    }
+
+    pub fn composer(&self) -> Option<String> {
+        self.imp().data.borrow().composer().map(|s| s.to_string())
+    }
+
+    pub fn comment(&self) -> Option<String> {
+        self.imp().data.borrow().comment().map(|s| s.to_string())
+    }
+
+    // The number of stars, between 0 (unrated) and MAX_RATING
+    pub fn rating(&self) -> u32 {
+        self.imp().data.borrow().rating().unwrap_or(0)
+    }
+
     pub fn cover_texture(&self) -> Option<gdk::Texture> {
-        self.imp().data.borrow().cover_texture().cloned()
+        self.imp().data.borrow().cover_texture()
     }
 
     pub fn cover_color(&self) -> Option<gdk::RGBA> {
@@ -356,6 +810,28 @@ impl Song {
         self.imp().data.borrow().duration()
     }
 
+    pub fn codec(&self) -> Option<String> {
+        self.imp().data.borrow().codec().map(|s| s.to_string())
+    }
+
+    // In kbps
+    pub fn bitrate(&self) -> Option<u32> {
+        self.imp().data.borrow().bitrate()
+    }
+
+    // In Hz
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.imp().data.borrow().sample_rate()
+    }
+
+    pub fn bit_depth(&self) -> Option<u32> {
+        self.imp().data.borrow().bit_depth()
+    }
+
+    pub fn channels(&self) -> Option<u32> {
+        self.imp().data.borrow().channels()
+    }
+
     pub fn playing(&self) -> bool {
         self.imp().playing.get()
     }
@@ -382,8 +858,22 @@ impl Song {
         self.imp().data.borrow().uuid().map(|s| s.to_string())
     }
 
-    pub fn search_key(&self) -> String {
-        format!("{} {} {}", self.artist(), self.album(), self.title())
+    // One search key for each credited artist
+    pub fn search_keys(&self) -> Vec<String> {
+        let artists = self.artists();
+        if artists.is_empty() {
+            return vec![format!(
+                "{} {} {}",
+                self.artist(),
+                self.album(),
+                self.title()
+            )];
+        }
+
+        artists
+            .iter()
+            .map(|artist| format!("{} {} {}", artist, self.album(), self.title()))
+            .collect()
     }
 
     pub fn file(&self) -> gio::File {
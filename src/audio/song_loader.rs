@@ -0,0 +1,111 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use async_channel::Receiver;
+use gtk::{gio, prelude::*};
+use log::{debug, warn};
+
+use crate::{audio::SongData, smart_playlist::SmartPlaylist};
+
+// The number of songs sent to the main context at once
+const BATCH_SIZE: usize = 32;
+
+// Reading the metadata is mostly bound by I/O, so there is little point
+// in having more workers than this
+const MAX_WORKERS: usize = 8;
+
+// The metadata of a song, and the position of its file in the list of
+// files to load; the data is None if the file could not be loaded
+pub type LoadedSong = (usize, Option<SongData>);
+
+// Loads the metadata of the given files using a pool of worker threads.
+//
+// The results are sent to the returned channel in batches, in no
+// particular order; the channel is closed once all the files have been
+// loaded, or when the cancellable is cancelled
+pub fn load_song_data(
+    files: &[gio::File],
+    cancellable: &gio::Cancellable,
+) -> Receiver<Vec<LoadedSong>> {
+    load(files, None, cancellable)
+}
+
+// Like load_song_data(), but the workers also match the songs against
+// the rules of the smart playlist; the data of the songs that do not
+// match is None
+pub fn load_matching_song_data(
+    files: &[gio::File],
+    playlist: &SmartPlaylist,
+    cancellable: &gio::Cancellable,
+) -> Receiver<Vec<LoadedSong>> {
+    load(files, Some(playlist.clone()), cancellable)
+}
+
+fn load(
+    files: &[gio::File],
+    playlist: Option<SmartPlaylist>,
+    cancellable: &gio::Cancellable,
+) -> Receiver<Vec<LoadedSong>> {
+    let (sender, receiver) = async_channel::unbounded();
+
+    let uris: Arc<Vec<String>> = Arc::new(files.iter().map(|f| f.uri().to_string()).collect());
+    let next = Arc::new(AtomicUsize::new(0));
+    let playlist = Arc::new(playlist);
+
+    let n_workers = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .clamp(1, MAX_WORKERS)
+        .min(uris.len().max(1));
+    debug!("Loading {} files using {} workers", uris.len(), n_workers);
+
+    for i in 0..n_workers {
+        let sender = sender.clone();
+        let uris = uris.clone();
+        let next = next.clone();
+        let playlist = playlist.clone();
+        let cancellable = cancellable.clone();
+
+        let res = thread::Builder::new()
+            .name(format!("song-loader-{}", i))
+            .spawn(move || {
+                let mut batch = Vec::with_capacity(BATCH_SIZE);
+                while !cancellable.is_cancelled() {
+                    let idx = next.fetch_add(1, Ordering::Relaxed);
+                    let uri = match uris.get(idx) {
+                        Some(uri) => uri,
+                        None => break,
+                    };
+
+                    let data = SongData::load(uri).filter(|data| match *playlist {
+                        Some(ref p) => p.matches(data),
+                        None => true,
+                    });
+                    batch.push((idx, data));
+                    if batch.len() == BATCH_SIZE
+                        && sender.send_blocking(std::mem::take(&mut batch)).is_err()
+                    {
+                        return;
+                    }
+                }
+
+                if !batch.is_empty() {
+                    let _ = sender.send_blocking(batch);
+                }
+            });
+
+        if let Err(e) = res {
+            warn!("Unable to spawn song loader thread: {}", e);
+        }
+    }
+
+    receiver
+}
@@ -0,0 +1,178 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The spectrogram of the current song is computed in the same way as its
+// waveform, using the "spectrum" element instead of the "level" one, with
+// the same interval between frames. Unlike the waveform, it is only
+// computed when it is shown, and it is not cached.
+
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+use glib::clone;
+use gtk::{glib, prelude::*, subclass::prelude::*};
+
+use crate::audio::{
+    analysis_pipeline::{AnalysisPipeline, AnalysisSpeed},
+    waveform_generator::PEAK_INTERVAL_MS,
+    Controller, PlaybackState, RepeatMode, Song,
+};
+
+// The number of frequency bands of each frame
+const SPECTRUM_BANDS: u32 = 64;
+// The magnitude of the quietest sound, in dB
+const SPECTRUM_THRESHOLD: f32 = -80.0;
+
+// Maps a magnitude between the threshold and 0 dB to a value between 0 and 1
+fn normalize_magnitude(db: f32) -> f64 {
+    ((db - SPECTRUM_THRESHOLD) / -SPECTRUM_THRESHOLD).clamp(0.0, 1.0) as f64
+}
+
+mod imp {
+    use glib::{ParamSpec, ParamSpecBoolean, Value};
+    use once_cell::sync::Lazy;
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    pub struct SpectrogramGenerator {
+        pub enabled: Cell<bool>,
+        pub song: RefCell<Option<Song>>,
+        // Each frame holds the magnitude of the frequency bands, from the
+        // lowest to the highest, normalised between 0 and 1
+        pub frames: RefCell<Option<Vec<Vec<f64>>>>,
+        pub pipeline: RefCell<Option<AnalysisPipeline>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for SpectrogramGenerator {
+        const NAME: &'static str = "AmberolSpectrogramGenerator";
+        type Type = super::SpectrogramGenerator;
+    }
+
+    impl ObjectImpl for SpectrogramGenerator {
+        fn dispose(&self) {
+            self.pipeline.take();
+        }
+
+        fn properties() -> &'static [ParamSpec] {
+            static PROPERTIES: Lazy<Vec<ParamSpec>> = Lazy::new(|| {
+                vec![ParamSpecBoolean::builder("has-spectrogram")
+                    .read_only()
+                    .build()]
+            });
+
+            PROPERTIES.as_ref()
+        }
+
+        fn property(&self, _id: usize, pspec: &ParamSpec) -> Value {
+            match pspec.name() {
+                "has-spectrogram" => self.frames.borrow().is_some().to_value(),
+                _ => unimplemented!(),
+            }
+        }
+    }
+}
+
+glib::wrapper! {
+    pub struct SpectrogramGenerator(ObjectSubclass<imp::SpectrogramGenerator>);
+}
+
+impl Default for SpectrogramGenerator {
+    fn default() -> Self {
+        glib::Object::new()
+    }
+}
+
+impl Controller for SpectrogramGenerator {
+    fn set_playback_state(&self, _playback_state: &PlaybackState) {}
+
+    fn set_song(&self, song: &Song) {
+        self.imp().song.replace(Some(song.clone()));
+        self.reset();
+        if self.imp().enabled.get() {
+            self.generate();
+        }
+    }
+
+    fn update_song(&self, _song: &Song) {}
+    fn set_position(&self, _position: u64) {}
+    fn seeked(&self, _position: u64) {}
+    fn set_repeat_mode(&self, _mode: RepeatMode) {}
+    fn set_shuffled(&self, _shuffled: bool) {}
+    fn set_volume(&self, _volume: f64) {}
+}
+
+impl SpectrogramGenerator {
+    pub fn new() -> Self {
+        SpectrogramGenerator::default()
+    }
+
+    pub fn spectrogram(&self) -> Option<Vec<Vec<f64>>> {
+        self.imp().frames.borrow().clone()
+    }
+
+    // Computing the spectrogram takes a while, so we only do it while
+    // something is showing it
+    pub fn set_enabled(&self, enabled: bool) {
+        if self.imp().enabled.replace(enabled) == enabled {
+            return;
+        }
+
+        if enabled {
+            self.generate();
+        } else {
+            self.reset();
+        }
+    }
+
+    fn reset(&self) {
+        self.imp().pipeline.take();
+        if self.imp().frames.take().is_some() {
+            self.notify("has-spectrogram");
+        }
+    }
+
+    fn generate(&self) {
+        let song = match self.imp().song.borrow().as_ref() {
+            Some(s) => s.clone(),
+            None => return,
+        };
+
+        let frames: Rc<RefCell<Vec<Vec<f64>>>> = Rc::new(RefCell::new(Vec::new()));
+        let pipeline = AnalysisPipeline::new(
+            &song.uri(),
+            "audio/x-raw,channels=1",
+            &format!(
+                "spectrum name=spectrum bands={} threshold={} interval={}",
+                SPECTRUM_BANDS,
+                SPECTRUM_THRESHOLD,
+                PEAK_INTERVAL_MS as u64 * 1_000_000
+            ),
+            AnalysisSpeed::Full,
+            clone!(@strong frames => move |s| {
+                if !s.has_name("spectrum") {
+                    return;
+                }
+
+                if let Ok(magnitude) = s.get::<gst::List>("magnitude") {
+                    let frame = magnitude
+                        .iter()
+                        .filter_map(|v| v.get::<f32>().ok())
+                        .map(normalize_magnitude)
+                        .collect();
+                    frames.borrow_mut().push(frame);
+                }
+            }),
+            clone!(@weak self as this => move || {
+                this.imp().pipeline.replace(None);
+                this.imp().frames.replace(Some(frames.take()));
+                this.notify("has-spectrogram");
+            }),
+        );
+
+        self.imp().pipeline.replace(pipeline);
+    }
+}
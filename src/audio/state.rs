@@ -19,6 +19,7 @@ mod imp {
     #[derive(Debug)]
     pub struct PlayerState {
         pub playback_state: Cell<PlaybackState>,
+        // In milliseconds
         pub position: Cell<u64>,
         pub current_song: RefCell<Option<Song>>,
         pub volume: Cell<f64>,
@@ -164,11 +165,26 @@ impl PlayerState {
         self.notify("position");
     }
 
+    // Notifies the changes in the metadata of the current song, without
+    // resetting the position
+    pub fn update_current_song(&self) {
+        self.notify("title");
+        self.notify("artist");
+        self.notify("album");
+        self.notify("duration");
+        self.notify("cover");
+    }
+
+    // The position of the current song, in seconds
     pub fn position(&self) -> u64 {
+        self.imp().position.get() / 1000
+    }
+
+    pub fn position_ms(&self) -> u64 {
         self.imp().position.get()
     }
 
-    pub fn set_position(&self, position: u64) {
+    pub fn set_position_ms(&self, position: u64) {
         self.imp().position.replace(position);
         self.notify("position");
     }
@@ -0,0 +1,662 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{borrow::Cow, io::Cursor, path::Path};
+
+use lofty::{
+    id3::v2::{Frame, FrameId, Id3v2Tag, PopularimeterFrame},
+    Accessor, AudioFile, MergeTag, SplitTag, TagExt, TaggedFileExt,
+};
+use log::debug;
+
+// The changes to apply to the tags of a song
+//
+// Fields set to `None` are left untouched; empty strings, and numbers
+// set to zero, remove the field from the tag
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TagChanges {
+    pub title: Option<String>,
+    pub artists: Option<Vec<String>>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub year: Option<u32>,
+    pub genre: Option<String>,
+    // The number of stars; zero removes the rating
+    pub rating: Option<u32>,
+    // The encoded image data of the new front cover; an empty buffer
+    // removes the existing front cover
+    pub cover: Option<Vec<u8>>,
+}
+
+impl TagChanges {
+    pub fn is_empty(&self) -> bool {
+        *self == TagChanges::default()
+    }
+
+    pub fn changes_cover(&self) -> bool {
+        self.cover.is_some()
+    }
+}
+
+pub const MAX_RATING: u32 = 5;
+
+// The POPM frame stores the rating as a byte; we use the same mapping
+// as other players, so that stars round-trip between them
+const POPM_RATINGS: [u8; 6] = [0, 1, 64, 128, 196, 255];
+
+const POPM_FRAME_ID: FrameId<'static> = FrameId::Valid(Cow::Borrowed("POPM"));
+
+// Vorbis comments do not have a standard rating field, but most players
+// agree on a RATING field, which lofty maps to the Popularimeter item, like
+// the rate atom of MP4; APE tags have no such mapping, so we use the same
+// field name. We also read the ratings written by other players
+const RATING_KEY: &str = "RATING";
+const MP4_RATING_KEY: &str = "----:com.apple.iTunes:RATING";
+const FMPS_RATING_KEY: &str = "FMPS_RATING";
+
+fn rating_from_popm_byte(byte: u8) -> u32 {
+    match byte {
+        0 => 0,
+        1..=31 => 1,
+        32..=95 => 2,
+        96..=159 => 3,
+        160..=223 => 4,
+        _ => 5,
+    }
+}
+
+fn popm_byte_from_rating(rating: u32) -> u8 {
+    POPM_RATINGS[rating.min(MAX_RATING) as usize]
+}
+
+// The contents of a POPM frame are a null-terminated email address,
+// followed by the rating byte and an optional play counter
+fn rating_from_popm(data: &[u8]) -> Option<u32> {
+    let end = data.iter().position(|b| *b == 0)?;
+    data.get(end + 1).map(|b| rating_from_popm_byte(*b))
+}
+
+// Ratings stored as text are either a number of stars, or a percentage
+fn rating_from_text(text: &str) -> Option<u32> {
+    let value = text.trim().parse::<f64>().ok()?;
+    let stars = if value <= MAX_RATING as f64 {
+        value
+    } else if value <= 100.0 {
+        value * MAX_RATING as f64 / 100.0
+    } else {
+        return None;
+    };
+
+    Some(stars.round().max(0.0) as u32)
+}
+
+// FMPS ratings are a fraction between 0 and 1
+fn rating_from_fmps(text: &str) -> Option<u32> {
+    let value = text.trim().parse::<f64>().ok()?;
+    if !(0.0..=1.0).contains(&value) {
+        return None;
+    }
+
+    Some((value * MAX_RATING as f64).round() as u32)
+}
+
+// The rating stored in the tag, in stars; see `read_tagged_file()` for
+// ID3v2 tags
+pub fn tag_rating(tag: &lofty::Tag) -> Option<u32> {
+    if let Some(item) = tag.get(&lofty::ItemKey::Popularimeter) {
+        let rating = match item.value() {
+            lofty::ItemValue::Binary(data) => rating_from_popm(data),
+            lofty::ItemValue::Text(text) => rating_from_text(text),
+            _ => None,
+        };
+        if rating.is_some() {
+            return rating;
+        }
+    }
+
+    for key in [RATING_KEY, MP4_RATING_KEY] {
+        let key = lofty::ItemKey::Unknown(key.to_string());
+        if let Some(rating) = tag.get_string(&key).and_then(rating_from_text) {
+            return Some(rating);
+        }
+    }
+
+    let key = lofty::ItemKey::Unknown(FMPS_RATING_KEY.to_string());
+    tag.get_string(&key).and_then(rating_from_fmps)
+}
+
+// The rating stored in the POPM frames of an ID3v2 tag, in stars; lofty
+// does not map them to generic tag items. A rating of zero is unknown
+fn id3v2_rating(tag: &Id3v2Tag) -> Option<u32> {
+    let mut ratings = tag.into_iter().filter_map(|frame| match frame {
+        Frame::Popularimeter(popm) if popm.rating > 0 => Some((popm.email.is_empty(), popm.rating)),
+        _ => None,
+    });
+
+    // Prefer our own, anonymous frame over the ones of other players
+    let (_, rating) = ratings
+        .clone()
+        .find(|(anonymous, _)| *anonymous)
+        .or_else(|| ratings.next())?;
+
+    Some(rating_from_popm_byte(rating))
+}
+
+fn set_rating(tag: &mut lofty::Tag, rating: u32) {
+    let rating = rating.min(MAX_RATING);
+
+    for key in [RATING_KEY, MP4_RATING_KEY, FMPS_RATING_KEY] {
+        tag.remove_key(&lofty::ItemKey::Unknown(key.to_string()));
+    }
+    tag.remove_key(&lofty::ItemKey::Popularimeter);
+    if rating == 0 {
+        return;
+    }
+
+    if !tag.insert_text(lofty::ItemKey::Popularimeter, rating.to_string()) {
+        tag.insert_text(
+            lofty::ItemKey::Unknown(RATING_KEY.to_string()),
+            rating.to_string(),
+        );
+    }
+}
+
+// Updates the rating of every POPM frame, to keep the play counters of
+// other players; if there are none, we add an anonymous frame, which we
+// drop again once it holds neither a rating nor a counter
+fn set_id3v2_rating(tag: &mut Id3v2Tag, rating: u32) {
+    let byte = popm_byte_from_rating(rating);
+
+    let mut frames: Vec<PopularimeterFrame<'static>> = tag
+        .remove(&POPM_FRAME_ID)
+        .filter_map(|frame| match frame {
+            Frame::Popularimeter(popm) => Some(popm),
+            _ => None,
+        })
+        .collect();
+    if frames.is_empty() && rating > 0 {
+        frames.push(PopularimeterFrame::new(String::new(), byte, 0));
+    }
+
+    for mut popm in frames {
+        if byte == 0 && popm.email.is_empty() && popm.counter == 0 {
+            continue;
+        }
+
+        popm.rating = byte;
+        tag.insert(Frame::Popularimeter(popm));
+    }
+}
+
+fn set_text(tag: &mut lofty::Tag, key: lofty::ItemKey, value: &str) {
+    if value.is_empty() {
+        tag.remove_key(&key);
+    } else {
+        tag.insert_text(key, value.to_string());
+    }
+}
+
+fn set_artists(tag: &mut lofty::Tag, artists: &[String]) {
+    tag.remove_key(&lofty::ItemKey::TrackArtist);
+
+    let artists: Vec<&str> = artists
+        .iter()
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .collect();
+    if artists.is_empty() {
+        return;
+    }
+
+    // ID3v2.4 stores multiple values in a single, null-separated
+    // frame; the other formats can repeat the field
+    if tag.tag_type() == lofty::TagType::Id3v2 {
+        tag.insert_text(lofty::ItemKey::TrackArtist, artists.join("\0"));
+    } else {
+        for artist in artists {
+            tag.push(lofty::TagItem::new(
+                lofty::ItemKey::TrackArtist,
+                lofty::ItemValue::Text(artist.to_string()),
+            ));
+        }
+    }
+}
+
+fn set_cover(tag: &mut lofty::Tag, data: &[u8]) -> Result<(), lofty::LoftyError> {
+    tag.remove_picture_type(lofty::PictureType::CoverFront);
+
+    if !data.is_empty() {
+        let mut picture = lofty::Picture::from_reader(&mut Cursor::new(data))?;
+        picture.set_pic_type(lofty::PictureType::CoverFront);
+        tag.push_picture(picture);
+    }
+
+    Ok(())
+}
+
+// Reads the file at the given path, with the rating stored in its ID3v2
+// tag, if any; the rating of the other tags is available through
+// `tag_rating()`
+pub fn read_tagged_file(
+    path: &Path,
+) -> Result<(lofty::TaggedFile, Option<u32>), lofty::LoftyError> {
+    let file_type = lofty::Probe::open(path)?.guess_file_type()?.file_type();
+    let options = lofty::ParseOptions::new();
+
+    let res = match file_type {
+        Some(lofty::FileType::Aac) => {
+            let file = lofty::aac::AacFile::read_from(&mut std::fs::File::open(path)?, options)?;
+            let rating = file.id3v2().and_then(id3v2_rating);
+            (file.into(), rating)
+        }
+        Some(lofty::FileType::Aiff) => {
+            let file =
+                lofty::iff::aiff::AiffFile::read_from(&mut std::fs::File::open(path)?, options)?;
+            let rating = file.id3v2().and_then(id3v2_rating);
+            (file.into(), rating)
+        }
+        Some(lofty::FileType::Mpeg) => {
+            let file = lofty::mpeg::MpegFile::read_from(&mut std::fs::File::open(path)?, options)?;
+            let rating = file.id3v2().and_then(id3v2_rating);
+            (file.into(), rating)
+        }
+        Some(lofty::FileType::Wav) => {
+            let file =
+                lofty::iff::wav::WavFile::read_from(&mut std::fs::File::open(path)?, options)?;
+            let rating = file.id3v2().and_then(id3v2_rating);
+            (file.into(), rating)
+        }
+        _ => (lofty::read_from_path(path)?, None),
+    };
+
+    Ok(res)
+}
+
+// The ID3v2 tag of the file at the given path, for the file types that
+// use it as their primary tag
+fn read_id3v2(
+    path: &Path,
+    file_type: lofty::FileType,
+) -> Result<Option<Id3v2Tag>, lofty::LoftyError> {
+    let mut file = std::fs::File::open(path)?;
+    let options = lofty::ParseOptions::new().read_properties(false);
+
+    let tag = match file_type {
+        lofty::FileType::Aac => lofty::aac::AacFile::read_from(&mut file, options)?.remove_id3v2(),
+        lofty::FileType::Aiff => {
+            lofty::iff::aiff::AiffFile::read_from(&mut file, options)?.remove_id3v2()
+        }
+        lofty::FileType::Mpeg => {
+            lofty::mpeg::MpegFile::read_from(&mut file, options)?.remove_id3v2()
+        }
+        lofty::FileType::Wav => {
+            lofty::iff::wav::WavFile::read_from(&mut file, options)?.remove_id3v2()
+        }
+        _ => None,
+    };
+
+    Ok(tag)
+}
+
+// Writes the changes into the primary tag of the file at the given
+// path; if the file has no primary tag, a new one is created
+pub fn write_tags(path: &Path, changes: &TagChanges) -> Result<(), lofty::LoftyError> {
+    let file_type = lofty::Probe::open(path)?.guess_file_type()?.file_type();
+    if let Some(file_type) = file_type {
+        if file_type.primary_tag_type() == lofty::TagType::Id3v2 {
+            return write_id3v2_tags(path, file_type, changes);
+        }
+    }
+
+    let mut tagged_file = lofty::read_from_path(path)?;
+
+    if tagged_file.primary_tag().is_none() {
+        let tag_type = tagged_file.primary_tag_type();
+        debug!("Creating a new {:?} tag for {:?}", tag_type, path);
+        tagged_file.insert_tag(lofty::Tag::new(tag_type));
+    }
+
+    let tag = tagged_file.primary_tag_mut().unwrap();
+
+    apply_changes(tag, changes)?;
+    if let Some(rating) = changes.rating {
+        set_rating(tag, rating);
+    }
+
+    tag.save_to_path(path, lofty::WriteOptions::default())?;
+
+    debug!("Tags written to {:?}", path);
+
+    Ok(())
+}
+
+// The POPM frames only exist in the concrete ID3v2 tag, so we apply the
+// generic changes to a copy of it, and merge them back
+fn write_id3v2_tags(
+    path: &Path,
+    file_type: lofty::FileType,
+    changes: &TagChanges,
+) -> Result<(), lofty::LoftyError> {
+    let id3v2 = match read_id3v2(path, file_type)? {
+        Some(tag) => tag,
+        None => {
+            debug!("Creating a new ID3v2 tag for {:?}", path);
+            Id3v2Tag::default()
+        }
+    };
+
+    let (remainder, mut tag) = id3v2.split_tag();
+    apply_changes(&mut tag, changes)?;
+
+    let mut id3v2 = remainder.merge_tag(tag);
+    if let Some(rating) = changes.rating {
+        set_id3v2_rating(&mut id3v2, rating);
+    }
+
+    id3v2.save_to_path(path, lofty::WriteOptions::default())?;
+
+    debug!("Tags written to {:?}", path);
+
+    Ok(())
+}
+
+fn apply_changes(tag: &mut lofty::Tag, changes: &TagChanges) -> Result<(), lofty::LoftyError> {
+    if let Some(ref title) = changes.title {
+        set_text(tag, lofty::ItemKey::TrackTitle, title);
+    }
+    if let Some(ref artists) = changes.artists {
+        set_artists(tag, artists);
+    }
+    if let Some(ref album) = changes.album {
+        set_text(tag, lofty::ItemKey::AlbumTitle, album);
+    }
+    if let Some(ref album_artist) = changes.album_artist {
+        set_text(tag, lofty::ItemKey::AlbumArtist, album_artist);
+    }
+    if let Some(ref genre) = changes.genre {
+        set_text(tag, lofty::ItemKey::Genre, genre);
+    }
+    match changes.track_number {
+        Some(0) => tag.remove_track(),
+        Some(n) => tag.set_track(n),
+        None => (),
+    }
+    match changes.disc_number {
+        Some(0) => tag.remove_disk(),
+        Some(n) => tag.set_disk(n),
+        None => (),
+    }
+    match changes.year {
+        Some(0) => tag.remove_year(),
+        Some(n) => tag.set_year(n),
+        None => (),
+    }
+    if let Some(ref cover) = changes.cover {
+        set_cover(tag, cover)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_popm_rating() {
+        for rating in 0..=MAX_RATING {
+            assert_eq!(rating_from_popm_byte(popm_byte_from_rating(rating)), rating);
+        }
+
+        assert_eq!(rating_from_popm_byte(0), 0);
+        assert_eq!(rating_from_popm_byte(50), 2);
+        assert_eq!(rating_from_popm_byte(255), 5);
+        assert_eq!(popm_byte_from_rating(10), 255);
+    }
+
+    #[test]
+    fn test_popm_frame() {
+        assert_eq!(rating_from_popm(b"\0\xc4"), Some(4));
+        assert_eq!(
+            rating_from_popm(b"someone@example.com\0\x01\0\0\0\x2a"),
+            Some(1)
+        );
+        assert_eq!(rating_from_popm(b"no-rating\0"), None);
+        assert_eq!(rating_from_popm(b"no-terminator"), None);
+    }
+
+    #[test]
+    fn test_text_rating() {
+        assert_eq!(rating_from_text("0"), Some(0));
+        assert_eq!(rating_from_text("3"), Some(3));
+        assert_eq!(rating_from_text(" 5 "), Some(5));
+        assert_eq!(rating_from_text("60"), Some(3));
+        assert_eq!(rating_from_text("100"), Some(5));
+        assert_eq!(rating_from_text("255"), None);
+        assert_eq!(rating_from_text("great"), None);
+    }
+
+    #[test]
+    fn test_fmps_rating() {
+        assert_eq!(rating_from_fmps("0.0"), Some(0));
+        assert_eq!(rating_from_fmps("0.6"), Some(3));
+        assert_eq!(rating_from_fmps("1"), Some(5));
+        assert_eq!(rating_from_fmps("2"), None);
+    }
+
+    // Minimal, silent files for each tag format; lofty only needs valid
+    // headers to read and write the tags
+    fn wav_file() -> Vec<u8> {
+        let mut data = b"RIFF".to_vec();
+        data.extend(44u32.to_le_bytes());
+        data.extend(b"WAVEfmt ");
+        data.extend(16u32.to_le_bytes());
+        // PCM, mono, 8 kHz, 16 bits
+        data.extend([1, 0, 1, 0]);
+        data.extend(8000u32.to_le_bytes());
+        data.extend(16000u32.to_le_bytes());
+        data.extend([2, 0, 16, 0]);
+        data.extend(b"data");
+        data.extend(8u32.to_le_bytes());
+        data.extend([0; 8]);
+        data
+    }
+
+    fn aiff_file() -> Vec<u8> {
+        let mut data = b"FORM".to_vec();
+        data.extend(54u32.to_be_bytes());
+        data.extend(b"AIFFCOMM");
+        data.extend(18u32.to_be_bytes());
+        // Mono, 4 frames, 16 bits, 8 kHz as an 80-bit float
+        data.extend([0, 1, 0, 0, 0, 4, 0, 16]);
+        data.extend([0x40, 0x0b, 0xfa, 0, 0, 0, 0, 0, 0, 0]);
+        data.extend(b"SSND");
+        data.extend(16u32.to_be_bytes());
+        data.extend([0; 16]);
+        data
+    }
+
+    fn mp3_file() -> Vec<u8> {
+        // MPEG-1 Layer III frames, at 128 kbps and 44.1 kHz
+        let mut data = Vec::new();
+        for _ in 0..4 {
+            data.extend([0xff, 0xfb, 0x90, 0x00]);
+            data.extend([0; 413]);
+        }
+        data
+    }
+
+    fn flac_file() -> Vec<u8> {
+        let mut data = b"fLaC".to_vec();
+        // The STREAMINFO block
+        data.extend([0, 0, 0, 34]);
+        data.extend([0x10, 0, 0x10, 0, 0, 0, 0, 0, 0, 0]);
+        // 44.1 kHz, stereo, 16 bits, 44100 samples
+        let info: u64 = (44100 << 44) | (1 << 41) | (15 << 36) | 44100;
+        data.extend(info.to_be_bytes());
+        data.extend([0; 16]);
+        // The last metadata block, some padding
+        data.extend([0x81, 0, 0, 8]);
+        data.extend([0; 8]);
+        // The start of an audio frame
+        data.extend([0xff, 0xf8, 0x69, 0x08, 0, 0, 0, 0]);
+        data
+    }
+
+    fn ogg_page(header_type: u8, granule: u64, sequence: u32, packet: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS".to_vec();
+        page.extend([0, header_type]);
+        page.extend(granule.to_le_bytes());
+        page.extend(1u32.to_le_bytes());
+        page.extend(sequence.to_le_bytes());
+        page.extend([0; 4]);
+        page.push(1);
+        page.push(packet.len() as u8);
+        page.extend(packet);
+
+        let mut crc: u32 = 0;
+        for byte in &page {
+            crc ^= (*byte as u32) << 24;
+            for _ in 0..8 {
+                crc = if crc & 0x8000_0000 != 0 {
+                    (crc << 1) ^ 0x04c1_1db7
+                } else {
+                    crc << 1
+                };
+            }
+        }
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+        page
+    }
+
+    fn opus_file() -> Vec<u8> {
+        let mut head = b"OpusHead".to_vec();
+        head.extend([1, 2, 0, 0]);
+        head.extend(48000u32.to_le_bytes());
+        head.extend([0, 0, 0]);
+
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend(0u32.to_le_bytes());
+        tags.extend(0u32.to_le_bytes());
+
+        let mut data = ogg_page(0x02, 0, 0, &head);
+        data.extend(ogg_page(0, 0, 1, &tags));
+        data.extend(ogg_page(0x04, 48000, 2, &[0xfc, 0xff, 0xfe]));
+        data
+    }
+
+    fn mp4_atom(name: &[u8], content: &[u8]) -> Vec<u8> {
+        let mut atom = ((content.len() + 8) as u32).to_be_bytes().to_vec();
+        atom.extend(name);
+        atom.extend(content);
+        atom
+    }
+
+    fn mp4_file() -> Vec<u8> {
+        let mut mvhd = vec![0; 100];
+        // A time scale of 1000, and a duration of 1000
+        mvhd[12..16].copy_from_slice(&1000u32.to_be_bytes());
+        mvhd[16..20].copy_from_slice(&1000u32.to_be_bytes());
+
+        let mut mdhd = vec![0; 24];
+        mdhd[12..16].copy_from_slice(&1000u32.to_be_bytes());
+        mdhd[16..20].copy_from_slice(&1000u32.to_be_bytes());
+
+        // An audio track, without any samples
+        let mut hdlr = vec![0; 8];
+        hdlr.extend(b"soun");
+        hdlr.extend([0; 13]);
+
+        let mut mdia = mp4_atom(b"mdhd", &mdhd);
+        mdia.extend(mp4_atom(b"hdlr", &hdlr));
+
+        let mut moov = mp4_atom(b"mvhd", &mvhd);
+        moov.extend(mp4_atom(b"trak", &mp4_atom(b"mdia", &mdia)));
+
+        let mut data = mp4_atom(b"ftyp", b"M4A \0\0\0\0M4A isom");
+        data.extend(mp4_atom(b"moov", &moov));
+        data.extend(mp4_atom(b"mdat", &[0; 8]));
+        data
+    }
+
+    // Sets the rating of a file, saves it, and reads it back
+    fn round_trip(name: &str, contents: &[u8]) {
+        let path =
+            std::env::temp_dir().join(format!("amberol-tags-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+
+        let read_rating = |path: &Path| {
+            let (tagged_file, id3v2_rating) = read_tagged_file(path).unwrap();
+            id3v2_rating.or_else(|| tagged_file.primary_tag().and_then(tag_rating))
+        };
+
+        for rating in [4, 2, 0, 5] {
+            let changes = TagChanges {
+                rating: Some(rating),
+                ..Default::default()
+            };
+            write_tags(&path, &changes).unwrap();
+
+            let expected = if rating > 0 { Some(rating) } else { None };
+            assert_eq!(read_rating(&path), expected, "{name}: rating {rating}");
+        }
+
+        // Setting the rating again does not add more fields
+        let (tagged_file, _) = read_tagged_file(&path).unwrap();
+        let tag = tagged_file.primary_tag().unwrap();
+        let n_ratings = tag
+            .items()
+            .filter(|item| match item.key() {
+                lofty::ItemKey::Popularimeter => true,
+                lofty::ItemKey::Unknown(key) => key == RATING_KEY,
+                _ => false,
+            })
+            .count();
+        assert!(n_ratings <= 1, "{}: {} ratings", name, n_ratings);
+
+        // Other changes leave the rating alone
+        let changes = TagChanges {
+            title: Some("Title".to_string()),
+            ..Default::default()
+        };
+        write_tags(&path, &changes).unwrap();
+        assert_eq!(read_rating(&path), Some(5), "{name}: title");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_rating_round_trip() {
+        round_trip("test.wav", &wav_file());
+        round_trip("test.aiff", &aiff_file());
+        round_trip("test.mp3", &mp3_file());
+        round_trip("test.flac", &flac_file());
+        round_trip("test.opus", &opus_file());
+        round_trip("test.m4a", &mp4_file());
+    }
+
+    #[test]
+    fn test_id3v2_rating_keeps_other_players() {
+        let mut tag = Id3v2Tag::default();
+        tag.insert(Frame::Popularimeter(PopularimeterFrame::new(
+            "someone@example.com".to_string(),
+            64,
+            42,
+        )));
+
+        set_id3v2_rating(&mut tag, 4);
+        assert_eq!(id3v2_rating(&tag), Some(4));
+
+        let frames: Vec<&Frame> = (&tag).into_iter().collect();
+        assert_eq!(frames.len(), 1);
+        match frames[0] {
+            Frame::Popularimeter(popm) => {
+                assert_eq!(popm.email, "someone@example.com");
+                assert_eq!(popm.counter, 42);
+            }
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,361 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The peaks of a waveform are stored on disk in a compact binary format,
+// instead of JSON; the values are quantised, since the waveform is drawn
+// at a much lower resolution than the one of a floating point number.
+//
+// Each file starts with a fixed size header, followed by the levels of
+// each interval: the left and right peaks, then the left and right RMS
+// levels. All values are little endian.
+//
+//   offset  size  field
+//        0     4  magic: "AMBW"
+//        4     1  version of the format
+//        5     1  bits per value: 8 or 16
+//        6     1  number of channels: 2
+//        7     1  number of levels per channel: 2
+//        8     4  interval between peaks, in milliseconds
+//       12     8  fingerprint of the source file
+//       20     4  number of intervals
+//       24     …  levels
+//
+// Files that are truncated, that were written by a different version, or
+// whose header does not match the song are rejected, and the waveform is
+// generated again.
+
+use std::{convert::TryInto, fmt};
+
+use crate::audio::WaveformLevels;
+
+const MAGIC: &[u8; 4] = b"AMBW";
+// Bump this whenever the layout of the file changes
+const VERSION: u8 = 2;
+const CHANNELS: u8 = 2;
+// The peak and the RMS level
+const LEVELS: u8 = 2;
+pub const HEADER_SIZE: usize = 24;
+const VALUES_PER_FRAME: usize = CHANNELS as usize * LEVELS as usize;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Quantization {
+    U8,
+    U16,
+}
+
+impl Quantization {
+    fn bits(&self) -> u8 {
+        match self {
+            Quantization::U8 => 8,
+            Quantization::U16 => 16,
+        }
+    }
+
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            8 => Some(Quantization::U8),
+            16 => Some(Quantization::U16),
+            _ => None,
+        }
+    }
+
+    fn value_size(&self) -> usize {
+        self.bits() as usize / 8
+    }
+
+    fn max_value(&self) -> f64 {
+        match self {
+            Quantization::U8 => u8::MAX as f64,
+            Quantization::U16 => u16::MAX as f64,
+        }
+    }
+
+    fn write(&self, value: f64, buf: &mut Vec<u8>) {
+        // Peaks are normalized between 0 and 1, but clipping can push
+        // them slightly above
+        let v = (value.clamp(0.0, 1.0) * self.max_value()).round();
+        match self {
+            Quantization::U8 => buf.push(v as u8),
+            Quantization::U16 => buf.extend_from_slice(&(v as u16).to_le_bytes()),
+        }
+    }
+
+    fn read(&self, buf: &[u8]) -> f64 {
+        let v = match self {
+            Quantization::U8 => buf[0] as f64,
+            Quantization::U16 => u16::from_le_bytes([buf[0], buf[1]]) as f64,
+        };
+        v / self.max_value()
+    }
+}
+
+// What identifies the peaks of a song
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WaveformHeader {
+    // The interval between two peaks, in milliseconds
+    pub interval_ms: u32,
+    // A fingerprint of the source file, to detect stale entries
+    pub fingerprint: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CacheError {
+    Truncated,
+    InvalidMagic,
+    UnsupportedVersion(u8),
+    InvalidFormat,
+    Stale,
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CacheError::Truncated => write!(f, "truncated file"),
+            CacheError::InvalidMagic => write!(f, "not a waveform file"),
+            CacheError::UnsupportedVersion(v) => write!(f, "unsupported version {}", v),
+            CacheError::InvalidFormat => write!(f, "invalid header"),
+            CacheError::Stale => write!(f, "the song has changed"),
+        }
+    }
+}
+
+pub fn encode(
+    header: &WaveformHeader,
+    peaks: &[WaveformLevels],
+    quantization: Quantization,
+) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(
+        HEADER_SIZE + peaks.len() * VALUES_PER_FRAME * quantization.value_size(),
+    );
+
+    buf.extend_from_slice(MAGIC);
+    buf.push(VERSION);
+    buf.push(quantization.bits());
+    buf.push(CHANNELS);
+    buf.push(LEVELS);
+    buf.extend_from_slice(&header.interval_ms.to_le_bytes());
+    buf.extend_from_slice(&header.fingerprint.to_le_bytes());
+    buf.extend_from_slice(&(peaks.len() as u32).to_le_bytes());
+
+    for levels in peaks {
+        quantization.write(levels.peak.0, &mut buf);
+        quantization.write(levels.peak.1, &mut buf);
+        quantization.write(levels.rms.0, &mut buf);
+        quantization.write(levels.rms.1, &mut buf);
+    }
+
+    buf
+}
+
+// Parses the header at the start of the data, and returns the size of
+// the values and the number of peaks
+fn parse_header(
+    data: &[u8],
+    expected: &WaveformHeader,
+) -> Result<(Quantization, usize), CacheError> {
+    if data.len() < HEADER_SIZE {
+        return Err(CacheError::Truncated);
+    }
+    if &data[0..4] != MAGIC {
+        return Err(CacheError::InvalidMagic);
+    }
+    if data[4] != VERSION {
+        return Err(CacheError::UnsupportedVersion(data[4]));
+    }
+
+    let quantization = Quantization::from_bits(data[5]).ok_or(CacheError::InvalidFormat)?;
+    if data[6] != CHANNELS || data[7] != LEVELS {
+        return Err(CacheError::InvalidFormat);
+    }
+
+    let header = WaveformHeader {
+        interval_ms: u32::from_le_bytes(data[8..12].try_into().unwrap()),
+        fingerprint: u64::from_le_bytes(data[12..20].try_into().unwrap()),
+    };
+    if header != *expected {
+        return Err(CacheError::Stale);
+    }
+
+    let n_peaks = u32::from_le_bytes(data[20..24].try_into().unwrap()) as usize;
+
+    Ok((quantization, n_peaks))
+}
+
+fn file_size(quantization: Quantization, n_peaks: usize) -> usize {
+    HEADER_SIZE + n_peaks * VALUES_PER_FRAME * quantization.value_size()
+}
+
+// Checks the header of a file of the given size, without reading the
+// peaks; `header` only needs to contain the first `HEADER_SIZE` bytes
+pub fn check(header: &[u8], size: usize, expected: &WaveformHeader) -> Result<(), CacheError> {
+    let (quantization, n_peaks) = parse_header(header, expected)?;
+    if size != file_size(quantization, n_peaks) {
+        return Err(CacheError::Truncated);
+    }
+
+    Ok(())
+}
+
+// Decodes the peaks, as long as they were generated for the expected
+// header
+pub fn decode(data: &[u8], expected: &WaveformHeader) -> Result<Vec<WaveformLevels>, CacheError> {
+    let (quantization, n_peaks) = parse_header(data, expected)?;
+    if data.len() != file_size(quantization, n_peaks) {
+        return Err(CacheError::Truncated);
+    }
+
+    let value_size = quantization.value_size();
+    let frame_size = VALUES_PER_FRAME * value_size;
+    let body = &data[HEADER_SIZE..];
+
+    let peaks = body
+        .chunks_exact(frame_size)
+        .map(|frame| {
+            let value = |idx: usize| quantization.read(&frame[idx * value_size..]);
+            WaveformLevels {
+                peak: (value(0), value(1)),
+                rms: (value(2), value(3)),
+            }
+        })
+        .collect();
+
+    Ok(peaks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: WaveformHeader = WaveformHeader {
+        interval_ms: 250,
+        fingerprint: 0x0123_4567_89ab_cdef,
+    };
+
+    fn peaks() -> Vec<WaveformLevels> {
+        vec![
+            WaveformLevels {
+                peak: (0.0, 1.0),
+                rms: (0.0, 0.7),
+            },
+            WaveformLevels {
+                peak: (0.5, 0.25),
+                rms: (0.3, 0.125),
+            },
+            WaveformLevels {
+                peak: (0.123, 0.987),
+                rms: (0.05, 0.4),
+            },
+            WaveformLevels {
+                peak: (1.5, -0.1),
+                rms: (1.2, 0.0),
+            },
+        ]
+    }
+
+    fn assert_close(a: f64, b: f64, epsilon: f64) {
+        assert!((a.clamp(0.0, 1.0) - b).abs() <= epsilon, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for (quantization, epsilon) in [
+            (Quantization::U8, 0.5 / 255.0),
+            (Quantization::U16, 0.5 / 65535.0),
+        ] {
+            let data = encode(&HEADER, &peaks(), quantization);
+            assert_eq!(
+                data.len(),
+                HEADER_SIZE + 4 * VALUES_PER_FRAME * quantization.value_size()
+            );
+
+            let res = decode(&data, &HEADER).unwrap();
+            assert_eq!(res.len(), 4);
+            for (expected, levels) in peaks().iter().zip(res.iter()) {
+                assert_close(expected.peak.0, levels.peak.0, epsilon);
+                assert_close(expected.peak.1, levels.peak.1, epsilon);
+                assert_close(expected.rms.0, levels.rms.0, epsilon);
+                assert_close(expected.rms.1, levels.rms.1, epsilon);
+            }
+        }
+
+        let data = encode(&HEADER, &[], Quantization::U16);
+        assert_eq!(decode(&data, &HEADER), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_corrupt() {
+        let data = encode(&HEADER, &peaks(), Quantization::U16);
+
+        for len in [0, 3, HEADER_SIZE - 1, HEADER_SIZE, data.len() - 1] {
+            assert_eq!(decode(&data[..len], &HEADER), Err(CacheError::Truncated));
+        }
+
+        let mut extra = data.clone();
+        extra.push(0);
+        assert_eq!(decode(&extra, &HEADER), Err(CacheError::Truncated));
+
+        assert_eq!(decode(b"[[0.5,0.5]]", &HEADER), Err(CacheError::Truncated));
+        assert_eq!(
+            decode(b"[[0.5,0.5],[0.25,0.25],[0.0,0.0]]", &HEADER),
+            Err(CacheError::InvalidMagic)
+        );
+
+        let mut version = data.clone();
+        version[4] = VERSION + 1;
+        assert_eq!(
+            decode(&version, &HEADER),
+            Err(CacheError::UnsupportedVersion(VERSION + 1))
+        );
+
+        let mut bits = data.clone();
+        bits[5] = 12;
+        assert_eq!(decode(&bits, &HEADER), Err(CacheError::InvalidFormat));
+
+        let mut channels = data.clone();
+        channels[6] = 1;
+        assert_eq!(decode(&channels, &HEADER), Err(CacheError::InvalidFormat));
+
+        let mut levels = data;
+        levels[7] = 1;
+        assert_eq!(decode(&levels, &HEADER), Err(CacheError::InvalidFormat));
+    }
+
+    #[test]
+    fn test_check() {
+        let data = encode(&HEADER, &peaks(), Quantization::U16);
+        let header = &data[..HEADER_SIZE];
+
+        assert_eq!(check(header, data.len(), &HEADER), Ok(()));
+        assert_eq!(
+            check(header, data.len() - 1, &HEADER),
+            Err(CacheError::Truncated)
+        );
+        assert_eq!(
+            check(&header[..HEADER_SIZE - 1], data.len(), &HEADER),
+            Err(CacheError::Truncated)
+        );
+
+        let other = WaveformHeader {
+            fingerprint: 42,
+            ..HEADER
+        };
+        assert_eq!(check(header, data.len(), &other), Err(CacheError::Stale));
+    }
+
+    #[test]
+    fn test_stale() {
+        let data = encode(&HEADER, &peaks(), Quantization::U8);
+
+        let other = WaveformHeader {
+            fingerprint: 42,
+            ..HEADER
+        };
+        assert_eq!(decode(&data, &other), Err(CacheError::Stale));
+
+        let other = WaveformHeader {
+            interval_ms: 100,
+            ..HEADER
+        };
+        assert_eq!(decode(&data, &other), Err(CacheError::Stale));
+    }
+}
@@ -1,14 +1,63 @@
 // SPDX-FileCopyrightText: 2022  Emmanuele Bassi
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use std::cell::RefCell;
+use std::{cell::RefCell, convert::TryInto, fs::File, io::Read, path::PathBuf, rc::Rc};
 
 use glib::clone;
-use gst::prelude::*;
 use gtk::{gio, glib, prelude::*, subclass::prelude::*};
 use log::{debug, warn};
+use sha2::{Digest, Sha256};
+
+use crate::audio::{
+    analysis_pipeline::{AnalysisPipeline, AnalysisSpeed},
+    waveform_cache::{self, Quantization, WaveformHeader},
+    Controller, PlaybackState, RepeatMode, Song,
+};
+
+// The interval between two peaks of the waveform
+pub const PEAK_INTERVAL_MS: u32 = 250;
+
+// The levels of the left and right channels over an interval of the song,
+// normalized between 0 and 1
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WaveformLevels {
+    pub peak: (f64, f64),
+    pub rms: (f64, f64),
+}
+
+// Converts the levels posted by the "level" element, in dB
+fn levels_from_db(array: &glib::ValueArray) -> (f64, f64) {
+    let v1 = array[0].get::<f64>().unwrap();
+    let v2 = array[1].get::<f64>().unwrap();
+    (f64::powf(10.0, v1 / 20.0), f64::powf(10.0, v2 / 20.0))
+}
 
-use crate::audio::{Controller, PlaybackState, RepeatMode, Song};
+// Starts computing the peaks of the song at the given URI; the peaks are
+// passed to the callback at the end of the stream, or if decoding fails
+pub fn peaks_pipeline<F>(uri: &str, speed: AnalysisSpeed, on_done: F) -> Option<AnalysisPipeline>
+where
+    F: FnOnce(Vec<WaveformLevels>) + 'static,
+{
+    let peaks: Rc<RefCell<Vec<WaveformLevels>>> = Rc::new(RefCell::new(Vec::new()));
+
+    AnalysisPipeline::new(
+        uri,
+        "audio/x-raw,channels=2",
+        &format!(
+            "level name=level interval={}",
+            PEAK_INTERVAL_MS as u64 * 1_000_000
+        ),
+        speed,
+        clone!(@strong peaks => move |s| {
+            if s.has_name("level") {
+                let peak = levels_from_db(s.get::<&glib::ValueArray>("peak").unwrap());
+                let rms = levels_from_db(s.get::<&glib::ValueArray>("rms").unwrap());
+                peaks.borrow_mut().push(WaveformLevels { peak, rms });
+            }
+        }),
+        move || on_done(peaks.take()),
+    )
+}
 
 mod imp {
     use glib::{ParamSpec, ParamSpecBoolean, Value};
@@ -19,8 +68,8 @@ mod imp {
     #[derive(Debug, Default)]
     pub struct WaveformGenerator {
         pub song: RefCell<Option<Song>>,
-        pub peaks: RefCell<Option<Vec<(f64, f64)>>>,
-        pub pipeline: RefCell<Option<(gst::Element, gst::bus::BusWatchGuard)>>,
+        pub peaks: RefCell<Option<Vec<WaveformLevels>>>,
+        pub pipeline: RefCell<Option<AnalysisPipeline>>,
     }
 
     #[glib::object_subclass]
@@ -31,13 +80,7 @@ mod imp {
 
     impl ObjectImpl for WaveformGenerator {
         fn dispose(&self) {
-            if let Some((pipeline, _bus_watch)) = self.pipeline.take() {
-                pipeline.send_event(gst::event::Eos::new());
-                match pipeline.set_state(gst::State::Null) {
-                    Ok(_) => {}
-                    Err(err) => warn!("Unable to set existing pipeline to Null state: {}", err),
-                }
-            }
+            self.pipeline.take();
         }
 
         fn properties() -> &'static [ParamSpec] {
@@ -70,12 +113,19 @@ impl Controller for WaveformGenerator {
     fn set_playback_state(&self, _playback_state: &PlaybackState) {}
 
     fn set_song(&self, song: &Song) {
+        // The peaks of the previous song are not needed any more
+        self.imp().pipeline.take();
         self.imp().song.replace(Some(song.clone()));
         self.load_peaks();
     }
 
+    fn update_song(&self, _song: &Song) {}
+
     fn set_position(&self, _position: u64) {}
+    fn seeked(&self, _position: u64) {}
     fn set_repeat_mode(&self, _mode: RepeatMode) {}
+    fn set_shuffled(&self, _shuffled: bool) {}
+    fn set_volume(&self, _volume: f64) {}
 }
 
 impl WaveformGenerator {
@@ -83,40 +133,123 @@ impl WaveformGenerator {
         WaveformGenerator::default()
     }
 
-    pub fn peaks(&self) -> Option<Vec<(f64, f64)>> {
+    pub fn peaks(&self) -> Option<Vec<WaveformLevels>> {
         (*self.imp().peaks.borrow()).as_ref().cloned()
     }
 
+    fn cache_path(uuid: &str) -> PathBuf {
+        let mut cache = glib::user_cache_dir();
+        cache.push("amberol");
+        cache.push("waveforms");
+        cache.push(format!("{}.peaks", uuid));
+        cache
+    }
+
+    // Older versions stored the peaks as JSON
+    fn legacy_cache_path(uuid: &str) -> PathBuf {
+        WaveformGenerator::cache_path(uuid).with_extension("json")
+    }
+
+    // The identity of a song already depends on its audio data; the
+    // fingerprint covers the stream properties that affect the peaks, so
+    // that entries moved to a different identity are not reused by mistake
+    fn cache_header(song: &Song) -> WaveformHeader {
+        let mut hasher = Sha256::new();
+        hasher.update(song.duration().to_le_bytes());
+        hasher.update(song.sample_rate().unwrap_or(0).to_le_bytes());
+        hasher.update(song.channels().unwrap_or(0).to_le_bytes());
+        let digest = hasher.finalize();
+
+        WaveformHeader {
+            interval_ms: PEAK_INTERVAL_MS,
+            fingerprint: u64::from_le_bytes(digest[..8].try_into().unwrap()),
+        }
+    }
+
+    // The peaks only depend on the audio data, so when the UUID of a song
+    // changes, for instance when migrating from the identity used by older
+    // versions, we can keep using the cached ones
+    pub fn move_cached_peaks(old_uuid: &str, new_uuid: &str) {
+        let old_cache = WaveformGenerator::cache_path(old_uuid);
+        if !old_cache.exists() {
+            return;
+        }
+
+        let new_cache = WaveformGenerator::cache_path(new_uuid);
+        match std::fs::rename(&old_cache, &new_cache) {
+            Ok(_) => debug!("Waveform cache moved to: {:?}", &new_cache),
+            Err(e) => warn!("Unable to move waveform cache {:?}: {}", &old_cache, e),
+        }
+    }
+
+    // The peaks stored by older versions lack the RMS levels, so they are
+    // generated again
+    fn remove_legacy_peaks(uuid: &str) {
+        let legacy_cache = WaveformGenerator::legacy_cache_path(uuid);
+        if !legacy_cache.exists() {
+            return;
+        }
+
+        match std::fs::remove_file(&legacy_cache) {
+            Ok(_) => debug!("Waveform cache removed: {:?}", &legacy_cache),
+            Err(e) => warn!("Unable to remove waveform cache {:?}: {}", &legacy_cache, e),
+        }
+    }
+
+    // Whether the peaks of the song are cached, and usable; only the
+    // header of the cache file is read
+    pub fn has_cached_peaks(song: &Song) -> bool {
+        let uuid = match song.uuid() {
+            Some(u) => u,
+            None => return false,
+        };
+
+        let cache = WaveformGenerator::cache_path(&uuid);
+        let mut file = match File::open(&cache) {
+            Ok(f) => f,
+            Err(_) => return false,
+        };
+
+        let mut header = [0u8; waveform_cache::HEADER_SIZE];
+        let size = file.metadata().map(|m| m.len() as usize);
+        match (file.read_exact(&mut header), size) {
+            (Ok(_), Ok(size)) => {
+                waveform_cache::check(&header, size, &WaveformGenerator::cache_header(song)).is_ok()
+            }
+            _ => false,
+        }
+    }
+
+    pub fn store_peaks(song: &Song, peaks: &[WaveformLevels]) {
+        if let Some(uuid) = song.uuid() {
+            let cache = WaveformGenerator::cache_path(&uuid);
+            if let Some(parent) = cache.parent() {
+                glib::mkdir_with_parents(parent, 0o755);
+            }
+
+            let data = waveform_cache::encode(
+                &WaveformGenerator::cache_header(song),
+                peaks,
+                Quantization::U16,
+            );
+            let file = gio::File::for_path(&cache);
+            file.replace_contents_async(
+                data,
+                None,
+                false,
+                gio::FileCreateFlags::NONE,
+                gio::Cancellable::NONE,
+                move |_| {
+                    debug!("Waveform cached at: {:?}", &cache);
+                },
+            );
+        }
+    }
+
     fn save_peaks(&self) {
         if let Some(peaks) = self.peaks() {
-            let song = match self.imp().song.borrow().as_ref() {
-                Some(s) => s.clone(),
-                None => {
-                    self.notify("has-peaks");
-                    return;
-                }
-            };
-
-            if let Some(uuid) = song.uuid() {
-                let mut cache = glib::user_cache_dir();
-                cache.push("amberol");
-                cache.push("waveforms");
-                glib::mkdir_with_parents(&cache, 0o755);
-
-                cache.push(format!("{}.json", uuid));
-
-                let j = serde_json::to_string(&peaks).unwrap();
-                let file = gio::File::for_path(&cache);
-                file.replace_contents_async(
-                    j,
-                    None,
-                    false,
-                    gio::FileCreateFlags::NONE,
-                    gio::Cancellable::NONE,
-                    move |_| {
-                        debug!("Waveform cached at: {:?}", &cache);
-                    },
-                );
+            if let Some(song) = self.imp().song.borrow().as_ref() {
+                WaveformGenerator::store_peaks(song, &peaks);
             }
         }
 
@@ -130,23 +263,26 @@ impl WaveformGenerator {
         };
 
         if let Some(uuid) = song.uuid() {
-            let mut cache = glib::user_cache_dir();
-            cache.push("amberol");
-            cache.push("waveforms");
-            cache.push(format!("{}.json", uuid));
-
+            let cache = WaveformGenerator::cache_path(&uuid);
+            let header = WaveformGenerator::cache_header(&song);
             let file = gio::File::for_path(&cache);
             file.load_contents_async(
                 gio::Cancellable::NONE,
                 clone!(@strong self as this => move |res| {
                     match res {
-                        Ok((bytes, _tag)) => {
-                            let p: Vec<(f64, f64)> = serde_json::from_slice(&bytes[..]).unwrap();
-                            this.imp().peaks.replace(Some(p));
-                            this.notify("has-peaks");
-                        }
+                        Ok((bytes, _tag)) => match waveform_cache::decode(&bytes, &header) {
+                            Ok(p) => {
+                                this.imp().peaks.replace(Some(p));
+                                this.notify("has-peaks");
+                            }
+                            Err(err) => {
+                                warn!("Discarding waveform cache {:?}: {}", &cache, err);
+                                this.generate_peaks();
+                            }
+                        },
                         Err(err) => {
                             debug!("Could not read waveform cache file: {}", err);
+                            WaveformGenerator::remove_legacy_peaks(&uuid);
                             this.generate_peaks();
                         }
                     }
@@ -156,115 +292,34 @@ impl WaveformGenerator {
     }
 
     fn generate_peaks(&self) {
-        if let Some((pipeline, _bus_watch)) = self.imp().pipeline.take() {
-            // Stop any running pipeline, and ensure that we have nothing to
-            // report
-            self.imp().peaks.replace(None);
-            pipeline.send_event(gst::event::Eos::new());
-            match pipeline.set_state(gst::State::Null) {
-                Ok(_) => {}
-                Err(err) => warn!("Unable to set existing pipeline to Null state: {}", err),
-            }
-        }
+        // Stop any running pipeline, and ensure that we have nothing to
+        // report
+        self.imp().pipeline.take();
+        self.imp().peaks.replace(None);
 
         let song = match self.imp().song.borrow().as_ref() {
             Some(s) => s.clone(),
             None => {
-                self.imp().peaks.replace(None);
                 self.notify("has-peaks");
                 return;
             }
         };
 
-        // Reset the peaks vector
-        let peaks: Vec<(f64, f64)> = Vec::new();
-        self.imp().peaks.replace(Some(peaks));
-
-        let pipeline_str = "uridecodebin name=uridecodebin ! audioconvert ! audio/x-raw,channels=2 ! level name=level interval=250000000 ! fakesink name=faked";
-        let pipeline = match gst::parse::launch(pipeline_str) {
-            Ok(pipeline) => pipeline,
-            Err(err) => {
-                warn!("Unable to generate the waveform: {}", err);
-                self.imp().peaks.replace(None);
-                self.notify("has-peaks");
-                return;
+        let pipeline = peaks_pipeline(
+            &song.uri(),
+            AnalysisSpeed::Full,
+            clone!(@weak self as this => move |peaks| {
+                this.imp().pipeline.replace(None);
+                this.imp().peaks.replace(Some(peaks));
+                this.save_peaks();
+            }),
+        );
+
+        match pipeline {
+            Some(pipeline) => {
+                self.imp().pipeline.replace(Some(pipeline));
             }
-        };
-
-        let uridecodebin = pipeline
-            .downcast_ref::<gst::Bin>()
-            .unwrap()
-            .by_name("uridecodebin")
-            .unwrap();
-        uridecodebin.set_property("uri", song.uri());
-
-        let fakesink = pipeline
-            .downcast_ref::<gst::Bin>()
-            .unwrap()
-            .by_name("faked")
-            .unwrap();
-        fakesink.set_property("qos", false);
-        fakesink.set_property("sync", false);
-
-        let bus = pipeline
-            .bus()
-            .expect("Pipeline without bus. Shouldn't happen!");
-
-        debug!("Adding bus watch");
-        let bus_watch = bus.add_watch_local(clone!(@weak self as this, @weak pipeline => @default-return glib::ControlFlow::Break, move |_, msg| {
-            use gst::MessageView;
-
-            match msg.view() {
-                MessageView::Eos(..) => {
-                    debug!("End of waveform stream");
-                    pipeline.set_state(gst::State::Null).expect("Unable to set 'null' state");
-                    // We're done
-                    this.imp().pipeline.replace(None);
-                    this.save_peaks();
-                    return glib::ControlFlow::Break;
-                }
-                MessageView::Error(err) => {
-                    warn!("Pipeline error: {:?}", err);
-                    pipeline.set_state(gst::State::Null).expect("Unable to set 'null' state");
-                    // We're done
-                    this.imp().pipeline.replace(None);
-                    this.save_peaks();
-                    return glib::ControlFlow::Break;
-                }
-                MessageView::Element(element) => {
-                    if let Some(s) = element.structure() {
-                        if s.has_name("level") {
-                            let peaks_array = s.get::<&glib::ValueArray>("peak").unwrap();
-                            let v1 = peaks_array[0].get::<f64>().unwrap();
-                            let v2 = peaks_array[1].get::<f64>().unwrap();
-                            // Normalize peaks between 0 and 1
-                            let peak1 = f64::powf(10.0, v1 / 20.0);
-                            let peak2 = f64::powf(10.0, v2 / 20.0);
-                            if let Some(ref mut peaks) = *this.imp().peaks.borrow_mut() {
-                                peaks.push((peak1, peak2));
-                            }
-                        }
-                    }
-                }
-                _ => (),
-            };
-
-            glib::ControlFlow::Continue
-        }))
-        .expect("failed to add bus watch");
-
-        match pipeline.set_state(gst::State::Playing) {
-            Ok(_) => {
-                self.imp().pipeline.replace(Some((pipeline, bus_watch)));
-            }
-            Err(err) => {
-                warn!("Unable to generate the waveform: {}", err);
-                pipeline
-                    .set_state(gst::State::Null)
-                    .expect("Pipeline reset failed");
-                self.imp().peaks.replace(None);
-                self.notify("has-peaks");
-            }
-        };
+            None => self.notify("has-peaks"),
+        }
     }
 }
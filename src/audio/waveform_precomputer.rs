@@ -0,0 +1,304 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Generating the waveform of a long song takes a while, so we compute the
+// waveforms of the songs coming up next in the background, and store them
+// in the cache before they start playing. While nothing is playing, we go
+// through the rest of the queue as well.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashSet, VecDeque},
+};
+
+use glib::clone;
+use gtk::{glib, prelude::*, subclass::prelude::*};
+use log::debug;
+
+use crate::audio::{
+    analysis_pipeline::{AnalysisPipeline, AnalysisSpeed},
+    waveform_generator::peaks_pipeline,
+    Controller, PlaybackState, Queue, RepeatMode, Song, WaveformGenerator, WaveformLevels,
+};
+
+// The number of songs after the current one to precompute while playing
+const PRECOMPUTE_AHEAD: usize = 3;
+
+// Decoding competes with the playback for the CPU, so we limit the number
+// of pipelines running at the same time
+const MAX_JOBS_PLAYING: usize = 1;
+const MAX_JOBS_IDLE: usize = 2;
+
+mod imp {
+    use super::*;
+
+    #[derive(Debug, Default)]
+    pub struct WaveformPrecomputer {
+        pub queue: RefCell<Option<Queue>>,
+        pub playing: Cell<bool>,
+        // The songs waiting to be precomputed, in order
+        pub pending: RefCell<VecDeque<Song>>,
+        // The running pipelines, with the UUID of their song
+        pub jobs: RefCell<Vec<(String, AnalysisPipeline)>>,
+        // The songs precomputed so far; their cache entry might still be
+        // in the process of being written
+        pub done: RefCell<HashSet<String>>,
+        pub schedule_id: RefCell<Option<glib::SourceId>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for WaveformPrecomputer {
+        const NAME: &'static str = "AmberolWaveformPrecomputer";
+        type Type = super::WaveformPrecomputer;
+    }
+
+    impl ObjectImpl for WaveformPrecomputer {
+        fn dispose(&self) {
+            if let Some(id) = self.schedule_id.take() {
+                id.remove();
+            }
+            self.pending.borrow_mut().clear();
+            self.jobs.take();
+        }
+    }
+}
+
+glib::wrapper! {
+    pub struct WaveformPrecomputer(ObjectSubclass<imp::WaveformPrecomputer>);
+}
+
+impl Default for WaveformPrecomputer {
+    fn default() -> Self {
+        glib::Object::new()
+    }
+}
+
+impl Controller for WaveformPrecomputer {
+    fn set_playback_state(&self, playback_state: &PlaybackState) {
+        let playing = *playback_state == PlaybackState::Playing;
+        if self.imp().playing.replace(playing) != playing {
+            self.schedule();
+        }
+    }
+
+    fn set_song(&self, _song: &Song) {
+        self.schedule();
+    }
+
+    fn update_song(&self, _song: &Song) {}
+    fn set_position(&self, _position: u64) {}
+
+    fn seeked(&self, _position: u64) {}
+
+    fn set_repeat_mode(&self, _mode: RepeatMode) {
+        self.schedule();
+    }
+
+    // Shuffling changes the items of the queue, which we already track
+    fn set_shuffled(&self, _shuffled: bool) {}
+
+    fn set_volume(&self, _volume: f64) {}
+}
+
+// The positions of the songs to precompute, in the order in which they are
+// going to be played; only the next few songs are returned, unless we want
+// the whole queue
+fn precompute_order(
+    n_songs: u32,
+    current: Option<u32>,
+    repeat_mode: RepeatMode,
+    whole_queue: bool,
+) -> Vec<u32> {
+    let start = current.map_or(0, |c| c.saturating_add(1));
+    let mut res: Vec<u32> = (start..n_songs).collect();
+
+    // The songs before the current one come up again when repeating the
+    // queue; otherwise, they come last
+    if whole_queue || repeat_mode == RepeatMode::RepeatAll {
+        res.extend(0..current.unwrap_or(0).min(n_songs));
+    }
+
+    if !whole_queue {
+        res.truncate(PRECOMPUTE_AHEAD);
+    }
+
+    res
+}
+
+impl WaveformPrecomputer {
+    pub fn new(queue: &Queue) -> Self {
+        let res = Self::default();
+        res.imp().queue.replace(Some(queue.clone()));
+
+        queue.model().connect_items_changed(clone!(@weak res => move |_, _, _, _| {
+            // Whatever we were doing is based on the old contents
+            // of the queue
+            res.cancel();
+            res.schedule();
+        }));
+
+        res
+    }
+
+    // Stops every precomputation in progress
+    pub fn cancel(&self) {
+        let imp = self.imp();
+        imp.pending.borrow_mut().clear();
+
+        let jobs = imp.jobs.take();
+        if !jobs.is_empty() {
+            debug!("Cancelling {} waveform precomputations", jobs.len());
+        }
+    }
+
+    // Updates the list of songs to precompute once the main loop is idle,
+    // so that multiple changes in a row are coalesced
+    fn schedule(&self) {
+        let imp = self.imp();
+        if imp.schedule_id.borrow().is_some() {
+            return;
+        }
+
+        let id = glib::idle_add_local_full(
+            glib::Priority::LOW,
+            clone!(@weak self as this => @default-return glib::ControlFlow::Break, move || {
+                this.imp().schedule_id.replace(None);
+                this.update_jobs();
+                glib::ControlFlow::Break
+            }),
+        );
+        imp.schedule_id.replace(Some(id));
+    }
+
+    fn max_jobs(&self) -> usize {
+        if self.imp().playing.get() {
+            MAX_JOBS_PLAYING
+        } else {
+            MAX_JOBS_IDLE
+        }
+    }
+
+    fn update_jobs(&self) {
+        let imp = self.imp();
+        let queue = match imp.queue.borrow().as_ref() {
+            Some(q) => q.clone(),
+            None => return,
+        };
+
+        let songs: Vec<Song> = precompute_order(
+            queue.n_songs(),
+            queue.current_song_index(),
+            queue.repeat_mode(),
+            !imp.playing.get(),
+        )
+        .into_iter()
+        .filter_map(|pos| queue.song_at(pos))
+        .collect();
+
+        // Stop the precomputations that are not needed any more, like the
+        // one for a song that just started playing, as the waveform
+        // generator takes care of it
+        let uuids: HashSet<String> = songs.iter().filter_map(|s| s.uuid()).collect();
+        {
+            let mut jobs = imp.jobs.borrow_mut();
+            jobs.retain(|(uuid, _)| uuids.contains(uuid));
+            // Playback started while we were going through the queue
+            jobs.truncate(self.max_jobs());
+        }
+
+        let running: HashSet<String> = imp.jobs.borrow().iter().map(|(u, _)| u.clone()).collect();
+        imp.pending.replace(
+            songs
+                .into_iter()
+                .filter(|s| s.uuid().is_some_and(|u| !running.contains(&u)))
+                .collect(),
+        );
+
+        self.start_jobs();
+    }
+
+    fn start_jobs(&self) {
+        let imp = self.imp();
+        while imp.jobs.borrow().len() < self.max_jobs() {
+            let song = match imp.pending.borrow_mut().pop_front() {
+                Some(s) => s,
+                None => break,
+            };
+            let uuid = match song.uuid() {
+                Some(u) => u,
+                None => continue,
+            };
+            if imp.done.borrow().contains(&uuid) || WaveformGenerator::has_cached_peaks(&song) {
+                continue;
+            }
+
+            debug!("Precomputing waveform for: {}", song.uri());
+            let pipeline = peaks_pipeline(
+                &song.uri(),
+                AnalysisSpeed::Background,
+                clone!(@weak self as this, @strong song, @strong uuid => move |peaks| {
+                    this.finish_job(&song, &uuid, peaks);
+                }),
+            );
+            if let Some(pipeline) = pipeline {
+                imp.jobs.borrow_mut().push((uuid, pipeline));
+            }
+        }
+    }
+
+    fn finish_job(&self, song: &Song, uuid: &str, peaks: Vec<WaveformLevels>) {
+        let imp = self.imp();
+        let job = {
+            let mut jobs = imp.jobs.borrow_mut();
+            jobs.iter()
+                .position(|(u, _)| u == uuid)
+                .map(|idx| jobs.remove(idx))
+        };
+        drop(job);
+
+        if !peaks.is_empty() {
+            WaveformGenerator::store_peaks(song, &peaks);
+        }
+        imp.done.borrow_mut().insert(uuid.to_string());
+
+        self.schedule();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_precompute_ahead() {
+        let order = |current, repeat_mode| precompute_order(10, current, repeat_mode, false);
+
+        assert_eq!(order(None, RepeatMode::Consecutive), vec![0, 1, 2]);
+        assert_eq!(order(Some(0), RepeatMode::Consecutive), vec![1, 2, 3]);
+        assert_eq!(order(Some(8), RepeatMode::Consecutive), vec![9]);
+        assert_eq!(order(Some(9), RepeatMode::Consecutive), Vec::<u32>::new());
+        assert_eq!(order(Some(8), RepeatMode::RepeatAll), vec![9, 0, 1]);
+        assert_eq!(order(Some(9), RepeatMode::RepeatAll), vec![0, 1, 2]);
+        assert_eq!(order(Some(4), RepeatMode::RepeatOne), vec![5, 6, 7]);
+    }
+
+    #[test]
+    fn test_precompute_whole_queue() {
+        let order = |current| precompute_order(5, current, RepeatMode::Consecutive, true);
+
+        assert_eq!(order(None), vec![0, 1, 2, 3, 4]);
+        assert_eq!(order(Some(0)), vec![1, 2, 3, 4]);
+        assert_eq!(order(Some(2)), vec![3, 4, 0, 1]);
+        assert_eq!(order(Some(4)), vec![0, 1, 2, 3]);
+
+        assert_eq!(
+            precompute_order(0, None, RepeatMode::RepeatAll, true),
+            Vec::<u32>::new()
+        );
+        // The current position can be out of date while the queue changes
+        assert_eq!(
+            precompute_order(2, Some(5), RepeatMode::RepeatAll, true),
+            vec![0, 1]
+        );
+    }
+}
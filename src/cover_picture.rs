@@ -24,7 +24,9 @@ impl AsRef<str> for CoverSize {
 }
 
 mod imp {
-    use glib::{ParamSpec, ParamSpecEnum, ParamSpecObject, Value};
+    use glib::{
+        subclass::Signal, ParamSpec, ParamSpecBoolean, ParamSpecEnum, ParamSpecObject, Value,
+    };
     use once_cell::sync::Lazy;
 
     use super::*;
@@ -36,6 +38,7 @@ mod imp {
     pub struct CoverPicture {
         pub cover: RefCell<Option<gdk::Texture>>,
         pub cover_size: Cell<CoverSize>,
+        pub activatable: Cell<bool>,
     }
 
     #[glib::object_subclass]
@@ -63,6 +66,33 @@ mod imp {
                     picture.queue_draw();
                 }),
             );
+
+            let click_gesture = gtk::GestureClick::new();
+            click_gesture.connect_released(
+                clone!(@weak self as obj => move |gesture, n_press, _, _| {
+                    if n_press == 1 && obj.activatable.get() {
+                        gesture.set_state(gtk::EventSequenceState::Claimed);
+                        obj.obj().emit_by_name::<()>("activated", &[]);
+                    }
+                }),
+            );
+            self.obj().add_controller(click_gesture);
+
+            let key_controller = gtk::EventControllerKey::new();
+            key_controller.connect_key_pressed(
+                clone!(@weak self as obj => @default-return glib::Propagation::Proceed, move |_, keyval, _, _| {
+                    let is_activate_key = matches!(
+                        keyval,
+                        gdk::Key::Return | gdk::Key::KP_Enter | gdk::Key::space
+                    );
+                    if is_activate_key && obj.activatable.get() {
+                        obj.obj().emit_by_name::<()>("activated", &[]);
+                        return glib::Propagation::Stop;
+                    }
+                    glib::Propagation::Proceed
+                }),
+            );
+            self.obj().add_controller(key_controller);
         }
 
         fn properties() -> &'static [ParamSpec] {
@@ -70,6 +100,7 @@ mod imp {
                 vec![
                     ParamSpecObject::builder::<gdk::Texture>("cover").build(),
                     ParamSpecEnum::builder::<CoverSize>("cover-size").build(),
+                    ParamSpecBoolean::builder("activatable").build(),
                 ]
             });
             PROPERTIES.as_ref()
@@ -79,6 +110,7 @@ mod imp {
             match pspec.name() {
                 "cover" => self.cover.borrow().to_value(),
                 "cover-size" => self.cover_size.get().to_value(),
+                "activatable" => self.activatable.get().to_value(),
                 _ => unimplemented!(),
             }
         }
@@ -91,9 +123,17 @@ mod imp {
                 "cover-size" => self
                     .obj()
                     .set_cover_size(value.get::<CoverSize>().expect("Required CoverSize")),
+                "activatable" => self.obj().set_activatable(value.get::<bool>().unwrap()),
                 _ => unimplemented!(),
             };
         }
+
+        fn signals() -> &'static [Signal] {
+            static SIGNALS: Lazy<Vec<Signal>> =
+                Lazy::new(|| vec![Signal::builder("activated").build()]);
+
+            SIGNALS.as_ref()
+        }
     }
 
     impl WidgetImpl for CoverPicture {
@@ -174,6 +214,21 @@ impl CoverPicture {
         self.notify("cover");
     }
 
+    // Activatable pictures emit the "activated" signal when clicked
+    pub fn set_activatable(&self, activatable: bool) {
+        if self.imp().activatable.replace(activatable) == activatable {
+            return;
+        }
+
+        self.set_focusable(activatable);
+        if activatable {
+            self.set_cursor_from_name(Some("pointer"));
+        } else {
+            self.set_cursor(None);
+        }
+        self.notify("activatable");
+    }
+
     pub fn set_cover_size(&self, cover_size: CoverSize) {
         self.imp().cover_size.replace(cover_size);
         self.queue_resize();
@@ -0,0 +1,251 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{cell::RefCell, collections::HashMap};
+
+use glib::clone;
+use gtk::{gio, glib, prelude::*, subclass::prelude::*};
+use log::{debug, warn};
+
+mod imp {
+    use glib::subclass::Signal;
+    use once_cell::sync::Lazy;
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    pub struct FolderMonitor {
+        // The folders explicitly watched
+        pub roots: RefCell<Vec<gio::File>>,
+        // A monitor for each directory inside the roots, keyed by URI;
+        // GFileMonitor is not recursive, so we need to track each
+        // directory ourselves
+        pub monitors: RefCell<HashMap<String, gio::FileMonitor>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for FolderMonitor {
+        const NAME: &'static str = "AmberolFolderMonitor";
+        type Type = super::FolderMonitor;
+    }
+
+    impl ObjectImpl for FolderMonitor {
+        fn signals() -> &'static [Signal] {
+            static SIGNALS: Lazy<Vec<Signal>> = Lazy::new(|| {
+                vec![Signal::builder("changed")
+                    .param_types([
+                        gio::File::static_type(),
+                        gio::File::static_type(),
+                        gio::FileMonitorEvent::static_type(),
+                    ])
+                    .build()]
+            });
+
+            SIGNALS.as_ref()
+        }
+
+        fn dispose(&self) {
+            for (_, monitor) in self.monitors.take() {
+                monitor.cancel();
+            }
+        }
+    }
+}
+
+// FolderMonitor recursively watches a set of folders, and emits the
+// "changed" signal for every file that is created, deleted, or moved
+// inside them
+glib::wrapper! {
+    pub struct FolderMonitor(ObjectSubclass<imp::FolderMonitor>);
+}
+
+impl Default for FolderMonitor {
+    fn default() -> Self {
+        glib::Object::new()
+    }
+}
+
+impl FolderMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_watching(&self, folder: &gio::File) -> bool {
+        self.imp().roots.borrow().iter().any(|f| f.equal(folder))
+    }
+
+    pub fn roots(&self) -> Vec<gio::File> {
+        self.imp().roots.borrow().clone()
+    }
+
+    pub fn watch(&self, folder: &gio::File) {
+        if self.is_watching(folder) {
+            return;
+        }
+
+        debug!("Watching folder '{}'", folder.uri());
+        self.imp().roots.borrow_mut().push(folder.clone());
+        self.monitor_directory(folder);
+    }
+
+    pub fn unwatch(&self, folder: &gio::File) {
+        if !self.is_watching(folder) {
+            return;
+        }
+
+        debug!("Unwatching folder '{}'", folder.uri());
+        self.imp().roots.borrow_mut().retain(|f| !f.equal(folder));
+
+        // Nested roots still need their monitors
+        let roots = self.roots();
+        self.imp().monitors.borrow_mut().retain(|uri, monitor| {
+            let dir = gio::File::for_uri(uri);
+            let in_folder = dir.equal(folder) || dir.has_prefix(folder);
+            let in_roots = roots.iter().any(|r| dir.equal(r) || dir.has_prefix(r));
+            if in_folder && !in_roots {
+                monitor.cancel();
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    pub fn unwatch_all(&self) {
+        self.imp().roots.replace(Vec::new());
+        for (_, monitor) in self.imp().monitors.take() {
+            monitor.cancel();
+        }
+    }
+
+    fn is_under_root(&self, dir: &gio::File) -> bool {
+        self.imp()
+            .roots
+            .borrow()
+            .iter()
+            .any(|r| dir.equal(r) || dir.has_prefix(r))
+    }
+
+    // Monitors the directory, and all the directories inside it; walking
+    // a large tree takes a while, so it happens off the main thread
+    fn monitor_directory(&self, dir: &gio::File) {
+        self.add_monitor(dir);
+
+        let dir = dir.clone();
+        let ctx = glib::MainContext::default();
+        ctx.spawn_local(clone!(@weak self as this => async move {
+            let subdirs = match gio::spawn_blocking(move || subdirectories(&dir)).await {
+                Ok(subdirs) => subdirs,
+                Err(_) => return,
+            };
+
+            for subdir in subdirs {
+                // The folder might have been unwatched in the meantime
+                if this.is_under_root(&subdir) {
+                    this.add_monitor(&subdir);
+                }
+            }
+        }));
+    }
+
+    fn add_monitor(&self, dir: &gio::File) {
+        let uri = dir.uri().to_string();
+        if self.imp().monitors.borrow().contains_key(&uri) {
+            return;
+        }
+
+        let monitor = match dir
+            .monitor_directory(gio::FileMonitorFlags::WATCH_MOVES, gio::Cancellable::NONE)
+        {
+            Ok(m) => m,
+            Err(e) => {
+                warn!("Unable to monitor folder '{}': {}", &uri, e);
+                return;
+            }
+        };
+
+        monitor.connect_changed(
+            clone!(@weak self as this => move |_, file, other_file, event| {
+                this.directory_changed(file, other_file, event);
+            }),
+        );
+
+        self.imp().monitors.borrow_mut().insert(uri, monitor);
+    }
+
+    // Drops the monitors of a directory that went away, and of all the
+    // directories inside it
+    fn remove_monitors(&self, dir: &gio::File) {
+        self.imp().monitors.borrow_mut().retain(|uri, monitor| {
+            let file = gio::File::for_uri(uri);
+            if file.equal(dir) || file.has_prefix(dir) {
+                monitor.cancel();
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    fn directory_changed(
+        &self,
+        file: &gio::File,
+        other_file: Option<&gio::File>,
+        event: gio::FileMonitorEvent,
+    ) {
+        match event {
+            gio::FileMonitorEvent::Created | gio::FileMonitorEvent::MovedIn => {
+                if file.query_file_type(gio::FileQueryInfoFlags::NONE, gio::Cancellable::NONE)
+                    == gio::FileType::Directory
+                {
+                    self.monitor_directory(file);
+                }
+            }
+            gio::FileMonitorEvent::Deleted | gio::FileMonitorEvent::MovedOut => {
+                self.remove_monitors(file);
+            }
+            gio::FileMonitorEvent::Renamed => {
+                let was_monitored = self
+                    .imp()
+                    .monitors
+                    .borrow()
+                    .contains_key(file.uri().as_str());
+                self.remove_monitors(file);
+                if let (true, Some(other_file)) = (was_monitored, other_file) {
+                    self.monitor_directory(other_file);
+                }
+            }
+            gio::FileMonitorEvent::ChangesDoneHint => (),
+            _ => return,
+        }
+
+        debug!("Folder change: {:?} for '{}'", event, file.uri());
+        self.emit_by_name::<()>("changed", &[&file, &other_file.cloned(), &event]);
+    }
+}
+
+// All the directories inside the given one, recursively
+fn subdirectories(dir: &gio::File) -> Vec<gio::File> {
+    let mut res = Vec::new();
+    let mut pending = vec![dir.clone()];
+    while let Some(dir) = pending.pop() {
+        let mut enumerator = match dir.enumerate_children(
+            "standard::name,standard::type",
+            gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS,
+            gio::Cancellable::NONE,
+        ) {
+            Ok(e) => e,
+            Err(_) => continue,
+        };
+
+        while let Some(info) = enumerator.next().and_then(|s| s.ok()) {
+            if info.file_type() == gio::FileType::Directory {
+                let child = enumerator.child(&info);
+                res.push(child.clone());
+                pending.push(child);
+            }
+        }
+    }
+
+    res
+}
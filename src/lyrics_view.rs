@@ -0,0 +1,183 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::cell::{Cell, RefCell};
+
+use adw::subclass::prelude::*;
+use glib::clone;
+use gtk::{glib, prelude::*, CompositeTemplate};
+
+use crate::audio::Lyrics;
+
+mod imp {
+    use glib::subclass::Signal;
+    use once_cell::sync::Lazy;
+
+    use super::*;
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/io/bassi/Amberol/lyrics-view.ui")]
+    pub struct LyricsView {
+        // Template widgets
+        #[template_child]
+        pub lyrics_stack: TemplateChild<gtk::Stack>,
+        #[template_child]
+        pub scrolled_window: TemplateChild<gtk::ScrolledWindow>,
+        #[template_child]
+        pub lines_box: TemplateChild<gtk::ListBox>,
+
+        pub lyrics: RefCell<Option<Lyrics>>,
+        pub current_line: Cell<Option<usize>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for LyricsView {
+        const NAME: &'static str = "AmberolLyricsView";
+        type Type = super::LyricsView;
+        type ParentType = gtk::Widget;
+
+        fn class_init(klass: &mut Self::Class) {
+            Self::bind_template(klass);
+
+            klass.set_layout_manager_type::<gtk::BinLayout>();
+            klass.set_css_name("lyricsview");
+            klass.set_accessible_role(gtk::AccessibleRole::Group);
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for LyricsView {
+        fn constructed(&self) {
+            self.parent_constructed();
+            self.obj().setup_widgets();
+        }
+
+        fn dispose(&self) {
+            while let Some(child) = self.obj().first_child() {
+                child.unparent();
+            }
+        }
+
+        fn signals() -> &'static [Signal] {
+            static SIGNALS: Lazy<Vec<Signal>> = Lazy::new(|| {
+                vec![Signal::builder("seek")
+                    .param_types([u64::static_type()])
+                    .build()]
+            });
+
+            SIGNALS.as_ref()
+        }
+    }
+
+    impl WidgetImpl for LyricsView {}
+}
+
+// LyricsView shows the lyrics of the current song; synchronized lyrics
+// follow the playback position, and emit the "seek" signal with the
+// start of a line, in milliseconds, when the line is clicked
+glib::wrapper! {
+    pub struct LyricsView(ObjectSubclass<imp::LyricsView>)
+        @extends gtk::Widget;
+}
+
+impl Default for LyricsView {
+    fn default() -> Self {
+        glib::Object::new()
+    }
+}
+
+impl LyricsView {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn setup_widgets(&self) {
+        self.imp().lines_box.connect_row_activated(clone!(@weak self as this => move |_, row| {
+            this.line_activated(row.index());
+        }));
+    }
+
+    pub fn set_lyrics(&self, lyrics: Option<Lyrics>) {
+        let imp = self.imp();
+
+        imp.lines_box.remove_all();
+        imp.current_line.set(None);
+
+        if let Some(ref lyrics) = lyrics {
+            let synced = lyrics.is_synced();
+            for line in lyrics.lines() {
+                let label = gtk::Label::builder()
+                    .label(&line.text)
+                    .wrap(true)
+                    .justify(gtk::Justification::Center)
+                    .build();
+                label.add_css_class("lyrics-line");
+
+                let row = gtk::ListBoxRow::builder()
+                    .child(&label)
+                    .activatable(synced)
+                    .build();
+                imp.lines_box.append(&row);
+            }
+
+            imp.lyrics_stack.set_visible_child_name("lyrics");
+        } else {
+            imp.lyrics_stack.set_visible_child_name("empty");
+        }
+
+        imp.scrolled_window.vadjustment().set_value(0.0);
+        imp.lyrics.replace(lyrics);
+    }
+
+    // Highlights the line at the given position, in milliseconds
+    pub fn set_position(&self, position: u64) {
+        let imp = self.imp();
+
+        let line = match *imp.lyrics.borrow() {
+            Some(ref lyrics) => lyrics.line_at(position),
+            None => None,
+        };
+
+        let old_line = imp.current_line.replace(line);
+        if old_line == line {
+            return;
+        }
+
+        if let Some(row) = old_line.and_then(|l| imp.lines_box.row_at_index(l as i32)) {
+            row.remove_css_class("current");
+        }
+
+        if let Some(row) = line.and_then(|l| imp.lines_box.row_at_index(l as i32)) {
+            row.add_css_class("current");
+            self.scroll_to_row(&row);
+        }
+    }
+
+    // Keep the current line in the middle of the view
+    fn scroll_to_row(&self, row: &gtk::ListBoxRow) {
+        let imp = self.imp();
+
+        if let Some(bounds) = row.compute_bounds(&*imp.lines_box) {
+            let adj = imp.scrolled_window.vadjustment();
+            let y = bounds.y() as f64 + bounds.height() as f64 / 2.0;
+            adj.set_value(y - adj.page_size() / 2.0);
+        }
+    }
+
+    fn line_activated(&self, index: i32) {
+        let time = match *self.imp().lyrics.borrow() {
+            Some(ref lyrics) => lyrics
+                .lines()
+                .get(index as usize)
+                .and_then(|line| line.time),
+            None => None,
+        };
+
+        if let Some(time) = time {
+            self.emit_by_name::<()>("seek", &[&time]);
+        }
+    }
+}
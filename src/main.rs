@@ -2,22 +2,31 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 mod application;
+mod artwork_viewer;
 mod audio;
 mod config;
 mod cover_picture;
 mod drag_overlay;
+mod folder_monitor;
 mod i18n;
+mod lyrics_view;
+mod palette;
 mod playback_control;
 mod playlist_view;
+mod preferences_dialog;
 mod queue_row;
 mod search;
+mod smart_playlist;
+mod smart_playlist_dialog;
 mod song_cover;
 mod song_details;
 mod sort;
+mod tag_editor_dialog;
 mod utils;
 mod volume_control;
 mod waveform_view;
 mod window;
+mod zoomable_picture;
 
 use std::env;
 
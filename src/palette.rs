@@ -0,0 +1,319 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The palette extracted from the cover art is used to recolor the window,
+// but the colors of a cover can be anything: very dark, very light, or
+// very saturated. Before using them, we adjust their lightness so that
+// the text drawn on top of them, and the waveform, remain readable.
+//
+// The contrast is measured using the WCAG 2 definitions of relative
+// luminance and contrast ratio; the colors are blended over the window
+// background the same way the style sheet does, so that we measure the
+// contrast of what ends up on screen.
+
+use gtk::gdk;
+
+// WCAG AA, for normal text
+const CONTRAST_RATIO: f32 = 4.5;
+// WCAG AAA, for normal text
+const HIGH_CONTRAST_RATIO: f32 = 7.0;
+
+// The opacity of the palette colors in the window background
+const BACKGROUND_ALPHA: f32 = 0.55;
+
+// Very saturated colors are tiring to look at, and make it harder to
+// tell the text apart
+const MAX_SATURATION: f32 = 0.85;
+const HIGH_CONTRAST_MAX_SATURATION: f32 = 0.5;
+
+const LIGHTNESS_STEP: f32 = 0.02;
+
+// The colors of the light and dark styles of libadwaita
+fn window_bg_color(dark: bool) -> gdk::RGBA {
+    if dark {
+        gdk::RGBA::new(0.141, 0.141, 0.141, 1.0)
+    } else {
+        gdk::RGBA::new(0.980, 0.980, 0.980, 1.0)
+    }
+}
+
+fn window_fg_color(dark: bool, high_contrast: bool) -> gdk::RGBA {
+    match (dark, high_contrast) {
+        (true, _) => gdk::RGBA::new(1.0, 1.0, 1.0, 1.0),
+        (false, false) => gdk::RGBA::new(0.0, 0.0, 0.024, 0.8),
+        (false, true) => gdk::RGBA::new(0.0, 0.0, 0.0, 1.0),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StyleInfo {
+    pub dark: bool,
+    pub high_contrast: bool,
+}
+
+impl StyleInfo {
+    fn target_ratio(&self) -> f32 {
+        if self.high_contrast {
+            HIGH_CONTRAST_RATIO
+        } else {
+            CONTRAST_RATIO
+        }
+    }
+
+    fn max_saturation(&self) -> f32 {
+        if self.high_contrast {
+            HIGH_CONTRAST_MAX_SATURATION
+        } else {
+            MAX_SATURATION
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub background: Vec<gdk::RGBA>,
+    pub foreground: gdk::RGBA,
+}
+
+// Blends a color over an opaque background
+pub fn blend(color: &gdk::RGBA, background: &gdk::RGBA, alpha: f32) -> gdk::RGBA {
+    let mix = |c: f32, b: f32| c * alpha + b * (1.0 - alpha);
+    gdk::RGBA::new(
+        mix(color.red(), background.red()),
+        mix(color.green(), background.green()),
+        mix(color.blue(), background.blue()),
+        1.0,
+    )
+}
+
+// The relative luminance of an opaque color, as defined by WCAG 2
+pub fn relative_luminance(color: &gdk::RGBA) -> f32 {
+    let linear = |c: f32| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+
+    0.2126 * linear(color.red()) + 0.7152 * linear(color.green()) + 0.0722 * linear(color.blue())
+}
+
+// The contrast ratio between a foreground color, which can be
+// translucent, and an opaque background color; the result is between
+// 1.0 and 21.0
+pub fn contrast_ratio(foreground: &gdk::RGBA, background: &gdk::RGBA) -> f32 {
+    let foreground = blend(foreground, background, foreground.alpha());
+    let l1 = relative_luminance(&foreground);
+    let l2 = relative_luminance(background);
+
+    (l1.max(l2) + 0.05) / (l1.min(l2) + 0.05)
+}
+
+pub fn rgb_to_hsl(color: &gdk::RGBA) -> (f32, f32, f32) {
+    let (r, g, b) = (color.red(), color.green(), color.blue());
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+
+    if max == min {
+        return (0.0, 0.0, l);
+    }
+
+    let d = max - min;
+    let s = if l > 0.5 {
+        d / (2.0 - max - min)
+    } else {
+        d / (max + min)
+    };
+    let h = if max == r {
+        (g - b) / d + if g < b { 6.0 } else { 0.0 }
+    } else if max == g {
+        (b - r) / d + 2.0
+    } else {
+        (r - g) / d + 4.0
+    };
+
+    (h / 6.0, s, l)
+}
+
+pub fn hsl_to_rgb(h: f32, s: f32, l: f32, alpha: f32) -> gdk::RGBA {
+    if s == 0.0 {
+        return gdk::RGBA::new(l, l, l, alpha);
+    }
+
+    let q = if l < 0.5 {
+        l * (1.0 + s)
+    } else {
+        l + s - l * s
+    };
+    let p = 2.0 * l - q;
+    let hue = |t: f32| {
+        let t = t.rem_euclid(1.0);
+        if t < 1.0 / 6.0 {
+            p + (q - p) * 6.0 * t
+        } else if t < 0.5 {
+            q
+        } else if t < 2.0 / 3.0 {
+            p + (q - p) * (2.0 / 3.0 - t) * 6.0
+        } else {
+            p
+        }
+    };
+
+    gdk::RGBA::new(hue(h + 1.0 / 3.0), hue(h), hue(h - 1.0 / 3.0), alpha)
+}
+
+// Adjusts a palette color until the foreground color reaches the target
+// contrast ratio on top of it; colors get darker with a dark style, and
+// lighter with a light style, to stay close to what the style expects
+fn adjust_color(color: &gdk::RGBA, foreground: &gdk::RGBA, style: &StyleInfo) -> gdk::RGBA {
+    let window_bg = window_bg_color(style.dark);
+    let (h, s, mut l) = rgb_to_hsl(color);
+    let s = s.min(style.max_saturation());
+
+    loop {
+        let res = hsl_to_rgb(h, s, l, color.alpha());
+        let background = blend(&res, &window_bg, BACKGROUND_ALPHA);
+        if contrast_ratio(foreground, &background) >= style.target_ratio() {
+            return res;
+        }
+
+        // Black and white always have enough contrast with the foreground
+        // of the corresponding style
+        if (style.dark && l <= 0.0) || (!style.dark && l >= 1.0) {
+            return res;
+        }
+
+        l = if style.dark {
+            (l - LIGHTNESS_STEP).max(0.0)
+        } else {
+            (l + LIGHTNESS_STEP).min(1.0)
+        };
+    }
+}
+
+// Picks the foreground color for the style, and adjusts the colors of
+// the palette so they can be used as its background
+pub fn adjust_palette(colors: &[gdk::RGBA], style: &StyleInfo) -> Palette {
+    let foreground = window_fg_color(style.dark, style.high_contrast);
+    let background = colors
+        .iter()
+        .map(|c| adjust_color(c, &foreground, style))
+        .collect();
+
+    Palette {
+        background,
+        foreground,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 0.01;
+
+    fn rgb(r: u8, g: u8, b: u8) -> gdk::RGBA {
+        gdk::RGBA::new(r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0)
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < EPSILON, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_relative_luminance() {
+        assert_close(relative_luminance(&rgb(0, 0, 0)), 0.0);
+        assert_close(relative_luminance(&rgb(255, 255, 255)), 1.0);
+        assert_close(relative_luminance(&rgb(255, 0, 0)), 0.2126);
+        assert_close(relative_luminance(&rgb(128, 128, 128)), 0.2158);
+    }
+
+    #[test]
+    fn test_contrast_ratio() {
+        let black = rgb(0, 0, 0);
+        let white = rgb(255, 255, 255);
+        assert_close(contrast_ratio(&black, &white), 21.0);
+        assert_close(contrast_ratio(&white, &black), 21.0);
+        assert_close(contrast_ratio(&white, &white), 1.0);
+        // The reference value for #777 on white
+        assert_close(contrast_ratio(&rgb(119, 119, 119), &white), 4.48);
+
+        // Translucent foregrounds are blended with the background
+        let gray = gdk::RGBA::new(0.0, 0.0, 0.0, 0.5);
+        let blended = blend(&black, &white, 0.5);
+        assert_close(
+            contrast_ratio(&gray, &white),
+            contrast_ratio(&blended, &white),
+        );
+    }
+
+    #[test]
+    fn test_hsl_round_trip() {
+        for color in [
+            rgb(0, 0, 0),
+            rgb(255, 255, 255),
+            rgb(255, 0, 0),
+            rgb(12, 200, 90),
+            rgb(80, 40, 160),
+            rgb(250, 240, 5),
+        ] {
+            let (h, s, l) = rgb_to_hsl(&color);
+            let res = hsl_to_rgb(h, s, l, 1.0);
+            assert_close(res.red(), color.red());
+            assert_close(res.green(), color.green());
+            assert_close(res.blue(), color.blue());
+        }
+
+        let (h, s, l) = rgb_to_hsl(&rgb(0, 0, 255));
+        assert_close(h, 2.0 / 3.0);
+        assert_close(s, 1.0);
+        assert_close(l, 0.5);
+    }
+
+    #[test]
+    fn test_adjust_palette() {
+        let colors = [
+            rgb(255, 255, 255),
+            rgb(0, 0, 0),
+            rgb(255, 0, 255),
+            rgb(250, 220, 10),
+            rgb(30, 60, 40),
+        ];
+
+        for dark in [true, false] {
+            for high_contrast in [true, false] {
+                let style = StyleInfo {
+                    dark,
+                    high_contrast,
+                };
+                let palette = adjust_palette(&colors, &style);
+                assert_eq!(palette.background.len(), colors.len());
+
+                for color in &palette.background {
+                    let background = blend(color, &window_bg_color(dark), BACKGROUND_ALPHA);
+                    let ratio = contrast_ratio(&palette.foreground, &background);
+                    assert!(ratio >= style.target_ratio(), "{} for {:?}", ratio, style);
+
+                    let (_, s, _) = rgb_to_hsl(color);
+                    assert!(s <= style.max_saturation() + EPSILON);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_adjust_keeps_readable_colors() {
+        // A dark, muted color is already fine with the dark style
+        let color = rgb(40, 50, 60);
+        let style = StyleInfo {
+            dark: true,
+            high_contrast: false,
+        };
+        let palette = adjust_palette(&[color], &style);
+        assert_close(palette.background[0].red(), color.red());
+        assert_close(palette.background[0].green(), color.green());
+        assert_close(palette.background[0].blue(), color.blue());
+    }
+}
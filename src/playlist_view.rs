@@ -25,6 +25,8 @@ mod imp {
         #[template_child]
         pub queue_selected_label: TemplateChild<gtk::Label>,
         #[template_child]
+        pub playlist_loading_box: TemplateChild<gtk::Box>,
+        #[template_child]
         pub playlist_progress: TemplateChild<gtk::ProgressBar>,
         #[template_child]
         pub playlist_searchbar: TemplateChild<gtk::SearchBar>,
@@ -128,11 +130,15 @@ impl PlaylistView {
 
     pub fn begin_loading(&self) {
         self.imp().playlist_progress.set_fraction(0.0);
-        self.imp().playlist_progress.set_visible(true);
+        self.imp().playlist_loading_box.set_visible(true);
     }
 
     pub fn end_loading(&self) {
-        self.imp().playlist_progress.set_visible(false);
+        self.imp().playlist_loading_box.set_visible(false);
+    }
+
+    pub fn is_loading(&self) -> bool {
+        self.imp().playlist_loading_box.is_visible()
     }
 
     pub fn update_loading(&self, cur: u32, max: u32) {
@@ -0,0 +1,148 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use adw::{prelude::*, subclass::prelude::*};
+use glib::clone;
+use gtk::{gio, glib, CompositeTemplate};
+use log::warn;
+
+use crate::{audio::DiskCache, utils};
+
+mod imp {
+    use glib::subclass::Signal;
+    use once_cell::sync::Lazy;
+
+    use super::*;
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/io/bassi/Amberol/preferences-dialog.ui")]
+    pub struct PreferencesDialog {
+        #[template_child]
+        pub cover_names_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub clear_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub covers_row: TemplateChild<adw::ActionRow>,
+        #[template_child]
+        pub waveforms_row: TemplateChild<adw::ActionRow>,
+        #[template_child]
+        pub max_age_row: TemplateChild<adw::SpinRow>,
+        #[template_child]
+        pub max_size_row: TemplateChild<adw::SpinRow>,
+        #[template_child]
+        pub memory_row: TemplateChild<adw::SpinRow>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for PreferencesDialog {
+        const NAME: &'static str = "AmberolPreferencesDialog";
+        type Type = super::PreferencesDialog;
+        type ParentType = adw::PreferencesDialog;
+
+        fn class_init(klass: &mut Self::Class) {
+            Self::bind_template(klass);
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for PreferencesDialog {
+        fn constructed(&self) {
+            self.parent_constructed();
+
+            let obj = self.obj();
+            obj.setup_widgets();
+            obj.update_sizes();
+        }
+
+        fn signals() -> &'static [Signal] {
+            static SIGNALS: Lazy<Vec<Signal>> =
+                Lazy::new(|| vec![Signal::builder("clear-caches").build()]);
+
+            SIGNALS.as_ref()
+        }
+    }
+
+    impl WidgetImpl for PreferencesDialog {}
+    impl AdwDialogImpl for PreferencesDialog {}
+    impl PreferencesDialogImpl for PreferencesDialog {}
+}
+
+glib::wrapper! {
+    pub struct PreferencesDialog(ObjectSubclass<imp::PreferencesDialog>)
+        @extends gtk::Widget, adw::Dialog, adw::PreferencesDialog;
+}
+
+impl Default for PreferencesDialog {
+    fn default() -> Self {
+        glib::Object::new()
+    }
+}
+
+impl PreferencesDialog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn setup_widgets(&self) {
+        let imp = self.imp();
+
+        let settings = utils::settings_manager();
+        settings
+            .bind("cache-max-age", &*imp.max_age_row, "value")
+            .build();
+        settings
+            .bind("cache-max-size", &*imp.max_size_row, "value")
+            .build();
+        settings
+            .bind("cover-cache-size", &*imp.memory_row, "value")
+            .build();
+
+        // The names are stored as a list, and edited as comma separated
+        // text
+        let names: Vec<String> = settings
+            .strv("cover-file-names")
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        imp.cover_names_row.set_text(&names.join(", "));
+        imp.cover_names_row.connect_apply(|row| {
+            let text = row.text();
+            let names: Vec<&str> = text
+                .split(',')
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+                .collect();
+            utils::settings_manager()
+                .set_strv("cover-file-names", names.as_slice())
+                .expect("Unable to store setting");
+        });
+
+        imp.clear_button.connect_clicked(clone!(@weak self as this => move |_| {
+            this.emit_by_name::<()>("clear-caches", &[]);
+        }));
+    }
+
+    // Computes the size of the caches on disk in a separate thread, as
+    // they can contain a lot of files
+    pub fn update_sizes(&self) {
+        let ctx = glib::MainContext::default();
+        ctx.spawn_local(clone!(@weak self as this => async move {
+            let res =
+                gio::spawn_blocking(|| (DiskCache::Covers.size(), DiskCache::Waveforms.size()))
+                    .await;
+
+            match res {
+                Ok((covers, waveforms)) => {
+                    let imp = this.imp();
+                    imp.covers_row.set_subtitle(&glib::format_size(covers));
+                    imp.waveforms_row.set_subtitle(&glib::format_size(waveforms));
+                    imp.clear_button.set_sensitive(covers + waveforms > 0);
+                }
+                Err(_) => warn!("Unable to compute the size of the caches"),
+            }
+        }));
+    }
+}
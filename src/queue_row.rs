@@ -6,8 +6,9 @@ use std::cell::{Cell, RefCell};
 use adw::subclass::prelude::*;
 use glib::clone;
 use gtk::{gdk, gio, glib, prelude::*, CompositeTemplate};
+use log::warn;
 
-use crate::{audio::Song, cover_picture::CoverPicture};
+use crate::{audio::Song, cover_picture::CoverPicture, utils};
 
 mod imp {
     use glib::{ParamSpec, ParamSpecBoolean, ParamSpecObject, ParamSpecString, Value};
@@ -39,7 +40,10 @@ mod imp {
         pub selected_button: TemplateChild<gtk::CheckButton>,
         #[template_child]
         pub selection_playing_image: TemplateChild<gtk::Image>,
+        #[template_child]
+        pub row_menu: TemplateChild<gio::MenuModel>,
 
+        pub menu_popover: RefCell<Option<gtk::PopoverMenu>>,
         pub song: RefCell<Option<Song>>,
         pub playing: Cell<bool>,
         pub selection_mode: Cell<bool>,
@@ -57,6 +61,19 @@ mod imp {
             klass.set_layout_manager_type::<gtk::BoxLayout>();
             klass.set_css_name("queuerow");
             klass.set_accessible_role(gtk::AccessibleRole::Group);
+
+            klass.install_action("row.edit-tags", None, move |row, _, _| {
+                row.edit_tags();
+            });
+            klass.install_action(
+                "row.set-rating",
+                Some(glib::VariantTy::UINT32),
+                move |row, _, param| {
+                    if let Some(rating) = param.and_then(u32::from_variant) {
+                        row.set_rating(rating);
+                    }
+                },
+            );
         }
 
         fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
@@ -97,6 +114,7 @@ mod imp {
                 "song" => {
                     let song = value.get::<Option<Song>>().unwrap();
                     self.song.replace(song);
+                    self.obj().update_tooltip();
                 }
                 "song-artist" => {
                     let p = value.get::<&str>().expect("The value needs to be a string");
@@ -146,7 +164,15 @@ mod imp {
         }
     }
 
-    impl WidgetImpl for QueueRow {}
+    impl WidgetImpl for QueueRow {
+        fn size_allocate(&self, width: i32, height: i32, baseline: i32) {
+            self.parent_size_allocate(width, height, baseline);
+
+            if let Some(ref popover) = *self.menu_popover.borrow() {
+                popover.present();
+            }
+        }
+    }
 }
 
 glib::wrapper! {
@@ -175,6 +201,60 @@ impl QueueRow {
                 this.notify("selected");
             }),
         );
+
+        let popover = gtk::PopoverMenu::from_model(Some(&*self.imp().row_menu));
+        popover.set_has_arrow(false);
+        popover.set_halign(gtk::Align::Start);
+        popover.set_parent(self);
+        self.imp().menu_popover.replace(Some(popover));
+
+        // Show the context menu on right click and on long press
+        let click_gesture = gtk::GestureClick::new();
+        click_gesture.set_button(gdk::BUTTON_SECONDARY);
+        click_gesture.connect_pressed(clone!(@weak self as this => move |gesture, _, x, y| {
+            gesture.set_state(gtk::EventSequenceState::Claimed);
+            this.show_menu(x, y);
+        }));
+        self.add_controller(click_gesture);
+
+        let long_press_gesture = gtk::GestureLongPress::new();
+        long_press_gesture.set_touch_only(true);
+        long_press_gesture.connect_pressed(clone!(@weak self as this => move |gesture, x, y| {
+            gesture.set_state(gtk::EventSequenceState::Claimed);
+            this.show_menu(x, y);
+        }));
+        self.add_controller(long_press_gesture);
+    }
+
+    fn show_menu(&self, x: f64, y: f64) {
+        if let Some(ref popover) = *self.imp().menu_popover.borrow() {
+            popover.set_pointing_to(Some(&gdk::Rectangle::new(x as i32, y as i32, 1, 1)));
+            popover.popup();
+        }
+    }
+
+    fn edit_tags(&self) {
+        if let Some(song) = self.song() {
+            let uri = song.uri().to_variant();
+            if let Err(e) = self
+                .upcast_ref::<gtk::Widget>()
+                .activate_action("queue.edit-tags", Some(&uri))
+            {
+                warn!("Unable to edit tags: {}", e);
+            }
+        }
+    }
+
+    fn set_rating(&self, rating: u32) {
+        if let Some(song) = self.song() {
+            let param = (song.uri(), rating).to_variant();
+            if let Err(e) = self
+                .upcast_ref::<gtk::Widget>()
+                .activate_action("queue.set-rating", Some(&param))
+            {
+                warn!("Unable to set rating: {}", e);
+            }
+        }
     }
 
     fn set_playing(&self, playing: bool) {
@@ -227,6 +307,17 @@ impl QueueRow {
         }
     }
 
+    fn update_tooltip(&self) {
+        let tooltip = self.imp().song.borrow().as_ref().map(|song| {
+            let mut lines = vec![song.title(), song.artist()];
+            if let Some(info) = utils::format_stream_info(song) {
+                lines.push(info);
+            }
+            lines.join("\n")
+        });
+        self.set_tooltip_text(tooltip.as_deref());
+    }
+
     pub fn song(&self) -> Option<Song> {
         self.imp().song.borrow().clone()
     }
@@ -58,9 +58,11 @@ mod imp {
             let song = song.downcast_ref::<Song>().unwrap();
 
             if let Some(search) = self.search.borrow().as_ref() {
-                let key = song.search_key();
                 let matcher = SkimMatcherV2::default();
-                matcher.fuzzy_match(&key, search).is_some() || search.is_empty()
+                song.search_keys()
+                    .iter()
+                    .any(|key| matcher.fuzzy_match(key, search).is_some())
+                    || search.is_empty()
             } else {
                 true
             }
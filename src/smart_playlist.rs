@@ -0,0 +1,633 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    cmp::Ordering,
+    path::{Path, PathBuf},
+};
+
+use gtk::{gio, glib, prelude::*};
+use log::debug;
+
+use crate::{
+    audio::{PlayHistory, Song, SongData},
+    i18n::i18n,
+    utils,
+};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RuleField {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Year,
+    Rating,
+    PlayCount,
+    LastPlayed,
+    DateAdded,
+}
+
+impl RuleField {
+    pub const ALL: [RuleField; 9] = [
+        RuleField::Title,
+        RuleField::Artist,
+        RuleField::Album,
+        RuleField::Genre,
+        RuleField::Year,
+        RuleField::Rating,
+        RuleField::PlayCount,
+        RuleField::LastPlayed,
+        RuleField::DateAdded,
+    ];
+
+    pub fn label(&self) -> String {
+        match self {
+            RuleField::Title => i18n("Title"),
+            RuleField::Artist => i18n("Artist"),
+            RuleField::Album => i18n("Album"),
+            RuleField::Genre => i18n("Genre"),
+            RuleField::Year => i18n("Year"),
+            RuleField::Rating => i18n("Rating"),
+            RuleField::PlayCount => i18n("Play Count"),
+            RuleField::LastPlayed => i18n("Last Played"),
+            RuleField::DateAdded => i18n("Date Added"),
+        }
+    }
+
+    pub fn operators(&self) -> &'static [RuleOperator] {
+        match self {
+            RuleField::Title | RuleField::Artist | RuleField::Album | RuleField::Genre => &[
+                RuleOperator::Is,
+                RuleOperator::IsNot,
+                RuleOperator::Contains,
+                RuleOperator::DoesNotContain,
+            ],
+            RuleField::Year | RuleField::Rating | RuleField::PlayCount => &[
+                RuleOperator::Is,
+                RuleOperator::IsNot,
+                RuleOperator::LessThan,
+                RuleOperator::GreaterThan,
+            ],
+            RuleField::LastPlayed | RuleField::DateAdded => &[
+                RuleOperator::InTheLast,
+                RuleOperator::NotInTheLast,
+                RuleOperator::Never,
+            ],
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.iter().find(|f| f.as_ref() == key).copied()
+    }
+}
+
+impl AsRef<str> for RuleField {
+    fn as_ref(&self) -> &str {
+        match self {
+            RuleField::Title => "title",
+            RuleField::Artist => "artist",
+            RuleField::Album => "album",
+            RuleField::Genre => "genre",
+            RuleField::Year => "year",
+            RuleField::Rating => "rating",
+            RuleField::PlayCount => "play-count",
+            RuleField::LastPlayed => "last-played",
+            RuleField::DateAdded => "date-added",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RuleOperator {
+    Is,
+    IsNot,
+    Contains,
+    DoesNotContain,
+    LessThan,
+    GreaterThan,
+    // The value is a number of days
+    InTheLast,
+    NotInTheLast,
+    // The value is ignored
+    Never,
+}
+
+impl RuleOperator {
+    pub fn label(&self) -> String {
+        match self {
+            RuleOperator::Is => i18n("is"),
+            RuleOperator::IsNot => i18n("is not"),
+            RuleOperator::Contains => i18n("contains"),
+            RuleOperator::DoesNotContain => i18n("does not contain"),
+            RuleOperator::LessThan => i18n("is less than"),
+            RuleOperator::GreaterThan => i18n("is greater than"),
+            // Translators: this is followed by a number of days
+            RuleOperator::InTheLast => i18n("in the last days"),
+            // Translators: this is followed by a number of days
+            RuleOperator::NotInTheLast => i18n("not in the last days"),
+            RuleOperator::Never => i18n("never"),
+        }
+    }
+
+    pub fn needs_value(&self) -> bool {
+        !matches!(self, RuleOperator::Never)
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        [
+            RuleOperator::Is,
+            RuleOperator::IsNot,
+            RuleOperator::Contains,
+            RuleOperator::DoesNotContain,
+            RuleOperator::LessThan,
+            RuleOperator::GreaterThan,
+            RuleOperator::InTheLast,
+            RuleOperator::NotInTheLast,
+            RuleOperator::Never,
+        ]
+        .iter()
+        .find(|o| o.as_ref() == key)
+        .copied()
+    }
+
+    fn matches_text(&self, text: Option<&str>, value: &str) -> bool {
+        let text = text.unwrap_or_default().to_lowercase();
+        let value = value.to_lowercase();
+        match self {
+            RuleOperator::Is => text == value,
+            RuleOperator::IsNot => text != value,
+            RuleOperator::Contains => text.contains(&value),
+            RuleOperator::DoesNotContain => !text.contains(&value),
+            _ => false,
+        }
+    }
+
+    fn matches_number(&self, number: Option<i64>, value: &str) -> bool {
+        let value = match value.trim().parse::<i64>() {
+            Ok(v) => v,
+            Err(_) => return false,
+        };
+
+        match (self, number) {
+            (RuleOperator::Is, Some(n)) => n == value,
+            (RuleOperator::IsNot, Some(n)) => n != value,
+            (RuleOperator::IsNot, None) => true,
+            (RuleOperator::LessThan, Some(n)) => n < value,
+            (RuleOperator::GreaterThan, Some(n)) => n > value,
+            _ => false,
+        }
+    }
+
+    fn matches_timestamp(&self, timestamp: Option<i64>, now: i64, value: &str) -> bool {
+        if let RuleOperator::Never = self {
+            return timestamp.is_none();
+        }
+
+        let days = match value.trim().parse::<i64>() {
+            Ok(v) => v,
+            Err(_) => return false,
+        };
+
+        let since = now - days * SECONDS_PER_DAY;
+        match (self, timestamp) {
+            (RuleOperator::InTheLast, Some(t)) => t >= since,
+            (RuleOperator::NotInTheLast, Some(t)) => t < since,
+            (RuleOperator::NotInTheLast, None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl AsRef<str> for RuleOperator {
+    fn as_ref(&self) -> &str {
+        match self {
+            RuleOperator::Is => "is",
+            RuleOperator::IsNot => "is-not",
+            RuleOperator::Contains => "contains",
+            RuleOperator::DoesNotContain => "does-not-contain",
+            RuleOperator::LessThan => "less-than",
+            RuleOperator::GreaterThan => "greater-than",
+            RuleOperator::InTheLast => "in-the-last",
+            RuleOperator::NotInTheLast => "not-in-the-last",
+            RuleOperator::Never => "never",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    pub field: RuleField,
+    pub operator: RuleOperator,
+    pub value: String,
+}
+
+impl Rule {
+    pub fn new(field: RuleField, operator: RuleOperator, value: &str) -> Self {
+        Self {
+            field,
+            operator,
+            value: value.to_string(),
+        }
+    }
+
+    fn matches(&self, song: &SongData, now: i64) -> bool {
+        let op = self.operator;
+        let value = self.value.as_str();
+
+        match self.field {
+            RuleField::Title => {
+                let title = song
+                    .title()
+                    .map_or_else(|| i18n("Unknown title"), String::from);
+                op.matches_text(Some(title.as_str()), value)
+            }
+            RuleField::Artist => {
+                // A song matches if any of its artists does; negated
+                // operators need to hold for all the artists
+                let artists = song.artists();
+                let mut matches = artists.iter().map(|a| op.matches_text(Some(a), value));
+                match op {
+                    RuleOperator::IsNot | RuleOperator::DoesNotContain => matches.all(|m| m),
+                    _ => matches.any(|m| m),
+                }
+            }
+            RuleField::Album => {
+                let album = song
+                    .album()
+                    .map_or_else(|| i18n("Unknown album"), String::from);
+                op.matches_text(Some(album.as_str()), value)
+            }
+            RuleField::Genre => op.matches_text(song.genre(), value),
+            RuleField::Year => op.matches_number(song.year().map(|y| y as i64), value),
+            RuleField::Rating => op.matches_number(Some(song.rating().unwrap_or(0) as i64), value),
+            RuleField::PlayCount => {
+                let record = PlayHistory::global().lock().unwrap().lookup(&song.uri());
+                op.matches_number(Some(record.play_count() as i64), value)
+            }
+            RuleField::LastPlayed => {
+                let record = PlayHistory::global().lock().unwrap().lookup(&song.uri());
+                op.matches_timestamp(record.last_played(), now, value)
+            }
+            RuleField::DateAdded => op.matches_timestamp(date_added(&song.file()), now, value),
+        }
+    }
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        Self::new(RuleField::Genre, RuleOperator::Is, "")
+    }
+}
+
+// We don't have a library database, so we use the time the file was
+// created on disk, and fall back to the last modification time on file
+// systems that do not record it
+fn date_added(file: &gio::File) -> Option<i64> {
+    let info = file
+        .query_info(
+            "time::created,time::modified",
+            gio::FileQueryInfoFlags::NONE,
+            gio::Cancellable::NONE,
+        )
+        .ok()?;
+
+    if info.has_attribute("time::created") {
+        Some(info.attribute_uint64("time::created") as i64)
+    } else if info.has_attribute("time::modified") {
+        Some(info.attribute_uint64("time::modified") as i64)
+    } else {
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum MatchMode {
+    #[default]
+    All,
+    Any,
+}
+
+// The order of the songs in the queue when playing a smart playlist
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum SortOrder {
+    #[default]
+    Album,
+    Rating,
+}
+
+impl SortOrder {
+    pub fn compare(&self, a: &Song, b: &Song) -> Ordering {
+        match self {
+            SortOrder::Album => utils::cmp_two_songs(a, b),
+            SortOrder::Rating => utils::cmp_songs_by_rating(a, b),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SmartPlaylist {
+    pub id: String,
+    pub name: String,
+    pub match_mode: MatchMode,
+    pub order: SortOrder,
+    pub folders: Vec<PathBuf>,
+    pub rules: Vec<Rule>,
+}
+
+impl Default for SmartPlaylist {
+    fn default() -> Self {
+        Self {
+            id: glib::uuid_string_random().to_string(),
+            name: i18n("New Smart Playlist"),
+            match_mode: MatchMode::default(),
+            order: SortOrder::default(),
+            folders: Vec::new(),
+            rules: vec![Rule::default()],
+        }
+    }
+}
+
+impl SmartPlaylist {
+    // Some rules query the file, or the play history, so this is best
+    // called off the main thread
+    pub fn matches(&self, song: &SongData) -> bool {
+        if self.rules.is_empty() {
+            return true;
+        }
+
+        let now = glib::DateTime::now_utc()
+            .map(|d| d.to_unix())
+            .unwrap_or_default();
+
+        match self.match_mode {
+            MatchMode::All => self.rules.iter().all(|r| r.matches(song, now)),
+            MatchMode::Any => self.rules.iter().any(|r| r.matches(song, now)),
+        }
+    }
+
+    pub fn folders(&self) -> Vec<gio::File> {
+        self.folders.iter().map(gio::File::for_path).collect()
+    }
+
+    // Collects the candidate files from the folder index; the songs
+    // still need to be matched against the rules once loaded
+    pub fn files(&self) -> Vec<gio::File> {
+        let mut res = Vec::new();
+        for folder in self.folders() {
+            if folder.query_exists(gio::Cancellable::NONE) {
+                res.extend(utils::load_files_from_folder(&folder, true));
+            } else {
+                debug!("Skipping missing folder '{}'", folder.uri());
+            }
+        }
+
+        res
+    }
+
+    pub fn contains_file(&self, file: &gio::File) -> bool {
+        self.folders()
+            .iter()
+            .any(|f| file.has_prefix(f) || file.equal(f))
+    }
+}
+
+fn smart_playlists_file(data_dir: &Path) -> PathBuf {
+    let mut pls_file = data_dir.to_path_buf();
+    pls_file.push("amberol");
+    pls_file.push("smart-playlists.ini");
+
+    pls_file
+}
+
+pub fn load_smart_playlists() -> Vec<SmartPlaylist> {
+    load_smart_playlists_in(&glib::user_data_dir())
+}
+
+// Loads the smart playlists stored in the given data directory
+pub fn load_smart_playlists_in(data_dir: &Path) -> Vec<SmartPlaylist> {
+    let keyfile = glib::KeyFile::new();
+    let pls_file = smart_playlists_file(data_dir);
+    if let Err(e) = keyfile.load_from_file(pls_file, glib::KeyFileFlags::NONE) {
+        debug!("Unable to load smart playlists: {e}");
+        return Vec::new();
+    }
+
+    let mut res = Vec::new();
+    for group in keyfile.groups() {
+        let id = group.to_string();
+        let name = match keyfile.string(&id, "Name") {
+            Ok(n) => n.to_string(),
+            Err(e) => {
+                debug!("Skipping smart playlist {id}: {e}");
+                continue;
+            }
+        };
+
+        let match_mode = match keyfile.string(&id, "Match").as_deref() {
+            Ok("any") => MatchMode::Any,
+            _ => MatchMode::All,
+        };
+
+        let order = match keyfile.string(&id, "Order").as_deref() {
+            Ok("rating") => SortOrder::Rating,
+            _ => SortOrder::Album,
+        };
+
+        let n_folders = keyfile.int64(&id, "NumberOfFolders").unwrap_or(0);
+        let mut folders = Vec::new();
+        for i in 0..n_folders {
+            match keyfile.value(&id, &format!("Folder{i}")) {
+                Ok(p) => folders.push(PathBuf::from(p.as_str())),
+                Err(e) => debug!("Skipping Folder{i} from smart playlist {id}: {e}"),
+            }
+        }
+
+        let n_rules = keyfile.int64(&id, "NumberOfRules").unwrap_or(0);
+        let mut rules = Vec::new();
+        for i in 0..n_rules {
+            let field = keyfile
+                .string(&id, &format!("Rule{i}Field"))
+                .ok()
+                .and_then(|s| RuleField::from_key(&s));
+            let operator = keyfile
+                .string(&id, &format!("Rule{i}Operator"))
+                .ok()
+                .and_then(|s| RuleOperator::from_key(&s));
+            let value = keyfile
+                .string(&id, &format!("Rule{i}Value"))
+                .map(|s| s.to_string())
+                .unwrap_or_default();
+
+            match (field, operator) {
+                (Some(field), Some(operator)) => rules.push(Rule::new(field, operator, &value)),
+                _ => debug!("Skipping invalid Rule{i} from smart playlist {id}"),
+            }
+        }
+
+        res.push(SmartPlaylist {
+            id,
+            name,
+            match_mode,
+            order,
+            folders,
+            rules,
+        });
+    }
+
+    res
+}
+
+pub fn store_smart_playlists(playlists: &[SmartPlaylist]) {
+    let keyfile = glib::KeyFile::new();
+
+    for pls in playlists {
+        let id = pls.id.as_str();
+        keyfile.set_string(id, "Name", &pls.name);
+        keyfile.set_string(
+            id,
+            "Match",
+            match pls.match_mode {
+                MatchMode::All => "all",
+                MatchMode::Any => "any",
+            },
+        );
+        keyfile.set_string(
+            id,
+            "Order",
+            match pls.order {
+                SortOrder::Album => "album",
+                SortOrder::Rating => "rating",
+            },
+        );
+
+        keyfile.set_int64(id, "NumberOfFolders", pls.folders.len() as i64);
+        for (i, folder) in pls.folders.iter().enumerate() {
+            keyfile.set_value(id, &format!("Folder{i}"), &folder.to_string_lossy());
+        }
+
+        keyfile.set_int64(id, "NumberOfRules", pls.rules.len() as i64);
+        for (i, rule) in pls.rules.iter().enumerate() {
+            keyfile.set_string(id, &format!("Rule{i}Field"), rule.field.as_ref());
+            keyfile.set_string(id, &format!("Rule{i}Operator"), rule.operator.as_ref());
+            keyfile.set_string(id, &format!("Rule{i}Value"), &rule.value);
+        }
+    }
+
+    let pls_file = smart_playlists_file(&glib::user_data_dir());
+    if let Some(parent) = pls_file.parent() {
+        glib::mkdir_with_parents(parent, 0o755);
+    }
+
+    match keyfile.save_to_file(&pls_file) {
+        Ok(_) => debug!("Smart playlists updated to: {:?}", &pls_file),
+        Err(e) => debug!("Unable to save smart playlists: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_text() {
+        use RuleOperator::*;
+
+        let cases = [
+            (Is, Some("Jazz"), "jazz", true),
+            (Is, Some("Jazz Fusion"), "jazz", false),
+            (Is, None, "", true),
+            (IsNot, Some("Rock"), "jazz", true),
+            (IsNot, Some("JAZZ"), "jazz", false),
+            (IsNot, None, "jazz", true),
+            (Contains, Some("Acid Jazz"), "JAZZ", true),
+            (Contains, Some("Rock"), "jazz", false),
+            (Contains, None, "jazz", false),
+            (DoesNotContain, Some("Acid Jazz"), "jazz", false),
+            (DoesNotContain, None, "jazz", true),
+            (LessThan, Some("Jazz"), "jazz", false),
+            (Never, Some("Jazz"), "jazz", false),
+        ];
+
+        for (op, text, value, expected) in cases {
+            assert_eq!(
+                op.matches_text(text, value),
+                expected,
+                "{:?} {:?} {:?}",
+                op,
+                text,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn test_matches_number() {
+        use RuleOperator::*;
+
+        let cases = [
+            (Is, Some(1969), "1969", true),
+            (Is, Some(1969), " 1969 ", true),
+            (Is, Some(1970), "1969", false),
+            (Is, None, "1969", false),
+            (IsNot, Some(1970), "1969", true),
+            (IsNot, Some(1969), "1969", false),
+            (IsNot, None, "1969", true),
+            (LessThan, Some(1969), "1970", true),
+            (LessThan, Some(1970), "1970", false),
+            (LessThan, None, "1970", false),
+            (GreaterThan, Some(4), "3", true),
+            (GreaterThan, Some(3), "3", false),
+            (GreaterThan, Some(4), "three", false),
+            (IsNot, None, "", false),
+            (Contains, Some(1969), "1969", false),
+        ];
+
+        for (op, number, value, expected) in cases {
+            assert_eq!(
+                op.matches_number(number, value),
+                expected,
+                "{:?} {:?} {:?}",
+                op,
+                number,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn test_matches_timestamp() {
+        use RuleOperator::*;
+
+        let now = 100 * SECONDS_PER_DAY;
+        let yesterday = Some(now - SECONDS_PER_DAY);
+        let last_month = Some(now - 30 * SECONDS_PER_DAY);
+
+        let cases = [
+            (InTheLast, yesterday, "7", true),
+            (InTheLast, last_month, "7", false),
+            (InTheLast, Some(now - 7 * SECONDS_PER_DAY), "7", true),
+            (InTheLast, None, "7", false),
+            (NotInTheLast, yesterday, "7", false),
+            (NotInTheLast, last_month, "7", true),
+            (NotInTheLast, None, "7", true),
+            (InTheLast, yesterday, "a week", false),
+            (Never, None, "", true),
+            (Never, yesterday, "", false),
+            (Is, yesterday, "7", false),
+        ];
+
+        for (op, timestamp, value, expected) in cases {
+            assert_eq!(
+                op.matches_timestamp(timestamp, now, value),
+                expected,
+                "{:?} {:?} {:?}",
+                op,
+                timestamp,
+                value
+            );
+        }
+    }
+}
@@ -0,0 +1,488 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{cell::RefCell, path::PathBuf, rc::Rc};
+
+use adw::{prelude::*, subclass::prelude::*};
+use glib::clone;
+use gtk::{gio, glib, CompositeTemplate};
+use log::debug;
+
+use crate::{
+    i18n::{i18n, ni18n_f},
+    smart_playlist::{
+        load_smart_playlists, store_smart_playlists, MatchMode, Rule, RuleField, RuleOperator,
+        SmartPlaylist, SortOrder,
+    },
+};
+
+// The widgets used to edit a single rule
+#[derive(Debug)]
+pub(crate) struct RuleRow {
+    row: gtk::ListBoxRow,
+    field: gtk::DropDown,
+    operator: gtk::DropDown,
+    value: gtk::Entry,
+}
+
+impl RuleRow {
+    fn new(rule: &Rule) -> Rc<Self> {
+        let labels: Vec<String> = RuleField::ALL.iter().map(|f| f.label()).collect();
+        let labels: Vec<&str> = labels.iter().map(|s| s.as_str()).collect();
+        let field = gtk::DropDown::from_strings(&labels);
+        field.set_valign(gtk::Align::Center);
+
+        let operator = gtk::DropDown::from_strings(&[]);
+        operator.set_valign(gtk::Align::Center);
+
+        let value = gtk::Entry::builder()
+            .hexpand(true)
+            .valign(gtk::Align::Center)
+            .build();
+
+        let remove = gtk::Button::builder()
+            .icon_name("list-remove-symbolic")
+            .tooltip_text(i18n("Remove Rule"))
+            .valign(gtk::Align::Center)
+            .build();
+        remove.add_css_class("flat");
+
+        let content = gtk::Box::builder()
+            .spacing(6)
+            .margin_top(6)
+            .margin_bottom(6)
+            .margin_start(12)
+            .margin_end(6)
+            .build();
+        content.append(&field);
+        content.append(&operator);
+        content.append(&value);
+        content.append(&remove);
+
+        let row = gtk::ListBoxRow::builder()
+            .activatable(false)
+            .child(&content)
+            .build();
+
+        let res = Rc::new(Self {
+            row,
+            field,
+            operator,
+            value,
+        });
+
+        let field_pos = RuleField::ALL
+            .iter()
+            .position(|f| *f == rule.field)
+            .unwrap_or_default();
+        res.field.set_selected(field_pos as u32);
+        res.update_operators(Some(rule.operator));
+        res.value.set_text(&rule.value);
+
+        res.field.connect_selected_notify(clone!(@weak res => move |_| {
+            res.update_operators(None);
+        }));
+        res.operator.connect_selected_notify(clone!(@weak res => move |_| {
+            res.value.set_sensitive(res.rule_operator().needs_value());
+        }));
+
+        res
+    }
+
+    fn rule_field(&self) -> RuleField {
+        RuleField::ALL
+            .get(self.field.selected() as usize)
+            .copied()
+            .unwrap_or(RuleField::Genre)
+    }
+
+    fn rule_operator(&self) -> RuleOperator {
+        let operators = self.rule_field().operators();
+        operators
+            .get(self.operator.selected() as usize)
+            .copied()
+            .unwrap_or(operators[0])
+    }
+
+    fn update_operators(&self, operator: Option<RuleOperator>) {
+        let operators = self.rule_field().operators();
+        let labels: Vec<String> = operators.iter().map(|o| o.label()).collect();
+        let labels: Vec<&str> = labels.iter().map(|s| s.as_str()).collect();
+        self.operator
+            .set_model(Some(&gtk::StringList::new(&labels)));
+
+        let pos = operator
+            .and_then(|op| operators.iter().position(|o| *o == op))
+            .unwrap_or_default();
+        self.operator.set_selected(pos as u32);
+        self.value.set_sensitive(self.rule_operator().needs_value());
+    }
+
+    fn rule(&self) -> Rule {
+        Rule::new(self.rule_field(), self.rule_operator(), &self.value.text())
+    }
+}
+
+mod imp {
+    use glib::subclass::Signal;
+    use once_cell::sync::Lazy;
+
+    use super::*;
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/io/bassi/Amberol/smart-playlist-dialog.ui")]
+    pub struct SmartPlaylistDialog {
+        #[template_child]
+        pub navigation_view: TemplateChild<adw::NavigationView>,
+        #[template_child]
+        pub new_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub playlists_stack: TemplateChild<gtk::Stack>,
+        #[template_child]
+        pub playlists_box: TemplateChild<gtk::ListBox>,
+        #[template_child]
+        pub save_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub name_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub match_row: TemplateChild<adw::ComboRow>,
+        #[template_child]
+        pub order_row: TemplateChild<adw::ComboRow>,
+        #[template_child]
+        pub folders_group: TemplateChild<adw::PreferencesGroup>,
+        #[template_child]
+        pub add_folder_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub rules_group: TemplateChild<adw::PreferencesGroup>,
+        #[template_child]
+        pub add_rule_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub delete_button: TemplateChild<gtk::Button>,
+
+        pub playlists: RefCell<Vec<SmartPlaylist>>,
+        // The playlist currently in the editor
+        pub editing: RefCell<Option<SmartPlaylist>>,
+        pub folders: RefCell<Vec<PathBuf>>,
+        pub folder_rows: RefCell<Vec<adw::ActionRow>>,
+        pub rule_rows: RefCell<Vec<Rc<RuleRow>>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for SmartPlaylistDialog {
+        const NAME: &'static str = "AmberolSmartPlaylistDialog";
+        type Type = super::SmartPlaylistDialog;
+        type ParentType = adw::Dialog;
+
+        fn class_init(klass: &mut Self::Class) {
+            Self::bind_template(klass);
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for SmartPlaylistDialog {
+        fn constructed(&self) {
+            self.parent_constructed();
+
+            let obj = self.obj();
+            obj.setup_widgets();
+            obj.reload_playlists();
+        }
+
+        fn signals() -> &'static [Signal] {
+            static SIGNALS: Lazy<Vec<Signal>> = Lazy::new(|| {
+                vec![
+                    Signal::builder("play-playlist")
+                        .param_types([String::static_type()])
+                        .build(),
+                    Signal::builder("playlist-changed")
+                        .param_types([String::static_type()])
+                        .build(),
+                ]
+            });
+
+            SIGNALS.as_ref()
+        }
+    }
+
+    impl WidgetImpl for SmartPlaylistDialog {}
+    impl AdwDialogImpl for SmartPlaylistDialog {}
+}
+
+glib::wrapper! {
+    pub struct SmartPlaylistDialog(ObjectSubclass<imp::SmartPlaylistDialog>)
+        @extends gtk::Widget, adw::Dialog;
+}
+
+impl Default for SmartPlaylistDialog {
+    fn default() -> Self {
+        glib::Object::new()
+    }
+}
+
+impl SmartPlaylistDialog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn setup_widgets(&self) {
+        let imp = self.imp();
+
+        imp.new_button.connect_clicked(clone!(@weak self as this => move |_| {
+            this.edit_playlist(SmartPlaylist::default());
+        }));
+
+        imp.add_folder_button.connect_clicked(clone!(@weak self as this => move |_| {
+            this.select_folders();
+        }));
+
+        imp.add_rule_button.connect_clicked(clone!(@weak self as this => move |_| {
+            this.add_rule_row(&Rule::default());
+        }));
+
+        imp.save_button.connect_clicked(clone!(@weak self as this => move |_| {
+            this.save_playlist();
+        }));
+
+        imp.delete_button.connect_clicked(clone!(@weak self as this => move |_| {
+            this.delete_playlist();
+        }));
+    }
+
+    fn reload_playlists(&self) {
+        let imp = self.imp();
+
+        imp.playlists.replace(load_smart_playlists());
+        imp.playlists_box.remove_all();
+
+        for pls in imp.playlists.borrow().iter() {
+            let n_rules = pls.rules.len() as u32;
+            let subtitle = ni18n_f(
+                // Translators: the `{}` must be left unmodified;
+                // it will be expanded to the number of rules in
+                // the smart playlist
+                "{} rule",
+                "{} rules",
+                n_rules,
+                &[&n_rules.to_string()],
+            );
+
+            let row = adw::ActionRow::builder()
+                .title(glib::markup_escape_text(&pls.name))
+                .subtitle(subtitle)
+                .build();
+
+            let play_button = gtk::Button::builder()
+                .icon_name("media-playback-start-symbolic")
+                .tooltip_text(i18n("Play Smart Playlist"))
+                .valign(gtk::Align::Center)
+                .build();
+            play_button.add_css_class("flat");
+            let id = pls.id.clone();
+            play_button.connect_clicked(clone!(@weak self as this, @strong id => move |_| {
+                this.emit_by_name::<()>("play-playlist", &[&id]);
+                this.close();
+            }));
+            row.add_suffix(&play_button);
+
+            let edit_button = gtk::Button::builder()
+                .icon_name("document-edit-symbolic")
+                .tooltip_text(i18n("Edit Smart Playlist"))
+                .valign(gtk::Align::Center)
+                .build();
+            edit_button.add_css_class("flat");
+            let pls = pls.clone();
+            edit_button.connect_clicked(clone!(@weak self as this => move |_| {
+                this.edit_playlist(pls.clone());
+            }));
+            row.add_suffix(&edit_button);
+            row.set_activatable_widget(Some(&edit_button));
+
+            imp.playlists_box.append(&row);
+        }
+
+        if imp.playlists.borrow().is_empty() {
+            imp.playlists_stack.set_visible_child_name("empty");
+        } else {
+            imp.playlists_stack.set_visible_child_name("list");
+        }
+    }
+
+    fn edit_playlist(&self, playlist: SmartPlaylist) {
+        let imp = self.imp();
+
+        imp.name_row.set_text(&playlist.name);
+        imp.match_row.set_selected(match playlist.match_mode {
+            MatchMode::All => 0,
+            MatchMode::Any => 1,
+        });
+        imp.order_row.set_selected(match playlist.order {
+            SortOrder::Album => 0,
+            SortOrder::Rating => 1,
+        });
+
+        for row in imp.folder_rows.take() {
+            imp.folders_group.remove(&row);
+        }
+        imp.folders.replace(Vec::new());
+        for folder in &playlist.folders {
+            self.add_folder_row(folder.clone());
+        }
+
+        for rule_row in imp.rule_rows.take() {
+            imp.rules_group.remove(&rule_row.row);
+        }
+        for rule in &playlist.rules {
+            self.add_rule_row(rule);
+        }
+
+        let is_new = !imp.playlists.borrow().iter().any(|p| p.id == playlist.id);
+        imp.delete_button.set_visible(!is_new);
+
+        imp.editing.replace(Some(playlist));
+        imp.navigation_view.push_by_tag("editor");
+    }
+
+    fn add_folder_row(&self, folder: PathBuf) {
+        let imp = self.imp();
+
+        if imp.folders.borrow().contains(&folder) {
+            return;
+        }
+
+        let file = gio::File::for_path(&folder);
+        let title = file
+            .basename()
+            .map(|b| b.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let row = adw::ActionRow::builder()
+            .title(glib::markup_escape_text(&title))
+            .subtitle(glib::markup_escape_text(&folder.to_string_lossy()))
+            .build();
+
+        let remove_button = gtk::Button::builder()
+            .icon_name("list-remove-symbolic")
+            .tooltip_text(i18n("Remove Folder"))
+            .valign(gtk::Align::Center)
+            .build();
+        remove_button.add_css_class("flat");
+        remove_button.connect_clicked(clone!(@weak self as this, @weak row, @strong folder => move |_| {
+            let imp = this.imp();
+            imp.folders.borrow_mut().retain(|f| *f != folder);
+            imp.folder_rows.borrow_mut().retain(|r| *r != row);
+            imp.folders_group.remove(&row);
+        }));
+        row.add_suffix(&remove_button);
+
+        imp.folders_group.add(&row);
+        imp.folder_rows.borrow_mut().push(row);
+        imp.folders.borrow_mut().push(folder);
+    }
+
+    fn add_rule_row(&self, rule: &Rule) {
+        let imp = self.imp();
+
+        let rule_row = RuleRow::new(rule);
+
+        // The remove button is the last child of the row's box
+        if let Some(remove_button) = rule_row
+            .row
+            .child()
+            .and_then(|c| c.last_child())
+            .and_downcast::<gtk::Button>()
+        {
+            remove_button.connect_clicked(clone!(@weak self as this, @weak rule_row.row as row => move |_| {
+                let imp = this.imp();
+                imp.rule_rows.borrow_mut().retain(|r| r.row != row);
+                imp.rules_group.remove(&row);
+            }));
+        }
+
+        imp.rules_group.add(&rule_row.row);
+        imp.rule_rows.borrow_mut().push(rule_row);
+    }
+
+    fn select_folders(&self) {
+        let ctx = glib::MainContext::default();
+        ctx.spawn_local(clone!(@weak self as this => async move {
+            let dialog = gtk::FileDialog::builder()
+                .accept_label(i18n("_Add Folder"))
+                .modal(true)
+                .title(i18n("Select Folder"))
+                .build();
+
+            let root = this.root().and_downcast::<gtk::Window>();
+            if let Ok(files) = dialog.select_multiple_folders_future(root.as_ref()).await {
+                for pos in 0..files.n_items() {
+                    let file = files.item(pos).and_downcast::<gio::File>().unwrap();
+                    if let Some(path) = file.path() {
+                        this.add_folder_row(path);
+                    }
+                }
+            }
+        }));
+    }
+
+    fn save_playlist(&self) {
+        let imp = self.imp();
+
+        let mut playlist = match imp.editing.take() {
+            Some(p) => p,
+            None => return,
+        };
+
+        let name = imp.name_row.text();
+        if !name.trim().is_empty() {
+            playlist.name = name.trim().to_string();
+        }
+        playlist.match_mode = match imp.match_row.selected() {
+            1 => MatchMode::Any,
+            _ => MatchMode::All,
+        };
+        playlist.order = match imp.order_row.selected() {
+            1 => SortOrder::Rating,
+            _ => SortOrder::Album,
+        };
+        playlist.folders = imp.folders.borrow().clone();
+        playlist.rules = imp.rule_rows.borrow().iter().map(|r| r.rule()).collect();
+
+        debug!("Saving smart playlist {:?}", &playlist);
+
+        {
+            let mut playlists = imp.playlists.borrow_mut();
+            if let Some(p) = playlists.iter_mut().find(|p| p.id == playlist.id) {
+                *p = playlist.clone();
+            } else {
+                playlists.push(playlist.clone());
+            }
+            store_smart_playlists(&playlists);
+        }
+
+        self.reload_playlists();
+        imp.navigation_view.pop();
+
+        self.emit_by_name::<()>("playlist-changed", &[&playlist.id]);
+    }
+
+    fn delete_playlist(&self) {
+        let imp = self.imp();
+
+        let playlist = match imp.editing.take() {
+            Some(p) => p,
+            None => return,
+        };
+
+        {
+            let mut playlists = imp.playlists.borrow_mut();
+            playlists.retain(|p| p.id != playlist.id);
+            store_smart_playlists(&playlists);
+        }
+
+        self.reload_playlists();
+        imp.navigation_view.pop();
+
+        self.emit_by_name::<()>("playlist-changed", &[&playlist.id]);
+    }
+}
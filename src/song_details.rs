@@ -1,10 +1,22 @@
 // SPDX-FileCopyrightText: 2022  Emmanuele Bassi
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use std::cell::{Cell, RefCell};
+
 use adw::subclass::prelude::*;
+use glib::clone;
 use gtk::{glib, prelude::*, CompositeTemplate};
 
+use crate::{
+    audio::{Song, MAX_RATING},
+    i18n::ni18n_f,
+    utils,
+};
+
 mod imp {
+    use glib::subclass::Signal;
+    use once_cell::sync::Lazy;
+
     use super::*;
 
     #[derive(Debug, Default, CompositeTemplate)]
@@ -17,6 +29,23 @@ mod imp {
         pub song_artist_label: TemplateChild<gtk::Label>,
         #[template_child]
         pub song_album_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub rating_box: TemplateChild<gtk::Box>,
+        #[template_child]
+        pub file_info_expander: TemplateChild<gtk::Expander>,
+        #[template_child]
+        pub codec_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub bit_depth_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub sample_rate_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub channels_label: TemplateChild<gtk::Label>,
+        #[template_child]
+        pub bitrate_label: TemplateChild<gtk::Label>,
+
+        pub rating_buttons: RefCell<Vec<gtk::Button>>,
+        pub rating: Cell<u32>,
     }
 
     #[glib::object_subclass]
@@ -39,11 +68,26 @@ mod imp {
     }
 
     impl ObjectImpl for SongDetails {
+        fn constructed(&self) {
+            self.parent_constructed();
+            self.obj().setup_rating();
+        }
+
         fn dispose(&self) {
             while let Some(child) = self.obj().first_child() {
                 child.unparent();
             }
         }
+
+        fn signals() -> &'static [Signal] {
+            static SIGNALS: Lazy<Vec<Signal>> = Lazy::new(|| {
+                vec![Signal::builder("rating-changed")
+                    .param_types([u32::static_type()])
+                    .build()]
+            });
+
+            SIGNALS.as_ref()
+        }
     }
 
     impl WidgetImpl for SongDetails {}
@@ -76,4 +120,102 @@ impl SongDetails {
     pub fn album_label(&self) -> gtk::Label {
         self.imp().song_album_label.get()
     }
+
+    fn setup_rating(&self) {
+        let imp = self.imp();
+
+        let mut buttons = Vec::new();
+        for stars in 1..=MAX_RATING {
+            let button = gtk::Button::builder()
+                .icon_name("non-starred-symbolic")
+                .tooltip_text(ni18n_f(
+                    // Translators: the `{}` must be left unmodified, and
+                    // it will be replaced by the number of stars
+                    "Rate {} Star",
+                    "Rate {} Stars",
+                    stars,
+                    &[&stars.to_string()],
+                ))
+                .build();
+            button.add_css_class("flat");
+            button.add_css_class("circular");
+
+            // Clicking on the current rating clears it
+            button.connect_clicked(clone!(@weak self as this => move |_| {
+                let rating = if this.imp().rating.get() == stars {
+                    0
+                } else {
+                    stars
+                };
+                this.emit_by_name::<()>("rating-changed", &[&rating]);
+            }));
+
+            imp.rating_box.append(&button);
+            buttons.push(button);
+        }
+
+        imp.rating_buttons.replace(buttons);
+    }
+
+    pub fn set_rating(&self, rating: Option<u32>) {
+        let imp = self.imp();
+
+        let rating = match rating {
+            Some(r) => r,
+            None => {
+                imp.rating_box.set_visible(false);
+                return;
+            }
+        };
+
+        imp.rating.set(rating);
+        for (i, button) in imp.rating_buttons.borrow().iter().enumerate() {
+            if (i as u32) < rating {
+                button.set_icon_name("starred-symbolic");
+            } else {
+                button.set_icon_name("non-starred-symbolic");
+            }
+        }
+        imp.rating_box.set_visible(true);
+    }
+
+    pub fn set_file_info(&self, song: Option<&Song>) {
+        let imp = self.imp();
+
+        let song = match song {
+            Some(s) => s,
+            None => {
+                imp.file_info_expander.set_visible(false);
+                return;
+            }
+        };
+
+        let unknown = || "—".to_string();
+        imp.codec_label.set_label(&song.codec().unwrap_or_else(unknown));
+        imp.bit_depth_label.set_label(
+            &song
+                .bit_depth()
+                .map(utils::format_bit_depth)
+                .unwrap_or_else(unknown),
+        );
+        imp.sample_rate_label.set_label(
+            &song
+                .sample_rate()
+                .map(utils::format_sample_rate)
+                .unwrap_or_else(unknown),
+        );
+        imp.channels_label.set_label(
+            &song
+                .channels()
+                .map(utils::format_channels)
+                .unwrap_or_else(unknown),
+        );
+        imp.bitrate_label.set_label(
+            &song
+                .bitrate()
+                .map(utils::format_bitrate)
+                .unwrap_or_else(unknown),
+        );
+        imp.file_info_expander.set_visible(true);
+    }
 }
@@ -56,10 +56,15 @@ mod imp {
 
             if let Some(search) = self.search.borrow().as_ref() {
                 let matcher = SkimMatcherV2::default();
-                let item1_key = item1.search_key();
-                let item2_key = item2.search_key();
-                let item1_score = matcher.fuzzy_match(&item1_key, search);
-                let item2_score = matcher.fuzzy_match(&item2_key, search);
+                // Use the best match among all the credited artists
+                let score = |song: &Song| {
+                    song.search_keys()
+                        .iter()
+                        .filter_map(|key| matcher.fuzzy_match(key, search))
+                        .max()
+                };
+                let item1_score = score(item1);
+                let item2_score = score(item2);
                 item1_score.cmp(&item2_score).reverse().into()
             } else {
                 cmp_two_files(None, &item1.file(), &item2.file()).into()
@@ -0,0 +1,345 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{cell::RefCell, collections::HashMap};
+
+use adw::{prelude::*, subclass::prelude::*};
+use glib::clone;
+use gtk::{gdk, gio, glib, CompositeTemplate};
+use log::warn;
+
+use crate::{
+    audio::{Song, TagChanges},
+    cover_picture::CoverPicture,
+    i18n::{i18n, ni18n_f},
+};
+
+// The value of a field shared by all the songs; if the songs have
+// different values, the field starts out empty
+fn common_value<F>(songs: &[Song], f: F) -> String
+where
+    F: Fn(&Song) -> String,
+{
+    let mut values = songs.iter().map(f);
+    let first = values.next().unwrap_or_default();
+    if values.all(|v| v == first) {
+        first
+    } else {
+        String::new()
+    }
+}
+
+fn number_text(n: Option<u32>) -> String {
+    n.map(|n| n.to_string()).unwrap_or_default()
+}
+
+mod imp {
+    use glib::subclass::Signal;
+    use once_cell::sync::Lazy;
+
+    use super::*;
+
+    #[derive(Debug, Default, CompositeTemplate)]
+    #[template(resource = "/io/bassi/Amberol/tag-editor-dialog.ui")]
+    pub struct TagEditorDialog {
+        #[template_child]
+        pub window_title: TemplateChild<adw::WindowTitle>,
+        #[template_child]
+        pub save_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub cover_stack: TemplateChild<gtk::Stack>,
+        #[template_child]
+        pub cover_image: TemplateChild<CoverPicture>,
+        #[template_child]
+        pub choose_cover_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub remove_cover_button: TemplateChild<gtk::Button>,
+        #[template_child]
+        pub title_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub artist_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub album_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub album_artist_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub genre_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub track_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub disc_row: TemplateChild<adw::EntryRow>,
+        #[template_child]
+        pub year_row: TemplateChild<adw::EntryRow>,
+
+        pub songs: RefCell<Vec<Song>>,
+        // The text of each row when the dialog was filled, used to
+        // find out which fields have been edited
+        pub initial_values: RefCell<HashMap<String, String>>,
+        pub cover: RefCell<Option<Vec<u8>>>,
+        pub changes: RefCell<TagChanges>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for TagEditorDialog {
+        const NAME: &'static str = "AmberolTagEditorDialog";
+        type Type = super::TagEditorDialog;
+        type ParentType = adw::Dialog;
+
+        fn class_init(klass: &mut Self::Class) {
+            Self::bind_template(klass);
+        }
+
+        fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+            obj.init_template();
+        }
+    }
+
+    impl ObjectImpl for TagEditorDialog {
+        fn constructed(&self) {
+            self.parent_constructed();
+            self.obj().setup_widgets();
+        }
+
+        fn signals() -> &'static [Signal] {
+            static SIGNALS: Lazy<Vec<Signal>> = Lazy::new(|| vec![Signal::builder("save").build()]);
+
+            SIGNALS.as_ref()
+        }
+    }
+
+    impl WidgetImpl for TagEditorDialog {}
+    impl AdwDialogImpl for TagEditorDialog {}
+}
+
+// TagEditorDialog edits the tags of one or more songs; when the user
+// saves, the "save" signal is emitted, and the changes can be retrieved
+// using TagEditorDialog::changes()
+glib::wrapper! {
+    pub struct TagEditorDialog(ObjectSubclass<imp::TagEditorDialog>)
+        @extends gtk::Widget, adw::Dialog;
+}
+
+impl TagEditorDialog {
+    pub fn new(songs: &[Song]) -> Self {
+        let dialog: Self = glib::Object::new();
+        dialog.set_songs(songs);
+        dialog
+    }
+
+    fn setup_widgets(&self) {
+        let imp = self.imp();
+
+        imp.save_button.connect_clicked(clone!(@weak self as this => move |_| {
+            this.save();
+        }));
+
+        imp.choose_cover_button.connect_clicked(clone!(@weak self as this => move |_| {
+            this.choose_cover();
+        }));
+
+        imp.remove_cover_button.connect_clicked(clone!(@weak self as this => move |_| {
+            this.set_cover(Some(Vec::new()), None);
+        }));
+
+        for row in self.number_rows() {
+            row.connect_changed(|row| {
+                row.remove_css_class("error");
+            });
+        }
+    }
+
+    fn text_rows(&self) -> [(&'static str, adw::EntryRow); 5] {
+        let imp = self.imp();
+        [
+            ("title", imp.title_row.get()),
+            ("artists", imp.artist_row.get()),
+            ("album", imp.album_row.get()),
+            ("album-artist", imp.album_artist_row.get()),
+            ("genre", imp.genre_row.get()),
+        ]
+    }
+
+    fn number_rows(&self) -> [adw::EntryRow; 3] {
+        let imp = self.imp();
+        [imp.track_row.get(), imp.disc_row.get(), imp.year_row.get()]
+    }
+
+    fn set_songs(&self, songs: &[Song]) {
+        let imp = self.imp();
+
+        let n_songs = songs.len() as u32;
+        if n_songs > 1 {
+            imp.window_title.set_subtitle(&ni18n_f(
+                // Translators: the `{}` must be left unmodified, and
+                // it will be replaced by the number of songs being
+                // edited
+                "{} song",
+                "{} songs",
+                n_songs,
+                &[&n_songs.to_string()],
+            ));
+        } else if let Some(song) = songs.first() {
+            imp.window_title.set_subtitle(&song.title());
+        }
+
+        let values = [
+            common_value(songs, |s| s.tagged_title().unwrap_or_default()),
+            common_value(songs, |s| s.artists().join("; ")),
+            common_value(songs, |s| s.tagged_album().unwrap_or_default()),
+            common_value(songs, |s| s.album_artist().unwrap_or_default()),
+            common_value(songs, |s| s.genre().unwrap_or_default()),
+        ];
+        let numbers = [
+            common_value(songs, |s| number_text(s.track_number())),
+            common_value(songs, |s| number_text(s.disc_number())),
+            common_value(songs, |s| number_text(s.year())),
+        ];
+
+        let mut initial_values = HashMap::new();
+        for ((name, row), value) in self.text_rows().iter().zip(values) {
+            row.set_text(&value);
+            initial_values.insert(name.to_string(), value);
+        }
+        for ((name, row), value) in ["track", "disc", "year"]
+            .iter()
+            .zip(self.number_rows())
+            .zip(numbers)
+        {
+            row.set_text(&value);
+            initial_values.insert(name.to_string(), value);
+        }
+        imp.initial_values.replace(initial_values);
+
+        // Only show the cover if it is shared by all songs
+        let cover_uuid = common_value(songs, |s| s.cover_uuid().unwrap_or_default());
+        let texture = if cover_uuid.is_empty() {
+            None
+        } else {
+            songs.first().and_then(|s| s.cover_texture())
+        };
+        imp.cover.replace(None);
+        self.update_cover(texture.as_ref());
+
+        imp.songs.replace(songs.to_vec());
+    }
+
+    pub fn songs(&self) -> Vec<Song> {
+        self.imp().songs.borrow().clone()
+    }
+
+    pub fn changes(&self) -> TagChanges {
+        self.imp().changes.borrow().clone()
+    }
+
+    fn update_cover(&self, texture: Option<&gdk::Texture>) {
+        let imp = self.imp();
+        imp.cover_image.set_cover(texture);
+        if texture.is_some() {
+            imp.cover_stack.set_visible_child_name("cover");
+        } else {
+            imp.cover_stack.set_visible_child_name("no-cover");
+        }
+    }
+
+    fn set_cover(&self, data: Option<Vec<u8>>, texture: Option<&gdk::Texture>) {
+        self.imp().cover.replace(data);
+        self.update_cover(texture);
+    }
+
+    fn choose_cover(&self) {
+        glib::spawn_future_local(clone!(@weak self as this => async move {
+            let filters = gio::ListStore::new::<gtk::FileFilter>();
+            let filter = gtk::FileFilter::new();
+            filter.set_name(Some(&i18n("Image files")));
+            filter.add_mime_type("image/jpeg");
+            filter.add_mime_type("image/png");
+            filters.append(&filter);
+
+            let dialog = gtk::FileDialog::builder()
+                .accept_label(i18n("_Select"))
+                .filters(&filters)
+                .modal(true)
+                .title(i18n("Select Cover Image"))
+                .build();
+
+            let root = this.root().and_downcast::<gtk::Window>();
+            let file = match dialog.open_future(root.as_ref()).await {
+                Ok(f) => f,
+                Err(_) => return,
+            };
+
+            match file.load_bytes_future().await {
+                Ok((bytes, _)) => match gdk::Texture::from_bytes(&bytes) {
+                    Ok(texture) => this.set_cover(Some(bytes.to_vec()), Some(&texture)),
+                    Err(e) => warn!("Unable to load cover image {}: {}", file.uri(), e),
+                },
+                Err(e) => warn!("Unable to read cover image {}: {}", file.uri(), e),
+            }
+        }));
+    }
+
+    // The text of a row, if it has been edited
+    fn edited_text(&self, name: &str, row: &adw::EntryRow) -> Option<String> {
+        let text = row.text().trim().to_string();
+        let initial_values = self.imp().initial_values.borrow();
+        if initial_values.get(name).is_some_and(|v| *v == text) {
+            None
+        } else {
+            Some(text)
+        }
+    }
+
+    // The value of a numeric row, if it has been edited; empty rows
+    // map to zero, to remove the field
+    fn edited_number(&self, name: &str, row: &adw::EntryRow) -> Result<Option<u32>, ()> {
+        match self.edited_text(name, row) {
+            Some(text) if text.is_empty() => Ok(Some(0)),
+            Some(text) => match text.parse::<u32>() {
+                Ok(n) => Ok(Some(n)),
+                Err(_) => {
+                    row.add_css_class("error");
+                    Err(())
+                }
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self) {
+        let imp = self.imp();
+
+        let track_number = self.edited_number("track", &imp.track_row);
+        let disc_number = self.edited_number("disc", &imp.disc_row);
+        let year = self.edited_number("year", &imp.year_row);
+        let (track_number, disc_number, year) = match (track_number, disc_number, year) {
+            (Ok(t), Ok(d), Ok(y)) => (t, d, y),
+            _ => return,
+        };
+
+        let changes = TagChanges {
+            title: self.edited_text("title", &imp.title_row),
+            artists: self.edited_text("artists", &imp.artist_row).map(|text| {
+                text.split(';')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            }),
+            album: self.edited_text("album", &imp.album_row),
+            album_artist: self.edited_text("album-artist", &imp.album_artist_row),
+            track_number,
+            disc_number,
+            year,
+            genre: self.edited_text("genre", &imp.genre_row),
+            // Ratings are set from the queue
+            rating: None,
+            cover: imp.cover.borrow().clone(),
+        };
+
+        if !changes.is_empty() {
+            imp.changes.replace(changes);
+            self.emit_by_name::<()>("save", &[]);
+        }
+
+        self.close();
+    }
+}
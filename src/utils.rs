@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use core::cmp::Ordering;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use color_thief::{get_palette, ColorFormat};
 use gtk::{gdk, gio, glib, prelude::*};
@@ -11,6 +11,7 @@ use log::{debug, warn};
 use crate::{
     audio::{Queue, Song},
     config::APPLICATION_ID,
+    i18n::{i18n, i18n_f, ni18n_f},
 };
 
 pub fn settings_manager() -> gio::Settings {
@@ -30,6 +31,67 @@ pub fn format_time(t: i64) -> String {
     format!("{}:{:02}", (t - (t % 60)) / 60, t % 60)
 }
 
+pub fn format_sample_rate(rate: u32) -> String {
+    let khz = format!("{:.1}", rate as f64 / 1000.0);
+    // Translators: the `{}` must be left unmodified;
+    // it will be expanded to the sample rate, e.g. 44.1
+    i18n_f("{} kHz", &[khz.trim_end_matches(".0")])
+}
+
+pub fn format_bitrate(bitrate: u32) -> String {
+    // Translators: the `{}` must be left unmodified;
+    // it will be expanded to the bitrate
+    i18n_f("{} kbps", &[&bitrate.to_string()])
+}
+
+pub fn format_bit_depth(depth: u32) -> String {
+    // Translators: the `{}` must be left unmodified;
+    // it will be expanded to the number of bits per sample
+    i18n_f("{} bit", &[&depth.to_string()])
+}
+
+pub fn format_channels(channels: u32) -> String {
+    match channels {
+        1 => i18n("Mono"),
+        2 => i18n("Stereo"),
+        n => ni18n_f(
+            // Translators: the `{}` must be left unmodified;
+            // it will be expanded to the number of audio channels
+            "{} channel",
+            "{} channels",
+            n,
+            &[&n.to_string()],
+        ),
+    }
+}
+
+// A one line summary of the technical details of the song, like
+// "FLAC, 24 bit, 96 kHz, Stereo"
+pub fn format_stream_info(song: &Song) -> Option<String> {
+    let mut res = Vec::new();
+    if let Some(codec) = song.codec() {
+        res.push(codec);
+    }
+    if let Some(depth) = song.bit_depth() {
+        res.push(format_bit_depth(depth));
+    }
+    if let Some(rate) = song.sample_rate() {
+        res.push(format_sample_rate(rate));
+    }
+    if let Some(channels) = song.channels() {
+        res.push(format_channels(channels));
+    }
+    if let Some(bitrate) = song.bitrate() {
+        res.push(format_bitrate(bitrate));
+    }
+
+    if res.is_empty() {
+        None
+    } else {
+        Some(res.join(", "))
+    }
+}
+
 // The base cover size is 192px, but we need to account for HiDPI;
 // better to scale down when rendering on displays with a scaling
 // factor of 1 than having to scale up on displays with a scaling
@@ -57,7 +119,7 @@ pub fn load_cover_texture(buffer: &glib::Bytes) -> Option<gdk_pixbuf::Pixbuf> {
 
         debug!("Cover size {width} x {height} (ratio: {ratio}), scaled: {w} x {h}");
 
-        pixbuf.scale_simple(w, h, gdk_pixbuf::InterpType::Nearest)
+        pixbuf.scale_simple(w, h, gdk_pixbuf::InterpType::Bilinear)
     } else {
         warn!("Unable to load cover art");
         None
@@ -75,17 +137,16 @@ pub fn cache_cover_art(uuid: &str, pixbuf: &gdk_pixbuf::Pixbuf) -> Option<PathBu
     match file.create(gio::FileCreateFlags::NONE, gio::Cancellable::NONE) {
         Ok(stream) => {
             debug!("Creating cover data cache at {:?}", &cache_dir);
-            pixbuf.save_to_streamv_async(
+            // Covers are loaded by worker threads, which do not have a
+            // main context to dispatch asynchronous operations to
+            if let Err(e) = pixbuf.save_to_streamv(
                 &stream,
                 "png",
                 &[("tEXt::Software", "amberol")],
                 gio::Cancellable::NONE,
-                move |res| {
-                    if let Err(e) = res {
-                        warn!("Unable to cache cover data: {}", e);
-                    }
-                },
-            );
+            ) {
+                warn!("Unable to cache cover data: {}", e);
+            }
         }
         Err(e) => {
             if let Some(file_error) = e.kind::<glib::FileError>() {
@@ -206,6 +267,52 @@ pub fn cmp_two_files(base: Option<&gio::File>, a: &gio::File, b: &gio::File) ->
     order
 }
 
+// Orders songs by album artist, album, disc and track number; songs
+// without a track number use the file name collation instead
+pub fn cmp_two_songs(a: &Song, b: &Song) -> Ordering {
+    fn collate(a: &str, b: &str) -> Ordering {
+        let key_a = glib::CollationKey::from(a);
+        let key_b = glib::CollationKey::from(b);
+        key_a.partial_cmp(&key_b).unwrap()
+    }
+
+    let album_artist_a = a.album_artist().unwrap_or_else(|| a.artist());
+    let album_artist_b = b.album_artist().unwrap_or_else(|| b.artist());
+
+    collate(&album_artist_a, &album_artist_b)
+        .then_with(|| collate(&a.album(), &b.album()))
+        .then_with(|| {
+            cmp_track_positions(
+                (a.disc_number(), a.track_number()),
+                (b.disc_number(), b.track_number()),
+            )
+        })
+        .then_with(|| cmp_two_files(None, &a.file(), &b.file()))
+}
+
+// Orders the (disc, track) numbers of two songs in the same album: songs
+// with a track number come first, by disc and track; songs without one
+// compare as equal, so the caller can fall back to their file names
+fn cmp_track_positions(a: (Option<u32>, Option<u32>), b: (Option<u32>, Option<u32>)) -> Ordering {
+    match (a, b) {
+        ((disc_a, Some(track_a)), (disc_b, Some(track_b))) => disc_a
+            .unwrap_or(1)
+            .cmp(&disc_b.unwrap_or(1))
+            .then(track_a.cmp(&track_b)),
+        ((_, Some(_)), (_, None)) => Ordering::Less,
+        ((_, None), (_, Some(_))) => Ordering::Greater,
+        ((_, None), (_, None)) => Ordering::Equal,
+    }
+}
+
+// Orders songs by rating, highest first; songs with the same rating
+// are ordered by album
+pub fn cmp_songs_by_rating(a: &Song, b: &Song) -> Ordering {
+    b.rating()
+        .cmp(&a.rating())
+        .then_with(|| cmp_two_songs(a, b))
+}
+
 fn cmp_like_nautilus(filename_a: &str, filename_b: &str) -> Ordering {
     let order;
 
@@ -253,6 +360,11 @@ pub fn store_playlist(queue: &Queue) {
         let path = song.file().path().expect("Unknown file");
         let path_str = path.to_string_lossy();
         pls.set_value("playlist", &format!("File{i}"), &path_str);
+
+        // Used to relocate the file, in case it goes missing
+        if let Some(size) = file_size(&song.file()) {
+            pls.set_uint64("playlist", &format!("Size{i}"), size);
+        }
     }
 
     let mut pls_cache = glib::user_cache_dir();
@@ -267,7 +379,56 @@ pub fn store_playlist(queue: &Queue) {
     }
 }
 
-pub fn load_cached_songs() -> Option<Vec<gio::File>> {
+fn watched_folders_file() -> PathBuf {
+    let mut path = glib::user_cache_dir();
+    path.push("amberol");
+    path.push("playlists");
+    path.push("watched-folders.ini");
+    path
+}
+
+// The folders watched for new songs are stored next to the playlist
+pub fn store_watched_folders(folders: &[gio::File]) {
+    let keyfile = glib::KeyFile::new();
+    let paths: Vec<PathBuf> = folders.iter().filter_map(|f| f.path()).collect();
+    keyfile.set_int64("folders", "NumberOfEntries", paths.len() as i64);
+    for (i, path) in paths.iter().enumerate() {
+        keyfile.set_value("folders", &format!("Folder{i}"), &path.to_string_lossy());
+    }
+
+    let path = watched_folders_file();
+    if let Some(parent) = path.parent() {
+        glib::mkdir_with_parents(parent, 0o755);
+    }
+    if let Err(e) = keyfile.save_to_file(&path) {
+        debug!("Unable to save watched folders: {e}");
+    }
+}
+
+pub fn load_watched_folders() -> Vec<gio::File> {
+    let keyfile = glib::KeyFile::new();
+    if let Err(e) = keyfile.load_from_file(watched_folders_file(), glib::KeyFileFlags::NONE) {
+        debug!("Unable to load watched folders: {e}");
+        return Vec::new();
+    }
+
+    let n_entries = keyfile.int64("folders", "NumberOfEntries").unwrap_or(0);
+    (0..n_entries)
+        .filter_map(|i| keyfile.value("folders", &format!("Folder{i}")).ok())
+        .map(|p| gio::File::for_path(p.as_str()))
+        .collect()
+}
+
+// An entry of the cached playlist
+#[derive(Clone, Debug)]
+pub struct CachedSong {
+    pub file: gio::File,
+    pub size: Option<u64>,
+    // The position of the song in the playlist
+    pub position: usize,
+}
+
+pub fn load_cached_songs() -> Option<Vec<CachedSong>> {
     let mut pls_cache = glib::user_cache_dir();
     pls_cache.push("amberol");
     pls_cache.push("playlists");
@@ -288,7 +449,11 @@ pub fn load_cached_songs() -> Option<Vec<gio::File>> {
 
     for i in 0..n_entries {
         match pls.value("playlist", &format!("File{i}")) {
-            Ok(p) => res.push(gio::File::for_path(p)),
+            Ok(p) => res.push(CachedSong {
+                file: gio::File::for_path(p),
+                size: pls.uint64("playlist", &format!("Size{i}")).ok(),
+                position: res.len(),
+            }),
             Err(e) => debug!("Skipping File{i} from playlist: {e}"),
         }
     }
@@ -296,11 +461,161 @@ pub fn load_cached_songs() -> Option<Vec<gio::File>> {
     Some(res)
 }
 
+fn file_size(file: &gio::File) -> Option<u64> {
+    file.query_info(
+        "standard::size",
+        gio::FileQueryInfoFlags::NONE,
+        gio::Cancellable::NONE,
+    )
+    .ok()
+    .map(|info| info.size() as u64)
+}
+
+// Looks for the missing songs inside the given folders, matching them
+// by file name and, if known, by size; the returned vector has the
+// same length and order of the missing songs
+pub fn find_missing_songs(missing: &[CachedSong], folders: &[gio::File]) -> Vec<Option<gio::File>> {
+    let mut candidates = Vec::new();
+    for folder in folders {
+        candidates.extend(load_files_from_folder(folder, true));
+    }
+
+    missing
+        .iter()
+        .map(|song| {
+            let basename = song.file.basename();
+            candidates
+                .iter()
+                .find(|c| {
+                    c.basename() == basename && (song.size.is_none() || file_size(c) == song.size)
+                })
+                .cloned()
+        })
+        .collect()
+}
+
+// Looks for the missing songs under their original parent folders; if
+// the parent folder itself is gone, we look inside its parent, to deal
+// with renamed album folders, but we don't go further up, to avoid
+// scanning the whole file system
+pub fn relocate_missing_songs(missing: &[CachedSong]) -> Vec<Option<gio::File>> {
+    const MAX_DEPTH: usize = 2;
+
+    let home = gio::File::for_path(glib::home_dir());
+    let mut folders: Vec<gio::File> = Vec::new();
+    for song in missing {
+        let mut parent = song.file.parent();
+        for _ in 0..MAX_DEPTH {
+            let folder = match parent {
+                Some(p) => p,
+                None => break,
+            };
+
+            // Never scan the home directory or the root
+            if folder.equal(&home) || folder.parent().is_none() {
+                break;
+            }
+
+            if folder.query_exists(gio::Cancellable::NONE) {
+                if !folders
+                    .iter()
+                    .any(|f| folder.equal(f) || folder.has_prefix(f))
+                {
+                    folders.retain(|f| !f.has_prefix(&folder));
+                    folders.push(folder);
+                }
+                break;
+            }
+
+            parent = folder.parent();
+        }
+    }
+
+    debug!(
+        "Looking for {} missing songs in {} folders",
+        missing.len(),
+        folders.len()
+    );
+
+    find_missing_songs(missing, &folders)
+}
+
+// Splits the songs of the cached playlist into the files we can restore,
+// in their original order, and the songs we could not find, even after
+// looking for them. This does blocking I/O, so it should be called from
+// a worker thread
+pub fn check_cached_songs(songs: Vec<CachedSong>) -> (Vec<gio::File>, Vec<CachedSong>) {
+    let (found, missing): (Vec<CachedSong>, Vec<CachedSong>) = songs
+        .into_iter()
+        .partition(|s| s.file.query_exists(gio::Cancellable::NONE));
+    let relocated = relocate_missing_songs(&missing);
+
+    let mut files: Vec<(usize, gio::File)> =
+        found.into_iter().map(|s| (s.position, s.file)).collect();
+    let mut still_missing = Vec::new();
+    for (song, file) in missing.into_iter().zip(relocated) {
+        match file {
+            Some(file) => {
+                debug!("Relocated '{}' to '{}'", song.file.uri(), file.uri());
+                files.push((song.position, file));
+            }
+            None => still_missing.push(song),
+        }
+    }
+
+    files.sort_by_key(|(position, _)| *position);
+
+    (files.into_iter().map(|(_, f)| f).collect(), still_missing)
+}
+
 pub fn has_cached_playlist() -> bool {
-    let mut pls_cache = glib::user_cache_dir();
+    has_cached_playlist_in(&glib::user_cache_dir())
+}
+
+// Whether the given cache directory has the playlist of the last session
+pub fn has_cached_playlist_in(cache_dir: &Path) -> bool {
+    let mut pls_cache = cache_dir.to_path_buf();
     pls_cache.push("amberol");
     pls_cache.push("playlists");
     pls_cache.push("current.pls");
 
     pls_cache.exists()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cmp_track_positions() {
+        // (disc, track, file name), in the expected order
+        let expected = [
+            (None, Some(1), "b.ogg"),
+            (Some(1), Some(2), "a.ogg"),
+            (None, Some(3), "a.ogg"),
+            (Some(2), Some(1), "d.ogg"),
+            (Some(2), Some(1), "e.ogg"),
+            (Some(2), Some(2), "c.ogg"),
+            (None, None, "a.ogg"),
+            (Some(1), None, "b.ogg"),
+            (None, None, "c.ogg"),
+            (Some(2), None, "z.ogg"),
+        ];
+        let cmp = |a: &(Option<u32>, Option<u32>, &str), b: &(Option<u32>, Option<u32>, &str)| {
+            cmp_track_positions((a.0, a.1), (b.0, b.1)).then_with(|| a.2.cmp(b.2))
+        };
+
+        let mut songs = expected.to_vec();
+        songs.reverse();
+        songs.swap(2, 7);
+        songs.sort_by(cmp);
+        assert_eq!(songs, expected);
+
+        // A total order: every pair compares consistently in both directions
+        for (i, a) in expected.iter().enumerate() {
+            for (j, b) in expected.iter().enumerate() {
+                assert_eq!(cmp(a, b), i.cmp(&j), "{a:?} vs {b:?}");
+            }
+        }
+    }
+}
@@ -10,14 +10,120 @@
 
 use std::{
     cell::{Cell, RefCell},
-    ops::DivAssign,
+    ops::{DivAssign, Range},
 };
 
 use adw::subclass::prelude::*;
 use glib::clone;
-use gtk::{gdk, glib, graphene, prelude::*};
+use gtk::{gdk, gio, glib, graphene, prelude::*};
 use log::{debug, warn};
 
+use crate::{
+    audio::WaveformLevels,
+    i18n::{i18n, i18n_k},
+    utils,
+};
+
+// What the view shows for the current song
+#[derive(Clone, Copy, Debug, glib::Enum, PartialEq, Default)]
+#[enum_type(name = "AmberolWaveformMode")]
+pub enum WaveformMode {
+    #[default]
+    Waveform,
+    Spectrogram,
+}
+
+// How the two channels of the waveform are drawn
+#[derive(Clone, Copy, Debug, glib::Enum, PartialEq, Default)]
+#[enum_type(name = "AmberolWaveformChannels")]
+pub enum WaveformChannels {
+    // One channel above the center line, and the other below it
+    #[default]
+    Mirrored,
+    // The average of the two channels, around the center line
+    Mono,
+    // The left channel in the upper half, and the right one in the
+    // lower half
+    Split,
+}
+
+// The opacity of the peaks, when the RMS level is drawn on top of them
+const RMS_PEAK_OPACITY: f32 = 0.5;
+
+// Returns the vertical position and the height of the rectangles making up
+// a bar, given the levels of the left and right channels, between 0 and 1,
+// and the height of the widget; rectangles are never thinner than 2 pixels
+fn bar_extents(left: f64, right: f64, h: f32, channels: WaveformChannels) -> Vec<(f32, f32)> {
+    let (left, right) = (left as f32, right as f32);
+    let center_y = h / 2.0;
+
+    match channels {
+        WaveformChannels::Mirrored => {
+            let y = f32::clamp(center_y - right / 2.0 * h, 0.0, center_y);
+            let height = f32::clamp((left + right) / 2.0 * h, 2.0, h);
+            vec![(y, height)]
+        }
+        WaveformChannels::Mono => {
+            let height = f32::clamp((left + right) / 2.0 * h, 2.0, h);
+            vec![(center_y - height / 2.0, height)]
+        }
+        WaveformChannels::Split => {
+            // Each channel gets its own lane, with a pixel of space
+            // between the two
+            let lane = center_y - 1.0;
+            [(left, lane / 2.0), (right, center_y + 1.0 + lane / 2.0)]
+                .iter()
+                .map(|&(v, lane_center)| {
+                    let height = f32::clamp(v * lane, 2.0, lane);
+                    (lane_center - height / 2.0, height)
+                })
+                .collect()
+        }
+    }
+}
+
+// The steps used when seeking with the keyboard, in seconds
+const SMALL_SEEK_STEP: i64 = 5;
+const LARGE_SEEK_STEP: i64 = 30;
+
+// Moves the position, between 0 and 1, by the given number of seconds
+fn step_position(position: f64, duration: u64, step: i64) -> f64 {
+    if duration == 0 {
+        return position;
+    }
+
+    (position + step as f64 / duration as f64).clamp(0.0, 1.0)
+}
+
+// Returns the text of the marker at the given time, in milliseconds: the
+// last marker starting before it
+fn marker_at(markers: &[(u64, String)], time: u64) -> Option<&str> {
+    let n_markers = markers.partition_point(|(t, _)| *t <= time);
+    n_markers
+        .checked_sub(1)
+        .map(|idx| markers[idx].1.as_str())
+        .filter(|text| !text.is_empty())
+}
+
+// The spectrogram is drawn as a grid of cells, like the bars of the
+// waveform
+const SPECTROGRAM_CELL_SIZE: i32 = 2;
+const SPECTROGRAM_CELL_SPACING: i32 = 1;
+
+// Frequencies are shown on a logarithmic scale, which is closer to the way
+// we hear them; returns the range of bands shown by a row of the
+// spectrogram, counting rows from the bottom
+fn bands_for_row(row: usize, n_rows: usize, n_bands: usize) -> Range<usize> {
+    let edge = |r: usize| {
+        let e = ((n_bands + 1) as f64).powf(r as f64 / n_rows as f64) - 1.0;
+        (e.round() as usize).min(n_bands)
+    };
+
+    let start = edge(row).min(n_bands.saturating_sub(1));
+    let end = edge(row + 1).max(start + 1).min(n_bands);
+    start..end
+}
+
 #[derive(Debug, PartialEq)]
 pub struct PeakPair {
     pub left: f64,
@@ -38,7 +144,9 @@ impl DivAssign<f64> for PeakPair {
 }
 
 mod imp {
-    use glib::{subclass::Signal, ParamSpec, ParamSpecDouble, Value};
+    use glib::{
+        subclass::Signal, ParamSpec, ParamSpecBoolean, ParamSpecDouble, ParamSpecEnum, Value,
+    };
     use once_cell::sync::Lazy;
 
     use super::*;
@@ -47,12 +155,28 @@ mod imp {
     pub struct WaveformView {
         pub position: Cell<f64>,
         pub hover_position: Cell<Option<f64>>,
-        // left and right channel peaks, normalised between 0 and 1
-        pub peaks: RefCell<Option<Vec<PeakPair>>>,
-        pub next_peaks: RefCell<Option<Vec<PeakPair>>>,
+        // left and right channel peaks and RMS levels, normalised between
+        // 0 and 1
+        pub peaks: RefCell<Option<Vec<(PeakPair, PeakPair)>>>,
+        pub next_peaks: RefCell<Option<Vec<(PeakPair, PeakPair)>>>,
         pub tick_id: RefCell<Option<gtk::TickCallbackId>>,
         pub first_frame_time: Cell<Option<i64>>,
         pub factor: Cell<Option<f64>>,
+        pub mode: Cell<WaveformMode>,
+        pub channels: Cell<WaveformChannels>,
+        pub show_rms: Cell<bool>,
+        // The magnitude of the frequency bands over time, normalised
+        // between 0 and 1
+        pub spectrogram: RefCell<Option<Vec<Vec<f64>>>>,
+        pub context_menu: RefCell<Option<gtk::PopoverMenu>>,
+        // The duration of the song, in seconds
+        pub duration: Cell<u64>,
+        // The time of the chapters or lyrics lines of the song, in
+        // milliseconds, and their text, sorted by time
+        pub markers: RefCell<Vec<(u64, String)>>,
+        // The color of the palette of the cover art, if the UI is
+        // recolored; the color of the style is used otherwise
+        pub foreground: Cell<Option<gdk::RGBA>>,
     }
 
     #[glib::object_subclass]
@@ -64,17 +188,40 @@ mod imp {
         fn class_init(klass: &mut Self::Class) {
             klass.set_css_name("waveformview");
             klass.set_accessible_role(gtk::AccessibleRole::Slider);
+
+            klass.install_property_action("waveform.mode", "mode");
+            klass.install_property_action("waveform.channels", "channels");
+            klass.install_property_action("waveform.show-rms", "show-rms");
+            klass.install_action("waveform.show-menu", None, move |view, _, _| {
+                view.show_context_menu(None);
+            });
+
+            klass.add_binding_action(
+                gdk::Key::F10,
+                gdk::ModifierType::SHIFT_MASK,
+                "waveform.show-menu",
+            );
+            klass.add_binding_action(
+                gdk::Key::Menu,
+                gdk::ModifierType::empty(),
+                "waveform.show-menu",
+            );
         }
     }
 
     impl ObjectImpl for WaveformView {
         fn properties() -> &'static [ParamSpec] {
             static PROPERTIES: Lazy<Vec<ParamSpec>> = Lazy::new(|| {
-                vec![ParamSpecDouble::builder("position")
-                    .minimum(0.0)
-                    .maximum(1.0)
-                    .default_value(0.0)
-                    .build()]
+                vec![
+                    ParamSpecDouble::builder("position")
+                        .minimum(0.0)
+                        .maximum(1.0)
+                        .default_value(0.0)
+                        .build(),
+                    ParamSpecEnum::builder::<WaveformMode>("mode").build(),
+                    ParamSpecEnum::builder::<WaveformChannels>("channels").build(),
+                    ParamSpecBoolean::builder("show-rms").build(),
+                ]
             });
 
             PROPERTIES.as_ref()
@@ -82,7 +229,14 @@ mod imp {
 
         fn set_property(&self, _id: usize, value: &Value, pspec: &ParamSpec) {
             match pspec.name() {
-                "position" => self.position.replace(value.get::<f64>().unwrap()),
+                "position" => {
+                    self.position.replace(value.get::<f64>().unwrap());
+                }
+                "mode" => self.obj().set_mode(value.get::<WaveformMode>().unwrap()),
+                "channels" => self
+                    .obj()
+                    .set_channels(value.get::<WaveformChannels>().unwrap()),
+                "show-rms" => self.obj().set_show_rms(value.get::<bool>().unwrap()),
                 _ => unimplemented!(),
             };
         }
@@ -90,6 +244,9 @@ mod imp {
         fn property(&self, _id: usize, pspec: &ParamSpec) -> Value {
             match pspec.name() {
                 "position" => self.position.get().to_value(),
+                "mode" => self.mode.get().to_value(),
+                "channels" => self.channels.get().to_value(),
+                "show-rms" => self.show_rms.get().to_value(),
                 _ => unimplemented!(),
             }
         }
@@ -110,6 +267,8 @@ mod imp {
             self.obj().set_focusable(true);
 
             self.obj().setup_gesture();
+            self.obj().setup_context_menu();
+            self.obj().setup_tooltip();
 
             self.obj()
                 .upcast_ref::<gtk::Accessible>()
@@ -124,6 +283,9 @@ mod imp {
             if let Some(tick_id) = self.tick_id.replace(None) {
                 tick_id.remove();
             }
+            if let Some(popover) = self.context_menu.take() {
+                popover.unparent();
+            }
         }
     }
 
@@ -147,6 +309,14 @@ mod imp {
             }
         }
 
+        fn size_allocate(&self, width: i32, height: i32, baseline: i32) {
+            self.parent_size_allocate(width, height, baseline);
+
+            if let Some(ref popover) = *self.context_menu.borrow() {
+                popover.present();
+            }
+        }
+
         fn request_mode(&self) -> gtk::SizeRequestMode {
             gtk::SizeRequestMode::ConstantSize
         }
@@ -183,24 +353,29 @@ mod imp {
             // Grab the colors
             let hc = adw::StyleManager::default().is_high_contrast();
 
-            let style_context = widget.style_context();
-            let color = style_context.color();
+            let color = match self.foreground.get() {
+                Some(color) => color,
+                None => widget.style_context().color(),
+            };
             let empty_opacity = if hc { 0.4 } else { 0.2 };
             let hover_opacity = if hc { 0.7 } else { 0.45 };
 
-            let empty_color = gdk::RGBA::new(
-                color.red(),
-                color.green(),
-                color.blue(),
-                color.alpha() * empty_opacity,
-            );
-
             let is_rtl = widget.direction() == gtk::TextDirection::Rtl;
             let bar_size = 2;
             let space_size = 2;
             let block_size = bar_size + space_size;
             let available_width = w;
 
+            // The waveform is shown until the spectrogram is ready
+            if self.mode.get() == WaveformMode::Spectrogram {
+                if let Some(ref spectrogram) = *self.spectrogram.borrow() {
+                    if !spectrogram.is_empty() {
+                        self.snapshot_spectrogram(snapshot, spectrogram, &color, hc);
+                        return;
+                    }
+                }
+            }
+
             if let Some(ref peaks) = *self.peaks.borrow() {
                 let n_peaks = peaks.len() as i32;
                 let waveform_width = w as f64;
@@ -220,26 +395,7 @@ mod imp {
                 // shown as a full cursor color; and the area between the hover
                 // position and the end of the waveform is meant to be shown as a
                 // current foreground color.
-                let position = if is_rtl {
-                    1.0 - self.position.get()
-                } else {
-                    self.position.get()
-                };
-                let mut cursor_pos: [f64; 2] =
-                    [position * waveform_width, position * waveform_width];
-                if let Some(hover) = self.hover_position.get() {
-                    if is_rtl {
-                        if hover <= position {
-                            cursor_pos[1] = hover * waveform_width;
-                        } else {
-                            cursor_pos[0] = hover * waveform_width;
-                        }
-                    } else if hover <= position {
-                        cursor_pos[0] = hover * waveform_width;
-                    } else {
-                        cursor_pos[1] = hover * waveform_width;
-                    }
-                }
+                let cursor_pos = self.cursor_positions(waveform_width, is_rtl);
 
                 // If the number of samples is too big to fit into the available
                 // width, we average the samples that fit within a bar
@@ -249,91 +405,96 @@ mod imp {
                     (available_width as f64 / 2.0) / n_peaks as f64
                 };
 
+                let channels = self.channels.get();
+                let show_rms = self.show_rms.get();
+
                 let mut current_pixel = 0.0;
                 let mut samples_in_accum = 0;
                 let mut accum = PeakPair::new(0.0, 0.0);
+                let mut accum_rms = PeakPair::new(0.0, 0.0);
                 let mut offset = if is_rtl { waveform_width } else { 0.0 };
 
-                for (i, sample) in peaks.iter().enumerate() {
+                for (i, (sample, rms)) in peaks.iter().enumerate() {
                     current_pixel += pixels_per_sample;
                     samples_in_accum += 1;
                     accum.left += sample.left;
                     accum.right += sample.right;
+                    accum_rms.left += rms.left;
+                    accum_rms.right += rms.right;
                     if current_pixel > bar_size as f64 || i == peaks.len() - 1 {
                         accum /= samples_in_accum as f64;
-
-                        // Scale by half: left goes in the upper half of the
-                        // available space, and right goes in the lower half
-                        let mut left = accum.left / 2.0;
-                        let mut right = accum.right / 2.0;
+                        accum_rms /= samples_in_accum as f64;
 
                         // We optionally apply the scaling factor computed
                         // during the animation
-                        if let Some(factor) = self.factor.get() {
-                            left *= factor.clamp(0.0, 1.0);
-                            right *= factor.clamp(0.0, 1.0);
-                        }
+                        let factor = self.factor.get().unwrap_or(1.0).clamp(0.0, 1.0);
 
-                        // The block rectangle, clamped to avoid overdrawing
                         let x = if is_rtl {
                             offset as f32 - bar_size as f32
                         } else {
                             offset as f32
                         };
-                        let y = f32::clamp(center_y - right as f32 * h as f32, 0.0, h as f32 / 2.0);
                         let width: f32 = 2.0;
-                        let height = f32::clamp(
-                            right as f32 * h as f32 + left as f32 * h as f32,
-                            2.0,
-                            h as f32,
-                        );
 
-                        if is_rtl {
+                        let opacity = if is_rtl {
                             if offset > cursor_pos[0] {
-                                snapshot.append_color(
-                                    &color,
-                                    &graphene::Rect::new(x, y, width, height),
-                                );
+                                1.0
                             } else if offset > cursor_pos[1] {
-                                let hover_color = gdk::RGBA::new(
-                                    color.red(),
-                                    color.green(),
-                                    color.blue(),
-                                    color.alpha() * hover_opacity,
-                                );
-                                snapshot.append_color(
-                                    &hover_color,
-                                    &graphene::Rect::new(x, y, width, height),
-                                );
+                                hover_opacity
                             } else {
-                                snapshot.append_color(
-                                    &empty_color,
-                                    &graphene::Rect::new(x, y, width, height),
-                                );
+                                empty_opacity
                             }
                         } else if offset < cursor_pos[0] {
-                            snapshot
-                                .append_color(&color, &graphene::Rect::new(x, y, width, height));
+                            1.0
                         } else if offset < cursor_pos[1] {
-                            let hover_color = gdk::RGBA::new(
-                                color.red(),
-                                color.green(),
-                                color.blue(),
-                                color.alpha() * hover_opacity,
-                            );
-                            snapshot.append_color(
-                                &hover_color,
-                                &graphene::Rect::new(x, y, width, height),
-                            );
+                            hover_opacity
                         } else {
+                            empty_opacity
+                        };
+
+                        // The RMS level is drawn on top of the peaks, which
+                        // are dimmed to tell them apart
+                        let peak_opacity = if show_rms { RMS_PEAK_OPACITY } else { 1.0 };
+                        let peak_color = gdk::RGBA::new(
+                            color.red(),
+                            color.green(),
+                            color.blue(),
+                            color.alpha() * opacity * peak_opacity,
+                        );
+                        for (y, height) in bar_extents(
+                            accum.left * factor,
+                            accum.right * factor,
+                            h as f32,
+                            channels,
+                        ) {
                             snapshot.append_color(
-                                &empty_color,
+                                &peak_color,
                                 &graphene::Rect::new(x, y, width, height),
                             );
                         }
 
-                        accum.left = 0.0;
-                        accum.right = 0.0;
+                        if show_rms {
+                            let rms_color = gdk::RGBA::new(
+                                color.red(),
+                                color.green(),
+                                color.blue(),
+                                color.alpha() * opacity,
+                            );
+                            for (y, height) in bar_extents(
+                                accum_rms.left * factor,
+                                accum_rms.right * factor,
+                                h as f32,
+                                channels,
+                            ) {
+                                snapshot.append_color(
+                                    &rms_color,
+                                    &graphene::Rect::new(x, y, width, height),
+                                );
+                            }
+                        }
+
+                        accum = PeakPair::new(0.0, 0.0);
+                        accum_rms = PeakPair::new(0.0, 0.0);
                         samples_in_accum = 0;
                         current_pixel -= bar_size as f64;
 
@@ -358,6 +519,133 @@ mod imp {
             }
         }
     }
+
+    impl WaveformView {
+        // The state position and the hover position, in widget coordinates,
+        // sorted along the text direction
+        fn cursor_positions(&self, width: f64, is_rtl: bool) -> [f64; 2] {
+            let position = if is_rtl {
+                1.0 - self.position.get()
+            } else {
+                self.position.get()
+            };
+            let mut cursor_pos: [f64; 2] = [position * width, position * width];
+            if let Some(hover) = self.hover_position.get() {
+                if is_rtl {
+                    if hover <= position {
+                        cursor_pos[1] = hover * width;
+                    } else {
+                        cursor_pos[0] = hover * width;
+                    }
+                } else if hover <= position {
+                    cursor_pos[0] = hover * width;
+                } else {
+                    cursor_pos[1] = hover * width;
+                }
+            }
+
+            cursor_pos
+        }
+
+        // Each column of cells covers the same span of time as a bar of the
+        // waveform; the brightness of a cell is the loudest magnitude of
+        // the bands and frames it covers
+        fn snapshot_spectrogram(
+            &self,
+            snapshot: &gtk::Snapshot,
+            spectrogram: &[Vec<f64>],
+            color: &gdk::RGBA,
+            hc: bool,
+        ) {
+            let widget = self.obj();
+            let w = widget.width();
+            let h = widget.height();
+            let is_rtl = widget.direction() == gtk::TextDirection::Rtl;
+
+            let bar_size = 2;
+            let block_size = 4;
+            let n_frames = spectrogram.len();
+            let n_bands = spectrogram.iter().map(|f| f.len()).max().unwrap_or(0);
+            let n_cols = usize::min((w / block_size) as usize, n_frames);
+            let row_size = SPECTROGRAM_CELL_SIZE + SPECTROGRAM_CELL_SPACING;
+            let n_rows = (h / row_size) as usize;
+            if n_bands == 0 || n_cols == 0 || n_rows == 0 {
+                return;
+            }
+
+            let cursor_pos = self.cursor_positions(w as f64, is_rtl);
+
+            // The part that has not been played yet is dimmed, like the
+            // waveform, but the cells need to remain visible
+            let played_opacity = 1.0;
+            let hover_opacity = if hc { 0.85 } else { 0.7 };
+            let empty_opacity = if hc { 0.7 } else { 0.45 };
+
+            for col in 0..n_cols {
+                let start = col * n_frames / n_cols;
+                let end = ((col + 1) * n_frames / n_cols).max(start + 1);
+                let frames = &spectrogram[start..end];
+
+                let offset = if is_rtl {
+                    (w - col as i32 * block_size) as f64
+                } else {
+                    (col as i32 * block_size) as f64
+                };
+                let x = if is_rtl {
+                    offset as f32 - bar_size as f32
+                } else {
+                    offset as f32
+                };
+
+                let opacity = if is_rtl {
+                    if offset > cursor_pos[0] {
+                        played_opacity
+                    } else if offset > cursor_pos[1] {
+                        hover_opacity
+                    } else {
+                        empty_opacity
+                    }
+                } else if offset < cursor_pos[0] {
+                    played_opacity
+                } else if offset < cursor_pos[1] {
+                    hover_opacity
+                } else {
+                    empty_opacity
+                };
+
+                for row in 0..n_rows {
+                    let bands = bands_for_row(row, n_rows, n_bands);
+                    let magnitude = frames
+                        .iter()
+                        .flat_map(|f| f.get(bands.clone()).unwrap_or(&[]).iter())
+                        .copied()
+                        .fold(0.0, f64::max);
+
+                    // Skip the cells that would be barely visible
+                    if magnitude < 0.05 {
+                        continue;
+                    }
+
+                    let cell_color = gdk::RGBA::new(
+                        color.red(),
+                        color.green(),
+                        color.blue(),
+                        color.alpha() * (magnitude * opacity) as f32,
+                    );
+                    let y = h - (row as i32 + 1) * row_size;
+                    snapshot.append_color(
+                        &cell_color,
+                        &graphene::Rect::new(
+                            x,
+                            y as f32,
+                            bar_size as f32,
+                            SPECTROGRAM_CELL_SIZE as f32,
+                        ),
+                    );
+                }
+            }
+        }
+    }
 }
 
 glib::wrapper! {
@@ -387,7 +675,7 @@ impl WaveformView {
     fn setup_gesture(&self) {
         let drag_gesture = gtk::GestureDrag::new();
         drag_gesture.set_name(Some("waveform-drag"));
-        drag_gesture.set_button(0);
+        drag_gesture.set_button(gdk::BUTTON_PRIMARY);
         drag_gesture.connect_drag_begin(
             clone!(@strong self as this => move |gesture, start_x, _| {
                 if !this.has_focus() {
@@ -414,6 +702,7 @@ impl WaveformView {
             let width = this.width() as f64;
             let position = x / width;
             this.imp().hover_position.replace(Some(position));
+            this.trigger_tooltip_query();
             this.queue_draw();
         }));
         motion_gesture.connect_leave(clone!(@strong self as this => move |_| {
@@ -424,27 +713,108 @@ impl WaveformView {
 
         let key_controller = gtk::EventControllerKey::new();
         key_controller.set_name(Some("waveform-key"));
-        key_controller.connect_key_released(
-            clone!(@strong self as this => move |_, keyval, _, _| {
-                let delta = match keyval {
-                    gdk::Key::Left => -0.05,
-                    gdk::Key::Right => 0.05,
-                    _ => return,
+        key_controller.connect_key_pressed(
+            clone!(@strong self as this => @default-return glib::Propagation::Proceed, move |_, keyval, _, state| {
+                // Leave the shortcuts of the window alone
+                if state.intersects(gdk::ModifierType::CONTROL_MASK | gdk::ModifierType::ALT_MASK) {
+                    return glib::Propagation::Proceed;
+                }
+
+                let step = if state.contains(gdk::ModifierType::SHIFT_MASK) {
+                    LARGE_SEEK_STEP
+                } else {
+                    SMALL_SEEK_STEP
+                };
+                // The arrows follow the direction of the waveform
+                let forward = if this.direction() == gtk::TextDirection::Rtl {
+                    gdk::Key::Left
+                } else {
+                    gdk::Key::Right
                 };
 
-                let position = this.imp().position.get() + delta;
-                this.emit_by_name::<()>("position-changed", &[&position]);
+                let position = this.imp().position.get();
+                let duration = this.imp().duration.get();
+                let new_position = match keyval {
+                    gdk::Key::Left | gdk::Key::Right if keyval == forward => {
+                        step_position(position, duration, step)
+                    }
+                    gdk::Key::Left | gdk::Key::Right => step_position(position, duration, -step),
+                    gdk::Key::Page_Up => step_position(position, duration, LARGE_SEEK_STEP),
+                    gdk::Key::Page_Down => step_position(position, duration, -LARGE_SEEK_STEP),
+                    gdk::Key::Home => 0.0,
+                    gdk::Key::End => 1.0,
+                    _ => return glib::Propagation::Proceed,
+                };
+
+                // Let the focus move out of the waveform at either end
+                if duration == 0 || new_position == position {
+                    return glib::Propagation::Proceed;
+                }
+
+                this.emit_by_name::<()>("position-changed", &[&new_position]);
+                this.announce(
+                    &this.time_text(new_position),
+                    gtk::AccessibleAnnouncementPriority::Medium,
+                );
+                glib::Propagation::Stop
             }),
         );
         self.add_controller(key_controller);
     }
 
-    fn seek_to_coord(&self, pos: f64) {
+    fn setup_tooltip(&self) {
+        self.set_has_tooltip(true);
+        self.connect_query_tooltip(|this, x, _, keyboard_mode, tooltip| {
+            let duration = this.imp().duration.get();
+            if duration == 0 {
+                return false;
+            }
+
+            // When using the keyboard, we show the current position
+            let position = if keyboard_mode {
+                this.imp().position.get()
+            } else {
+                this.position_at_coord(x as f64).clamp(0.0, 1.0)
+            };
+
+            let time = (position * duration as f64) as u64;
+            let text = match marker_at(&this.imp().markers.borrow(), time * 1000) {
+                Some(marker) => format!("{}\n{}", utils::format_time(time as i64), marker),
+                None => utils::format_time(time as i64),
+            };
+            tooltip.set_text(Some(&text));
+
+            true
+        });
+    }
+
+    fn position_at_coord(&self, pos: f64) -> f64 {
         let width = self.width();
-        let position = match self.direction() {
+        match self.direction() {
             gtk::TextDirection::Rtl => 1.0 - (pos / width as f64),
             _ => pos / width as f64,
-        };
+        }
+    }
+
+    // The time at the given position, followed by the duration of the song
+    fn time_text(&self, position: f64) -> String {
+        let duration = self.imp().duration.get();
+        let time = (position * duration as f64) as i64;
+        i18n_k(
+            // Translators: `{time}` and `{duration}` must be left
+            // untranslated; they will expand to the position in the
+            // song and to its duration, respectively
+            "{time} of {duration}",
+            &[
+                ("time", &utils::format_time(time)),
+                ("duration", &utils::format_time(duration as i64)),
+            ],
+        )
+    }
+
+    fn seek_to_coord(&self, pos: f64) {
+        let width = self.width();
+        let position = self.position_at_coord(pos);
         debug!(
             "Seeking to coord {} (width: {}, position: {})",
             pos, width, position
@@ -452,29 +822,35 @@ impl WaveformView {
         self.emit_by_name::<()>("position-changed", &[&position]);
     }
 
-    fn normalize_peaks(&self, peaks: Vec<(f64, f64)>) -> Vec<PeakPair> {
-        let right_channel: Vec<f64> = peaks.iter().map(|p| p.0).collect();
-        let left_channel: Vec<f64> = peaks.iter().map(|p| p.1).collect();
-
-        let max_left: f64 = left_channel
+    fn normalize_peaks(&self, peaks: Vec<WaveformLevels>) -> Vec<(PeakPair, PeakPair)> {
+        // The RMS level is never above the peak, so we scale both by the
+        // loudest peak of each channel
+        let max_left: f64 = peaks
             .iter()
-            .copied()
+            .map(|p| p.peak.0)
             .fold(f64::NEG_INFINITY, f64::max);
-        let max_right: f64 = right_channel
+        let max_right: f64 = peaks
             .iter()
-            .copied()
+            .map(|p| p.peak.1)
             .fold(f64::NEG_INFINITY, f64::max);
+        let max_left = if max_left > 0.0 { max_left } else { 1.0 };
+        let max_right = if max_right > 0.0 { max_right } else { 1.0 };
 
-        let normalized: Vec<PeakPair> = peaks
+        let normalized: Vec<(PeakPair, PeakPair)> = peaks
             .iter()
-            .map(|p| PeakPair::new(p.0 / max_left, p.1 / max_right))
+            .map(|p| {
+                (
+                    PeakPair::new(p.peak.0 / max_left, p.peak.1 / max_right),
+                    PeakPair::new(p.rms.0 / max_left, p.rms.1 / max_right),
+                )
+            })
             .collect();
 
         debug!("Peaks: {}", normalized.len());
         normalized
     }
 
-    pub fn set_peaks(&self, peaks: Option<Vec<(f64, f64)>>) {
+    pub fn set_peaks(&self, peaks: Option<Vec<WaveformLevels>>) {
         if let Some(tick_id) = self.imp().tick_id.replace(None) {
             tick_id.remove();
         }
@@ -552,10 +928,235 @@ impl WaveformView {
         self.queue_resize();
     }
 
+    fn setup_context_menu(&self) {
+        let mode_section = gio::Menu::new();
+        mode_section.append(Some(&i18n("_Waveform")), Some("waveform.mode::waveform"));
+        mode_section.append(
+            Some(&i18n("_Spectrogram")),
+            Some("waveform.mode::spectrogram"),
+        );
+
+        let channels_section = gio::Menu::new();
+        channels_section.append(
+            Some(&i18n("_Mirrored Channels")),
+            Some("waveform.channels::mirrored"),
+        );
+        channels_section.append(
+            Some(&i18n("Mi_xed Channels")),
+            Some("waveform.channels::mono"),
+        );
+        channels_section.append(
+            Some(&i18n("Sp_lit Channels")),
+            Some("waveform.channels::split"),
+        );
+        channels_section.append(Some(&i18n("Show _RMS Level")), Some("waveform.show-rms"));
+
+        let menu = gio::Menu::new();
+        menu.append_section(None, &mode_section);
+        menu.append_section(None, &channels_section);
+
+        let popover = gtk::PopoverMenu::from_model(Some(&menu));
+        popover.set_parent(self);
+        popover.set_has_arrow(false);
+        popover.set_halign(gtk::Align::Start);
+        self.imp().context_menu.replace(Some(popover));
+
+        let click_gesture = gtk::GestureClick::new();
+        click_gesture.set_name(Some("waveform-context-click"));
+        click_gesture.set_button(gdk::BUTTON_SECONDARY);
+        click_gesture.connect_pressed(clone!(@weak self as this => move |gesture, _, x, y| {
+            gesture.set_state(gtk::EventSequenceState::Claimed);
+            this.show_context_menu(Some((x, y)));
+        }));
+        self.add_controller(click_gesture);
+
+        let long_press_gesture = gtk::GestureLongPress::new();
+        long_press_gesture.set_name(Some("waveform-context-long-press"));
+        long_press_gesture.set_touch_only(true);
+        long_press_gesture.connect_pressed(clone!(@weak self as this => move |gesture, x, y| {
+            gesture.set_state(gtk::EventSequenceState::Claimed);
+            this.show_context_menu(Some((x, y)));
+        }));
+        self.add_controller(long_press_gesture);
+    }
+
+    // Shows the context menu at the given coordinates, or at the current
+    // position when using the keyboard
+    fn show_context_menu(&self, coords: Option<(f64, f64)>) {
+        let (x, y) = coords.unwrap_or_else(|| {
+            let position = self.imp().position.get();
+            let x = match self.direction() {
+                gtk::TextDirection::Rtl => 1.0 - position,
+                _ => position,
+            };
+            (x * self.width() as f64, self.height() as f64 / 2.0)
+        });
+
+        if let Some(ref popover) = *self.imp().context_menu.borrow() {
+            popover.set_pointing_to(Some(&gdk::Rectangle::new(x as i32, y as i32, 1, 1)));
+            popover.popup();
+        }
+    }
+
+    pub fn mode(&self) -> WaveformMode {
+        self.imp().mode.get()
+    }
+
+    pub fn set_mode(&self, mode: WaveformMode) {
+        if mode != self.imp().mode.replace(mode) {
+            self.queue_draw();
+            self.notify("mode");
+        }
+    }
+
+    pub fn channels(&self) -> WaveformChannels {
+        self.imp().channels.get()
+    }
+
+    pub fn set_channels(&self, channels: WaveformChannels) {
+        if channels != self.imp().channels.replace(channels) {
+            self.queue_draw();
+            self.notify("channels");
+        }
+    }
+
+    pub fn show_rms(&self) -> bool {
+        self.imp().show_rms.get()
+    }
+
+    pub fn set_show_rms(&self, show_rms: bool) {
+        if show_rms != self.imp().show_rms.replace(show_rms) {
+            self.queue_draw();
+            self.notify("show-rms");
+        }
+    }
+
+    pub fn set_spectrogram(&self, spectrogram: Option<Vec<Vec<f64>>>) {
+        self.imp().spectrogram.replace(spectrogram);
+        self.queue_draw();
+    }
+
     pub fn set_position(&self, position: f64) {
         let pos = position.clamp(0.0, 1.0);
         self.imp().position.replace(pos);
         self.update_property(&[gtk::accessible::Property::ValueNow(pos)]);
+        if self.imp().duration.get() > 0 {
+            self.update_property(&[gtk::accessible::Property::ValueText(&self.time_text(pos))]);
+        }
         self.queue_draw();
     }
+
+    // The duration of the song, in seconds, used to show the time under
+    // the pointer and to seek with the keyboard
+    pub fn set_duration(&self, duration: u64) {
+        if duration != self.imp().duration.replace(duration) {
+            if duration == 0 {
+                self.reset_property(gtk::AccessibleProperty::ValueText);
+            }
+            self.trigger_tooltip_query();
+        }
+    }
+
+    // The chapters or lyrics lines of the song, shown along with the time
+    // under the pointer; their time is in milliseconds
+    pub fn set_markers(&self, mut markers: Vec<(u64, String)>) {
+        markers.sort_by_key(|(t, _)| *t);
+        self.imp().markers.replace(markers);
+        self.trigger_tooltip_query();
+    }
+
+    pub fn set_foreground(&self, color: Option<&gdk::RGBA>) {
+        self.imp().foreground.set(color.copied());
+        self.queue_draw();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bands_for_row() {
+        for (n_rows, n_bands) in [(16, 64), (16, 8), (4, 64), (1, 64), (20, 20)] {
+            let ranges: Vec<Range<usize>> = (0..n_rows)
+                .map(|row| bands_for_row(row, n_rows, n_bands))
+                .collect();
+
+            // Every row shows at least one band, and every band is shown
+            assert_eq!(ranges[0].start, 0);
+            assert_eq!(ranges[n_rows - 1].end, n_bands);
+            for r in &ranges {
+                assert!(r.start < r.end, "{:?}", r);
+                assert!(r.end <= n_bands, "{:?}", r);
+            }
+            for pair in ranges.windows(2) {
+                assert!(pair[0].start <= pair[1].start);
+                assert!(pair[1].start <= pair[0].end);
+            }
+        }
+
+        // The low frequencies get more room than the high ones
+        let low = bands_for_row(0, 16, 64);
+        let high = bands_for_row(15, 16, 64);
+        assert!(low.len() < high.len());
+    }
+
+    #[test]
+    fn test_step_position() {
+        assert_eq!(step_position(0.5, 100, 5), 0.55);
+        assert_eq!(step_position(0.5, 100, -30), 0.2);
+        assert_eq!(step_position(0.98, 100, 5), 1.0);
+        assert_eq!(step_position(0.02, 100, -5), 0.0);
+        // Nothing to seek into
+        assert_eq!(step_position(0.0, 0, 5), 0.0);
+    }
+
+    #[test]
+    fn test_marker_at() {
+        let markers = vec![
+            (1000, "First".to_string()),
+            (5000, String::new()),
+            (9000, "Third".to_string()),
+        ];
+
+        assert_eq!(marker_at(&markers, 0), None);
+        assert_eq!(marker_at(&markers, 1000), Some("First"));
+        assert_eq!(marker_at(&markers, 4999), Some("First"));
+        // Empty lines mark the end of the previous one
+        assert_eq!(marker_at(&markers, 5000), None);
+        assert_eq!(marker_at(&markers, 100_000), Some("Third"));
+        assert_eq!(marker_at(&[], 1000), None);
+    }
+
+    #[test]
+    fn test_bar_extents() {
+        let h = 48.0;
+        for channels in [
+            WaveformChannels::Mirrored,
+            WaveformChannels::Mono,
+            WaveformChannels::Split,
+        ] {
+            for (left, right) in [(0.0, 0.0), (1.0, 1.0), (0.5, 0.25), (1.0, 0.0)] {
+                for (y, height) in bar_extents(left, right, h, channels) {
+                    assert!(height >= 2.0);
+                    assert!(y >= 0.0 && y + height <= h, "{} {}", y, height);
+                }
+            }
+        }
+
+        assert_eq!(
+            bar_extents(0.5, 0.25, h, WaveformChannels::Mirrored),
+            vec![(18.0, 18.0)]
+        );
+        assert_eq!(
+            bar_extents(0.5, 0.25, h, WaveformChannels::Mono),
+            vec![(15.0, 18.0)]
+        );
+
+        // Each channel stays in its own half
+        let split = bar_extents(1.0, 0.5, h, WaveformChannels::Split);
+        assert_eq!(split, vec![(0.0, 23.0), (30.75, 11.5)]);
+        assert!(split[0].0 + split[0].1 < h / 2.0);
+        assert!(split[1].0 > h / 2.0);
+    }
 }
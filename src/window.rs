@@ -3,37 +3,66 @@
 
 use std::{
     cell::{Cell, RefCell},
+    collections::{BTreeMap, HashSet},
     rc::Rc,
     time::Instant,
 };
 
-use adw::subclass::prelude::*;
+use adw::{prelude::*, subclass::prelude::*};
 use glib::{clone, closure_local};
-use gtk::{gdk, gio, glib, prelude::*, CompositeTemplate};
-use log::debug;
+use gtk::{gdk, gio, glib, CompositeTemplate};
+use log::{debug, warn};
 
 use crate::{
-    audio::{AudioPlayer, RepeatMode, ReplayGainMode, Song},
+    artwork_viewer::ArtworkViewer,
+    audio::{
+        clear_disk_caches, load_artwork, load_matching_song_data, load_song_data,
+        prune_disk_caches, store_metadata_index, write_tags, AudioPlayer, CoverCache, CoverFiles,
+        Lyrics, PrunePolicy, Queue, RepeatMode, ReplayGainMode, SavedPlaylist, Song, SongData,
+        TagChanges, TrackPosition, WaveformGenerator,
+    },
     config::APPLICATION_ID,
+    cover_picture::CoverPicture,
     drag_overlay::DragOverlay,
-    i18n::{i18n, i18n_k, ni18n_f, ni18n_k},
+    folder_monitor::FolderMonitor,
+    i18n::{i18n, i18n_f, i18n_k, ni18n_f, ni18n_k},
+    lyrics_view::LyricsView,
+    palette::{adjust_palette, StyleInfo},
     playback_control::PlaybackControl,
     playlist_view::PlaylistView,
+    preferences_dialog::PreferencesDialog,
     queue_row::QueueRow,
     search::FuzzyFilter,
+    smart_playlist::{load_smart_playlists, SmartPlaylist},
+    smart_playlist_dialog::SmartPlaylistDialog,
     song_cover::SongCover,
     song_details::SongDetails,
     sort::FuzzySorter,
+    tag_editor_dialog::TagEditorDialog,
     utils,
     volume_control::VolumeControl,
-    waveform_view::WaveformView,
+    waveform_view::{WaveformMode, WaveformView},
 };
 
+// The number of songs before and after the current one whose cover art
+// is kept in memory
+const PINNED_COVERS: u32 = 5;
+
 pub enum WindowMode {
     InitialView,
     MainView,
 }
 
+// A set of files being loaded into the queue
+#[derive(Debug)]
+pub struct SongLoad {
+    cancellable: gio::Cancellable,
+    // Drop the songs loaded so far once cancelled, instead of queueing them
+    discard: Cell<bool>,
+    n_files: u32,
+    n_loaded: Cell<u32>,
+}
+
 mod imp {
     use glib::{ParamSpec, ParamSpecBoolean, ParamSpecEnum, Value};
     use once_cell::sync::Lazy;
@@ -53,8 +82,12 @@ mod imp {
         #[template_child]
         pub status_page: TemplateChild<adw::StatusPage>,
         #[template_child]
+        pub cover_stack: TemplateChild<gtk::Stack>,
+        #[template_child]
         pub song_cover: TemplateChild<SongCover>,
         #[template_child]
+        pub lyrics_view: TemplateChild<LyricsView>,
+        #[template_child]
         pub song_details: TemplateChild<SongDetails>,
         #[template_child]
         pub waveform_view: TemplateChild<WaveformView>,
@@ -80,10 +113,27 @@ mod imp {
         pub playlist_visible: Cell<bool>,
         pub playlist_selection: Cell<bool>,
         pub playlist_search: Cell<bool>,
+        pub lyrics_visible: Cell<bool>,
         pub replaygain_mode: Cell<ReplayGainMode>,
 
         pub playlist_filtermodel: RefCell<Option<gio::ListModel>>,
 
+        pub folder_monitor: FolderMonitor,
+        pub smart_playlist: RefCell<Option<SmartPlaylist>>,
+        pub watched_folders: RefCell<Vec<gio::File>>,
+        // The new files in the watched folders, keyed by URI, so they
+        // are queued in order
+        pub pending_files: RefCell<BTreeMap<String, gio::File>>,
+        pub refresh_source: RefCell<Option<glib::SourceId>>,
+        // The rows of the playlist bound to a song, which are the visible
+        // ones, give or take a few
+        pub bound_rows: RefCell<HashSet<gtk::ListItem>>,
+        pub pin_covers_source: RefCell<Option<glib::SourceId>>,
+        pub song_loads: RefCell<Vec<Rc<SongLoad>>>,
+        pub refresh_cancellable: RefCell<Option<gio::Cancellable>>,
+        // Start playing once the songs being loaded are in the queue
+        pub play_when_loaded: Cell<bool>,
+
         pub notify_playing_id: RefCell<Option<glib::SignalHandlerId>>,
         pub notify_position_id: RefCell<Option<glib::SignalHandlerId>>,
         pub notify_song_id: RefCell<Option<glib::SignalHandlerId>>,
@@ -91,6 +141,7 @@ mod imp {
         pub notify_nsongs_id: RefCell<Option<glib::SignalHandlerId>>,
         pub notify_current_id: RefCell<Option<glib::SignalHandlerId>>,
         pub notify_peaks_id: RefCell<Option<glib::SignalHandlerId>>,
+        pub notify_spectrogram_id: RefCell<Option<glib::SignalHandlerId>>,
     }
 
     #[glib::object_subclass]
@@ -150,10 +201,53 @@ mod imp {
                 debug!("Window::queue.restore-playlist()");
                 win.restore_playlist();
             });
+            klass.install_action("queue.smart-playlists", None, move |win, _, _| {
+                debug!("Window::queue.smart-playlists()");
+                win.show_smart_playlists();
+            });
+            klass.install_action(
+                "queue.watch-folder",
+                Some(glib::VariantTy::STRING),
+                move |win, _, param| {
+                    if let Some(uri) = param.and_then(String::from_variant) {
+                        win.watch_folder(&gio::File::for_uri(&uri));
+                    }
+                },
+            );
+            klass.install_action(
+                "queue.edit-tags",
+                Some(glib::VariantTy::STRING),
+                move |win, _, param| {
+                    if let Some(uri) = param.and_then(String::from_variant) {
+                        win.edit_song_tags(&uri);
+                    }
+                },
+            );
+            klass.install_action(
+                "queue.set-rating",
+                Some(glib::VariantTy::new("(su)").unwrap()),
+                move |win, _, param| {
+                    if let Some((uri, rating)) = param.and_then(<(String, u32)>::from_variant) {
+                        win.rate_song(&uri, rating);
+                    }
+                },
+            );
+            klass.install_action("queue.edit-selected", None, move |win, _, _| {
+                debug!("Window::queue.edit-selected()");
+                win.edit_selected_tags();
+            });
             klass.install_action("win.copy", None, move |win, _, _| {
                 debug!("Window::win.copy()");
                 win.copy_song();
             });
+            klass.install_action("win.preferences", None, move |win, _, _| {
+                debug!("Window::win.preferences()");
+                win.show_preferences();
+            });
+            klass.install_action("queue.cancel-loading", None, move |win, _, _| {
+                debug!("Window::queue.cancel-loading()");
+                win.cancel_loading();
+            });
             klass.install_action("queue.clear", None, move |win, _, _| {
                 debug!("Window::queue.clear()");
                 win.clear_queue();
@@ -163,6 +257,7 @@ mod imp {
             klass.install_property_action("queue.select", "playlist-selection");
             klass.install_property_action("queue.search", "playlist-search");
             klass.install_property_action("win.replaygain", "replaygain-mode");
+            klass.install_property_action("win.show-lyrics", "lyrics-visible");
 
             klass.install_action(
                 "win.skip-to",
@@ -185,7 +280,9 @@ mod imp {
         fn new() -> Self {
             Self {
                 song_details: TemplateChild::default(),
+                cover_stack: TemplateChild::default(),
                 song_cover: TemplateChild::default(),
+                lyrics_view: TemplateChild::default(),
                 split_view: TemplateChild::default(),
                 toast_overlay: TemplateChild::default(),
                 drag_overlay: TemplateChild::default(),
@@ -202,7 +299,18 @@ mod imp {
                 playlist_visible: Cell::new(true),
                 playlist_selection: Cell::new(false),
                 playlist_search: Cell::new(false),
+                lyrics_visible: Cell::new(false),
                 playlist_filtermodel: RefCell::default(),
+                folder_monitor: FolderMonitor::new(),
+                smart_playlist: RefCell::default(),
+                watched_folders: RefCell::default(),
+                pending_files: RefCell::default(),
+                refresh_source: RefCell::default(),
+                bound_rows: RefCell::default(),
+                pin_covers_source: RefCell::default(),
+                song_loads: RefCell::default(),
+                refresh_cancellable: RefCell::default(),
+                play_when_loaded: Cell::new(false),
                 replaygain_mode: Cell::new(ReplayGainMode::default()),
                 provider: gtk::CssProvider::new(),
                 settings: utils::settings_manager(),
@@ -213,6 +321,7 @@ mod imp {
                 notify_nsongs_id: RefCell::new(None),
                 notify_current_id: RefCell::new(None),
                 notify_peaks_id: RefCell::new(None),
+                notify_spectrogram_id: RefCell::new(None),
             }
         }
     }
@@ -233,6 +342,7 @@ mod imp {
                     ParamSpecBoolean::builder("playlist-visible").build(),
                     ParamSpecBoolean::builder("playlist-selection").build(),
                     ParamSpecBoolean::builder("playlist-search").build(),
+                    ParamSpecBoolean::builder("lyrics-visible").build(),
                     ParamSpecEnum::builder::<ReplayGainMode>("replaygain-mode").build(),
                 ]
             });
@@ -246,6 +356,7 @@ mod imp {
                 "playlist-visible" => obj.set_playlist_visible(value.get::<bool>().unwrap()),
                 "playlist-selection" => obj.set_playlist_selection(value.get::<bool>().unwrap()),
                 "playlist-search" => obj.set_playlist_search(value.get::<bool>().unwrap()),
+                "lyrics-visible" => obj.set_lyrics_visible(value.get::<bool>().unwrap()),
                 "replaygain-mode" => obj.set_replaygain(value.get::<ReplayGainMode>().unwrap()),
                 _ => unimplemented!(),
             }
@@ -258,6 +369,7 @@ mod imp {
                 "playlist-visible" => obj.playlist_visible().to_value(),
                 "playlist-selection" => obj.playlist_selection().to_value(),
                 "playlist-search" => obj.playlist_search().to_value(),
+                "lyrics-visible" => obj.lyrics_visible().to_value(),
                 "replaygain-mode" => obj.replaygain().to_value(),
                 _ => unimplemented!(),
             }
@@ -292,6 +404,7 @@ impl Window {
         win.connect_signals();
         win.restore_window_state();
         win.set_initial_state();
+        win.prune_disk_caches();
 
         win
     }
@@ -323,7 +436,11 @@ impl Window {
                     .set_boolean("enable-recoloring", enable_recoloring)
                     .expect("Unable to store setting");
             })
-            .build()])
+            .build()]);
+
+        // Enabled when selecting songs in the playlist
+        self.action_set_enabled("queue.edit-selected", false);
+        self.action_set_enabled("queue.cancel-loading", false);
     }
 
     fn setup_waveform(&self) {
@@ -339,6 +456,47 @@ impl Window {
                 }),
             );
             self.imp().notify_peaks_id.replace(Some(notify_peaks_id));
+
+            let notify_spectrogram_id = player.spectrogram_generator().connect_notify_local(
+                Some("has-spectrogram"),
+                clone!(@weak self as win => move |gen, _| {
+                    win.imp().waveform_view.set_spectrogram(gen.spectrogram());
+                }),
+            );
+            self.imp()
+                .notify_spectrogram_id
+                .replace(Some(notify_spectrogram_id));
+        }
+
+        let waveform_view = self.imp().waveform_view.get();
+        self.imp()
+            .settings
+            .bind("waveform-mode", &waveform_view, "mode")
+            .build();
+        self.imp()
+            .settings
+            .bind("waveform-channels", &waveform_view, "channels")
+            .build();
+        self.imp()
+            .settings
+            .bind("waveform-show-rms", &waveform_view, "show-rms")
+            .build();
+
+        // The spectrogram is only computed while it is visible
+        waveform_view.connect_notify_local(
+            Some("mode"),
+            clone!(@weak self as win => move |view, _| {
+                win.update_spectrogram_generator(view.mode());
+            }),
+        );
+        self.update_spectrogram_generator(waveform_view.mode());
+    }
+
+    fn update_spectrogram_generator(&self, mode: WaveformMode) {
+        if let Some(player) = self.player() {
+            player
+                .spectrogram_generator()
+                .set_enabled(mode == WaveformMode::Spectrogram);
         }
     }
 
@@ -347,6 +505,9 @@ impl Window {
             if let Some(id) = self.imp().notify_peaks_id.take() {
                 player.waveform_generator().disconnect(id);
             }
+            if let Some(id) = self.imp().notify_spectrogram_id.take() {
+                player.spectrogram_generator().disconnect(id);
+            }
         }
     }
 
@@ -365,6 +526,9 @@ impl Window {
     }
 
     fn clear_queue(&self) {
+        self.imp().watched_folders.replace(Vec::new());
+        self.stop_smart_playlist();
+
         if let Some(p) = self.player() {
             p.clear_queue();
         }
@@ -381,6 +545,18 @@ impl Window {
         }
     }
 
+    fn lyrics_visible(&self) -> bool {
+        self.imp().lyrics_visible.get()
+    }
+
+    fn set_lyrics_visible(&self, visible: bool) {
+        if visible != self.imp().lyrics_visible.replace(visible) {
+            let page = if visible { "lyrics" } else { "cover" };
+            self.imp().cover_stack.set_visible_child_name(page);
+            self.notify("lyrics-visible");
+        }
+    }
+
     fn playlist_shuffled(&self) -> bool {
         self.imp().playlist_shuffled.get()
     }
@@ -390,15 +566,7 @@ impl Window {
 
         if shuffled != imp.playlist_shuffled.replace(shuffled) {
             if let Some(player) = self.player() {
-                let queue = player.queue();
-                let state = player.state();
-                let reset_song = queue.is_first_song() && !state.playing();
-
-                queue.set_shuffled(shuffled);
-
-                if reset_song {
-                    player.skip_to(0);
-                }
+                player.set_shuffled(shuffled);
             }
 
             self.notify("playlist-shuffled");
@@ -482,128 +650,1015 @@ impl Window {
                     win.add_toast(i18n("Unable to access files"));
                 } else {
                     win.add_files_to_queue(&files);
+
+                    // Offer to keep the queue in sync with the folders
+                    for pos in 0..files.n_items() {
+                        let folder = files.item(pos).and_downcast::<gio::File>().unwrap();
+                        win.add_watch_folder_toast(&folder);
+                    }
                 }
             }
         }));
     }
 
     fn restore_playlist(&self) {
-        if let Some(songs) = utils::load_cached_songs() {
-            self.queue_songs(songs);
+        // Nothing will be loaded, so there is nothing to play either
+        let songs = match utils::load_cached_songs() {
+            Some(s) => s,
+            None => {
+                self.imp().play_when_loaded.set(false);
+                return;
+            }
+        };
+
+        // Looking for the missing songs scans their folders
+        let ctx = glib::MainContext::default();
+        ctx.spawn_local(clone!(@weak self as win => async move {
+            let (files, missing) = match gio::spawn_blocking(move || utils::check_cached_songs(songs)).await {
+                Ok(res) => res,
+                Err(_) => {
+                    win.imp().play_when_loaded.set(false);
+                    return;
+                }
+            };
+
+            // The folders watched in the last session, as long as they
+            // still hold some of its songs
+            let folders: Vec<gio::File> = utils::load_watched_folders()
+                .into_iter()
+                .filter(|folder| files.iter().any(|f| f.has_prefix(folder)))
+                .collect();
+            for folder in &folders {
+                win.watch_folder(folder);
+            }
+
+            if !files.is_empty() || missing.is_empty() {
+                win.queue_songs(files);
+            } else {
+                win.imp().play_when_loaded.set(false);
+            }
+
+            if !missing.is_empty() {
+                win.show_missing_songs(missing);
+            }
+        }));
+    }
+
+    // Replaces the queue with the songs of a saved playlist, and plays them
+    pub fn activate_playlist(&self, playlist: &SavedPlaylist) {
+        debug!("Activating playlist {:?}", playlist);
+        self.discard_loading();
+        self.imp().play_when_loaded.set(true);
+
+        match playlist {
+            SavedPlaylist::Current => {
+                self.clear_queue();
+                self.restore_playlist();
+            }
+            SavedPlaylist::Smart(id) => self.play_smart_playlist(id),
+        }
+    }
+
+    fn show_missing_songs(&self, missing: Vec<utils::CachedSong>) {
+        let n_missing = missing.len() as u32;
+        let body = ni18n_f(
+            // Translators: the `{}` must be left unmodified;
+            // it will be expanded to the number of songs that
+            // could not be found
+            "One song of the restored playlist could not be found",
+            "{} songs of the restored playlist could not be found",
+            n_missing,
+            &[&n_missing.to_string()],
+        );
+
+        let names: Vec<String> = missing
+            .iter()
+            .filter_map(|s| s.file.basename())
+            .map(|b| b.to_string_lossy().to_string())
+            .collect();
+        let label = gtk::Label::builder()
+            .label(names.join("\n"))
+            .wrap(true)
+            .selectable(true)
+            .xalign(0.0)
+            .build();
+        label.add_css_class("dim-label");
+        let scrolled = gtk::ScrolledWindow::builder()
+            .hscrollbar_policy(gtk::PolicyType::Never)
+            .propagate_natural_height(true)
+            .max_content_height(200)
+            .child(&label)
+            .build();
+
+        let dialog = adw::AlertDialog::builder()
+            .heading(i18n("Missing Songs"))
+            .body(body)
+            .extra_child(&scrolled)
+            .close_response("remove")
+            .default_response("locate")
+            .build();
+        dialog.add_responses(&[
+            ("remove", i18n("_Remove Missing").as_str()),
+            ("locate", i18n("_Locate Folder…").as_str()),
+        ]);
+        dialog.set_response_appearance("locate", adw::ResponseAppearance::Suggested);
+
+        let ctx = glib::MainContext::default();
+        ctx.spawn_local(clone!(@weak self as win => async move {
+            let response = dialog.choose_future(&win).await;
+            if response == "locate" {
+                win.locate_missing_songs(missing);
+            } else if !win.imp().playlist_view.is_loading() {
+                // Drop the missing songs from the cached playlist, so we
+                // don't ask again; songs still being loaded will store
+                // the playlist once they are in the queue
+                if let Some(player) = win.player() {
+                    utils::store_playlist(player.queue());
+                }
+            }
+        }));
+    }
+
+    fn locate_missing_songs(&self, missing: Vec<utils::CachedSong>) {
+        let ctx = glib::MainContext::default();
+        ctx.spawn_local(clone!(@weak self as win => async move {
+            let dialog = gtk::FileDialog::builder()
+                .accept_label(i18n("_Select"))
+                .modal(true)
+                .title(i18n("Locate Folder"))
+                .build();
+
+            let folder = match dialog.select_folder_future(Some(&win)).await {
+                Ok(f) => f,
+                Err(_) => return,
+            };
+
+            let songs = missing.clone();
+            let found = gio::spawn_blocking(move || utils::find_missing_songs(&songs, &[folder]))
+                .await
+                .unwrap_or_default();
+
+            // Put the songs back where they were in the playlist; the
+            // songs that are still missing leave no gap
+            let mut n_missing: u32 = 0;
+            let mut entries = Vec::new();
+            for (song, file) in missing.iter().zip(found) {
+                match file {
+                    Some(file) => entries.push((song.position - n_missing as usize, file)),
+                    None => n_missing += 1,
+                }
+            }
+
+            if !entries.is_empty() {
+                win.insert_songs(entries);
+            }
+
+            if n_missing > 0 {
+                let msg = ni18n_f(
+                    // Translators: the `{}` must be left unmodified;
+                    // it will be expanded to the number of songs that
+                    // could not be found
+                    "One song is still missing",
+                    "{} songs are still missing",
+                    n_missing,
+                    &[&n_missing.to_string()],
+                );
+                win.add_toast(msg);
+            }
+        }));
+    }
+
+    // Load the given files, and insert the songs at the given positions
+    // of the queue
+    fn insert_songs(&self, entries: Vec<(usize, gio::File)>) {
+        self.switch_mode(WindowMode::MainView);
+
+        let files: Vec<gio::File> = entries.iter().map(|(_, f)| f.clone()).collect();
+        let receiver = load_song_data(&files, &gio::Cancellable::new());
+
+        let ctx = glib::MainContext::default();
+        ctx.spawn_local(clone!(@weak self as win => async move {
+            let mut loaded = Vec::new();
+            while let Ok(batch) = receiver.recv().await {
+                for (idx, data) in batch {
+                    if let Some(data) = data {
+                        loaded.push((entries[idx].0, Song::from_data(data)));
+                    }
+                }
+            }
+
+            store_metadata_index();
+
+            let player = match win.player() {
+                Some(p) => p,
+                None => return,
+            };
+            let queue = player.queue();
+            let was_empty = queue.is_empty();
+
+            // Insert in order, so every song ends up after the songs that
+            // preceded it in the playlist
+            loaded.sort_by_key(|(position, _)| *position);
+            for (position, song) in &loaded {
+                if !queue.contains(song) {
+                    queue.insert_song(*position as u32, song);
+                }
+            }
+
+            utils::store_playlist(queue);
+            if was_empty {
+                player.skip_to(0);
+            }
+            win.update_playlist_time();
+        }));
+    }
+
+    fn show_smart_playlists(&self) {
+        let dialog = SmartPlaylistDialog::new();
+
+        dialog.connect_closure(
+            "play-playlist",
+            false,
+            closure_local!(@watch self as win => move |_dialog: SmartPlaylistDialog, id: String| {
+                win.play_smart_playlist(&id);
+            }),
+        );
+        dialog.connect_closure(
+            "playlist-changed",
+            false,
+            closure_local!(@watch self as win => move |_dialog: SmartPlaylistDialog, id: String| {
+                let is_active = win
+                    .imp()
+                    .smart_playlist
+                    .borrow()
+                    .as_ref()
+                    .is_some_and(|p| p.id == id);
+                if is_active {
+                    win.reload_smart_playlist(&id);
+                }
+            }),
+        );
+
+        dialog.present(Some(self));
+    }
+
+    fn show_preferences(&self) {
+        let dialog = PreferencesDialog::new();
+
+        dialog.connect_closure(
+            "clear-caches",
+            false,
+            closure_local!(@watch self as win => move |dialog: PreferencesDialog| {
+                win.clear_disk_caches(&dialog);
+            }),
+        );
+
+        dialog.present(Some(self));
+    }
+
+    fn show_artwork(&self) {
+        let song = match self.player().and_then(|p| p.state().current_song()) {
+            Some(s) => s,
+            None => return,
+        };
+        let path = match song.file().path() {
+            Some(p) => p,
+            None => return,
+        };
+        let album = song.tagged_album();
+
+        let ctx = glib::MainContext::default();
+        ctx.spawn_local(clone!(@weak self as win => async move {
+            // Decoding the pictures at full size can take a while
+            let res = gio::spawn_blocking(move || load_artwork(&path, album.as_deref())).await;
+            match res {
+                Ok(artwork) if !artwork.is_empty() => {
+                    ArtworkViewer::new(&artwork).present(Some(&win));
+                }
+                _ => win.add_toast(i18n("No artwork found")),
+            }
+        }));
+    }
+
+    // The UUIDs of the songs in the playlist
+    fn queued_song_uuids(&self) -> HashSet<String> {
+        let mut res = HashSet::new();
+        if let Some(player) = self.player() {
+            let queue = player.queue();
+            for idx in 0..queue.n_songs() {
+                if let Some(uuid) = queue.song_at(idx).and_then(|s| s.uuid()) {
+                    res.insert(uuid);
+                }
+            }
+        }
+
+        res
+    }
+
+    fn prune_disk_caches(&self) {
+        let settings = &self.imp().settings;
+        let policy = PrunePolicy::new(
+            settings.uint("cache-max-age"),
+            settings.uint("cache-max-size"),
+        );
+        prune_disk_caches(policy, self.queued_song_uuids());
+    }
+
+    fn clear_disk_caches(&self, dialog: &PreferencesDialog) {
+        // We keep the waveform of the current song, as it is the only one
+        // that is guaranteed to be used again
+        let mut songs = HashSet::new();
+        if let Some(uuid) = self
+            .player()
+            .and_then(|p| p.state().current_song())
+            .and_then(|s| s.uuid())
+        {
+            songs.insert(uuid);
+        }
+
+        let ctx = glib::MainContext::default();
+        ctx.spawn_local(clone!(@weak self as win, @weak dialog => async move {
+            let res = gio::spawn_blocking(move || clear_disk_caches(&songs)).await;
+            match res {
+                Ok(n_removed) => {
+                    debug!("Removed {} cached files", n_removed);
+                    dialog.add_toast(adw::Toast::new(&i18n("Cached data removed")));
+                }
+                Err(_) => win.add_toast(i18n("Unable to remove the cached data")),
+            }
+            dialog.update_sizes();
+        }));
+    }
+
+    fn play_smart_playlist(&self, id: &str) {
+        let playlist = match load_smart_playlists().into_iter().find(|p| p.id == id) {
+            Some(p) => p,
+            None => {
+                self.imp().play_when_loaded.set(false);
+                return;
+            }
+        };
+
+        debug!("Playing smart playlist '{}'", &playlist.name);
+
+        self.clear_queue();
+
+        self.imp().smart_playlist.replace(Some(playlist.clone()));
+        self.update_folder_monitor();
+
+        // Walking the folders of a large library takes a while
+        let ctx = glib::MainContext::default();
+        ctx.spawn_local(clone!(@weak self as win => async move {
+            let folders = playlist.clone();
+            let files = gio::spawn_blocking(move || folders.files()).await.unwrap_or_default();
+
+            // Another playlist might have been selected in the meantime,
+            // and it decides whether to play; if the playlist was stopped
+            // instead, there is nothing to play
+            let active_id = win.imp().smart_playlist.borrow().as_ref().map(|p| p.id.clone());
+            match active_id {
+                Some(active_id) if active_id == playlist.id => {
+                    win.load_songs(files, Some(playlist), true);
+                }
+                Some(_) => (),
+                None => win.imp().play_when_loaded.set(false),
+            }
+        }));
+    }
+
+    // The rules or the folders of the active smart playlist changed,
+    // or the playlist was removed entirely
+    fn reload_smart_playlist(&self, id: &str) {
+        match load_smart_playlists().into_iter().find(|p| p.id == id) {
+            Some(playlist) => {
+                self.imp().smart_playlist.replace(Some(playlist));
+                self.update_folder_monitor();
+                self.refresh_smart_playlist();
+            }
+            None => self.stop_smart_playlist(),
+        }
+    }
+
+    fn stop_smart_playlist(&self) {
+        self.imp().smart_playlist.replace(None);
+        if let Some(cancellable) = self.imp().refresh_cancellable.take() {
+            cancellable.cancel();
+        }
+        self.update_folder_monitor();
+    }
+
+    fn watch_folder(&self, folder: &gio::File) {
+        let imp = self.imp();
+
+        if imp.watched_folders.borrow().iter().any(|f| f.equal(folder)) {
+            return;
+        }
+
+        debug!("Watching '{}' for changes", folder.uri());
+        imp.watched_folders.borrow_mut().push(folder.clone());
+        utils::store_watched_folders(&imp.watched_folders.borrow());
+        self.update_folder_monitor();
+    }
+
+    fn is_in_watched_folder(&self, file: &gio::File) -> bool {
+        self.imp()
+            .watched_folders
+            .borrow()
+            .iter()
+            .any(|f| file.equal(f) || file.has_prefix(f))
+    }
+
+    // Keep the monitored folders in sync with the watched folders and
+    // the folders of the active smart playlist
+    fn update_folder_monitor(&self) {
+        let imp = self.imp();
+
+        let mut folders = imp.watched_folders.borrow().clone();
+        if let Some(ref playlist) = *imp.smart_playlist.borrow() {
+            folders.extend(playlist.folders());
+        }
+
+        for root in imp.folder_monitor.roots() {
+            if !folders.iter().any(|f| f.equal(&root)) {
+                imp.folder_monitor.unwatch(&root);
+            }
+        }
+        for folder in &folders {
+            imp.folder_monitor.watch(folder);
+        }
+
+        if folders.is_empty() {
+            if let Some(source) = imp.refresh_source.take() {
+                source.remove();
+            }
+            imp.pending_files.borrow_mut().clear();
+        }
+    }
+
+    fn folder_changed(
+        &self,
+        file: &gio::File,
+        other_file: Option<&gio::File>,
+        event: gio::FileMonitorEvent,
+    ) {
+        match event {
+            gio::FileMonitorEvent::Renamed => {
+                if let Some(other_file) = other_file {
+                    self.rename_songs(file, other_file);
+                }
+            }
+            gio::FileMonitorEvent::MovedOut => match other_file {
+                // Moving a file between two watched directories is
+                // just a rename
+                Some(other_file) if self.is_in_watched_folder(other_file) => {
+                    self.rename_songs(file, other_file);
+                }
+                _ => self.remove_missing_songs(file),
+            },
+            gio::FileMonitorEvent::Deleted => self.remove_missing_songs(file),
+            gio::FileMonitorEvent::Created
+            | gio::FileMonitorEvent::MovedIn
+            | gio::FileMonitorEvent::ChangesDoneHint => {
+                if self.is_in_watched_folder(file) {
+                    self.imp()
+                        .pending_files
+                        .borrow_mut()
+                        .entry(file.uri().to_string())
+                        .or_insert_with(|| file.clone());
+                }
+            }
+            _ => (),
+        }
+
+        self.queue_folder_refresh();
+    }
+
+    // Update the songs for a renamed file, or for all the files inside a
+    // renamed directory, without touching the playback
+    fn rename_songs(&self, file: &gio::File, new_file: &gio::File) {
+        let player = match self.player() {
+            Some(p) => p,
+            None => return,
+        };
+
+        let queue = player.queue();
+        let mut renamed = false;
+        for idx in 0..queue.n_songs() {
+            let song = queue.song_at(idx).unwrap();
+            let song_file = song.file();
+            let dest = if song_file.equal(file) {
+                Some(new_file.clone())
+            } else {
+                file.relative_path(&song_file)
+                    .map(|p| new_file.resolve_relative_path(p))
+            };
+
+            if let Some(dest) = dest {
+                debug!("Song '{}' renamed to '{}'", song_file.uri(), dest.uri());
+                song.set_file(&dest);
+                renamed = true;
+            }
+        }
+
+        if renamed {
+            utils::store_playlist(queue);
+        }
+    }
+
+    // Remove the songs for a deleted file, or for all the files inside
+    // a deleted directory
+    fn remove_missing_songs(&self, file: &gio::File) {
+        if !self.is_in_watched_folder(file) {
+            return;
+        }
+
+        let player = match self.player() {
+            Some(p) => p,
+            None => return,
+        };
+
+        let queue = player.queue();
+        let mut removed = Vec::new();
+        for idx in 0..queue.n_songs() {
+            let song = queue.song_at(idx).unwrap();
+            let song_file = song.file();
+            if song_file.equal(file) || song_file.has_prefix(file) {
+                removed.push(song);
+            }
         }
+
+        if removed.is_empty() {
+            return;
+        }
+
+        for song in &removed {
+            debug!("Removing deleted song '{}'", song.uri());
+            self.remove_song(song);
+        }
+
+        utils::store_playlist(queue);
+
+        let msg = ni18n_f(
+            // Translators: the `{}` must be left unmodified;
+            // it will be expanded to the number of songs removed
+            // from the playlist
+            "Removed one deleted song",
+            "Removed {} deleted songs",
+            removed.len() as u32,
+            &[&removed.len().to_string()],
+        );
+        self.add_toast(msg);
+    }
+
+    // Coalesce bursts of file system events into a single refresh
+    fn queue_folder_refresh(&self) {
+        let imp = self.imp();
+
+        if imp.refresh_source.borrow().is_some() {
+            return;
+        }
+
+        if imp.smart_playlist.borrow().is_none() && imp.pending_files.borrow().is_empty() {
+            return;
+        }
+
+        let source = glib::timeout_add_local_once(
+            std::time::Duration::from_secs(1),
+            clone!(@weak self as win => move || {
+                win.imp().refresh_source.replace(None);
+                // Wait until the songs currently being loaded are in the queue
+                if win.imp().playlist_view.is_loading() {
+                    win.queue_folder_refresh();
+                } else {
+                    win.refresh_smart_playlist();
+                    win.queue_pending_files();
+                }
+            }),
+        );
+        imp.refresh_source.replace(Some(source));
+    }
+
+    // Append the new audio files found in the watched folders
+    fn queue_pending_files(&self) {
+        let pending: Vec<gio::File> = self.imp().pending_files.take().into_values().collect();
+        if pending.is_empty() {
+            return;
+        }
+
+        let player = match self.player() {
+            Some(p) => p,
+            None => return,
+        };
+
+        let queue = player.queue();
+        let queued: HashSet<String> = (0..queue.n_songs())
+            .filter_map(|pos| queue.song_at(pos))
+            .map(|song| song.uri())
+            .collect();
+
+        // New folders can hold many files, so we walk them off the main
+        // thread
+        let ctx = glib::MainContext::default();
+        ctx.spawn_local(clone!(@weak self as win => async move {
+            let res = gio::spawn_blocking(move || {
+                let is_queued = |file: &gio::File| queued.contains(file.uri().as_str());
+
+                let mut files = Vec::new();
+                for file in pending {
+                    let info = match file.query_info(
+                        "standard::type,standard::content-type",
+                        gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS,
+                        gio::Cancellable::NONE,
+                    ) {
+                        Ok(info) => info,
+                        Err(_) => continue,
+                    };
+
+                    match info.file_type() {
+                        gio::FileType::Regular => {
+                            if let Some(content_type) = info.content_type() {
+                                if gio::content_type_is_mime_type(&content_type, "audio/*")
+                                    && !is_queued(&file)
+                                {
+                                    files.push(file);
+                                }
+                            }
+                        }
+                        gio::FileType::Directory => {
+                            for child in utils::load_files_from_folder(&file, true) {
+                                if !is_queued(&child) {
+                                    files.push(child);
+                                }
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+
+                files
+            })
+            .await;
+
+            let files = match res {
+                Ok(files) => files,
+                Err(_) => return,
+            };
+            if !files.is_empty() {
+                debug!("Adding {} new files from watched folders", files.len());
+                win.queue_songs(files);
+            }
+        }));
+    }
+
+    // Re-evaluate the active smart playlist against its folders: songs
+    // that do not exist or match any more are removed, and new matching
+    // songs are appended to the queue
+    fn refresh_smart_playlist(&self) {
+        let playlist = match self.imp().smart_playlist.borrow().clone() {
+            Some(p) => p,
+            None => return,
+        };
+
+        let player = match self.player() {
+            Some(p) => p,
+            None => return,
+        };
+
+        // Only the latest refresh gets to change the queue
+        let cancellable = gio::Cancellable::new();
+        if let Some(previous) = self
+            .imp()
+            .refresh_cancellable
+            .replace(Some(cancellable.clone()))
+        {
+            previous.cancel();
+        }
+
+        let queue = player.queue();
+        let queued: Vec<(u64, SongData)> = (0..queue.n_songs())
+            .filter_map(|pos| queue.song_at(pos))
+            .map(|song| (song.serial(), song.data()))
+            .collect();
+
+        // Checking the queued songs, and walking the folders, happens off
+        // the main thread, and so does loading the new songs
+        let ctx = glib::MainContext::default();
+        ctx.spawn_local(clone!(@weak self as win => async move {
+            let rules = playlist.clone();
+            let res = gio::spawn_blocking(move || {
+                let mut stale = HashSet::new();
+                let mut uris = HashSet::new();
+                for (serial, data) in &queued {
+                    let file = data.file();
+                    if !file.query_exists(gio::Cancellable::NONE)
+                        || (rules.contains_file(&file) && !rules.matches(data))
+                    {
+                        stale.insert(*serial);
+                    } else {
+                        uris.insert(data.uri());
+                    }
+                }
+
+                let files: Vec<gio::File> = rules
+                    .files()
+                    .into_iter()
+                    .filter(|file| !uris.contains(file.uri().as_str()))
+                    .collect();
+
+                (stale, files)
+            })
+            .await;
+
+            let (stale, files) = match res {
+                Ok(res) => res,
+                Err(_) => return,
+            };
+            if cancellable.is_cancelled() {
+                return;
+            }
+
+            if !stale.is_empty() {
+                if let Some(player) = win.player() {
+                    let queue = player.queue();
+                    let songs: Vec<Song> = (0..queue.n_songs())
+                        .filter_map(|pos| queue.song_at(pos))
+                        .filter(|song| stale.contains(&song.serial()))
+                        .collect();
+                    for song in &songs {
+                        debug!("Removing '{}' from smart playlist", song.uri());
+                        player.remove_song(song);
+                    }
+
+                    utils::store_playlist(queue);
+                    win.update_selected_count();
+                    win.update_playlist_time();
+                }
+            }
+
+            if files.is_empty() {
+                return;
+            }
+
+            let receiver = load_matching_song_data(&files, &playlist, &cancellable);
+            let mut loaded = Vec::new();
+            while let Ok(batch) = receiver.recv().await {
+                for (idx, data) in batch {
+                    if let Some(data) = data {
+                        loaded.push((idx, Song::from_data(data)));
+                    }
+                }
+            }
+
+            if cancellable.is_cancelled() {
+                return;
+            }
+            win.imp().refresh_cancellable.replace(None);
+
+            let player = match win.player() {
+                Some(p) => p,
+                None => return,
+            };
+            let queue = player.queue();
+
+            loaded.sort_by_key(|(idx, _)| *idx);
+            let songs: Vec<Song> = loaded
+                .into_iter()
+                .map(|(_, s)| s)
+                .filter(|s| !queue.contains(s))
+                .collect();
+            if songs.is_empty() {
+                return;
+            }
+
+            debug!("Adding {} songs to smart playlist", songs.len());
+            let was_empty = queue.is_empty();
+            queue.add_songs(&songs);
+            if was_empty {
+                player.skip_to(0);
+            }
+
+            store_metadata_index();
+            utils::store_playlist(queue);
+            win.update_selected_count();
+            win.update_playlist_time();
+        }));
     }
 
     fn queue_songs(&self, queue: Vec<gio::File>) {
+        self.load_songs(queue, None, false);
+    }
+
+    // Load the songs for the given files into the queue; if a smart
+    // playlist is passed, only the songs matching its rules are added.
+    // If `sort` is set, the songs are ordered by album artist, disc, and
+    // track number, or using the order of the smart playlist, instead of
+    // using the order of the files
+    fn load_songs(&self, queue: Vec<gio::File>, playlist: Option<SmartPlaylist>, sort: bool) {
         if queue.is_empty() {
+            self.imp().play_when_loaded.set(false);
             self.add_toast(i18n("No available song found"));
             return;
         }
 
         self.switch_mode(WindowMode::MainView);
 
-        // Disable actions on the queue; loading is "atomic"
-        self.action_set_enabled("queue.add-song", false);
-        self.action_set_enabled("queue.add-folder", false);
-        self.action_set_enabled("queue.clear", false);
+        // Read the metadata in a pool of worker threads, and turn it into
+        // songs in the main context as it arrives
+        let load = Rc::new(SongLoad {
+            cancellable: gio::Cancellable::new(),
+            discard: Cell::new(false),
+            n_files: queue.len() as u32,
+            n_loaded: Cell::new(0),
+        });
+        let receiver = match playlist {
+            Some(ref p) => load_matching_song_data(&queue, p, &load.cancellable),
+            None => load_song_data(&queue, &load.cancellable),
+        };
+        self.begin_song_load(&load);
+
+        // Begin the trace
+        let now = Instant::now();
+
+        let order = playlist.as_ref().map(|p| p.order).unwrap_or_default();
+
+        let ctx = glib::MainContext::default();
+        ctx.spawn_local(clone!(@weak self as win => async move {
+            let mut loaded = Vec::new();
+            let mut duplicates: u32 = 0;
+
+            while let Ok(batch) = receiver.recv().await {
+                load.n_loaded.set(load.n_loaded.get() + batch.len() as u32);
+                win.update_loading_progress();
+
+                for (idx, data) in batch {
+                    let s = match data {
+                        Some(data) => Song::from_data(data),
+                        None => continue,
+                    };
+
+                    if let Some(player) = win.player() {
+                        if player.queue().contains(&s) {
+                            duplicates += 1;
+                        } else {
+                            loaded.push((idx, s));
+                        }
+                    }
+                }
+            }
+
+            debug!(
+                "Total loading time for {} files: {} ms",
+                load.n_loaded.get(),
+                now.elapsed().as_millis()
+            );
+
+            // Store the metadata of the new songs for the next session
+            store_metadata_index();
+
+            win.end_song_load(&load);
+
+            let cancelled = load.cancellable.is_cancelled();
+            if cancelled {
+                debug!("Loading cancelled");
+
+                if load.discard.get() {
+                    return;
+                }
+            }
+
+            let play_when_loaded = win.imp().play_when_loaded.take();
+
+            // The workers deliver the songs out of order
+            loaded.sort_by_key(|(idx, _)| *idx);
+            let mut songs: Vec<Song> = loaded.into_iter().map(|(_, s)| s).collect();
+
+            if songs.is_empty() {
+                if duplicates == 0 && !cancelled {
+                    win.add_toast(i18n("No songs found"));
+                }
+            } else if let Some(player) = win.player() {
+                let queue = player.queue();
+                let was_empty = queue.is_empty();
+
+                if sort {
+                    songs.sort_by(|a, b| order.compare(a, b));
+                }
+
+                // Bulk add to avoid hammering the UI with list model updates
+                queue.add_songs(&songs);
+
+                // Store the current state of the playlist
+                utils::store_playlist(queue);
 
-        self.imp().playlist_view.begin_loading();
+                debug!("Queue was empty: {}, new size: {}", was_empty, queue.n_songs());
+                if was_empty {
+                    player.skip_to(0);
+                }
+                if play_when_loaded {
+                    player.play();
+                }
+
+                // Allow jumping to the song we just added
+                if songs.len() == 1 {
+                    // If we added a single song, and the queue was empty, we
+                    // dispense with the pleasantries and we start playing
+                    // immediately; otherwise, we let the user choose whether
+                    // to jump to the newly added song
+                    if was_empty {
+                        player.play();
+                    } else {
+                        win.add_skip_to_toast(
+                            i18n("Added a new song"),
+                            i18n("Play"),
+                            queue.n_songs() - 1,
+                        );
+                    }
+                } else {
+                    let msg = ni18n_f(
+                        // Translators: the `{}` must be left unmodified;
+                        // it will be expanded to the number of songs added
+                        // to the playlist
+                        "Added one song",
+                        "Added {} songs",
+                        songs.len() as u32,
+                        &[&songs.len().to_string()],
+                    );
 
-        // Begin the trace
-        let now = Instant::now();
+                    win.add_toast(msg);
+                }
+            }
+        }));
+    }
 
-        // Turn the list of files into songs one at a time into the main loop
-        let n_files = queue.len() as u32;
-
-        let mut files = queue.into_iter();
-        let mut songs = Vec::new();
-        let mut cur_file: u32 = 0;
-        let mut duplicates: u32 = 0;
-
-        glib::idle_add_local(
-            clone!(@weak self as win => @default-return glib::ControlFlow::Break, move || {
-                files.next()
-                    .map(|f| {
-                        win.imp().playlist_view.update_loading(cur_file, n_files);
-                        if let Ok(s) = Song::from_uri(f.uri().as_str()) {
-                            if let Some(player) = win.player() {
-                                let queue = player.queue();
-                                if queue.contains(&s) {
-                                    duplicates += 1;
-                                } else {
-                                    songs.push(s);
-                                    cur_file += 1;
-                                }
-                            }
-                        }
-                    })
-                    .map(|_| glib::ControlFlow::Continue)
-                    .unwrap_or_else(|| {
-                        debug!("Total loading time for {} files: {} ms", n_files, now.elapsed().as_millis());
-
-                        // Re-enable the actions
-                        win.action_set_enabled("queue.add-song", true);
-                        win.action_set_enabled("queue.add-folder", true);
-                        win.action_set_enabled("queue.clear", true);
-
-                        if songs.is_empty() {
-                            if duplicates == 0 {
-                                win.add_toast(i18n("No songs found"));
-                            }
-                        } else if let Some(player) = win.player() {
-                            let queue = player.queue();
-                            let was_empty = queue.is_empty();
+    // Loads can overlap, e.g. when dropping files while restoring the
+    // playlist; the queue actions and the loading indicator are shared
+    // by all of them
+    fn begin_song_load(&self, load: &Rc<SongLoad>) {
+        let was_loading = !self.imp().song_loads.borrow().is_empty();
+        self.imp().song_loads.borrow_mut().push(Rc::clone(load));
+
+        if !was_loading {
+            // Disable actions on the queue; loading is "atomic"
+            self.action_set_enabled("queue.add-song", false);
+            self.action_set_enabled("queue.add-folder", false);
+            self.action_set_enabled("queue.clear", false);
+            self.action_set_enabled("queue.smart-playlists", false);
+            self.action_set_enabled("queue.cancel-loading", true);
+
+            self.imp().playlist_view.begin_loading();
+        }
 
-                            win.imp().playlist_view.end_loading();
+        self.update_loading_progress();
+    }
 
-                            // Bulk add to avoid hammering the UI with list model updates
-                            queue.add_songs(&songs);
+    fn end_song_load(&self, load: &Rc<SongLoad>) {
+        self.imp()
+            .song_loads
+            .borrow_mut()
+            .retain(|l| !Rc::ptr_eq(l, load));
+
+        if self.imp().song_loads.borrow().is_empty() {
+            // Re-enable the actions
+            self.action_set_enabled("queue.add-song", true);
+            self.action_set_enabled("queue.add-folder", true);
+            self.action_set_enabled("queue.clear", true);
+            self.action_set_enabled("queue.smart-playlists", true);
+            self.action_set_enabled("queue.cancel-loading", false);
+
+            self.imp().playlist_view.end_loading();
+        } else {
+            self.update_loading_progress();
+        }
+    }
 
-                            // Store the current state of the playlist
-                            utils::store_playlist(queue);
+    fn update_loading_progress(&self) {
+        let loads = self.imp().song_loads.borrow();
+        let n_files: u32 = loads.iter().map(|l| l.n_files).sum();
+        let n_loaded: u32 = loads.iter().map(|l| l.n_loaded.get()).sum();
 
-                            debug!("Queue was empty: {}, new size: {}", was_empty, queue.n_songs());
-                            if was_empty {
-                                player.skip_to(0);
-                            }
+        if n_files > 0 {
+            self.imp().playlist_view.update_loading(n_loaded, n_files);
+        }
+    }
 
-                            // Allow jumping to the song we just added
-                            if songs.len() == 1 {
-                                // If we added a single song, and the queue was empty, we
-                                // dispense with the pleasantries and we start playing
-                                // immediately; otherwise, we let the user choose whether
-                                // to jump to the newly added song
-                                if was_empty {
-                                    player.play();
-                                } else {
-                                    win.add_skip_to_toast(
-                                        i18n("Added a new song"),
-                                        i18n("Play"),
-                                        queue.n_songs() - 1,
-                                    );
-                                }
-                            } else {
-                                let msg = ni18n_f(
-                                    // Translators: the `{}` must be left unmodified;
-                                    // it will be expanded to the number of songs added
-                                    // to the playlist
-                                    "Added one song",
-                                    "Added {} songs",
-                                    songs.len() as u32,
-                                    &[&songs.len().to_string()],
-                                );
-
-                                win.add_toast(msg);
-                            }
-                        }
+    // Stops loading songs; the songs loaded so far are still queued
+    fn cancel_loading(&self) {
+        for load in self.imp().song_loads.borrow().iter() {
+            load.cancellable.cancel();
+        }
+    }
 
-                        glib::ControlFlow::Break
-                    })
-            }),
-        );
+    // Stops loading songs, and drops the songs loaded so far
+    fn discard_loading(&self) {
+        for load in self.imp().song_loads.borrow().iter() {
+            load.discard.set(true);
+            load.cancellable.cancel();
+        }
     }
 
     fn add_files_to_queue(&self, model: &gio::ListModel) {
         let mut queue: Vec<gio::File> = vec![];
+        let mut has_folders = false;
 
         for pos in 0..model.n_items() {
             let file = model.item(pos).unwrap().downcast::<gio::File>().unwrap();
@@ -626,13 +1681,14 @@ impl Window {
                         debug!("Adding folder '{}' to the queue", file.uri());
                         let files = utils::load_files_from_folder(&file, true);
                         queue.extend(files);
+                        has_folders = true;
                     }
                     _ => (),
                 }
             }
         }
 
-        self.queue_songs(queue);
+        self.load_songs(queue, None, has_folders);
     }
 
     // Bind the PlayerState to the UI
@@ -764,6 +1820,14 @@ impl Window {
                 }),
             );
 
+            // The queue can be shuffled remotely, through MPRIS
+            queue.connect_notify_local(
+                Some("shuffled"),
+                clone!(@weak self as win => move |queue, _| {
+                    win.set_playlist_shuffled(queue.is_shuffled());
+                }),
+            );
+
             let notify_current_id = queue.connect_notify_local(
                 Some("current"),
                 clone!(@weak self as win => move |queue, _| {
@@ -800,6 +1864,22 @@ impl Window {
     }
 
     fn connect_signals(&self) {
+        self.imp().song_cover.album_image().connect_closure(
+            "activated",
+            false,
+            closure_local!(@watch self as win => move |_picture: CoverPicture| {
+                win.show_artwork();
+            }),
+        );
+
+        self.imp().folder_monitor.connect_closure(
+            "changed",
+            false,
+            closure_local!(@watch self as win => move |_monitor: FolderMonitor, file: gio::File, other_file: Option<gio::File>, event: gio::FileMonitorEvent| {
+                win.folder_changed(&file, other_file.as_ref(), event);
+            }),
+        );
+
         self.imp().split_view.connect_notify_local(
             Some("collapsed"),
             clone!(@weak self as win => move |split_view, _| {
@@ -830,6 +1910,35 @@ impl Window {
             }),
         );
 
+        self.imp().song_details.connect_closure(
+            "rating-changed",
+            false,
+            closure_local!(@watch self as win => move |_sd: SongDetails, rating: u32| {
+                if let Some(player) = win.player() {
+                    if let Some(song) = player.state().current_song() {
+                        win.save_tags(vec![song], TagChanges {
+                            rating: Some(rating),
+                            ..Default::default()
+                        });
+                    }
+                }
+            }),
+        );
+
+        self.imp().lyrics_view.connect_closure(
+            "seek",
+            false,
+            closure_local!(@watch self as win => move |_lv: LyricsView, time: u64| {
+                debug!("Seeking to lyrics line at {} ms", time);
+                if let Some(player) = win.player() {
+                    if player.state().current_song().is_some() {
+                        player.seek_position_ms(time);
+                        player.play();
+                    }
+                }
+            }),
+        );
+
         self.imp()
             .playback_control
             .volume_control()
@@ -912,6 +2021,25 @@ impl Window {
         );
         let _dummy = self.imp().settings.boolean("enable-recoloring");
 
+        self.imp().settings.connect_changed(
+            Some("cover-cache-size"),
+            clone!(@weak self as this => move |settings, _| {
+                debug!("GSettings:cover-cache-size: {}", settings.uint("cover-cache-size"));
+                this.update_cover_cache_size();
+            }),
+        );
+        self.update_cover_cache_size();
+
+        self.imp().settings.connect_changed(
+            Some("cover-file-names"),
+            clone!(@weak self as this => move |settings, _| {
+                debug!("GSettings:cover-file-names: {:?}", settings.strv("cover-file-names"));
+                this.update_cover_file_names();
+                this.reload_cover_files();
+            }),
+        );
+        self.update_cover_file_names();
+
         self.connect_close_request(move |window| {
             debug!("Saving window state");
             let width = window.default_size().0;
@@ -1041,6 +2169,16 @@ impl Window {
                 .chain_property::<Song>("selected")
                 .bind(&row, "selected", gtk::Widget::NONE);
         }));
+        factory.connect_bind(clone!(@weak self as win => move |_, item| {
+            let list_item = item.downcast_ref::<gtk::ListItem>().unwrap();
+            win.imp().bound_rows.borrow_mut().insert(list_item.clone());
+            win.queue_pin_covers();
+        }));
+        factory.connect_unbind(clone!(@weak self as win => move |_, item| {
+            let list_item = item.downcast_ref::<gtk::ListItem>().unwrap();
+            win.imp().bound_rows.borrow_mut().remove(list_item);
+            win.queue_pin_covers();
+        }));
         imp.playlist_view
             .queue_view()
             .set_factory(Some(&factory.upcast::<gtk::ListItemFactory>()));
@@ -1165,13 +2303,17 @@ impl Window {
             if state.current_song().is_some() {
                 let elapsed = state.position();
                 let duration = state.duration();
-                let remaining = duration.checked_sub(elapsed).unwrap_or_default();
+                let remaining = duration.saturating_sub(elapsed);
                 self.set_song_time(Some(elapsed), Some(remaining));
 
+                self.imp().waveform_view.set_duration(duration);
                 let position = state.position() as f64 / state.duration() as f64;
                 self.set_song_position(position);
+
+                self.imp().lyrics_view.set_position(state.position_ms());
             } else {
                 self.set_song_time(None, None);
+                self.imp().waveform_view.set_duration(0);
                 self.set_song_position(0.0);
             }
         }
@@ -1184,7 +2326,162 @@ impl Window {
             self.update_playlist_time();
             self.update_title(state.current_song().as_ref());
             self.update_style(state.current_song().as_ref());
+            self.imp()
+                .song_details
+                .set_file_info(state.current_song().as_ref());
+            self.imp()
+                .song_details
+                .set_rating(state.current_song().map(|s| s.rating()));
+            self.update_lyrics(state.current_song().as_ref());
+            self.pin_covers(player.queue());
+        }
+    }
+
+    fn update_cover_cache_size(&self) {
+        let size = self.imp().settings.uint("cover-cache-size") as usize;
+        CoverCache::global()
+            .lock()
+            .unwrap()
+            .set_budget(size * 1024 * 1024);
+    }
+
+    fn update_cover_file_names(&self) {
+        let names = self
+            .imp()
+            .settings
+            .strv("cover-file-names")
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        CoverFiles::global().lock().unwrap().set_names(names);
+    }
+
+    // The covers picked from the cover files depend on their names, so we
+    // need to look for them again, and reload the songs that might have
+    // a different cover
+    fn reload_cover_files(&self) {
+        let uuids = CoverCache::forget_cover_files();
+        store_metadata_index();
+
+        let player = match self.player() {
+            Some(p) => p,
+            None => return,
+        };
+        let queue = player.queue();
+
+        let songs: Vec<Song> = (0..queue.n_songs())
+            .filter_map(|idx| queue.song_at(idx))
+            .filter(|s| match s.cover_uuid() {
+                Some(uuid) => uuids.contains(&uuid),
+                None => true,
+            })
+            .collect();
+        if songs.is_empty() {
+            return;
+        }
+
+        debug!("Reloading the covers of {} songs", songs.len());
+        let files: Vec<gio::File> = songs.iter().map(|s| s.file()).collect();
+        let receiver = load_song_data(&files, &gio::Cancellable::new());
+
+        let ctx = glib::MainContext::default();
+        ctx.spawn_local(clone!(@weak self as win => async move {
+            while let Ok(batch) = receiver.recv().await {
+                for (idx, data) in batch {
+                    let song = &songs[idx];
+                    if let Some(data) = data.filter(|d| d.file().equal(&song.file())) {
+                        song.set_data(data);
+                        player.update_song(song);
+                    }
+                }
+            }
+
+            store_metadata_index();
+            win.update_song();
+        }));
+    }
+
+    // Keep the covers of the current song, of the songs around it, and
+    // of the songs visible in the playlist in memory
+    fn pin_covers(&self, queue: &Queue) {
+        let mut pinned = HashSet::new();
+        if let Some(current) = queue.current_song_index() {
+            let first = current.saturating_sub(PINNED_COVERS);
+            for idx in first..=current + PINNED_COVERS {
+                if let Some(uuid) = queue.song_at(idx).and_then(|s| s.cover_uuid()) {
+                    pinned.insert(uuid);
+                }
+            }
+        }
+
+        for row in self.imp().bound_rows.borrow().iter() {
+            if let Some(uuid) = row
+                .item()
+                .and_then(|item| item.downcast::<Song>().ok())
+                .and_then(|s| s.cover_uuid())
+            {
+                pinned.insert(uuid);
+            }
+        }
+
+        CoverCache::global().lock().unwrap().set_pinned(pinned);
+    }
+
+    // Rows are bound and unbound in bursts while scrolling, so we update
+    // the pinned covers once they are done
+    fn queue_pin_covers(&self) {
+        if self.imp().pin_covers_source.borrow().is_some() {
+            return;
         }
+
+        let source_id = glib::idle_add_local_once(clone!(@weak self as win => move || {
+            win.imp().pin_covers_source.replace(None);
+            if let Some(player) = win.player() {
+                win.pin_covers(player.queue());
+            }
+        }));
+        self.imp().pin_covers_source.replace(Some(source_id));
+    }
+
+    fn update_lyrics(&self, song: Option<&Song>) {
+        self.set_lyrics(None);
+
+        let song = match song {
+            Some(s) => s.clone(),
+            None => return,
+        };
+
+        // Reading the tags can take a while, so we do it in a worker thread
+        let file = song.file();
+        let ctx = glib::MainContext::default();
+        ctx.spawn_local(clone!(@weak self as win => async move {
+            let lyrics = gio::spawn_blocking(move || Lyrics::load(&file)).await.ok().flatten();
+
+            // Ignore the lyrics if the song changed in the meantime
+            let is_current = win
+                .player()
+                .and_then(|p| p.state().current_song())
+                .is_some_and(|s| s == song);
+            if is_current {
+                win.set_lyrics(lyrics);
+            }
+        }));
+    }
+
+    fn set_lyrics(&self, lyrics: Option<Lyrics>) {
+        // The synchronized lines are shown when hovering the waveform
+        let markers = lyrics
+            .as_ref()
+            .map(|l| {
+                l.lines()
+                    .iter()
+                    .filter_map(|line| line.time.map(|t| (t, line.text.clone())))
+                    .collect()
+            })
+            .unwrap_or_default();
+        self.imp().waveform_view.set_markers(markers);
+
+        self.imp().lyrics_view.set_lyrics(lyrics);
     }
 
     fn update_cover(&self) {
@@ -1285,6 +2582,21 @@ impl Window {
         if let Some(display) = gdk::Display::default() {
             gtk::style_context_add_provider_for_display(&display, &imp.provider, 400);
         }
+
+        // The palette depends on the style, so we need to update it
+        // whenever the style changes
+        let style_manager = adw::StyleManager::default();
+        for property in ["dark", "high-contrast"] {
+            style_manager.connect_notify_local(
+                Some(property),
+                clone!(@weak self as win => move |_, _| {
+                    if let Some(player) = win.player() {
+                        let state = player.state();
+                        win.update_style(state.current_song().as_ref());
+                    }
+                }),
+            );
+        }
     }
 
     fn update_style(&self, song: Option<&Song>) {
@@ -1293,15 +2605,27 @@ impl Window {
         if !imp.settings.boolean("enable-recoloring") {
             imp.provider.load_from_data("");
             imp.main_stack.remove_css_class("main-window");
+            imp.waveform_view.set_foreground(None);
             return;
         }
 
         if let Some(song) = song {
             if let Some(bg_colors) = song.cover_palette() {
+                let style_manager = adw::StyleManager::default();
+                let style = StyleInfo {
+                    dark: style_manager.is_dark(),
+                    high_contrast: style_manager.is_high_contrast(),
+                };
+                let palette = adjust_palette(&bg_colors, &style);
+
                 let mut css = String::new();
+                css.push_str(&format!(
+                    "@define-color foreground_color {};",
+                    palette.foreground
+                ));
 
-                let n_colors = bg_colors.len();
-                for (i, color) in bg_colors.iter().enumerate().take(n_colors) {
+                let n_colors = palette.background.len();
+                for (i, color) in palette.background.iter().enumerate().take(n_colors) {
                     let s = format!("@define-color background_color_{} {};", i, color);
                     css.push_str(&s);
                 }
@@ -1318,6 +2642,9 @@ impl Window {
                     imp.main_stack.add_css_class("main-window");
                 }
 
+                // The waveform is drawn with the same contrast as the text
+                imp.waveform_view.set_foreground(Some(&palette.foreground));
+
                 self.action_set_enabled("win.enable-recoloring", true);
 
                 return;
@@ -1326,6 +2653,7 @@ impl Window {
 
         imp.provider.load_from_data("");
         imp.main_stack.remove_css_class("main-window");
+        imp.waveform_view.set_foreground(None);
         self.action_set_enabled("win.enable-recoloring", false);
     }
 
@@ -1359,6 +2687,193 @@ impl Window {
                 .playlist_view
                 .queue_selected_label()
                 .set_label(&selected_str);
+
+            self.action_set_enabled("queue.edit-selected", n_selected > 0);
+        }
+    }
+
+    // Edits the tags of the song with the given URI; in selection mode,
+    // if the song is selected, all the selected songs are edited
+    fn edit_song_tags(&self, uri: &str) {
+        if let Some(player) = self.player() {
+            let queue = player.queue();
+            let song = (0..queue.n_songs())
+                .filter_map(|idx| queue.song_at(idx))
+                .find(|s| s.uri() == uri);
+
+            if let Some(song) = song {
+                if self.playlist_selection() && song.selected() {
+                    self.edit_selected_tags();
+                } else {
+                    self.edit_tags(vec![song]);
+                }
+            }
+        }
+    }
+
+    // Rates the song with the given URI, and only that song
+    pub fn set_song_rating(&self, uri: &str, rating: u32) {
+        if let Some(player) = self.player() {
+            let queue = player.queue();
+            let song = (0..queue.n_songs())
+                .filter_map(|idx| queue.song_at(idx))
+                .find(|s| s.uri() == uri);
+
+            if let Some(song) = song {
+                self.save_tags(
+                    vec![song],
+                    TagChanges {
+                        rating: Some(rating),
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+    }
+
+    // Rates the song with the given URI; in selection mode, if the song
+    // is selected, all the selected songs are rated
+    fn rate_song(&self, uri: &str, rating: u32) {
+        if let Some(player) = self.player() {
+            let queue = player.queue();
+            let song = (0..queue.n_songs())
+                .filter_map(|idx| queue.song_at(idx))
+                .find(|s| s.uri() == uri);
+
+            if let Some(song) = song {
+                let songs = if self.playlist_selection() && song.selected() {
+                    (0..queue.n_songs())
+                        .filter_map(|idx| queue.song_at(idx))
+                        .filter(|s| s.selected())
+                        .collect()
+                } else {
+                    vec![song]
+                };
+
+                self.save_tags(
+                    songs,
+                    TagChanges {
+                        rating: Some(rating),
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+    }
+
+    fn edit_selected_tags(&self) {
+        if let Some(player) = self.player() {
+            let queue = player.queue();
+            let songs: Vec<Song> = (0..queue.n_songs())
+                .filter_map(|idx| queue.song_at(idx))
+                .filter(|s| s.selected())
+                .collect();
+
+            if !songs.is_empty() {
+                self.edit_tags(songs);
+            }
+        }
+    }
+
+    fn edit_tags(&self, songs: Vec<Song>) {
+        let dialog = TagEditorDialog::new(&songs);
+
+        dialog.connect_closure(
+            "save",
+            false,
+            closure_local!(@watch self as win => move |dialog: TagEditorDialog| {
+                win.save_tags(dialog.songs(), dialog.changes());
+            }),
+        );
+
+        dialog.present(Some(self));
+    }
+
+    fn save_tags(&self, songs: Vec<Song>, changes: TagChanges) {
+        let changes_cover = changes.changes_cover();
+        let paths: Vec<_> = songs.iter().map(|s| s.file().path()).collect();
+
+        let ctx = glib::MainContext::default();
+        ctx.spawn_local(clone!(@weak self as win => async move {
+            // Writing the tags can take a while, especially when
+            // embedding a new cover, so we do it in a separate thread
+            let res = gio::spawn_blocking(move || {
+                paths
+                    .iter()
+                    .map(|path| match path {
+                        Some(path) => write_tags(path, &changes).map_err(|e| e.to_string()),
+                        None => Err("Not a local file".to_string()),
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .await;
+
+            let results = match res {
+                Ok(results) => results,
+                Err(_) => {
+                    win.add_toast(i18n("Unable to save tags"));
+                    return;
+                }
+            };
+
+            let mut n_failed = 0;
+            for (song, res) in songs.iter().zip(results) {
+                match res {
+                    Ok(_) => win.reload_song(song, changes_cover),
+                    Err(e) => {
+                        warn!("Unable to save tags for {}: {}", song.uri(), e);
+                        n_failed += 1;
+                    }
+                }
+            }
+
+            store_metadata_index();
+
+            if n_failed > 0 {
+                win.add_toast(ni18n_f(
+                    // Translators: the `{}` must be left unmodified, and
+                    // it will be replaced by the number of songs
+                    "Unable to save the tags of one song",
+                    "Unable to save the tags of {} songs",
+                    n_failed,
+                    &[&n_failed.to_string()],
+                ));
+            }
+
+            if let Some(player) = win.player() {
+                let state = player.state();
+                if state.current_song().is_some_and(|s| songs.contains(&s)) {
+                    win.update_song();
+                }
+            }
+        }));
+    }
+
+    // Reloads the metadata of a song after its tags have been written,
+    // and moves the data we cache for it
+    fn reload_song(&self, song: &Song, cover_changed: bool) {
+        let old_uuid = song.uuid();
+
+        // The cover art is cached by album, so we need to drop the old
+        // one, otherwise the reloaded song would pick it up again
+        if cover_changed {
+            if let Some(cover_uuid) = song.cover_uuid() {
+                CoverCache::global().lock().unwrap().remove(&cover_uuid);
+            }
+        }
+
+        if !song.reload() {
+            return;
+        }
+
+        if let (Some(old_uuid), Some(new_uuid)) = (old_uuid, song.uuid()) {
+            if old_uuid != new_uuid {
+                WaveformGenerator::move_cached_peaks(&old_uuid, &new_uuid);
+            }
+        }
+
+        if let Some(player) = self.player() {
+            player.update_song(song);
         }
     }
 
@@ -1375,6 +2890,82 @@ impl Window {
         self.add_files_to_queue(model.upcast_ref::<gio::ListModel>());
     }
 
+    // Add a single song to the queue, at the given position; the position
+    // is resolved once the song is loaded, as the queue may have changed
+    // in the meantime
+    pub fn add_track(&self, file: &gio::File, position: TrackPosition, set_as_current: bool) {
+        let file = file.clone();
+
+        let ctx = glib::MainContext::default();
+        ctx.spawn_local(clone!(@weak self as win => async move {
+            let song = match load_track(&file).await {
+                Some(song) => song,
+                None => return,
+            };
+
+            let player = match win.player() {
+                Some(p) => p,
+                None => return,
+            };
+            let queue = player.queue();
+            let was_empty = queue.is_empty();
+
+            let position = match position {
+                TrackPosition::Start => 0,
+                TrackPosition::After(serial) => queue
+                    .song_position(serial)
+                    .map_or(queue.n_songs(), |pos| pos + 1),
+                TrackPosition::End => queue.n_songs(),
+            };
+            debug!("Adding track {} at {}", file.uri(), position);
+            let position = queue.insert_song(position, &song);
+
+            utils::store_playlist(queue);
+            if set_as_current || was_empty {
+                player.skip_to(position);
+            }
+            win.switch_mode(WindowMode::MainView);
+            win.update_playlist_time();
+        }));
+    }
+
+    // Play a single song, from the queue if it is already there, or after
+    // adding it at the end of the queue
+    pub fn open_track(&self, file: &gio::File) {
+        let file = file.clone();
+
+        let ctx = glib::MainContext::default();
+        ctx.spawn_local(clone!(@weak self as win => async move {
+            let song = match load_track(&file).await {
+                Some(song) => song,
+                None => return,
+            };
+
+            let player = match win.player() {
+                Some(p) => p,
+                None => return,
+            };
+            let queue = player.queue();
+
+            let existing = (0..queue.n_songs())
+                .find(|pos| queue.song_at(*pos).is_some_and(|s| s.equals(&song)));
+            let position = match existing {
+                Some(pos) => pos,
+                None => {
+                    let pos = queue.insert_song(queue.n_songs(), &song);
+                    utils::store_playlist(queue);
+                    win.update_playlist_time();
+                    pos
+                }
+            };
+
+            debug!("Opening {} at {}", file.uri(), position);
+            player.skip_to(position);
+            player.play();
+            win.switch_mode(WindowMode::MainView);
+        }));
+    }
+
     pub fn remove_song(&self, song: &Song) {
         if let Some(p) = self.player() {
             p.remove_song(song);
@@ -1396,6 +2987,20 @@ impl Window {
         self.imp().toast_overlay.add_toast(toast);
     }
 
+    fn add_watch_folder_toast(&self, folder: &gio::File) {
+        let name = folder
+            .basename()
+            .map(|b| b.to_string_lossy().to_string())
+            .unwrap_or_else(|| folder.uri().to_string());
+        // Translators: the `{}` must be left unmodified, and
+        // it will be replaced by the name of a folder
+        let toast = adw::Toast::new(&i18n_f("Watch “{}” for changes?", &[&name]));
+        toast.set_button_label(Some(&i18n("Watch")));
+        toast.set_action_name(Some("queue.watch-folder"));
+        toast.set_action_target_value(Some(&folder.uri().to_variant()));
+        self.imp().toast_overlay.add_toast(toast);
+    }
+
     fn copy_song(&self) {
         if let Some(player) = self.player() {
             let state = player.state();
@@ -1468,3 +3073,24 @@ impl Window {
         self.imp().waveform_view.set_position(position);
     }
 }
+
+// Loads the song for a single file, outside of the regular loads, as
+// remote controls add songs one at a time
+async fn load_track(file: &gio::File) -> Option<Song> {
+    let receiver = load_song_data(std::slice::from_ref(file), &gio::Cancellable::new());
+
+    let mut song = None;
+    while let Ok(batch) = receiver.recv().await {
+        for (_, data) in batch {
+            song = data.map(Song::from_data);
+        }
+    }
+
+    if song.is_some() {
+        store_metadata_index();
+    } else {
+        warn!("Unable to load track {}", file.uri());
+    }
+
+    song
+}
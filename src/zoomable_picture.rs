@@ -0,0 +1,332 @@
+// SPDX-FileCopyrightText: 2022  Emmanuele Bassi
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::cell::{Cell, RefCell};
+
+use glib::clone;
+use gtk::{gdk, glib, graphene, gsk, prelude::*, subclass::prelude::*};
+
+// The zoom level is the number of device pixels for each pixel of the
+// texture, so a zoom level of 1.0 shows the picture at its native
+// resolution
+const MAX_ZOOM: f64 = 8.0;
+const ZOOM_STEP: f64 = 1.25;
+
+mod imp {
+    use glib::{ParamSpec, ParamSpecBoolean, ParamSpecObject, Value};
+    use once_cell::sync::Lazy;
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    pub struct ZoomablePicture {
+        pub texture: RefCell<Option<gdk::Texture>>,
+        // The zoom level, or None to fit the picture into the widget
+        pub zoom: Cell<Option<f64>>,
+        // The offset of the center of the picture from the center of the
+        // widget, in logical pixels
+        pub offset: Cell<(f64, f64)>,
+        pub zoomed: Cell<bool>,
+        pub pointer: Cell<Option<(f64, f64)>>,
+        pub drag_start: Cell<(f64, f64)>,
+        pub zoom_start: Cell<f64>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for ZoomablePicture {
+        const NAME: &'static str = "AmberolZoomablePicture";
+        type Type = super::ZoomablePicture;
+        type ParentType = gtk::Widget;
+
+        fn class_init(klass: &mut Self::Class) {
+            klass.set_css_name("zoomablepicture");
+            klass.set_accessible_role(gtk::AccessibleRole::Img);
+        }
+    }
+
+    impl ObjectImpl for ZoomablePicture {
+        fn constructed(&self) {
+            self.parent_constructed();
+
+            let obj = self.obj();
+            obj.set_overflow(gtk::Overflow::Hidden);
+            obj.set_hexpand(true);
+            obj.set_vexpand(true);
+            obj.setup_controllers();
+        }
+
+        fn properties() -> &'static [ParamSpec] {
+            static PROPERTIES: Lazy<Vec<ParamSpec>> = Lazy::new(|| {
+                vec![
+                    ParamSpecObject::builder::<gdk::Texture>("texture").build(),
+                    ParamSpecBoolean::builder("zoomed").read_only().build(),
+                ]
+            });
+            PROPERTIES.as_ref()
+        }
+
+        fn property(&self, _id: usize, pspec: &ParamSpec) -> Value {
+            match pspec.name() {
+                "texture" => self.texture.borrow().to_value(),
+                "zoomed" => self.zoomed.get().to_value(),
+                _ => unimplemented!(),
+            }
+        }
+
+        fn set_property(&self, _id: usize, value: &Value, pspec: &ParamSpec) {
+            match pspec.name() {
+                "texture" => self
+                    .obj()
+                    .set_texture(value.get::<gdk::Texture>().ok().as_ref()),
+                _ => unimplemented!(),
+            };
+        }
+    }
+
+    impl WidgetImpl for ZoomablePicture {
+        fn measure(&self, _orientation: gtk::Orientation, _for_size: i32) -> (i32, i32, i32, i32) {
+            (0, 0, -1, -1)
+        }
+
+        fn size_allocate(&self, width: i32, height: i32, baseline: i32) {
+            self.parent_size_allocate(width, height, baseline);
+
+            // The zoom level needed to fit the picture changes with the
+            // size of the widget
+            let obj = self.obj();
+            obj.clamp_offset();
+            obj.update_zoomed();
+        }
+
+        fn snapshot(&self, snapshot: &gtk::Snapshot) {
+            if let Some(ref texture) = *self.texture.borrow() {
+                let widget = self.obj();
+                let zoom = widget.zoom();
+                let scale = zoom / widget.scale_factor() as f64;
+                let w = texture.width() as f64 * scale;
+                let h = texture.height() as f64 * scale;
+                let (offset_x, offset_y) = self.offset.get();
+                let x = (widget.width() as f64 - w) / 2.0 + offset_x;
+                let y = (widget.height() as f64 - h) / 2.0 + offset_y;
+
+                // Keep the pixels sharp when magnifying the picture
+                let filter = if zoom > 1.0 {
+                    gsk::ScalingFilter::Nearest
+                } else {
+                    gsk::ScalingFilter::Trilinear
+                };
+
+                snapshot.append_scaled_texture(
+                    texture,
+                    filter,
+                    &graphene::Rect::new(x.round() as f32, y.round() as f32, w as f32, h as f32),
+                );
+            }
+        }
+    }
+}
+
+glib::wrapper! {
+    pub struct ZoomablePicture(ObjectSubclass<imp::ZoomablePicture>)
+        @extends gtk::Widget,
+        @implements gtk::Accessible, gtk::Buildable, gtk::ConstraintTarget;
+}
+
+impl Default for ZoomablePicture {
+    fn default() -> Self {
+        glib::Object::new()
+    }
+}
+
+impl ZoomablePicture {
+    pub fn new(texture: &gdk::Texture) -> Self {
+        glib::Object::builder().property("texture", texture).build()
+    }
+
+    fn setup_controllers(&self) {
+        let drag_gesture = gtk::GestureDrag::new();
+        drag_gesture.connect_drag_begin(clone!(@weak self as this => move |gesture, _, _| {
+            // Leave the gesture to the parent, for instance to swipe
+            // between pictures, if there is nothing to pan
+            if !this.is_zoomed() {
+                gesture.set_state(gtk::EventSequenceState::Denied);
+                return;
+            }
+
+            this.imp().drag_start.set(this.imp().offset.get());
+            this.set_cursor_from_name(Some("grabbing"));
+        }));
+        drag_gesture.connect_drag_update(clone!(@weak self as this => move |gesture, offset_x, offset_y| {
+            gesture.set_state(gtk::EventSequenceState::Claimed);
+
+            let (start_x, start_y) = this.imp().drag_start.get();
+            this.imp().offset.set((start_x + offset_x, start_y + offset_y));
+            this.clamp_offset();
+            this.queue_draw();
+        }));
+        drag_gesture.connect_drag_end(clone!(@weak self as this => move |_, _, _| {
+            this.update_cursor();
+        }));
+        self.add_controller(drag_gesture);
+
+        let zoom_gesture = gtk::GestureZoom::new();
+        zoom_gesture.connect_begin(clone!(@weak self as this => move |_, _| {
+            this.imp().zoom_start.set(this.zoom());
+        }));
+        zoom_gesture.connect_scale_changed(clone!(@weak self as this => move |gesture, scale| {
+            let zoom = this.imp().zoom_start.get() * scale;
+            this.set_zoom_at(zoom, gesture.bounding_box_center());
+        }));
+        self.add_controller(zoom_gesture);
+
+        let motion_controller = gtk::EventControllerMotion::new();
+        motion_controller.connect_motion(clone!(@weak self as this => move |_, x, y| {
+            this.imp().pointer.set(Some((x, y)));
+        }));
+        motion_controller.connect_leave(clone!(@weak self as this => move |_| {
+            this.imp().pointer.set(None);
+        }));
+        self.add_controller(motion_controller);
+
+        let scroll_controller =
+            gtk::EventControllerScroll::new(gtk::EventControllerScrollFlags::VERTICAL);
+        scroll_controller.connect_scroll(clone!(@weak self as this => @default-return glib::Propagation::Proceed, move |_, _, dy| {
+            let zoom = if dy < 0.0 {
+                this.zoom() * ZOOM_STEP
+            } else {
+                this.zoom() / ZOOM_STEP
+            };
+            this.set_zoom_at(zoom, this.imp().pointer.get());
+            glib::Propagation::Stop
+        }));
+        self.add_controller(scroll_controller);
+
+        let click_gesture = gtk::GestureClick::new();
+        click_gesture.connect_pressed(clone!(@weak self as this => move |_, n_press, x, y| {
+            if n_press != 2 {
+                return;
+            }
+
+            // Double clicking switches between the whole picture and
+            // its native resolution
+            if this.is_zoomed() {
+                this.zoom_to_fit();
+            } else {
+                let zoom = if this.fit_zoom() < 1.0 { 1.0 } else { 2.0 };
+                this.set_zoom_at(zoom, Some((x, y)));
+            }
+        }));
+        self.add_controller(click_gesture);
+    }
+
+    pub fn set_texture(&self, texture: Option<&gdk::Texture>) {
+        self.imp().texture.replace(texture.cloned());
+        self.zoom_to_fit();
+        self.notify("texture");
+    }
+
+    // The zoom level that fits the whole picture into the widget; small
+    // pictures are not scaled up
+    fn fit_zoom(&self) -> f64 {
+        let texture = match self.imp().texture.borrow().as_ref() {
+            Some(t) => (t.width() as f64, t.height() as f64),
+            None => return 1.0,
+        };
+
+        let scale_factor = self.scale_factor() as f64;
+        let width = self.width() as f64 * scale_factor;
+        let height = self.height() as f64 * scale_factor;
+        if width <= 0.0 || height <= 0.0 || texture.0 <= 0.0 || texture.1 <= 0.0 {
+            return 1.0;
+        }
+
+        (width / texture.0).min(height / texture.1).min(1.0)
+    }
+
+    pub fn zoom(&self) -> f64 {
+        self.imp().zoom.get().unwrap_or_else(|| self.fit_zoom())
+    }
+
+    // Whether the picture is zoomed in beyond the size of the widget
+    pub fn is_zoomed(&self) -> bool {
+        self.imp().zoomed.get()
+    }
+
+    fn update_zoomed(&self) {
+        let zoomed = self.zoom() > self.fit_zoom() * 1.001;
+        if zoomed != self.imp().zoomed.replace(zoomed) {
+            self.update_cursor();
+            self.notify("zoomed");
+        }
+    }
+
+    fn update_cursor(&self) {
+        if self.is_zoomed() {
+            self.set_cursor_from_name(Some("grab"));
+        } else {
+            self.set_cursor(None);
+        }
+    }
+
+    // Keeps the picture within the widget, and centered along the
+    // directions in which it fits
+    fn clamp_offset(&self) {
+        let texture = match self.imp().texture.borrow().as_ref() {
+            Some(t) => (t.width() as f64, t.height() as f64),
+            None => return,
+        };
+
+        let scale = self.zoom() / self.scale_factor() as f64;
+        let max_x = ((texture.0 * scale - self.width() as f64) / 2.0).max(0.0);
+        let max_y = ((texture.1 * scale - self.height() as f64) / 2.0).max(0.0);
+
+        let (x, y) = self.imp().offset.get();
+        self.imp()
+            .offset
+            .set((x.clamp(-max_x, max_x), y.clamp(-max_y, max_y)));
+    }
+
+    // Sets the zoom level, keeping the point of the picture under the
+    // anchor in place; without an anchor, the center of the widget is
+    // used
+    fn set_zoom_at(&self, zoom: f64, anchor: Option<(f64, f64)>) {
+        let fit_zoom = self.fit_zoom();
+        let zoom = zoom.clamp(fit_zoom, MAX_ZOOM.max(fit_zoom));
+
+        let center_x = self.width() as f64 / 2.0;
+        let center_y = self.height() as f64 / 2.0;
+        let (anchor_x, anchor_y) = anchor.unwrap_or((center_x, center_y));
+
+        let ratio = zoom / self.zoom();
+        let (x, y) = self.imp().offset.get();
+        let x = anchor_x - center_x - (anchor_x - center_x - x) * ratio;
+        let y = anchor_y - center_y - (anchor_y - center_y - y) * ratio;
+
+        if zoom <= fit_zoom {
+            self.imp().zoom.set(None);
+            self.imp().offset.set((0.0, 0.0));
+        } else {
+            self.imp().zoom.set(Some(zoom));
+            self.imp().offset.set((x, y));
+        }
+
+        self.clamp_offset();
+        self.update_zoomed();
+        self.queue_draw();
+    }
+
+    pub fn zoom_in(&self) {
+        self.set_zoom_at(self.zoom() * ZOOM_STEP, None);
+    }
+
+    pub fn zoom_out(&self) {
+        self.set_zoom_at(self.zoom() / ZOOM_STEP, None);
+    }
+
+    pub fn zoom_to_fit(&self) {
+        self.imp().zoom.set(None);
+        self.imp().offset.set((0.0, 0.0));
+        self.update_zoomed();
+        self.queue_draw();
+    }
+}